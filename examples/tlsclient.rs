@@ -19,7 +19,6 @@ extern crate docopt;
 use docopt::Docopt;
 
 extern crate rustls;
-extern crate webpki;
 extern crate webpki_roots;
 extern crate ct_logs;
 
@@ -77,12 +76,12 @@ impl io::Read for TlsClient {
 }
 
 impl TlsClient {
-    fn new(sock: TcpStream, hostname: webpki::DNSNameRef, cfg: Arc<rustls::ClientConfig>) -> TlsClient {
+    fn new(sock: TcpStream, server_name: rustls::ServerName, cfg: Arc<rustls::ClientConfig>) -> TlsClient {
         TlsClient {
             socket: sock,
             closing: false,
             clean_closure: false,
-            tls_session: rustls::ClientSession::new(&cfg, hostname),
+            tls_session: rustls::ClientSession::new(&cfg, server_name),
         }
     }
 
@@ -377,11 +376,29 @@ fn load_certs(filename: &str) -> Vec<rustls::Certificate> {
 }
 
 fn load_private_key(filename: &str) -> rustls::PrivateKey {
-    let keyfile = fs::File::open(filename).expect("cannot open private key file");
-    let mut reader = BufReader::new(keyfile);
-    let keys = rustls::internal::pemfile::rsa_private_keys(&mut reader).unwrap();
-    assert!(keys.len() == 1);
-    keys[0].clone()
+    let rsa_keys = {
+        let keyfile = fs::File::open(filename)
+            .expect("cannot open private key file");
+        let mut reader = BufReader::new(keyfile);
+        rustls::internal::pemfile::rsa_private_keys(&mut reader)
+            .expect("file contains invalid rsa private key")
+    };
+
+    let pkcs8_keys = {
+        let keyfile = fs::File::open(filename)
+            .expect("cannot open private key file");
+        let mut reader = BufReader::new(keyfile);
+        rustls::internal::pemfile::pkcs8_private_keys(&mut reader)
+            .expect("file contains invalid pkcs8 private key (encrypted keys not supported)")
+    };
+
+    // prefer to load pkcs8 keys
+    if !pkcs8_keys.is_empty() {
+        pkcs8_keys[0].clone()
+    } else {
+        assert!(!rsa_keys.is_empty());
+        rsa_keys[0].clone()
+    }
 }
 
 fn load_key_and_cert(config: &mut rustls::ClientConfig, keyfile: &str, certsfile: &str) {
@@ -394,7 +411,6 @@ fn load_key_and_cert(config: &mut rustls::ClientConfig, keyfile: &str, certsfile
 #[cfg(feature = "dangerous_configuration")]
 mod danger {
     use super::rustls;
-    use webpki;
 
     pub struct NoCertificateVerification {}
 
@@ -402,7 +418,7 @@ mod danger {
         fn verify_server_cert(&self,
                               _roots: &rustls::RootCertStore,
                               _presented_certs: &[rustls::Certificate],
-                              _dns_name: webpki::DNSNameRef,
+                              _server_name: &rustls::ServerName,
                               _ocsp: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
             Ok(rustls::ServerCertVerified::assertion())
         }
@@ -428,6 +444,7 @@ fn apply_dangerous_options(args: &Args, _: &mut rustls::ClientConfig) {
 /// Build a `ClientConfig` from our arguments
 fn make_config(args: &Args) -> Arc<rustls::ClientConfig> {
     let mut config = rustls::ClientConfig::new();
+    config.key_log = Arc::new(rustls::KeyLogFile::new());
 
     if !args.flag_suite.is_empty() {
         config.ciphersuites = lookup_suites(&args.flag_suite);
@@ -498,8 +515,8 @@ fn main() {
     let config = make_config(&args);
 
     let sock = TcpStream::connect(&addr).unwrap();
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str(&args.arg_hostname).unwrap();
-    let mut tlsclient = TlsClient::new(sock, dns_name, config);
+    let server_name = rustls::ServerName::try_from_ascii_str(&args.arg_hostname).unwrap();
+    let mut tlsclient = TlsClient::new(sock, server_name, config);
 
     if args.flag_http {
         let httpreq = format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: \
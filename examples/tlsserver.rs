@@ -374,7 +374,7 @@ connection.
 localhost:fport.
 
 `--certs' names the full certificate chain, `--key' provides the
-RSA private key.
+RSA or ECDSA private key.
 
 Usage:
   tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] \
@@ -393,7 +393,8 @@ Options:
                         in the right order (the first certificate should
                         certify KEYFILE, the last should be a root CA).
     --key KEYFILE       Read private key from KEYFILE.  This should be a RSA
-                        private key or PKCS8-encoded private key, in PEM format.
+                        private key or PKCS8-encoded RSA or ECDSA private key,
+                        in PEM format.
     --ocsp OCSPFILE     Read DER-encoded OCSP response from OCSPFILE and staple
                         to certificate.  Optional.
     --auth CERTFILE     Enable client authentication, and accept certificates
@@ -518,6 +519,7 @@ fn make_config(args: &Args) -> Arc<rustls::ServerConfig> {
     };
 
     let mut config = rustls::ServerConfig::new(client_auth);
+    config.key_log = Arc::new(rustls::KeyLogFile::new());
 
     let certs = load_certs(args.flag_certs.as_ref().expect("--certs option missing"));
     let privkey = load_private_key(args.flag_key.as_ref().expect("--key option missing"));
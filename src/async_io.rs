@@ -0,0 +1,329 @@
+use std::io::{self, Read, Write};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use session::Session;
+use client::{ClientConfig, ClientSession, ServerName};
+use server::{ServerConfig, ServerSession};
+
+/// Adapts an async transport to `io::Read` and `io::Write`, so we can
+/// give it to `Session::read_tls` and `Session::write_tls`.  Pending
+/// operations turn into `io::ErrorKind::WouldBlock` errors.
+struct SyncAdapter<'a, 'b: 'a, T: 'a> {
+    io: &'a mut T,
+    cx: &'a mut Context<'b>,
+}
+
+impl<'a, 'b, T> Read for SyncAdapter<'a, 'b, T> where T: AsyncRead + Unpin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_read(self.cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<'a, 'b, T> Write for SyncAdapter<'a, 'b, T> where T: AsyncWrite + Unpin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+/// This type implements `AsyncRead` and `AsyncWrite`, encapsulating
+/// a Session `S` and an underlying async transport `T`.
+///
+/// This is the async equivalent of `StreamOwned`: any handshake IO
+/// happens as needed when it is first read or written, though you
+/// can use `connect` or `accept` to complete the handshake first.
+/// Closing it sends a close_notify alert before closing `T`.
+pub struct AsyncStream<S: Session, T: AsyncRead + AsyncWrite + Unpin> {
+    sess: S,
+    io: T,
+    eof: bool,
+    closing: bool,
+}
+
+impl<S, T> AsyncStream<S, T> where S: Session + Unpin, T: AsyncRead + AsyncWrite + Unpin {
+    /// Make a new AsyncStream using the Session `sess` and async
+    /// transport `io`.  This does not fail and does no IO.
+    pub fn new(sess: S, io: T) -> AsyncStream<S, T> {
+        AsyncStream {
+            sess: sess,
+            io: io,
+            eof: false,
+            closing: false,
+        }
+    }
+
+    /// Get a reference to the underlying session.
+    pub fn get_session(&self) -> &S {
+        &self.sess
+    }
+
+    /// Get a mutable reference to the underlying session.
+    pub fn get_mut_session(&mut self) -> &mut S {
+        &mut self.sess
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_io(&self) -> &T {
+        &self.io
+    }
+
+    /// Take back the session and transport.
+    pub fn into_parts(self) -> (S, T) {
+        (self.sess, self.io)
+    }
+
+    /// Read some TLS from the transport, and process it.  On a TLS
+    /// error, we make an effort to send any alert to the peer.
+    fn read_tls(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        let read = {
+            let mut reader = SyncAdapter { io: &mut self.io, cx: cx };
+            self.sess.read_tls(&mut reader)
+        };
+
+        let len = match read {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+
+        if len == 0 {
+            self.eof = true;
+        }
+
+        if let Err(err) = self.sess.process_new_packets() {
+            while self.sess.wants_write() {
+                match self.write_tls(cx) {
+                    Poll::Ready(Ok(_)) => {}
+                    _ => break,
+                }
+            }
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, err)));
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    /// Write some pending TLS to the transport.
+    fn write_tls(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        let mut writer = SyncAdapter { io: &mut self.io, cx: cx };
+        match self.sess.write_tls(&mut writer) {
+            Ok(len) => Poll::Ready(Ok(len)),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+
+    /// Write as much pending TLS as the transport will take.
+    /// Returns `Ready` once there is none left.
+    fn poll_write_all_tls(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.sess.wants_write() {
+            match self.write_tls(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Do IO until the handshake is complete.  Our final handshake
+    /// messages may still be queued afterwards; they are written
+    /// along with the next data, or on `poll_flush`.
+    fn poll_handshake(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            if let Poll::Ready(Err(err)) = self.poll_write_all_tls(cx) {
+                return Poll::Ready(Err(err));
+            }
+
+            if !self.sess.is_handshaking() {
+                return Poll::Ready(Ok(()));
+            }
+
+            // Read even if `wants_read` is false: we might have early
+            // data buffered, but still need the rest of the handshake.
+            match self.read_tls(cx) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                          "tls handshake eof")));
+                }
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S, T> AsyncRead for AsyncStream<S, T>
+    where S: Session + Unpin, T: AsyncRead + AsyncWrite + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.sess.is_handshaking() {
+            match this.poll_handshake(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other.map(|r| r.map(|_| 0)),
+            }
+        }
+
+        loop {
+            match this.sess.read(buf) {
+                Ok(0) if !buf.is_empty() => {}
+                Ok(len) => return Poll::Ready(Ok(len)),
+                // The peer sent close_notify: that's a clean EOF.
+                Err(ref err) if err.kind() == io::ErrorKind::ConnectionAborted => {
+                    return Poll::Ready(Ok(0));
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+
+            if this.eof {
+                return Poll::Ready(Ok(0));
+            }
+
+            // Send anything we owe the peer, like a KeyUpdate, but
+            // don't wait for it.
+            if let Poll::Ready(Err(err)) = this.poll_write_all_tls(cx) {
+                return Poll::Ready(Err(err));
+            }
+
+            match this.read_tls(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S, T> AsyncWrite for AsyncStream<S, T>
+    where S: Session + Unpin, T: AsyncRead + AsyncWrite + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.sess.is_handshaking() {
+            match this.poll_handshake(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other.map(|r| r.map(|_| 0)),
+            }
+        }
+
+        loop {
+            let len = this.sess.write(buf)?;
+
+            let write_pending = match this.poll_write_all_tls(cx) {
+                Poll::Ready(Ok(())) => false,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => true,
+            };
+
+            // A zero-length write means our buffer is full: wait
+            // for the transport to take some of it.
+            if len != 0 || buf.is_empty() {
+                return Poll::Ready(Ok(len));
+            } else if write_pending {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.sess.is_handshaking() {
+            match this.poll_handshake(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other,
+            }
+        }
+
+        this.sess.flush()?;
+        match this.poll_write_all_tls(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.closing {
+            this.sess.send_close_notify();
+            this.closing = true;
+        }
+
+        match this.poll_write_all_tls(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+/// A future which completes a handshake over an `AsyncStream`,
+/// and then yields it.  Made by `connect` or `accept`.
+pub struct Handshake<S: Session, T: AsyncRead + AsyncWrite + Unpin> {
+    stream: Option<AsyncStream<S, T>>,
+}
+
+impl<S, T> Future for Handshake<S, T>
+    where S: Session + Unpin, T: AsyncRead + AsyncWrite + Unpin
+{
+    type Output = io::Result<AsyncStream<S, T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        match this.stream
+            .as_mut()
+            .expect("Handshake polled after completion")
+            .poll_handshake(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(this.stream.take().unwrap())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Start a client handshake with `server_name` over `io`, using
+/// `config`.  The returned future resolves once the handshake is
+/// complete.
+pub fn connect<T, N>(config: &Arc<ClientConfig>,
+                     server_name: N,
+                     io: T) -> Handshake<ClientSession, T>
+    where T: AsyncRead + AsyncWrite + Unpin,
+          N: Into<ServerName>
+{
+    Handshake {
+        stream: Some(AsyncStream::new(ClientSession::new(config, server_name), io)),
+    }
+}
+
+/// Accept a client's handshake over `io`, using `config`.  The
+/// returned future resolves once the handshake is complete.
+pub fn accept<T>(config: &Arc<ServerConfig>, io: T) -> Handshake<ServerSession, T>
+    where T: AsyncRead + AsyncWrite + Unpin
+{
+    Handshake {
+        stream: Some(AsyncStream::new(ServerSession::new(config), io)),
+    }
+}
@@ -0,0 +1,265 @@
+// Finding the certificate chains webpki accepts.
+//
+// webpki doesn't say which chain it built, so checks which depend
+// on the chain (OCSP, CRLs and pinning) use every chain where each
+// certificate is signed by the next and which webpki accepts given
+// only that chain's certificates.  A certificate which merely has
+// the right name, but didn't issue anything in such a chain, is
+// never used.
+
+use untrusted;
+use webpki;
+
+use anchors::RootCertStore;
+use error::TLSError;
+use key::Certificate;
+use verify;
+use x509;
+
+/// The most intermediates a chain can have.  This is webpki's limit.
+const MAX_INTERMEDIATES: usize = 6;
+
+/// The most chains we have webpki validate.  This bounds the work
+/// a peer can make us do by sending lots of cross-signed
+/// certificates.
+const MAX_CANDIDATE_CHAINS: usize = 16;
+
+/// What the end-entity certificate of a chain is used for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Usage {
+    TLSServer,
+    TLSClient,
+}
+
+/// A certificate on a validated chain.
+pub struct ChainCert {
+    pub subject: Vec<u8>,
+    pub spki: Vec<u8>,
+
+    /// The serial number, or `None` for the trust anchor.
+    pub serial: Option<Vec<u8>>,
+}
+
+impl ChainCert {
+    fn from_fields(fields: &x509::CertFields) -> ChainCert {
+        ChainCert {
+            subject: fields.subject.as_slice_less_safe().to_vec(),
+            spki: fields.spki.as_slice_less_safe().to_vec(),
+            serial: Some(fields.serial.as_slice_less_safe().to_vec()),
+        }
+    }
+
+    fn from_anchor(anchor: &webpki::TrustAnchor) -> ChainCert {
+        ChainCert {
+            subject: anchor.subject.to_vec(),
+            spki: anchor.spki.to_vec(),
+            serial: None,
+        }
+    }
+}
+
+/// A chain from the end-entity certificate to a trust anchor, where
+/// each certificate is signed by the next.  `intermediates` are
+/// indexes into the presented certificates, in order, and `anchor`
+/// is an index into the trust anchors.
+struct CandidateChain {
+    intermediates: Vec<usize>,
+    anchor: usize,
+}
+
+/// Return each chain from the end-entity certificate in
+/// `presented_certs` up to a trust anchor in `roots` which webpki
+/// accepts for `usage` at `now`.  Chains start with the end-entity
+/// certificate and end with the trust anchor.
+pub fn validated_chains(roots: &RootCertStore,
+                        presented_certs: &[Certificate],
+                        usage: Usage,
+                        now: webpki::Time) -> Result<Vec<Vec<ChainCert>>, TLSError> {
+    if presented_certs.is_empty() {
+        return Err(TLSError::NoCertificatesPresented);
+    }
+
+    let mut certs = Vec::new();
+    for cert in presented_certs {
+        let fields = x509::cert_fields(untrusted::Input::from(&cert.0))
+            .map_err(|_| TLSError::General("cannot parse certificate".to_string()))?;
+        certs.push(fields);
+    }
+
+    let anchors: Vec<_> = roots.roots
+        .iter()
+        .map(|ota| ota.to_trust_anchor())
+        .collect();
+
+    let mut candidates = Vec::new();
+    find_chains(&certs, &anchors, &mut vec![0], &mut candidates);
+
+    let end_entity = webpki::EndEntityCert::from(untrusted::Input::from(&presented_certs[0].0))
+        .map_err(TLSError::WebPKIError)?;
+
+    let mut chains = Vec::new();
+    for candidate in candidates {
+        let intermediates: Vec<_> = candidate.intermediates
+            .iter()
+            .map(|i| untrusted::Input::from(&presented_certs[*i].0))
+            .collect();
+        let anchor = &anchors[candidate.anchor..candidate.anchor + 1];
+
+        let valid = match usage {
+            Usage::TLSServer => {
+                end_entity.verify_is_valid_tls_server_cert(verify::SUPPORTED_SIG_ALGS,
+                                                           &webpki::TLSServerTrustAnchors(anchor),
+                                                           &intermediates,
+                                                           now)
+            }
+            Usage::TLSClient => {
+                end_entity.verify_is_valid_tls_client_cert(verify::SUPPORTED_SIG_ALGS,
+                                                           &webpki::TLSClientTrustAnchors(anchor),
+                                                           &intermediates,
+                                                           now)
+            }
+        };
+        if valid.is_err() {
+            continue;
+        }
+
+        let mut chain = vec![ChainCert::from_fields(&certs[0])];
+        for i in candidate.intermediates {
+            chain.push(ChainCert::from_fields(&certs[i]));
+        }
+        chain.push(ChainCert::from_anchor(&anchor[0]));
+        chains.push(chain);
+    }
+
+    Ok(chains)
+}
+
+/// Extend `path`, indexes into `certs` starting with the end-entity
+/// certificate, towards the trust anchors `anchors`.  Each complete
+/// chain found is added to `chains`.
+fn find_chains(certs: &[x509::CertFields],
+               anchors: &[webpki::TrustAnchor],
+               path: &mut Vec<usize>,
+               chains: &mut Vec<CandidateChain>) {
+    let current = &certs[*path.last().unwrap()];
+    let issuer = current.issuer.as_slice_less_safe();
+
+    for (i, anchor) in anchors.iter().enumerate() {
+        if chains.len() >= MAX_CANDIDATE_CHAINS {
+            return;
+        }
+
+        if anchor.subject == issuer && signed_by(current, untrusted::Input::from(anchor.spki)) {
+            chains.push(CandidateChain {
+                intermediates: path[1..].to_vec(),
+                anchor: i,
+            });
+        }
+    }
+
+    if path.len() > MAX_INTERMEDIATES {
+        return;
+    }
+
+    for (i, cert) in certs.iter().enumerate() {
+        if chains.len() >= MAX_CANDIDATE_CHAINS {
+            return;
+        }
+
+        if !path.contains(&i) &&
+           cert.subject.as_slice_less_safe() == issuer &&
+           signed_by(current, cert.spki) {
+            path.push(i);
+            find_chains(certs, anchors, path, chains);
+            path.pop();
+        }
+    }
+}
+
+/// Is `cert` signed by the key `spki`?
+pub fn signed_by(cert: &x509::CertFields, spki: untrusted::Input) -> bool {
+    x509::verify_signed_data(spki,
+                             cert.signature_alg.as_slice_less_safe(),
+                             cert.signed_data,
+                             cert.signature).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pemfile;
+    use std::fs;
+    use std::io;
+
+    fn load(name: &str) -> Vec<Certificate> {
+        let path = format!("test-ca/{}", name);
+        pemfile::certs(&mut io::BufReader::new(fs::File::open(path).unwrap())).unwrap()
+    }
+
+    fn now() -> webpki::Time {
+        // 2020-01-01, when the test certificates are valid.
+        webpki::Time::from_seconds_since_unix_epoch(1_577_836_800)
+    }
+
+    fn spki_of(cert: &Certificate) -> Vec<u8> {
+        x509::cert_fields(untrusted::Input::from(&cert.0))
+            .unwrap()
+            .spki
+            .as_slice_less_safe()
+            .to_vec()
+    }
+
+    #[test]
+    fn chain_includes_trust_anchor() {
+        let chain = load("rsa/end.fullchain");
+        let mut roots = RootCertStore::empty();
+        roots.add(&chain[2]).unwrap();
+
+        let mut chains = validated_chains(&roots, &chain[..2], Usage::TLSServer, now()).unwrap();
+        assert_eq!(chains.len(), 1);
+        let found = chains.pop().unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(found[0].serial.is_some());
+        assert!(found[1].serial.is_some());
+        assert!(found[2].serial.is_none());
+
+        let spkis: Vec<_> = found.into_iter().map(|cert| cert.spki).collect();
+        let expect: Vec<_> = chain.iter().map(spki_of).collect();
+        assert_eq!(spkis, expect);
+    }
+
+    #[test]
+    fn client_chains_need_client_usage() {
+        let chain = load("rsa/client.fullchain");
+        let mut roots = RootCertStore::empty();
+        roots.add(&chain[2]).unwrap();
+
+        assert_eq!(validated_chains(&roots, &chain[..2], Usage::TLSClient, now())
+                   .unwrap()
+                   .len(),
+                   1);
+        assert!(validated_chains(&roots, &chain[..2], Usage::TLSServer, now())
+                .unwrap()
+                .is_empty());
+    }
+
+    #[test]
+    fn certs_off_validated_chains_are_ignored() {
+        // The server also sends its intermediate cross-signed by
+        // the ECDSA CA.  That cross-signature has expired, so only
+        // the chain to the RSA CA is valid.
+        let rsa = load("rsa/end.fullchain");
+        let ecdsa_ca = load("ecdsa/ca.cert");
+        let cross = load("rsa/inter.expired-cross.cert");
+        let presented = vec![rsa[0].clone(), cross[0].clone(), rsa[1].clone()];
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&ecdsa_ca[0]).unwrap();
+        roots.add(&rsa[2]).unwrap();
+
+        let chains = validated_chains(&roots, &presented, Usage::TLSServer, now()).unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].len(), 3);
+        assert_eq!(chains[0][2].spki, spki_of(&rsa[2]));
+    }
+}
@@ -1,4 +1,4 @@
-use ring;
+use std::cmp;
 use std::io::Write;
 use msgs::codec;
 use msgs::codec::Codec;
@@ -9,6 +9,8 @@ use error::TLSError;
 use session::SessionSecrets;
 use suites::{SupportedCipherSuite, BulkAlgorithm};
 use key_schedule::{derive_traffic_key, derive_traffic_iv};
+use padding::RecordPadding;
+use crypto::{Aead, AeadKey};
 
 // accum[i] ^= offset[i] for all i in 0..len(accum)
 fn xor(accum: &mut [u8], offset: &[u8]) {
@@ -20,11 +22,34 @@ fn xor(accum: &mut [u8], offset: &[u8]) {
 /// Objects with this trait can decrypt TLS messages.
 pub trait MessageDecrypter : Send + Sync {
     fn decrypt(&self, m: Message, seq: u64) -> Result<Message, TLSError>;
+
+    /// How many records the peer may send us under this key before
+    /// we should ask it to use a new one.
+    fn confidentiality_limit(&self) -> u64 {
+        u64::MAX
+    }
 }
 
 /// Objects with this trait can encrypt TLS messages.
 pub trait MessageEncrypter : Send + Sync {
     fn encrypt(&self, m: BorrowMessage, seq: u64) -> Result<Message, TLSError>;
+
+    /// Encrypt `m`, padded as `padding` decides if this record
+    /// protection allows it.  The padded content is no longer than
+    /// `max_len`.
+    fn encrypt_padded(&self,
+                      m: BorrowMessage,
+                      seq: u64,
+                      _padding: &RecordPadding,
+                      _max_len: usize) -> Result<Message, TLSError> {
+        self.encrypt(m, seq)
+    }
+
+    /// How many records we may encrypt under this key before we
+    /// must move to a new one.
+    fn confidentiality_limit(&self) -> u64 {
+        u64::MAX
+    }
 }
 
 impl MessageEncrypter {
@@ -110,6 +135,20 @@ pub fn new_tls12(scs: &'static SupportedCipherSuite,
     }
 }
 
+/// RFC8446 section 5.5: up to 2^24.5 full-size records may be
+/// protected with one AES-GCM key.  We stop a little short of that.
+///
+/// The same bound applies to TLS1.2, which has no key updates: the
+/// connection is closed instead when it is reached.
+const AES_GCM_RECORD_LIMIT: u64 = 1 << 24;
+
+fn tls13_confidentiality_limit(scs: &'static SupportedCipherSuite) -> u64 {
+    match scs.bulk {
+        BulkAlgorithm::AES_128_GCM | BulkAlgorithm::AES_256_GCM => AES_GCM_RECORD_LIMIT,
+        BulkAlgorithm::CHACHA20_POLY1305 => u64::MAX,
+    }
+}
+
 pub fn new_tls13_read(scs: &'static SupportedCipherSuite,
                       secret: &[u8]) -> Box<MessageDecrypter> {
     let hash = scs.get_hash();
@@ -117,7 +156,8 @@ pub fn new_tls13_read(scs: &'static SupportedCipherSuite,
     let iv = derive_traffic_iv(hash, secret, scs.fixed_iv_len);
     let aead_alg = scs.get_aead_alg();
 
-    Box::new(TLS13MessageDecrypter::new(aead_alg, &key, &iv))
+    Box::new(TLS13MessageDecrypter::new(aead_alg, &key, &iv,
+                                        tls13_confidentiality_limit(scs)))
 }
 
 pub fn new_tls13_write(scs: &'static SupportedCipherSuite,
@@ -127,20 +167,21 @@ pub fn new_tls13_write(scs: &'static SupportedCipherSuite,
     let iv = derive_traffic_iv(hash, secret, scs.fixed_iv_len);
     let aead_alg = scs.get_aead_alg();
 
-    Box::new(TLS13MessageEncrypter::new(aead_alg, &key, &iv))
+    Box::new(TLS13MessageEncrypter::new(aead_alg, &key, &iv,
+                                        tls13_confidentiality_limit(scs)))
 }
 
 /// A `MessageEncrypter` for AES-GCM AEAD ciphersuites. TLS 1.2 only.
 pub struct GCMMessageEncrypter {
-    alg: &'static ring::aead::Algorithm,
-    enc_key: ring::aead::SealingKey,
+    alg: &'static Aead,
+    enc_key: Box<AeadKey>,
     enc_salt: [u8; 4],
     nonce_offset: [u8; 8],
 }
 
 /// A `MessageDecrypter` for AES-GCM AEAD ciphersuites.  TLS1.2 only.
 pub struct GCMMessageDecrypter {
-    dec_key: ring::aead::OpeningKey,
+    dec_key: Box<AeadKey>,
     dec_salt: [u8; 4],
 }
 
@@ -164,19 +205,17 @@ impl MessageDecrypter for GCMMessageDecrypter {
         let mut aad = [0u8; TLS12_AAD_SIZE];
         make_tls12_aad(seq, msg.typ, msg.version, buf.len() - GCM_OVERHEAD, &mut aad);
 
-        let plain_len = ring::aead::open_in_place(&self.dec_key,
-                                                  &nonce,
-                                                  &aad,
-                                                  GCM_EXPLICIT_NONCE_LEN,
-                                                  &mut buf)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(&nonce,
+                                                   &aad,
+                                                   &mut buf[GCM_EXPLICIT_NONCE_LEN..])
+            .map_err(|_| TLSError::DecryptError)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(TLSError::PeerSentOversizedRecord);
         }
 
-        buf.truncate(plain_len);
+        buf.truncate(GCM_EXPLICIT_NONCE_LEN + plain_len);
+        buf.drain(..GCM_EXPLICIT_NONCE_LEN);
 
         Ok(Message {
             typ: msg.typ,
@@ -184,6 +223,10 @@ impl MessageDecrypter for GCMMessageDecrypter {
             payload: MessagePayload::new_opaque(buf),
         })
     }
+
+    fn confidentiality_limit(&self) -> u64 {
+        AES_GCM_RECORD_LIMIT
+    }
 }
 
 impl MessageEncrypter for GCMMessageEncrypter {
@@ -212,8 +255,7 @@ impl MessageEncrypter for GCMMessageEncrypter {
         let mut aad = [0u8; TLS12_AAD_SIZE];
         make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len(), &mut aad);
 
-        ring::aead::seal_in_place(&self.enc_key, &nonce, &aad, &mut buf[8..], tag_len)
-            .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
+        self.enc_key.seal_in_place(&nonce, &aad, &mut buf[8..])?;
 
         Ok(Message {
             typ: msg.typ,
@@ -221,17 +263,21 @@ impl MessageEncrypter for GCMMessageEncrypter {
             payload: MessagePayload::new_opaque(buf),
         })
     }
+
+    fn confidentiality_limit(&self) -> u64 {
+        AES_GCM_RECORD_LIMIT
+    }
 }
 
 impl GCMMessageEncrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            enc_key: &[u8],
            enc_iv: &[u8],
            nonce_offset: &[u8])
            -> GCMMessageEncrypter {
         let mut ret = GCMMessageEncrypter {
             alg: alg,
-            enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
+            enc_key: alg.new_key(enc_key),
             enc_salt: [0u8; 4],
             nonce_offset: [0u8; 8],
         };
@@ -246,11 +292,11 @@ impl GCMMessageEncrypter {
 }
 
 impl GCMMessageDecrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            dec_key: &[u8],
            dec_iv: &[u8]) -> GCMMessageDecrypter {
         let mut ret = GCMMessageDecrypter {
-            dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
+            dec_key: alg.new_key(dec_key),
             dec_salt: [0u8; 4],
         };
 
@@ -261,15 +307,17 @@ impl GCMMessageDecrypter {
 }
 
 struct TLS13MessageEncrypter {
-    alg: &'static ring::aead::Algorithm,
-    enc_key: ring::aead::SealingKey,
+    alg: &'static Aead,
+    enc_key: Box<AeadKey>,
     enc_offset: [u8; 12],
+    record_limit: u64,
 }
 
 struct TLS13MessageDecrypter {
-    alg: &'static ring::aead::Algorithm,
-    dec_key: ring::aead::OpeningKey,
+    alg: &'static Aead,
+    dec_key: Box<AeadKey>,
     dec_offset: [u8; 12],
+    record_limit: u64,
 }
 
 fn unpad_tls13(v: &mut Vec<u8>) -> ContentType {
@@ -284,22 +332,21 @@ fn unpad_tls13(v: &mut Vec<u8>) -> ContentType {
     }
 }
 
-impl MessageEncrypter for TLS13MessageEncrypter {
-    fn encrypt(&self, msg: BorrowMessage, seq: u64) -> Result<Message, TLSError> {
+impl TLS13MessageEncrypter {
+    fn seal(&self, msg: BorrowMessage, seq: u64, padding_len: usize) -> Result<Message, TLSError> {
         let mut nonce = [0u8; 12];
         codec::put_u64(seq, &mut nonce[4..]);
         xor(&mut nonce, &self.enc_offset);
 
-        // make output buffer with room for content type and tag
+        // make output buffer with room for content type, padding and tag
         let tag_len = self.alg.tag_len();
-        let total_len = msg.payload.len() + 1 + tag_len;
+        let total_len = msg.payload.len() + 1 + padding_len + tag_len;
         let mut buf = Vec::with_capacity(total_len);
         buf.extend_from_slice(msg.payload);
         msg.typ.encode(&mut buf);
         buf.resize(total_len, 0u8);
 
-        ring::aead::seal_in_place(&self.enc_key, &nonce, &[], &mut buf, tag_len)
-            .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
+        self.enc_key.seal_in_place(&nonce, &[], &mut buf)?;
 
         Ok(Message {
             typ: ContentType::ApplicationData,
@@ -309,6 +356,27 @@ impl MessageEncrypter for TLS13MessageEncrypter {
     }
 }
 
+impl MessageEncrypter for TLS13MessageEncrypter {
+    fn encrypt(&self, msg: BorrowMessage, seq: u64) -> Result<Message, TLSError> {
+        self.seal(msg, seq, 0)
+    }
+
+    fn encrypt_padded(&self,
+                      msg: BorrowMessage,
+                      seq: u64,
+                      padding: &RecordPadding,
+                      max_len: usize) -> Result<Message, TLSError> {
+        let len = msg.payload.len();
+        let padding_len = padding.padding_len(msg.typ, len, max_len);
+        let padding_len = cmp::min(padding_len, max_len.saturating_sub(len));
+        self.seal(msg, seq, padding_len)
+    }
+
+    fn confidentiality_limit(&self) -> u64 {
+        self.record_limit
+    }
+}
+
 impl MessageDecrypter for TLS13MessageDecrypter {
     fn decrypt(&self, mut msg: Message, seq: u64) -> Result<Message, TLSError> {
         let mut nonce = [0u8; 12];
@@ -323,9 +391,8 @@ impl MessageDecrypter for TLS13MessageDecrypter {
             return Err(TLSError::DecryptError);
         }
 
-        let plain_len = ring::aead::open_in_place(&self.dec_key, &nonce, &[], 0, &mut buf)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(&nonce, &[], &mut buf)
+            .map_err(|_| TLSError::DecryptError)?;
 
         buf.truncate(plain_len);
 
@@ -349,16 +416,22 @@ impl MessageDecrypter for TLS13MessageDecrypter {
             payload: MessagePayload::new_opaque(buf),
         })
     }
+
+    fn confidentiality_limit(&self) -> u64 {
+        self.record_limit
+    }
 }
 
 impl TLS13MessageEncrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            enc_key: &[u8],
-           enc_iv: &[u8]) -> TLS13MessageEncrypter {
+           enc_iv: &[u8],
+           record_limit: u64) -> TLS13MessageEncrypter {
         let mut ret = TLS13MessageEncrypter {
             alg: alg,
-            enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
+            enc_key: alg.new_key(enc_key),
             enc_offset: [0u8; 12],
+            record_limit: record_limit,
         };
 
         ret.enc_offset.as_mut().write_all(enc_iv).unwrap();
@@ -367,13 +440,15 @@ impl TLS13MessageEncrypter {
 }
 
 impl TLS13MessageDecrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            dec_key: &[u8],
-           dec_iv: &[u8]) -> TLS13MessageDecrypter {
+           dec_iv: &[u8],
+           record_limit: u64) -> TLS13MessageDecrypter {
         let mut ret = TLS13MessageDecrypter {
             alg: alg,
-            dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
+            dec_key: alg.new_key(dec_key),
             dec_offset: [0u8; 12],
+            record_limit: record_limit,
         };
 
         ret.dec_offset.as_mut().write_all(dec_iv).unwrap();
@@ -385,8 +460,8 @@ impl TLS13MessageDecrypter {
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageEncrypter`.
 pub struct ChaCha20Poly1305MessageEncrypter {
-    alg: &'static ring::aead::Algorithm,
-    enc_key: ring::aead::SealingKey,
+    alg: &'static Aead,
+    enc_key: Box<AeadKey>,
     enc_offset: [u8; 12],
 }
 
@@ -394,17 +469,17 @@ pub struct ChaCha20Poly1305MessageEncrypter {
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageDecrypter`.
 pub struct ChaCha20Poly1305MessageDecrypter {
-    dec_key: ring::aead::OpeningKey,
+    dec_key: Box<AeadKey>,
     dec_offset: [u8; 12],
 }
 
 impl ChaCha20Poly1305MessageEncrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            enc_key: &[u8],
            enc_iv: &[u8]) -> ChaCha20Poly1305MessageEncrypter {
         let mut ret = ChaCha20Poly1305MessageEncrypter {
             alg: alg,
-            enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
+            enc_key: alg.new_key(enc_key),
             enc_offset: [0u8; 12],
         };
 
@@ -414,11 +489,11 @@ impl ChaCha20Poly1305MessageEncrypter {
 }
 
 impl ChaCha20Poly1305MessageDecrypter {
-    fn new(alg: &'static ring::aead::Algorithm,
+    fn new(alg: &'static Aead,
            dec_key: &[u8],
            dec_iv: &[u8]) -> ChaCha20Poly1305MessageDecrypter {
         let mut ret = ChaCha20Poly1305MessageDecrypter {
-            dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
+            dec_key: alg.new_key(dec_key),
             dec_offset: [0u8; 12],
         };
 
@@ -447,9 +522,8 @@ impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
         let mut aad = [0u8; TLS12_AAD_SIZE];
         make_tls12_aad(seq, msg.typ, msg.version, buf.len() - CHACHAPOLY1305_OVERHEAD, &mut aad);
 
-        let plain_len = ring::aead::open_in_place(&self.dec_key, &nonce, &aad, 0, &mut buf)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(&nonce, &aad, &mut buf)
+            .map_err(|_| TLSError::DecryptError)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(TLSError::PeerSentOversizedRecord);
@@ -481,8 +555,7 @@ impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
         buf.extend_from_slice(msg.payload);
         buf.resize(total_len, 0u8);
 
-        ring::aead::seal_in_place(&self.enc_key, &nonce, &aad, &mut buf, tag_len)
-            .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
+        self.enc_key.seal_in_place(&nonce, &aad, &mut buf)?;
 
         Ok(Message {
             typ: msg.typ,
@@ -7,10 +7,12 @@ use msgs::persist;
 use msgs::enums::ExtensionType;
 use msgs::enums::NamedGroup;
 use session::SessionRandoms;
+use crypto::SecureRandom;
+use client::{ExternalPsk, ServerName};
+use ech;
 use hash_hs;
 use sign;
 use suites;
-use webpki;
 
 use std::mem;
 
@@ -55,19 +57,23 @@ pub struct HandshakeDetails {
     pub using_ems: bool,
     pub session_id: SessionID,
     pub sent_tls13_fake_ccs: bool,
-    pub dns_name: webpki::DNSName,
+    pub server_name: ServerName,
+    pub ech_rejected: bool,
+    pub ech_retry_configs: Option<Vec<u8>>,
 }
 
 impl HandshakeDetails {
-    pub fn new(host_name: webpki::DNSName) -> HandshakeDetails {
+    pub fn new(server_name: ServerName, rng: &SecureRandom) -> HandshakeDetails {
         HandshakeDetails {
             transcript: hash_hs::HandshakeHash::new(),
             resuming_session: None,
-            randoms: SessionRandoms::for_client(),
+            randoms: SessionRandoms::for_client(rng),
             using_ems: false,
             session_id: SessionID::empty(),
             sent_tls13_fake_ccs: false,
-            dns_name: host_name,
+            server_name: server_name,
+            ech_rejected: false,
+            ech_retry_configs: None,
         }
     }
 }
@@ -75,6 +81,9 @@ impl HandshakeDetails {
 pub struct ClientHelloDetails {
     pub sent_extensions: Vec<ExtensionType>,
     pub offered_key_shares: Vec<suites::KeyExchange>,
+    pub offered_ticket_psk: bool,
+    pub offered_psks: Vec<ExternalPsk>,
+    pub ech: Option<ech::ClientECHOffer>,
 }
 
 impl ClientHelloDetails {
@@ -82,6 +91,9 @@ impl ClientHelloDetails {
         ClientHelloDetails {
             sent_extensions: Vec::new(),
             offered_key_shares: Vec::new(),
+            offered_ticket_psk: false,
+            offered_psks: Vec::new(),
+            ech: None,
         }
     }
 
@@ -2,6 +2,7 @@ use msgs::enums::SignatureScheme;
 use sign;
 use key;
 use client;
+use webpki;
 
 use std::collections;
 use std::sync::{Arc, Mutex};
@@ -19,21 +20,31 @@ impl client::StoresClientSessions for NoClientSessionStorage {
     }
 }
 
+/// How many TLS1.3 tickets `ClientSessionMemoryCache` keeps for
+/// each server.
+const MAX_TICKETS_PER_SERVER: usize = 8;
+
 /// An implementor of `StoresClientSessions` that stores everything
 /// in memory.  It enforces a limit on the number of entries
 /// to bound memory usage.
+///
+/// TLS1.3 tickets are kept in a queue for each server, holding
+/// up to eight.  Each is handed out once, oldest first.
 pub struct ClientSessionMemoryCache {
     cache: Mutex<collections::HashMap<Vec<u8>, Vec<u8>>>,
+    tickets: Mutex<collections::HashMap<Vec<u8>, collections::VecDeque<Vec<u8>>>>,
     max_entries: usize,
 }
 
 impl ClientSessionMemoryCache {
     /// Make a new ClientSessionMemoryCache.  `size` is the
-    /// maximum number of stored sessions.
+    /// maximum number of stored sessions, and separately the
+    /// maximum number of servers we keep tickets for.
     pub fn new(size: usize) -> Arc<ClientSessionMemoryCache> {
         debug_assert!(size > 0);
         Arc::new(ClientSessionMemoryCache {
             cache: Mutex::new(collections::HashMap::new()),
+            tickets: Mutex::new(collections::HashMap::new()),
             max_entries: size,
         })
     }
@@ -45,6 +56,14 @@ impl ClientSessionMemoryCache {
             cache.remove(&k);
         }
     }
+
+    fn limit_tickets_size(&self) {
+        let mut tickets = self.tickets.lock().unwrap();
+        while tickets.len() > self.max_entries {
+            let k = tickets.keys().next().unwrap().clone();
+            tickets.remove(&k);
+        }
+    }
 }
 
 impl client::StoresClientSessions for ClientSessionMemoryCache {
@@ -61,6 +80,73 @@ impl client::StoresClientSessions for ClientSessionMemoryCache {
             .unwrap()
             .get(key).cloned()
     }
+
+    fn put_ticket(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        {
+            let mut tickets = self.tickets.lock().unwrap();
+            let queue = tickets.entry(key)
+                .or_insert_with(collections::VecDeque::new);
+            queue.push_back(value);
+            while queue.len() > MAX_TICKETS_PER_SERVER {
+                queue.pop_front();
+            }
+        }
+        self.limit_tickets_size();
+        true
+    }
+
+    fn take_ticket(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut tickets = self.tickets.lock().unwrap();
+        let (value, now_empty) = match tickets.get_mut(key) {
+            Some(queue) => (queue.pop_front(), queue.is_empty()),
+            None => return None,
+        };
+
+        if now_empty {
+            tickets.remove(key);
+        }
+        value
+    }
+}
+
+/// An implementor of `StoresClientPsks` which has no keys.
+pub struct NoClientPsks {}
+
+impl client::StoresClientPsks for NoClientPsks {
+    fn get(&self, _dns_name: webpki::DNSNameRef) -> Vec<client::ExternalPsk> {
+        Vec::new()
+    }
+}
+
+/// An implementor of `StoresClientPsks` that holds a fixed
+/// set of keys for each server name.
+pub struct ClientPskStore {
+    by_name: collections::HashMap<String, Vec<client::ExternalPsk>>,
+}
+
+impl ClientPskStore {
+    /// Make a new, empty `ClientPskStore`.
+    pub fn new() -> ClientPskStore {
+        ClientPskStore { by_name: collections::HashMap::new() }
+    }
+
+    /// Add `psk` to those offered to the server named `dns_name`,
+    /// after any already added.
+    pub fn add(&mut self, dns_name: webpki::DNSNameRef, psk: client::ExternalPsk) {
+        let name: &str = dns_name.into();
+        self.by_name.entry(name.to_string())
+            .or_insert_with(Vec::new)
+            .push(psk);
+    }
+}
+
+impl client::StoresClientPsks for ClientPskStore {
+    fn get(&self, dns_name: webpki::DNSNameRef) -> Vec<client::ExternalPsk> {
+        let name: &str = dns_name.into();
+        self.by_name.get(name)
+            .cloned()
+            .unwrap_or_else(Vec::new)
+    }
 }
 
 pub struct FailResolveClientCert {}
@@ -81,11 +167,10 @@ impl client::ResolvesClientCert for FailResolveClientCert {
 pub struct AlwaysResolvesClientCert(sign::CertifiedKey);
 
 impl AlwaysResolvesClientCert {
-    pub fn new_rsa(chain: Vec<key::Certificate>,
-                   priv_key: &key::PrivateKey) -> AlwaysResolvesClientCert {
-        let key = sign::RSASigningKey::new(priv_key).expect("Invalid RSA private key");
-        let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
-        AlwaysResolvesClientCert(sign::CertifiedKey::new(chain, key))
+    pub fn new(chain: Vec<key::Certificate>,
+               priv_key: &key::PrivateKey) -> AlwaysResolvesClientCert {
+        let key = sign::any_supported_type(priv_key).expect("Invalid private key");
+        AlwaysResolvesClientCert(sign::CertifiedKey::new(chain, Arc::new(key)))
     }
 }
 
@@ -106,6 +191,7 @@ impl client::ResolvesClientCert for AlwaysResolvesClientCert {
 mod test {
     use super::*;
     use StoresClientSessions;
+    use StoresClientPsks;
 
     #[test]
     fn test_noclientsessionstorage_drops_put() {
@@ -144,6 +230,69 @@ mod test {
         assert_eq!(c.get(&[0x01]), Some(vec![0x04]));
     }
 
+    #[test]
+    fn test_noclientsessionstorage_drops_tickets() {
+        let c = NoClientSessionStorage {};
+        assert_eq!(c.put_ticket(vec![0x01], vec![0x02]), false);
+        assert_eq!(c.take_ticket(&[0x01]), None);
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_takes_tickets_once_in_order() {
+        let c = ClientSessionMemoryCache::new(4);
+        assert_eq!(c.put_ticket(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put_ticket(vec![0x01], vec![0x03]), true);
+        assert_eq!(c.put_ticket(vec![0x04], vec![0x05]), true);
+        assert_eq!(c.get(&[0x01]), None);
+
+        assert_eq!(c.take_ticket(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.take_ticket(&[0x01]), Some(vec![0x03]));
+        assert_eq!(c.take_ticket(&[0x01]), None);
+        assert_eq!(c.take_ticket(&[0x04]), Some(vec![0x05]));
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_bounds_tickets_per_server() {
+        let c = ClientSessionMemoryCache::new(4);
+        for i in 0..(MAX_TICKETS_PER_SERVER + 2) {
+            assert_eq!(c.put_ticket(vec![0x01], vec![i as u8]), true);
+        }
+
+        for i in 2..(MAX_TICKETS_PER_SERVER + 2) {
+            assert_eq!(c.take_ticket(&[0x01]), Some(vec![i as u8]));
+        }
+        assert_eq!(c.take_ticket(&[0x01]), None);
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_bounds_ticket_servers() {
+        let c = ClientSessionMemoryCache::new(2);
+        for i in 0..4u8 {
+            c.put_ticket(vec![i], vec![i]);
+        }
+
+        let count = (0..4u8)
+            .filter(|i| c.take_ticket(&[*i]).is_some())
+            .count();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_clientpskstore_finds_by_name() {
+        let mut c = ClientPskStore::new();
+        let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+        let other = webpki::DNSNameRef::try_from_ascii_str("example.org").unwrap();
+        c.add(name, client::ExternalPsk::new(b"one", b"secret1"));
+        c.add(name, client::ExternalPsk::new(b"two", b"secret2"));
+
+        let psks = c.get(name);
+        assert_eq!(psks.len(), 2);
+        assert_eq!(psks[0].identity, b"one".to_vec());
+        assert_eq!(psks[1].secret, b"secret2".to_vec());
+        assert!(c.get(other).is_empty());
+        assert!(NoClientPsks {}.get(name).is_empty());
+    }
+
     #[test]
     fn test_clientsessionmemorycache_drops_to_maintain_size_invariant() {
         let c = ClientSessionMemoryCache::new(4);
@@ -1,32 +1,42 @@
 use msgs::enums::{ContentType, HandshakeType, ExtensionType, SignatureScheme};
 use msgs::enums::{Compression, ProtocolVersion, AlertDescription, NamedGroup};
 use msgs::message::{Message, MessagePayload};
-use msgs::base::{Payload, PayloadU8};
+use msgs::base::{Payload, PayloadU8, PayloadU16};
 use msgs::handshake::{HandshakePayload, HandshakeMessagePayload, ClientHelloPayload};
 use msgs::handshake::{SessionID, Random, ServerHelloPayload};
 use msgs::handshake::{ClientExtension, HasServerExtensions};
 use msgs::handshake::{SupportedSignatureSchemes, SupportedMandatedSignatureSchemes};
 use msgs::handshake::DecomposedSignatureScheme;
-use msgs::handshake::{NamedGroups, SupportedGroups, KeyShareEntry, EncryptedExtensions};
+use msgs::handshake::{KeyShareEntry, EncryptedExtensions};
 use msgs::handshake::{ECPointFormatList, SupportedPointFormats};
 use msgs::handshake::{ProtocolNameList, ConvertProtocolNameList};
 use msgs::handshake::{CertificatePayloadTLS13, CertificateEntry};
-use msgs::handshake::ServerKeyExchangePayload;
+use msgs::handshake::CertificateRequestPayloadTLS13;
+use msgs::handshake::{ServerKeyExchangePayload, KeyExchangeAlgorithm};
 use msgs::handshake::DigitallySignedStruct;
-use msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer, HelloRetryRequest};
+use msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer, PresharedKeyBinder};
+use msgs::handshake::HelloRetryRequest;
 use msgs::handshake::{CertificateStatusRequest, SCTList};
 use msgs::enums::{ClientCertificateType, PSKKeyExchangeMode, ECPointFormat};
+use msgs::enums::MaxFragmentLength;
 use msgs::codec::Codec;
 use msgs::persist;
+use msgs::fragmenter;
+use compress;
+use ech;
 use msgs::ccs::ChangeCipherSpecPayload;
-use client::ClientSessionImpl;
-use session::SessionSecrets;
+use client::{ClientSessionImpl, ServerName};
+use session::{SessionSecrets, Protocol};
 use key_schedule::{KeySchedule, SecretKind};
+use hash_hs::HandshakeHash;
 use cipher;
 use suites;
-use hash_hs;
+use ffdhe::FfdheGroup;
 use verify;
+use x509;
+use untrusted;
 use rand;
+use crypto::{Hash, SecureRandom};
 use ticketer;
 use error::TLSError;
 use handshake::{check_message, check_handshake_message};
@@ -87,82 +97,126 @@ fn check_aligned_handshake(sess: &mut ClientSessionImpl) -> Result<(), TLSError>
     }
 }
 
-fn find_session(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef)
+fn read_unexpired_session(value: &[u8]) -> Option<persist::ClientSessionValue> {
+    persist::ClientSessionValue::read_bytes(value)
+        .and_then(|result| if result.has_expired(ticketer::timebase()) {
+            None
+        } else {
+            Some(result)
+        })
+}
+
+fn find_session(sess: &mut ClientSessionImpl, server_name: &ServerName)
                 -> Option<persist::ClientSessionValue> {
-    let key = persist::ClientSessionKey::session_for_dns_name(dns_name);
+    // TLS1.3 tickets are single-use: take one, discarding any which
+    // have expired.
+    if sess.config.versions.contains(&ProtocolVersion::TLSv1_3) {
+        let key = persist::ClientSessionKey::tls13_tickets_for_server_name(server_name);
+        let key_buf = key.get_encoding();
+        let mut previous = None;
+
+        while let Some(value) = sess.config.session_persistence.take_ticket(&key_buf) {
+            if let Some(result) = read_unexpired_session(&value) {
+                return Some(result);
+            }
+
+            // A store which doesn't remove tickets gives us the same one
+            // again.
+            if previous.as_ref() == Some(&value) {
+                break;
+            }
+            previous = Some(value);
+        }
+    }
+
+    let key = persist::ClientSessionKey::session_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
     let maybe_value = sess.config.session_persistence.get(&key_buf);
 
     if maybe_value.is_none() {
-        debug!("No cached session for {:?}", dns_name);
+        debug!("No cached session for {:?}", server_name);
         return None;
     }
 
-    let value = maybe_value.unwrap();
-    if let Some(result) = persist::ClientSessionValue::read_bytes(&value) {
-        if result.has_expired(ticketer::timebase()) {
-            None
-        } else {
-            Some(result)
-        }
-    } else {
-        None
-    }
+    read_unexpired_session(&maybe_value.unwrap())
 }
 
-fn find_kx_hint(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef) -> Option<NamedGroup> {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+fn find_kx_hint(sess: &mut ClientSessionImpl, server_name: &ServerName) -> Option<NamedGroup> {
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
     let maybe_value = sess.config.session_persistence.get(&key_buf);
     maybe_value.and_then(|enc| NamedGroup::read_bytes(&enc))
 }
 
-fn save_kx_hint(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef, group: NamedGroup) {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+fn save_kx_hint(sess: &mut ClientSessionImpl, server_name: &ServerName, group: NamedGroup) {
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
 
     sess.config.session_persistence.put(key.get_encoding(), group.get_encoding());
 }
 
 /// If we have a ticket, we use the sessionid as a signal that we're
 /// doing an abbreviated handshake.  See section 3.4 in RFC5077.
-fn randomise_sessionid_for_ticket(csv: &mut persist::ClientSessionValue) {
+fn randomise_sessionid_for_ticket(rng: &SecureRandom, csv: &mut persist::ClientSessionValue) {
     if csv.ticket.len() > 0 {
         let mut random_id = [0u8; 32];
-        rand::fill_random(&mut random_id);
+        rand::fill_random(rng, &mut random_id);
         csv.session_id = SessionID::new(&random_id);
     }
 }
 
+/// Compute the binder for `psk`, given the hash of the partial
+/// clienthello.  `kind` says whether it's a resumption or external PSK.
+fn compute_psk_binder(hash: &'static Hash,
+                      psk: &[u8],
+                      kind: SecretKind,
+                      handshake_hash: &[u8]) -> Vec<u8> {
+    // Run a fake key_schedule to simulate what the server will do if it choses
+    // to use this PSK.
+    let mut key_schedule = KeySchedule::new(hash);
+    key_schedule.input_secret(psk);
+    let base_key = key_schedule.derive(kind, key_schedule.get_hash_of_empty_message());
+    key_schedule.sign_verify_data(&base_key, handshake_hash)
+}
+
 /// This implements the horrifying TLS1.3 hack where PSK binders have a
 /// data dependency on the message they are contained within.
-pub fn fill_in_psk_binder(sess: &mut ClientSessionImpl,
-                          handshake: &mut HandshakeDetails,
-                          hmp: &mut HandshakeMessagePayload) {
-    // We need to know the hash function of the suite we're trying to resume into.
-    let resuming = handshake.resuming_session.as_ref().unwrap();
-    let suite_hash = sess.find_cipher_suite(resuming.cipher_suite).unwrap().get_hash();
-
-    // The binder is calculated over the clienthello, but doesn't include itself or its
-    // length, or the length of its container.
+pub fn fill_in_psk_binders(sess: &ClientSessionImpl,
+                           handshake: &HandshakeDetails,
+                           hello: &ClientHelloDetails,
+                           hmp: &mut HandshakeMessagePayload) {
+    // The binders are calculated over the clienthello, but don't include themselves or
+    // their length, or the length of their container.
     let binder_plaintext = hmp.get_encoding_for_binder_signing();
-    let handshake_hash =
-        handshake.transcript.get_hash_given(suite_hash, &binder_plaintext);
+    let mut binders = Vec::new();
 
-    let mut empty_hash_ctx = hash_hs::HandshakeHash::new();
-    empty_hash_ctx.start_hash(suite_hash);
-    let empty_hash = empty_hash_ctx.get_current_hash();
+    if hello.offered_ticket_psk {
+        // We need to know the hash function of the suite we're trying to resume into.
+        let resuming = handshake.resuming_session.as_ref().unwrap();
+        let suite_hash = sess.find_cipher_suite(resuming.cipher_suite).unwrap().get_hash();
+        let handshake_hash = handshake.transcript.get_hash_given(suite_hash, &binder_plaintext);
+        binders.push(compute_psk_binder(suite_hash,
+                                        &resuming.master_secret.0,
+                                        SecretKind::ResumptionPSKBinderKey,
+                                        &handshake_hash));
+    }
 
-    // Run a fake key_schedule to simulate what the server will do if it choses
-    // to resume.
-    let mut key_schedule = KeySchedule::new(suite_hash);
-    key_schedule.input_secret(&resuming.master_secret.0);
-    let base_key = key_schedule.derive(SecretKind::ResumptionPSKBinderKey, &empty_hash);
-    let real_binder = key_schedule.sign_verify_data(&base_key, &handshake_hash);
+    if !hello.offered_psks.is_empty() {
+        let hash = sess.external_psk_hash().unwrap();
+        let handshake_hash = handshake.transcript.get_hash_given(hash, &binder_plaintext);
+        for psk in &hello.offered_psks {
+            binders.push(compute_psk_binder(hash,
+                                            &psk.secret,
+                                            SecretKind::ExternalPSKBinderKey,
+                                            &handshake_hash));
+        }
+    }
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
-        ch.set_psk_binder(real_binder);
+        for (i, binder) in binders.into_iter().enumerate() {
+            ch.set_psk_binder(i, binder);
+        }
     };
 }
 
@@ -171,9 +225,9 @@ struct InitialState {
 }
 
 impl InitialState {
-    fn new(host_name: webpki::DNSName) -> InitialState {
+    fn new(server_name: ServerName, rng: &SecureRandom) -> InitialState {
         InitialState {
-            handshake: HandshakeDetails::new(host_name),
+            handshake: HandshakeDetails::new(server_name, rng),
         }
     }
 
@@ -187,8 +241,8 @@ impl InitialState {
 }
 
 
-pub fn start_handshake(sess: &mut ClientSessionImpl, host_name: webpki::DNSName) -> NextState {
-    InitialState::new(host_name)
+pub fn start_handshake(sess: &mut ClientSessionImpl, server_name: ServerName) -> NextState {
+    InitialState::new(server_name, sess.config.provider.secure_random())
         .emit_initial_client_hello(sess)
 }
 
@@ -203,7 +257,8 @@ struct ExpectServerHello {
 struct ExpectServerHelloOrHelloRetryRequest(ExpectServerHello);
 
 fn emit_fake_ccs(hs: &mut HandshakeDetails, sess: &mut ClientSessionImpl) {
-    if hs.sent_tls13_fake_ccs {
+    // There are no middleboxes to fool in QUIC.
+    if hs.sent_tls13_fake_ccs || sess.common.protocol == Protocol::Quic {
         return;
     }
 
@@ -230,16 +285,40 @@ fn compatible_suite(sess: &ClientSessionImpl,
     }
 }
 
+fn start_early_traffic(sess: &mut ClientSessionImpl, handshake: &HandshakeDetails) {
+    let resuming = handshake.resuming_session.as_ref().unwrap();
+    let resuming_suite = sess.find_cipher_suite(resuming.cipher_suite).unwrap();
+    let hash = resuming_suite.get_hash();
+    let client_hello_hash = handshake.transcript.get_hash_given(hash, &[]);
+
+    let mut key_schedule = KeySchedule::new(hash);
+    key_schedule.input_secret(&resuming.master_secret.0);
+    let client_early_traffic_secret = key_schedule
+        .derive_logged_secret(SecretKind::ClientEarlyTrafficSecret,
+                              &client_hello_hash,
+                              &*sess.config.key_log,
+                              &handshake.randoms.client);
+    sess.common.set_message_encrypter(cipher::new_tls13_write(resuming_suite,
+                                                              &client_early_traffic_secret));
+    debug!("Starting early data traffic");
+}
+
 fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                                mut handshake: HandshakeDetails,
                                mut hello: ClientHelloDetails,
                                retryreq: Option<&HelloRetryRequest>) -> NextState {
-    // Do we have a SessionID or ticket cached for this host?
-    handshake.resuming_session = find_session(sess, handshake.dns_name.as_ref());
+    // Do we have a SessionID or ticket cached for this host?  Not if
+    // the server rejected ECH in its HelloRetryRequest: then we're
+    // talking to the public name.
+    handshake.resuming_session = if handshake.ech_rejected {
+        None
+    } else {
+        find_session(sess, &handshake.server_name)
+    };
     let (session_id, ticket, resume_version) = if handshake.resuming_session.is_some() {
         let resuming = handshake.resuming_session.as_mut().unwrap();
         if resuming.version == ProtocolVersion::TLSv1_2 {
-            randomise_sessionid_for_ticket(resuming);
+            randomise_sessionid_for_ticket(sess.config.provider.secure_random(), resuming);
         }
         debug!("Resuming session");
         (resuming.session_id, resuming.ticket.0.clone(), resuming.version)
@@ -251,6 +330,19 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
     let support_tls12 = sess.config.versions.contains(&ProtocolVersion::TLSv1_2);
     let support_tls13 = sess.config.versions.contains(&ProtocolVersion::TLSv1_3);
 
+    // Encrypt this ClientHello with ECH?  The first one if we've
+    // been given a configuration, and the second one if the server
+    // accepted the first.
+    let mut ech_offer = if !support_tls13 {
+        None
+    } else if retryreq.is_none() {
+        let rng = sess.config.provider.secure_random();
+        sess.config.ech_config.as_ref()
+            .and_then(|config| ech::ClientECHOffer::new(config, rng))
+    } else {
+        hello.ech.take()
+    };
+
     let mut supported_versions = Vec::new();
     if support_tls13 {
         supported_versions.push(ProtocolVersion::Unknown(TLS13_DRAFT));
@@ -266,14 +358,19 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         // Choose our groups:
         // - if we've been asked via HelloRetryRequest for a specific
         //   one, do that.
-        // - if not, we might have a hint of what the server supports
-        // - if not, send just X25519.
+        // - if not, we might have a hint of what the server supports,
+        //   if we still support it.
+        // - if not, send just our most preferred group.
         //
+        let kx_groups = sess.get_kx_groups();
         let groups = retryreq.and_then(|req| req.get_requested_key_share_group())
-            .or_else(|| find_kx_hint(sess, handshake.dns_name.as_ref()))
-            .or_else(|| Some(NamedGroup::X25519))
+            .or_else(|| {
+                find_kx_hint(sess, &handshake.server_name)
+                    .filter(|group| kx_groups.contains(group))
+            })
+            .or_else(|| kx_groups.first().cloned())
             .map(|grp| vec![ grp ])
-            .unwrap();
+            .unwrap_or_else(Vec::new);
 
         for group in groups {
             // in reply to HelloRetryRequest, we must not alter any existing key
@@ -284,7 +381,7 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                 continue;
             }
 
-            if let Some(key_share) = suites::KeyExchange::start_ecdhe(group) {
+            if let Some(key_share) = suites::KeyExchange::start(sess.config.provider.as_ref(), group) {
                 key_shares.push(KeyShareEntry::new(group, &key_share.pubkey));
                 hello.offered_key_shares.push(key_share);
             }
@@ -295,12 +392,15 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
     if !supported_versions.is_empty() {
         exts.push(ClientExtension::SupportedVersions(supported_versions));
     }
-    if sess.config.enable_sni {
-        exts.push(ClientExtension::make_sni(handshake.dns_name.as_ref()));
+    match handshake.server_name.as_dns_name() {
+        Some(dns_name) if sess.config.enable_sni => {
+            exts.push(ClientExtension::make_sni(dns_name));
+        }
+        _ => {}
     }
     exts.push(ClientExtension::ECPointFormats(ECPointFormatList::supported()));
-    exts.push(ClientExtension::NamedGroups(NamedGroups::supported()));
-    exts.push(ClientExtension::SignatureAlgorithms(SupportedSignatureSchemes::supported_verify()));
+    exts.push(ClientExtension::NamedGroups(sess.get_kx_groups()));
+    exts.push(ClientExtension::SignatureAlgorithms(sess.config.provider.signature_verifier().supported_schemes()));
     exts.push(ClientExtension::ExtendedMasterSecretRequest);
     exts.push(ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()));
 
@@ -312,15 +412,54 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         exts.push(ClientExtension::KeyShare(key_shares));
     }
 
+    // QUIC has no use for post-handshake authentication.
+    let quic = sess.common.protocol == Protocol::Quic;
+    if support_tls13 && sess.config.enable_post_handshake_auth && !quic {
+        exts.push(ClientExtension::PostHandshakeAuth);
+    }
+
+    if quic {
+        let params = Payload::new(sess.common.quic.params.clone());
+        exts.push(ClientExtension::TransportParameters(params));
+    }
+
+    if support_tls13 && !sess.config.certificate_decompressors.is_empty() {
+        let algs = sess.config.certificate_decompressors
+            .iter()
+            .map(|d| d.algorithm())
+            .collect();
+        exts.push(ClientExtension::CertificateCompressionAlgorithms(algs));
+    }
+
+    if let Some(limit) = sess.config.record_size_limit {
+        let limit = fragmenter::clamp_record_size_limit(limit);
+        exts.push(ClientExtension::RecordSizeLimit(limit));
+    }
+
+    if let Some(mfl) = sess.config.max_fragment_length {
+        exts.push(ClientExtension::MaxFragmentLength(mfl));
+    }
+
     if let Some(cookie) = retryreq.and_then(|req| req.get_cookie()) {
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
-    if support_tls13 && sess.config.enable_tickets {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![ PSKKeyExchangeMode::PSK_DHE_KE ];
-        exts.push(ClientExtension::PresharedKeyModes(psk_modes));
+    // Find any external PSKs we share with this server.  These use
+    // a fixed hash, so can't be offered after a retry into a suite
+    // with a different one, or if we have no suite using it.
+    hello.offered_psks = if support_tls13 && !handshake.ech_rejected &&
+                            sess.external_psk_hash().is_some() &&
+                            sess.common.get_suite().map_or(true, |cs| cs.usable_for_external_psk()) {
+        handshake.server_name
+            .as_dns_name()
+            .map(|dns_name| sess.config.psk_store.get(dns_name))
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    if support_tls13 && (sess.config.enable_tickets || !hello.offered_psks.is_empty()) {
+        exts.push(ClientExtension::PresharedKeyModes(sess.config.psk_modes.clone()));
     }
 
     if !sess.config.alpn_protocols.is_empty() {
@@ -329,7 +468,10 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
     }
 
 
-    let fill_in_binder = if support_tls13 && sess.config.enable_tickets &&
+    let mut psk_identities = Vec::new();
+    let mut psk_binders = Vec::new();
+
+    hello.offered_ticket_psk = if support_tls13 && sess.config.enable_tickets &&
                             resume_version == ProtocolVersion::TLSv1_3 &&
                             !ticket.is_empty() {
         let resuming_suite = handshake.resuming_session
@@ -337,8 +479,8 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
             .and_then(|resume| sess.find_cipher_suite(resume.cipher_suite));
 
         if compatible_suite(sess, resuming_suite) {
-            // Finally, and only for TLS1.3 with a ticket resumption, include a binder
-            // for our ticket.  This must go last.
+            // For TLS1.3 with a ticket resumption, offer our ticket as
+            // the first PSK.
             //
             // Include an empty binder. It gets filled in below because it depends on
             // the message it's contained in (!!!).
@@ -349,12 +491,23 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                 (resuming.get_obfuscated_ticket_age(ticketer::timebase()), resuming.cipher_suite)
             };
 
-            let binder_len = sess.find_cipher_suite(suite).unwrap().get_hash().output_len;
+            let binder_len = sess.find_cipher_suite(suite).unwrap().get_hash().output_len();
             let binder = vec![0u8; binder_len];
 
-            let psk_identity = PresharedKeyIdentity::new(ticket, obfuscated_ticket_age);
-            let psk_ext = PresharedKeyOffer::new(psk_identity, binder);
-            exts.push(ClientExtension::PresharedKey(psk_ext));
+            // We can send early data if the ticket allows it, unless
+            // this is a retry.  We don't do it in QUIC.
+            let max_early_data_size = handshake.resuming_session
+                .as_ref()
+                .unwrap()
+                .max_early_data_size;
+            if sess.config.enable_early_data && max_early_data_size > 0 && retryreq.is_none() &&
+               !quic {
+                sess.early_data.enable(max_early_data_size as usize);
+                exts.push(ClientExtension::EarlyData);
+            }
+
+            psk_identities.push(PresharedKeyIdentity::new(ticket, obfuscated_ticket_age));
+            psk_binders.push(PresharedKeyBinder::new(binder));
             true
         } else {
             false
@@ -371,6 +524,28 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         false
     };
 
+    // Then any external PSKs, again with empty binders for now.
+    for psk in &hello.offered_psks {
+        let binder_len = sess.external_psk_hash().unwrap().output_len();
+        psk_identities.push(PresharedKeyIdentity::new(psk.identity.clone(), 0));
+        psk_binders.push(PresharedKeyBinder::new(vec![0u8; binder_len]));
+    }
+
+    // The PSK extension must go last.
+    if !psk_identities.is_empty() {
+        exts.push(ClientExtension::PresharedKey(PresharedKeyOffer {
+            identities: psk_identities,
+            binders: psk_binders,
+        }));
+    }
+
+    // With ECH, these extensions are for the inner ClientHello.  The
+    // outer one gets a sanitised copy.
+    let outer_exts = ech_offer.as_ref().map(|ech| ech.outer_extensions(&exts));
+    if ech_offer.is_some() {
+        ech::ClientECHOffer::make_inner_extensions(&mut exts);
+    }
+
     // Note what extensions we sent.
     hello.sent_extensions = exts.iter()
         .map(|ext| ext.get_type())
@@ -388,17 +563,43 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         }),
     };
 
-    if fill_in_binder {
-        fill_in_psk_binder(sess, &mut handshake, &mut chp);
+    if hello.offered_ticket_psk || !hello.offered_psks.is_empty() {
+        fill_in_psk_binders(sess, &handshake, &hello, &mut chp);
     }
 
+    let version = if retryreq.is_some() {
+        ProtocolVersion::TLSv1_2
+    } else {
+        ProtocolVersion::TLSv1_0
+    };
+
+    // The outer ClientHello carries the inner one, encrypted.
+    let outer_chp = match ech_offer {
+        Some(ref mut ech) => {
+            let mut outer = ClientHelloPayload {
+                client_version: ProtocolVersion::TLSv1_2,
+                random: Random::from_slice(&ech.outer_random),
+                session_id: session_id,
+                cipher_suites: sess.get_cipher_suites(),
+                compression_methods: vec![Compression::Null],
+                extensions: outer_exts.unwrap(),
+            };
+
+            if let HandshakePayload::ClientHello(ref inner) = chp.payload {
+                ech.seal(inner, &mut outer);
+            }
+
+            Some(HandshakeMessagePayload {
+                typ: HandshakeType::ClientHello,
+                payload: HandshakePayload::ClientHello(outer),
+            })
+        }
+        None => None,
+    };
+
     let ch = Message {
         typ: ContentType::Handshake,
-        version: if retryreq.is_some() {
-            ProtocolVersion::TLSv1_2
-        } else {
-            ProtocolVersion::TLSv1_0
-        },
+        version: version,
         payload: MessagePayload::Handshake(chp),
     };
 
@@ -410,8 +611,34 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
 
     trace!("Sending ClientHello {:#?}", ch);
 
-    handshake.transcript.add_message(&ch);
-    sess.common.send_msg(ch, false);
+    // With ECH, our transcript is of the inner ClientHello, which we
+    // never send.  We keep one of the outer ClientHello too, in case
+    // the server can't decrypt the inner one.
+    if let (Some(mut ech), Some(outer_chp)) = (ech_offer, outer_chp) {
+        let outer = Message {
+            typ: ContentType::Handshake,
+            version: version,
+            payload: MessagePayload::Handshake(outer_chp),
+        };
+
+        trace!("Encrypted it into {:#?}", outer);
+        ech.outer_transcript = handshake.transcript.clone();
+        ech.outer_transcript.add_message(&outer);
+        handshake.transcript.add_message(&ch);
+        sess.common.send_msg(outer, false);
+        hello.ech = Some(ech);
+    } else {
+        handshake.transcript.add_message(&ch);
+        sess.common.send_msg(ch, false);
+    }
+
+    // Calculate the early traffic key, so the caller can now write early data.
+    if sess.early_data.is_enabled() {
+        // In compatibility mode the fake CCS goes straight after the
+        // first ClientHello if we're sending early data.
+        emit_fake_ccs(&mut handshake, sess);
+        start_early_traffic(sess, &handshake);
+    }
 
     let next = ExpectServerHello {
         handshake, hello,
@@ -469,6 +696,64 @@ fn process_alpn_protocol(sess: &mut ClientSessionImpl,
     Ok(())
 }
 
+fn process_fragment_limits(sess: &mut ClientSessionImpl,
+                           record_size_limit: Option<u16>,
+                           max_fragment_length: Option<MaxFragmentLength>)
+                           -> Result<(), TLSError> {
+    let tls13 = sess.common.is_tls13();
+
+    match (record_size_limit, max_fragment_length) {
+        (Some(_), Some(_)) => {
+            Err(illegal_param(sess, "server sent both record_size_limit and max_fragment_length"))
+        }
+        (Some(limit), None) => {
+            if limit < fragmenter::MIN_RECORD_SIZE_LIMIT {
+                return Err(illegal_param(sess, "server sent record_size_limit below minimum"));
+            }
+
+            sess.common.set_peer_fragment_limit(
+                fragmenter::record_size_limit_to_fragment_len(limit, tls13));
+
+            if let Some(ours) = sess.config.record_size_limit {
+                let ours = fragmenter::clamp_record_size_limit(ours);
+                sess.common.set_our_fragment_limit(
+                    fragmenter::record_size_limit_to_fragment_len(ours, tls13));
+            }
+
+            debug!("Server record_size_limit is {}", limit);
+            Ok(())
+        }
+        (None, Some(mfl)) => {
+            let len = match fragmenter::max_fragment_length_to_len(mfl) {
+                Some(len) if Some(mfl) == sess.config.max_fragment_length => len,
+                _ => return Err(illegal_param(sess, "server sent non-offered max_fragment_length")),
+            };
+
+            sess.common.set_peer_fragment_limit(len);
+            sess.common.set_our_fragment_limit(len);
+            debug!("Negotiated max_fragment_length of {}", len);
+            Ok(())
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+/// The server couldn't decrypt our ECH offer, so is continuing the
+/// handshake with the outer ClientHello.  Follow suit, so we can
+/// authenticate it as the public name.
+fn use_outer_client_hello(handshake: &mut HandshakeDetails,
+                          hello: &mut ClientHelloDetails,
+                          ech: ech::ClientECHOffer) {
+    handshake.server_name = ech.get_public_name().into();
+    handshake.transcript = ech.outer_transcript;
+    handshake.randoms.client = ech.outer_random;
+    handshake.resuming_session = None;
+    handshake.ech_rejected = true;
+    hello.sent_extensions = ech.outer_extensions;
+    hello.offered_ticket_psk = false;
+    hello.offered_psks.clear();
+}
+
 impl ExpectServerHello {
     fn start_handshake_traffic(&mut self,
                                sess: &mut ClientSessionImpl,
@@ -478,21 +763,36 @@ impl ExpectServerHello {
         let hash = suite.get_hash();
         let mut key_schedule = KeySchedule::new(hash);
 
-        if let Some(selected_psk) = server_hello.get_psk_index() {
-            if let Some(ref resuming) = self.handshake.resuming_session {
+        let selected_psk = server_hello.get_psk_index().map(|idx| idx as usize);
+        if let Some(selected_psk) = selected_psk {
+            // Our ticket, if we offered one, is the first PSK.  Any
+            // external PSKs follow it.
+            let ticket_psks = if self.hello.offered_ticket_psk { 1 } else { 0 };
+
+            if selected_psk < ticket_psks {
+                let resuming = self.handshake.resuming_session.as_ref().unwrap();
                 let resume_from_suite = sess.find_cipher_suite(resuming.cipher_suite).unwrap();
                 if !resume_from_suite.can_resume_to(suite) {
                     return Err(TLSError::PeerMisbehavedError("server resuming incompatible suite"
                         .to_string()));
                 }
 
-                if selected_psk != 0 {
-                    return Err(TLSError::PeerMisbehavedError("server selected invalid psk"
-                        .to_string()));
-                }
-
                 debug!("Resuming using PSK");
                 key_schedule.input_secret(&resuming.master_secret.0);
+            } else if let Some(psk) = self.hello.offered_psks.get(selected_psk - ticket_psks) {
+                if !suite.usable_for_external_psk() {
+                    return Err(illegal_param(sess, "server used external psk with wrong suite"));
+                }
+
+                debug!("Authenticating using external PSK");
+                key_schedule.input_secret(&psk.secret);
+                sess.psk_identity = Some(psk.identity.clone());
+                self.handshake.resuming_session.take();
+
+                // We only send early data with a ticket.
+                if sess.early_data.is_enabled() {
+                    sess.early_data.rejected();
+                }
             } else {
                 return Err(TLSError::PeerMisbehavedError("server selected unoffered psk".to_string()));
             }
@@ -500,30 +800,55 @@ impl ExpectServerHello {
             debug!("Not resuming");
             key_schedule.input_empty();
             self.handshake.resuming_session.take();
-        }
 
-        let their_key_share = server_hello.get_key_share()
-            .ok_or_else(|| {
-                sess.common.send_fatal_alert(AlertDescription::MissingExtension);
-                TLSError::PeerMisbehavedError("missing key share".to_string())
-                })?;
+            if sess.early_data.is_enabled() {
+                sess.early_data.rejected();
+            }
+        }
 
-        let our_key_share = self.hello.find_key_share_and_discard_others(their_key_share.group)
-            .ok_or_else(|| illegal_param(sess, "wrong group for key share"))?;
-        let shared = our_key_share.complete(&their_key_share.payload.0)
-            .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed"
-                                                         .to_string()))?;
+        match server_hello.get_key_share() {
+            Some(their_key_share) => {
+                let our_key_share = self.hello.find_key_share_and_discard_others(their_key_share.group)
+                    .ok_or_else(|| illegal_param(sess, "wrong group for key share"))?;
+                let shared = our_key_share.complete(&their_key_share.payload.0)
+                    .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed"
+                                                                 .to_string()))?;
 
-        save_kx_hint(sess, self.handshake.dns_name.as_ref(), their_key_share.group);
-        key_schedule.input_secret(&shared.premaster_secret);
+                save_kx_hint(sess, &self.handshake.server_name, their_key_share.group);
+                key_schedule.input_secret(&shared.premaster_secret);
+            }
+            None if selected_psk.is_some() &&
+                    sess.config.psk_modes.contains(&PSKKeyExchangeMode::PSK_KE) => {
+                debug!("Using PSK without key exchange");
+                key_schedule.input_empty();
+            }
+            None => {
+                sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+                return Err(TLSError::PeerMisbehavedError("missing key share".to_string()));
+            }
+        }
 
         check_aligned_handshake(sess)?;
 
         let handshake_hash = self.handshake.transcript.get_current_hash();
-        let write_key = key_schedule.derive(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
-        let read_key = key_schedule.derive(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
-        sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        let write_key = key_schedule.derive_logged_secret(SecretKind::ClientHandshakeTrafficSecret,
+                                                          &handshake_hash,
+                                                          &*sess.config.key_log,
+                                                          &self.handshake.randoms.client);
+        let read_key = key_schedule.derive_logged_secret(SecretKind::ServerHandshakeTrafficSecret,
+                                                         &handshake_hash,
+                                                         &*sess.config.key_log,
+                                                         &self.handshake.randoms.client);
+
+        // If early data might still be accepted, we keep sending under the
+        // early traffic key until EncryptedExtensions tells us.
+        if !sess.early_data.is_enabled() {
+            sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        }
         sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+        if sess.common.protocol == Protocol::Quic {
+            sess.common.quic.set_secrets(write_key.clone(), read_key.clone());
+        }
         key_schedule.current_client_traffic_secret = write_key;
         key_schedule.current_server_traffic_secret = read_key;
         sess.common.set_key_schedule(key_schedule);
@@ -531,6 +856,41 @@ impl ExpectServerHello {
         Ok(())
     }
 
+    fn check_ech_acceptance(&mut self,
+                            sess: &mut ClientSessionImpl,
+                            m: &Message,
+                            ech: ech::ClientECHOffer) -> Result<(), TLSError> {
+        // TLS1.2 can't tell us about new ECH configurations, so there's
+        // no point continuing.
+        if !sess.common.is_tls13() {
+            sess.common.send_fatal_alert(AlertDescription::ECHRequired);
+            return Err(TLSError::ECHRejected(None));
+        }
+
+        let server_hello = extract_handshake!(m, HandshakePayload::ServerHello).unwrap();
+        let mut server_random = [0u8; 32];
+        server_hello.random.write_slice(&mut server_random);
+
+        let confirmation = ech::compute_confirmation(sess.common.get_suite_assert().get_hash(),
+                                                     &self.handshake.transcript,
+                                                     &self.handshake.randoms.client,
+                                                     ech::ACCEPT_CONFIRMATION_LABEL,
+                                                     m);
+
+        if constant_time::verify_slices_are_equal(&confirmation, &server_random[24..]).is_ok() {
+            debug!("Server accepted ECH");
+            return Ok(());
+        }
+
+        if ech.accepted_at_hrr {
+            return Err(illegal_param(sess, "server rejected ech after accepting it in hrr"));
+        }
+
+        debug!("Server rejected ECH");
+        use_outer_client_hello(&mut self.handshake, &mut self.hello, ech);
+        Ok(())
+    }
+
     fn into_expect_tls13_encrypted_extensions(self) -> NextState {
         Box::new(ExpectTLS13EncryptedExtensions {
             handshake: self.handshake,
@@ -629,6 +989,9 @@ impl State for ExpectServerHello {
         // Extract ALPN protocol
         if !sess.common.is_tls13() {
             process_alpn_protocol(sess, server_hello.get_alpn_protocol())?;
+            process_fragment_limits(sess,
+                                    server_hello.get_record_size_limit(),
+                                    server_hello.get_max_fragment_length())?;
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
@@ -659,6 +1022,11 @@ impl State for ExpectServerHello {
             return Err(illegal_param(sess, "server chose unusable ciphersuite for version"));
         }
 
+        // Did the server accept our Encrypted Client Hello?
+        if let Some(ech) = self.hello.ech.take() {
+            self.check_ech_acceptance(sess, &m, ech)?;
+        }
+
         // Start our handshake hash, and input the server-hello.
         self.handshake.transcript.start_hash(sess.common.get_suite_assert().get_hash());
         self.handshake.transcript.add_message(&m);
@@ -674,6 +1042,11 @@ impl State for ExpectServerHello {
 
         // TLS1.2 only from here-on
 
+        // Early data is a TLS1.3 thing.
+        if sess.early_data.is_enabled() {
+            sess.early_data.rejected();
+        }
+
         // Save ServerRandom and SessionID
         server_hello.random.write_slice(&mut self.handshake.randoms.server);
         self.handshake.session_id = server_hello.session_id;
@@ -732,7 +1105,8 @@ impl State for ExpectServerHello {
                 sess.common.start_encryption_tls12(
                     SessionSecrets::new_resume(&self.handshake.randoms,
                                                scs.unwrap().get_hash(),
-                                               &resuming.master_secret.0)
+                                               &resuming.master_secret.0,
+                                               &*sess.config.key_log)
                 );
             }
         }
@@ -776,7 +1150,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // Or asks for us to retry on an unsupported group.
         if let Some(group) = req_group {
-            if !NamedGroups::supported().contains(&group) {
+            if !sess.get_kx_groups().contains(&group) {
                 return Err(illegal_param(sess, "server requested hrr with bad group"));
             }
         }
@@ -826,11 +1200,42 @@ impl ExpectServerHelloOrHelloRetryRequest {
         // HRR selects the ciphersuite.
         sess.common.set_suite(cs);
 
+        // Did the server accept our Encrypted Client Hello?  If so, we
+        // must offer it again.
+        if let Some(mut ech) = self.0.hello.ech.take() {
+            let mut transcript = self.0.handshake.transcript.clone();
+            transcript.start_hash(cs.get_hash());
+            transcript.rollup_for_hrr();
+            let confirmation = ech::compute_confirmation(cs.get_hash(),
+                                                         &transcript,
+                                                         &self.0.handshake.randoms.client,
+                                                         ech::HRR_ACCEPT_CONFIRMATION_LABEL,
+                                                         &m);
+
+            match hrr.get_ech_confirmation() {
+                Some(received) if constant_time::verify_slices_are_equal(&confirmation,
+                                                                         received).is_ok() => {
+                    debug!("Server accepted ECH");
+                    ech.accepted_at_hrr = true;
+                    self.0.hello.ech = Some(ech);
+                }
+                _ => {
+                    debug!("Server rejected ECH");
+                    use_outer_client_hello(&mut self.0.handshake, &mut self.0.hello, ech);
+                }
+            }
+        }
+
         // This is the draft19 change where the transcript became a tree
         self.0.handshake.transcript.start_hash(cs.get_hash());
         self.0.handshake.transcript.rollup_for_hrr();
         self.0.handshake.transcript.add_message(&m);
 
+        // Early data is not allowed after HelloRetryRequest.
+        if sess.early_data.is_enabled() {
+            sess.early_data.rejected();
+        }
+
         Ok(emit_client_hello_for_retry(sess,
                                        self.0.handshake,
                                        self.0.hello,
@@ -907,6 +1312,40 @@ impl ExpectTLS13EncryptedExtensions {
     }
 }
 
+impl ExpectTLS13EncryptedExtensions {
+    fn process_early_data(&self,
+                          sess: &mut ClientSessionImpl,
+                          exts: &EncryptedExtensions) -> Result<(), TLSError> {
+        if !exts.early_data_extension_offered() {
+            if sess.early_data.is_enabled() {
+                // Rejected: move to handshake keys now.
+                sess.early_data.rejected();
+                let suite = sess.common.get_suite_assert();
+                let write_key = sess.common.get_key_schedule().current_client_traffic_secret.clone();
+                sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+            }
+            return Ok(());
+        }
+
+        if !sess.early_data.is_enabled() {
+            return Err(illegal_param(sess, "server accepted early data we didn't send"));
+        }
+
+        // Our early data was protected using the suite of the original
+        // session; the server must not have changed it.
+        let resumed_suite = self.handshake.resuming_session
+            .as_ref()
+            .map(|resuming| resuming.cipher_suite);
+        if resumed_suite != Some(sess.common.get_suite_assert().suite) {
+            return Err(illegal_param(sess, "server accepted early data with different suite"));
+        }
+
+        debug!("Server accepted early data");
+        sess.early_data.accepted();
+        Ok(())
+    }
+}
+
 impl State for ExpectTLS13EncryptedExtensions {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
         check_handshake_message(m, &[HandshakeType::EncryptedExtensions])
@@ -919,8 +1358,30 @@ impl State for ExpectTLS13EncryptedExtensions {
 
         validate_encrypted_extensions(sess, &self.hello, exts)?;
         process_alpn_protocol(sess, exts.get_alpn_protocol())?;
+        process_fragment_limits(sess,
+                                exts.get_record_size_limit(),
+                                exts.get_max_fragment_length())?;
+        self.process_early_data(sess, exts)?;
+
+        if sess.common.protocol == Protocol::Quic {
+            match exts.get_quic_params_extension() {
+                Some(params) => sess.common.quic.peer_params = Some(params.to_vec()),
+                None => {
+                    sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+                    let msg = "server did not send quic transport parameters".to_string();
+                    return Err(TLSError::PeerMisbehavedError(msg));
+                }
+            }
+        }
+
+        if self.handshake.ech_rejected {
+            self.handshake.ech_retry_configs = exts.get_ech_retry_configs()
+                .map(|configs| configs.get_encoding());
+        }
 
-        if self.handshake.resuming_session.is_some() {
+        // The server is authenticated by the PSK if we're resuming
+        // or using an external PSK.
+        if self.handshake.resuming_session.is_some() || sess.psk_identity.is_some() {
             let certv = verify::ServerCertVerified::assertion();
             let sigv =  verify::HandshakeSignatureValid::assertion();
             Ok(self.into_expect_tls13_finished_resume(certv, sigv))
@@ -954,13 +1415,31 @@ impl ExpectTLS13Certificate {
 
 impl State for ExpectTLS13Certificate {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
-        check_handshake_message(m, &[HandshakeType::Certificate])
+        check_handshake_message(m,
+                                &[HandshakeType::Certificate,
+                                  HandshakeType::CompressedCertificate])
     }
 
     fn handle(mut self: Box<Self>, sess: &mut ClientSessionImpl, m: Message) -> NextStateOrError {
-        let cert_chain = extract_handshake!(m, HandshakePayload::CertificateTLS13).unwrap();
         self.handshake.transcript.add_message(&m);
 
+        let decompressed;
+        let cert_chain = match extract_handshake!(m, HandshakePayload::CompressedCertificate) {
+            Some(compressed) => {
+                let decompressors = &sess.config.certificate_decompressors;
+                decompressed = match compress::decompress_certificate(decompressors, compressed) {
+                    Some(cert_chain) => cert_chain,
+                    None => {
+                        sess.common.send_fatal_alert(AlertDescription::BadCertificate);
+                        let error_msg = "server sent bad compressed certificate".to_string();
+                        return Err(TLSError::PeerMisbehavedError(error_msg));
+                    }
+                };
+                &decompressed
+            }
+            None => extract_handshake!(m, HandshakePayload::CertificateTLS13).unwrap(),
+        };
+
         // This is only non-empty for client auth.
         if cert_chain.context.len() > 0 {
             warn!("certificate with non-empty context during handshake");
@@ -1136,11 +1615,12 @@ impl State for ExpectTLS13CertificateOrCertReq {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
         check_handshake_message(m,
                                 &[HandshakeType::Certificate,
+                                  HandshakeType::CompressedCertificate,
                                   HandshakeType::CertificateRequest])
     }
 
     fn handle(self: Box<Self>, sess: &mut ClientSessionImpl, m: Message) -> NextStateOrError {
-        if m.is_handshake_type(HandshakeType::Certificate) {
+        if !m.is_handshake_type(HandshakeType::CertificateRequest) {
             self.into_expect_tls13_certificate().handle(sess, m)
         } else {
             self.into_expect_tls13_certificate_req().handle(sess, m)
@@ -1187,8 +1667,25 @@ impl State for ExpectTLS12ServerKX {
         decoded_kx.encode_params(&mut kx_params);
         let skx = ServerKXDetails::new(kx_params, decoded_kx.get_sig().unwrap());
 
-        if let ServerKeyExchangePayload::ECDHE(ecdhe) = decoded_kx {
-            debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
+        let group = match decoded_kx {
+            ServerKeyExchangePayload::ECDHE(ecdhe) => {
+                debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
+                Some(ecdhe.params.curve_params.named_group)
+            }
+            ServerKeyExchangePayload::DHE(dhe) => {
+                let group = FfdheGroup::from_params(&dhe.params.dh_p.0, &dhe.params.dh_g.0)
+                    .map(|group| group.named_group);
+                debug!("DHE group is {:?}", group);
+                group
+            }
+            _ => None,
+        };
+
+        // We only accept the groups we offered.
+        if !group.map(|group| sess.get_kx_groups().contains(&group)).unwrap_or(false) {
+            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+            return Err(TLSError::PeerMisbehavedError("server chose non-offered kx group"
+                                                     .to_string()));
         }
 
         Ok(self.into_expect_tls12_server_done_or_certreq(skx))
@@ -1223,6 +1720,13 @@ fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLSErro
         TLSError::PeerMisbehavedError(_) => {
             sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
         }
+        TLSError::CertRevoked => {
+            sess.common.send_fatal_alert(AlertDescription::CertificateRevoked);
+        }
+        TLSError::InvalidOCSPResponse(_) |
+        TLSError::OCSPStapleRequired => {
+            sess.common.send_fatal_alert(AlertDescription::BadCertificateStatusResponse);
+        }
         _ => {
             sess.common.send_fatal_alert(AlertDescription::BadCertificate);
         }
@@ -1231,6 +1735,21 @@ fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLSErro
     err
 }
 
+/// If configured to, insist the server staples an OCSP response
+/// when its certificate says it must (RFC7633).
+fn check_must_staple(sess: &ClientSessionImpl,
+                     server_cert: &ServerCertDetails) -> Result<(), TLSError> {
+    if !sess.config.enforce_ocsp_must_staple || !server_cert.ocsp_response.is_empty() {
+        return Ok(());
+    }
+
+    let leaf = untrusted::Input::from(&server_cert.cert_chain[0].0);
+    match x509::cert_fields(leaf) {
+        Ok(ref fields) if fields.requires_ocsp_staple() => Err(TLSError::OCSPStapleRequired),
+        _ => Ok(()),
+    }
+}
+
 impl State for ExpectTLS13CertificateVerify {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
         check_handshake_message(m, &[HandshakeType::CertificateVerify])
@@ -1250,13 +1769,16 @@ impl State for ExpectTLS13CertificateVerify {
             .get_verifier()
             .verify_server_cert(&sess.config.root_store,
                                 &self.server_cert.cert_chain,
-                                self.handshake.dns_name.as_ref(),
+                                &self.handshake.server_name,
                                 &self.server_cert.ocsp_response)
             .map_err(|err| send_cert_error_alert(sess, err))?;
+        check_must_staple(sess, &self.server_cert)
+            .map_err(|err| send_cert_error_alert(sess, err))?;
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.handshake.transcript.get_current_hash();
-        let sigv = verify::verify_tls13(&self.server_cert.cert_chain[0],
+        let sigv = verify::verify_tls13(sess.config.provider.signature_verifier(),
+                                        &self.server_cert.cert_chain[0],
                                         cert_verify,
                                         &handshake_hash,
                                         b"TLS 1.3, server CertificateVerify\x00")
@@ -1301,8 +1823,10 @@ fn emit_clientkx(handshake: &mut HandshakeDetails,
                  sess: &mut ClientSessionImpl,
                  kxd: &suites::KeyExchangeResult) {
     let mut buf = Vec::new();
-    let ecpoint = PayloadU8::new(kxd.pubkey.clone());
-    ecpoint.encode(&mut buf);
+    match sess.common.get_suite_assert().kx {
+        KeyExchangeAlgorithm::DHE => PayloadU16::new(kxd.pubkey.clone()).encode(&mut buf),
+        _ => PayloadU8::new(kxd.pubkey.clone()).encode(&mut buf),
+    }
     let pubkey = Payload::new(buf);
 
     let ckx = Message {
@@ -1446,6 +1970,48 @@ impl State for ExpectTLS12CertificateRequest {
 
 // TLS1.3 version of the above.  We then move to expecting the server Certificate.
 // Unfortunately the CertificateRequest type changed in an annoying way in TLS1.3.
+/// Pick our certificate and signing key in response to a TLS1.3
+/// CertificateRequest, either during or after the handshake.
+fn choose_client_auth_tls13(sess: &mut ClientSessionImpl,
+                            certreq: &CertificateRequestPayloadTLS13)
+                            -> Result<ClientAuthDetails, TLSError> {
+    let tls13_sign_schemes = SupportedSignatureSchemes::supported_sign_tls13();
+    let no_sigschemes = Vec::new();
+    let compat_sigschemes = certreq.get_sigalgs_extension()
+        .unwrap_or(&no_sigschemes)
+        .iter()
+        .cloned()
+        .filter(|scheme| tls13_sign_schemes.contains(scheme))
+        .collect::<Vec<SignatureScheme>>();
+
+    if compat_sigschemes.is_empty() {
+        sess.common.send_fatal_alert(AlertDescription::DecodeError);
+        return Err(TLSError::PeerIncompatibleError("server sent bad certreq schemes".to_string()));
+    }
+
+    let no_canames = Vec::new();
+    let canames = certreq.get_authorities_extension()
+        .unwrap_or(&no_canames)
+        .iter()
+        .map(|p| p.0.as_slice())
+        .collect::<Vec<&[u8]>>();
+    let maybe_certkey =
+        sess.config.client_auth_cert_resolver.resolve(&canames, &compat_sigschemes);
+
+    let mut client_auth = ClientAuthDetails::new();
+    client_auth.auth_context = Some(certreq.context.0.clone());
+    if let Some(mut certkey) = maybe_certkey {
+        debug!("Attempting client auth");
+        let maybe_signer = certkey.key.choose_scheme(&compat_sigschemes);
+        client_auth.cert = Some(certkey.take_cert());
+        client_auth.signer = maybe_signer;
+    } else {
+        debug!("Client auth requested but no cert selected");
+    }
+
+    Ok(client_auth)
+}
+
 struct ExpectTLS13CertificateRequest {
     handshake: HandshakeDetails,
     server_cert: ServerCertDetails,
@@ -1481,40 +2047,7 @@ impl State for ExpectTLS13CertificateRequest {
             return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
         }
 
-        let tls13_sign_schemes = SupportedSignatureSchemes::supported_sign_tls13();
-        let no_sigschemes = Vec::new();
-        let compat_sigschemes = certreq.get_sigalgs_extension()
-            .unwrap_or(&no_sigschemes)
-            .iter()
-            .cloned()
-            .filter(|scheme| tls13_sign_schemes.contains(scheme))
-            .collect::<Vec<SignatureScheme>>();
-
-        if compat_sigschemes.is_empty() {
-            sess.common.send_fatal_alert(AlertDescription::DecodeError);
-            return Err(TLSError::PeerIncompatibleError("server sent bad certreq schemes".to_string()));
-        }
-
-        let no_canames = Vec::new();
-        let canames = certreq.get_authorities_extension()
-            .unwrap_or(&no_canames)
-            .iter()
-            .map(|p| p.0.as_slice())
-            .collect::<Vec<&[u8]>>();
-        let maybe_certkey =
-            sess.config.client_auth_cert_resolver.resolve(&canames, &compat_sigschemes);
-
-        let mut client_auth = ClientAuthDetails::new();
-        if let Some(mut certkey) = maybe_certkey {
-            debug!("Attempting client auth");
-            let maybe_signer = certkey.key.choose_scheme(&compat_sigschemes);
-            client_auth.cert = Some(certkey.take_cert());
-            client_auth.signer = maybe_signer;
-            client_auth.auth_context = Some(certreq.context.0.clone());
-        } else {
-            debug!("Client auth requested but no cert selected");
-        }
-
+        let client_auth = choose_client_auth_tls13(sess, certreq)?;
         Ok(self.into_expect_tls13_certificate(client_auth))
     }
 }
@@ -1608,7 +2141,7 @@ impl State for ExpectTLS12ServerDone {
         st.handshake.transcript.add_message(&m);
 
         debug!("Server cert is {:?}", st.server_cert.cert_chain);
-        debug!("Server DNS name is {:?}", st.handshake.dns_name);
+        debug!("Server name is {:?}", st.handshake.server_name);
 
         // 1. Verify the cert chain.
         // 2. Verify any SCTs provided with the certificate.
@@ -1631,9 +2164,11 @@ impl State for ExpectTLS12ServerDone {
             .get_verifier()
             .verify_server_cert(&sess.config.root_store,
                                 &st.server_cert.cert_chain,
-                                st.handshake.dns_name.as_ref(),
+                                &st.handshake.server_name,
                                 &st.server_cert.ocsp_response)
             .map_err(|err| send_cert_error_alert(sess, err))?;
+        check_must_staple(sess, &st.server_cert)
+            .map_err(|err| send_cert_error_alert(sess, err))?;
 
         // 2. Verify any included SCTs.
         match (st.server_cert.scts.as_ref(), sess.config.ct_logs) {
@@ -1657,14 +2192,15 @@ impl State for ExpectTLS12ServerDone {
             // Check the signature is compatible with the ciphersuite.
             let sig = &st.server_kx.kx_sig;
             let scs = sess.common.get_suite_assert();
-            if scs.sign != sig.scheme.sign() {
+            if !scs.usable_for_sigalg(sig.scheme.sign()) {
                 let error_message =
                     format!("peer signed kx with wrong algorithm (got {:?} expect {:?})",
                                       sig.scheme.sign(), scs.sign);
                 return Err(TLSError::PeerMisbehavedError(error_message));
             }
 
-            verify::verify_signed_struct(&message,
+            verify::verify_signed_struct(sess.config.provider.signature_verifier(),
+                                         &message,
                                          &st.server_cert.cert_chain[0],
                                          sig)
                 .map_err(|err| send_cert_error_alert(sess, err))?
@@ -1679,7 +2215,7 @@ impl State for ExpectTLS12ServerDone {
 
         // 5a.
         let kxd = sess.common.get_suite_assert()
-            .do_client_kx(&st.server_kx.kx_params)
+            .do_client_kx(sess.config.provider.as_ref(), &st.server_kx.kx_params)
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
 
         // 5b.
@@ -1703,11 +2239,13 @@ impl State for ExpectTLS12ServerDone {
             SessionSecrets::new_ems(&st.handshake.randoms,
                                     &handshake_hash,
                                     hashalg,
-                                    &kxd.premaster_secret)
+                                    &kxd.premaster_secret,
+                                    &*sess.config.key_log)
         } else {
             SessionSecrets::new(&st.handshake.randoms,
                                 hashalg,
-                                &kxd.premaster_secret)
+                                &kxd.premaster_secret,
+                                &*sess.config.key_log)
         };
         sess.common.start_encryption_tls12(secrets);
 
@@ -1815,7 +2353,7 @@ fn save_session(handshake: &mut HandshakeDetails,
         return;
     }
 
-    let key = persist::ClientSessionKey::session_for_dns_name(handshake.dns_name.as_ref());
+    let key = persist::ClientSessionKey::session_for_server_name(&handshake.server_name);
 
     let scs = sess.common.get_suite_assert();
     let master_secret = sess.common.secrets.as_ref().unwrap().get_master_secret();
@@ -1842,7 +2380,7 @@ fn save_session(handshake: &mut HandshakeDetails,
     }
 }
 
-fn emit_certificate_tls13(handshake: &mut HandshakeDetails,
+fn emit_certificate_tls13(transcript: &mut HandshakeHash,
                           client_auth: &mut ClientAuthDetails,
                           sess: &mut ClientSessionImpl) {
     let context = client_auth.auth_context
@@ -1868,11 +2406,11 @@ fn emit_certificate_tls13(handshake: &mut HandshakeDetails,
             payload: HandshakePayload::CertificateTLS13(cert_payload),
         }),
     };
-    handshake.transcript.add_message(&m);
+    transcript.add_message(&m);
     sess.common.send_msg(m, true);
 }
 
-fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
+fn emit_certverify_tls13(transcript: &mut HandshakeHash,
                          client_auth: &mut ClientAuthDetails,
                          sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
     if client_auth.signer.is_none() {
@@ -1883,7 +2421,7 @@ fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
     let mut message = Vec::new();
     message.resize(64, 0x20u8);
     message.extend_from_slice(b"TLS 1.3, client CertificateVerify\x00");
-    message.extend_from_slice(&handshake.transcript.get_current_hash());
+    message.extend_from_slice(&transcript.get_current_hash());
 
     let signer = client_auth.signer.take().unwrap();
     let scheme = signer.get_scheme();
@@ -1899,17 +2437,18 @@ fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
         }),
     };
 
-    handshake.transcript.add_message(&m);
+    transcript.add_message(&m);
     sess.common.send_msg(m, true);
     Ok(())
 }
 
-fn emit_finished_tls13(handshake: &mut HandshakeDetails,
+fn emit_finished_tls13(transcript: &mut HandshakeHash,
+                       kind: SecretKind,
                        sess: &mut ClientSessionImpl) {
-    let handshake_hash = handshake.transcript.get_current_hash();
+    let handshake_hash = transcript.get_current_hash();
     let verify_data = sess.common
         .get_key_schedule()
-        .sign_finish(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+        .sign_finish(kind, &handshake_hash);
     let verify_data_payload = Payload::new(verify_data);
 
     let m = Message {
@@ -1921,6 +2460,21 @@ fn emit_finished_tls13(handshake: &mut HandshakeDetails,
         }),
     };
 
+    transcript.add_message(&m);
+    sess.common.send_msg(m, true);
+}
+
+fn emit_end_of_early_data_tls13(handshake: &mut HandshakeDetails,
+                                sess: &mut ClientSessionImpl) {
+    let m = Message {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_3,
+        payload: MessagePayload::Handshake(HandshakeMessagePayload {
+            typ: HandshakeType::EndOfEarlyData,
+            payload: HandshakePayload::EndOfEarlyData,
+        }),
+    };
+
     handshake.transcript.add_message(&m);
     sess.common.send_msg(m, true);
 }
@@ -1934,9 +2488,11 @@ struct ExpectTLS13Finished {
 
 impl ExpectTLS13Finished {
     fn into_expect_tls13_traffic(self,
+                                 post_handshake_auth: bool,
                                  fin: verify::FinishedMessageVerified) -> NextState {
         Box::new(ExpectTLS13Traffic {
             handshake: self.handshake,
+            post_handshake_auth: post_handshake_auth,
             _cert_verified: self.cert_verified,
             _sig_verified: self.sig_verified,
             _fin_verified: fin,
@@ -1965,6 +2521,14 @@ impl State for ExpectTLS13Finished {
                     })
             .map(|_| verify::FinishedMessageVerified::assertion())?;
 
+        // If the server rejected our ECH offer, we've now authenticated
+        // it as the public name, so can believe its retry configurations.
+        // But we mustn't continue: the caller wanted the hidden name.
+        if st.handshake.ech_rejected {
+            sess.common.send_fatal_alert(AlertDescription::ECHRequired);
+            return Err(TLSError::ECHRejected(st.handshake.ech_retry_configs.take()));
+        }
+
         st.handshake.transcript.add_message(&m);
 
         /* Transition to application data */
@@ -1974,7 +2538,10 @@ impl State for ExpectTLS13Finished {
         let handshake_hash = st.handshake.transcript.get_current_hash();
         let read_key = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ServerApplicationTrafficSecret, &handshake_hash);
+            .derive_logged_secret(SecretKind::ServerApplicationTrafficSecret,
+                                  &handshake_hash,
+                                  &*sess.config.key_log,
+                                  &st.handshake.randoms.client);
         let suite = sess.common.get_suite_assert();
         sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
         sess.common
@@ -1983,38 +2550,60 @@ impl State for ExpectTLS13Finished {
 
         let exporter_secret = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ExporterMasterSecret, &handshake_hash);
+            .derive_logged_secret(SecretKind::ExporterMasterSecret,
+                                  &handshake_hash,
+                                  &*sess.config.key_log,
+                                  &st.handshake.randoms.client);
         sess.common
             .get_mut_key_schedule()
             .current_exporter_secret = exporter_secret;
 
+        /* If the server accepted our early data, mark its end (still under the
+         * early traffic key) and then move to our handshake keys. */
+        if sess.early_data.is_accepted() {
+            emit_end_of_early_data_tls13(&mut st.handshake, sess);
+            sess.early_data.finished();
+
+            let write_key = sess.common.get_key_schedule().current_client_traffic_secret.clone();
+            sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        }
+
         /* Send our authentication/finished messages.  These are still encrypted
          * with our handshake keys. */
         if st.client_auth.is_some() {
-            emit_certificate_tls13(&mut st.handshake,
+            emit_certificate_tls13(&mut st.handshake.transcript,
                                    st.client_auth.as_mut().unwrap(),
                                    sess);
-            emit_certverify_tls13(&mut st.handshake,
+            emit_certverify_tls13(&mut st.handshake.transcript,
                                   st.client_auth.as_mut().unwrap(),
                                   sess)?;
         }
 
-        emit_finished_tls13(&mut st.handshake,
+        emit_finished_tls13(&mut st.handshake.transcript,
+                            SecretKind::ClientHandshakeTrafficSecret,
                             sess);
 
         /* Now move to our application traffic keys. */
         check_aligned_handshake(sess)?;
         let write_key = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ClientApplicationTrafficSecret, &handshake_hash);
+            .derive_logged_secret(SecretKind::ClientApplicationTrafficSecret,
+                                  &handshake_hash,
+                                  &*sess.config.key_log,
+                                  &st.handshake.randoms.client);
         sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        if sess.common.protocol == Protocol::Quic {
+            let read_key = sess.common.get_key_schedule().current_server_traffic_secret.clone();
+            sess.common.quic.set_secrets(write_key.clone(), read_key);
+        }
         sess.common
             .get_mut_key_schedule()
             .current_client_traffic_secret = write_key;
 
         sess.common.we_now_encrypting();
         sess.common.start_traffic();
-        Ok(st.into_expect_tls13_traffic(fin))
+        let post_handshake_auth = sess.config.enable_post_handshake_auth;
+        Ok(st.into_expect_tls13_traffic(post_handshake_auth, fin))
     }
 }
 
@@ -2103,6 +2692,7 @@ impl State for ExpectTLS12Traffic {
 // and application data.
 struct ExpectTLS13Traffic {
     handshake: HandshakeDetails,
+    post_handshake_auth: bool,
     _cert_verified: verify::ServerCertVerified,
     _sig_verified: verify::HandshakeSignatureValid,
     _fin_verified: verify::FinishedMessageVerified,
@@ -2128,10 +2718,15 @@ impl ExpectTLS13Traffic {
                         nst.lifetime,
                         nst.age_add);
 
-        let key = persist::ClientSessionKey::session_for_dns_name(self.handshake.dns_name.as_ref());
+        if let Some(sz) = nst.get_max_early_data_size() {
+            value.set_max_early_data_size(sz);
+        }
+
+        let server_name = &self.handshake.server_name;
+        let key = persist::ClientSessionKey::tls13_tickets_for_server_name(server_name);
 
-        let worked = sess.config.session_persistence.put(key.get_encoding(),
-                                                         value.get_encoding());
+        let worked = sess.config.session_persistence.put_ticket(key.get_encoding(),
+                                                                value.get_encoding());
 
         if worked {
             debug!("Ticket saved");
@@ -2145,13 +2740,45 @@ impl ExpectTLS13Traffic {
         let kur = extract_handshake!(m, HandshakePayload::KeyUpdate).unwrap();
         sess.common.process_key_update(kur, SecretKind::ServerApplicationTrafficSecret)
     }
+
+    fn handle_certificate_request(&mut self, sess: &mut ClientSessionImpl, m: Message) -> Result<(), TLSError> {
+        let certreq = extract_handshake!(m, HandshakePayload::CertificateRequestTLS13).unwrap();
+        debug!("Got post-handshake CertificateRequest {:?}", certreq);
+
+        // Each post-handshake authentication covers the handshake through
+        // our Finished, then its own messages only.
+        let mut transcript = self.handshake.transcript.clone();
+        transcript.add_message(&m);
+
+        if certreq.context.len() == 0 {
+            warn!("Server sent empty post-handshake certreq context");
+            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+            return Err(TLSError::PeerMisbehavedError("empty post-handshake certreq context"
+                                                     .to_string()));
+        }
+
+        let mut client_auth = choose_client_auth_tls13(sess, certreq)?;
+        emit_certificate_tls13(&mut transcript, &mut client_auth, sess);
+        emit_certverify_tls13(&mut transcript, &mut client_auth, sess)?;
+        emit_finished_tls13(&mut transcript,
+                            SecretKind::ClientApplicationTrafficSecret,
+                            sess);
+        Ok(())
+    }
 }
 
 impl State for ExpectTLS13Traffic {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
-        check_message(m,
-                      &[ContentType::ApplicationData, ContentType::Handshake],
-                      &[HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate])
+        if self.post_handshake_auth {
+            check_message(m,
+                          &[ContentType::ApplicationData, ContentType::Handshake],
+                          &[HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate,
+                            HandshakeType::CertificateRequest])
+        } else {
+            check_message(m,
+                          &[ContentType::ApplicationData, ContentType::Handshake],
+                          &[HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate])
+        }
     }
 
     fn handle(mut self: Box<Self>, sess: &mut ClientSessionImpl, mut m: Message) -> NextStateOrError {
@@ -2161,6 +2788,8 @@ impl State for ExpectTLS13Traffic {
             self.handle_new_ticket_tls13(sess, m)?;
         } else if m.is_handshake_type(HandshakeType::KeyUpdate) {
             self.handle_key_update(sess, m)?;
+        } else if m.is_handshake_type(HandshakeType::CertificateRequest) {
+            self.handle_certificate_request(sess, m)?;
         }
 
         Ok(self)
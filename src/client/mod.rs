@@ -1,20 +1,35 @@
 use msgs::enums::CipherSuite;
 use msgs::enums::{AlertDescription, HandshakeType};
-use session::{Session, SessionCommon};
-use suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
+use session::{Session, SessionCommon, Protocol};
+use suites::SupportedCipherSuite;
+use crypto::{CryptoProvider, Hash};
+use crypto::ring::RingProvider;
+use msgs::enums::NamedGroup;
+use msgs::handshake::{NamedGroups, SupportedGroups};
 use msgs::handshake::CertificatePayload;
 use msgs::enums::SignatureScheme;
 use msgs::enums::{ContentType, ProtocolVersion};
-use msgs::message::Message;
+use msgs::enums::{PSKKeyExchangeMode, MaxFragmentLength};
+use msgs::message::{Message, MessagePayload};
 use verify;
 use anchors;
+use crl::{CRLStore, CRLPolicy};
+use pinning::SPKIPinSet;
 use sign;
 use error::TLSError;
 use key;
+use keylog::{KeyLog, NoKeyLog};
+use padding::{RecordPadding, NoPadding};
+use compress;
+use compress::CertificateDecompressor;
+use ech;
+use quic::{QuicExt, QuicSecrets};
 
 use std::sync::Arc;
 use std::io;
 use std::fmt;
+use std::mem;
+use std::net;
 
 use sct;
 use webpki;
@@ -23,6 +38,63 @@ mod hs;
 mod common;
 pub mod handy;
 
+/// The name of the server we want to talk to: a DNS name or an IP
+/// address.  We check the server's certificate is valid for this
+/// name, and use it to find sessions to resume.
+///
+/// Only DNS names are sent in the SNI extension: RFC6066 doesn't
+/// allow IP addresses there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ServerName {
+    /// A DNS name, which must be in the certificate's subject
+    /// alternative names as a dNSName.
+    DNSName(webpki::DNSName),
+
+    /// An IPv4 or IPv6 address, which must be in the certificate's
+    /// subject alternative names as an iPAddress.
+    IPAddress(net::IpAddr),
+}
+
+impl ServerName {
+    /// Parse `name` as an IP address literal if it is one, or
+    /// otherwise as a DNS name.
+    pub fn try_from_ascii_str(name: &str) -> Result<ServerName, TLSError> {
+        if let Ok(ip) = name.parse::<net::IpAddr>() {
+            return Ok(ServerName::IPAddress(ip));
+        }
+
+        webpki::DNSNameRef::try_from_ascii_str(name)
+            .map(ServerName::from)
+            .map_err(|_| TLSError::InvalidDNSName(name.to_string()))
+    }
+
+    /// Return the DNS name, if this is one.
+    pub fn as_dns_name(&self) -> Option<webpki::DNSNameRef> {
+        match *self {
+            ServerName::DNSName(ref name) => Some(name.as_ref()),
+            ServerName::IPAddress(_) => None,
+        }
+    }
+}
+
+impl<'a> From<webpki::DNSNameRef<'a>> for ServerName {
+    fn from(name: webpki::DNSNameRef<'a>) -> ServerName {
+        ServerName::DNSName(name.into())
+    }
+}
+
+impl From<webpki::DNSName> for ServerName {
+    fn from(name: webpki::DNSName) -> ServerName {
+        ServerName::DNSName(name)
+    }
+}
+
+impl From<net::IpAddr> for ServerName {
+    fn from(ip: net::IpAddr) -> ServerName {
+        ServerName::IPAddress(ip)
+    }
+}
+
 /// A trait for the ability to store client session data.
 /// The keys and values are opaque.
 ///
@@ -42,6 +114,26 @@ pub trait StoresClientSessions : Send + Sync {
     /// Returns the latest value for `key`.  Returns `None`
     /// if there's no such value.
     fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Adds `value`, a TLS1.3 ticket, to those kept for `key`.
+    /// TLS1.3 tickets should only be used once, and servers may
+    /// issue several per connection.  Returns `true` if the value
+    /// was stored.
+    ///
+    /// The default implementation keeps only the latest ticket,
+    /// using `put`.
+    fn put_ticket(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.put(key, value)
+    }
+
+    /// Removes and returns a TLS1.3 ticket kept for `key`, the
+    /// oldest first.  Returns `None` if there are none left.
+    ///
+    /// The default implementation returns the latest ticket
+    /// without removing it, using `get`.
+    fn take_ticket(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(key)
+    }
 }
 
 /// A trait for the ability to choose a certificate chain and
@@ -67,6 +159,44 @@ pub trait ResolvesClientCert : Send + Sync {
     fn has_certs(&self) -> bool;
 }
 
+/// A TLS1.3 external pre-shared key: one agreed with a server
+/// out-of-band, rather than a resumption ticket.
+///
+/// External PSKs are always used with the SHA256 hash, so
+/// are only usable with ciphersuites using SHA256.
+#[derive(Clone)]
+pub struct ExternalPsk {
+    /// The identity the server knows this key by.
+    pub identity: Vec<u8>,
+
+    /// The key itself.
+    pub secret: Vec<u8>,
+}
+
+impl ExternalPsk {
+    /// Make a new `ExternalPsk`.
+    pub fn new(identity: &[u8], secret: &[u8]) -> ExternalPsk {
+        ExternalPsk {
+            identity: identity.to_vec(),
+            secret: secret.to_vec(),
+        }
+    }
+}
+
+/// A trait for the ability to find the external pre-shared keys
+/// we have agreed with a server.
+///
+/// These keys authenticate the server in place of its certificate,
+/// so should be treated as **highly sensitive data**.
+pub trait StoresClientPsks : Send + Sync {
+    /// Returns the keys to offer to the server named `dns_name`,
+    /// most preferred first.  Returns an empty `Vec` if there
+    /// are none.
+    ///
+    /// Servers named by IP address are never offered external PSKs.
+    fn get(&self, dns_name: webpki::DNSNameRef) -> Vec<ExternalPsk>;
+}
+
 /// Common configuration for (typically) all connections made by
 /// a program.
 ///
@@ -74,6 +204,12 @@ pub trait ResolvesClientCert : Send + Sync {
 /// once per process rather than once per connection.
 #[derive(Clone)]
 pub struct ClientConfig {
+    /// Where we get our cryptography.  `ciphersuites` should
+    /// contain this provider's suites.
+    ///
+    /// The default is `RingProvider`.
+    pub provider: Arc<CryptoProvider>,
+
     /// List of ciphersuites, in preference order.
     pub ciphersuites: Vec<&'static SupportedCipherSuite>,
 
@@ -90,6 +226,22 @@ pub struct ClientConfig {
     /// Our MTU.  If None, we don't limit TLS message sizes.
     pub mtu: Option<usize>,
 
+    /// The largest record we want to receive, sent to the server as
+    /// an RFC8449 `record_size_limit`.  Values outside the range 64
+    /// to 16385 are clamped to it.  We only hold the server to this
+    /// if it replies with its own `record_size_limit`.
+    ///
+    /// The default is None.
+    pub record_size_limit: Option<u16>,
+
+    /// Ask the server to send and receive records no larger than
+    /// this, using RFC6066 `max_fragment_length`.  If the server
+    /// agrees, we hold it to that.  Servers which support
+    /// `record_size_limit` use that instead when both are sent.
+    ///
+    /// The default is None.
+    pub max_fragment_length: Option<MaxFragmentLength>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<ResolvesClientCert>,
 
@@ -104,6 +256,18 @@ pub struct ClientConfig {
     /// is all supported versions.
     pub versions: Vec<ProtocolVersion>,
 
+    /// The key exchange groups we support, most preferred first.
+    /// These are offered to the server, and we only accept its
+    /// choice of group if it's one of them.  Our first TLS1.3
+    /// ClientHello carries a key share for the first group, unless
+    /// we remember that the server prefers another.  Groups we
+    /// don't implement are ignored.
+    ///
+    /// The default is X25519, P-384 and P-256.  Add the RFC7919
+    /// `FFDHE` groups to use finite field Diffie-Hellman, in TLS1.3
+    /// or with the TLS1.2 `DHE_RSA` suites.
+    pub kx_groups: Vec<NamedGroup>,
+
     /// Collection of certificate transparency logs.
     /// If this collection is empty, then certificate transparency
     /// checking is disabled.
@@ -115,6 +279,67 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// Whether to send TLS1.3 early data ("0-RTT data") when resuming
+    /// a session whose ticket allows it.  See `ClientSession::early_data`.
+    ///
+    /// The default is false.
+    pub enable_early_data: bool,
+
+    /// How to output key material for debugging.  The default
+    /// does nothing.
+    pub key_log: Arc<KeyLog>,
+
+    /// How much padding to add to the TLS1.3 records we send, to
+    /// hide the length of their contents from eavesdroppers.
+    ///
+    /// The default adds no padding.
+    pub record_padding: Arc<RecordPadding>,
+
+    /// Where to find TLS1.3 external pre-shared keys for a server.
+    /// If the server accepts one of these, it authenticates the server
+    /// and its certificate is not needed.
+    ///
+    /// The default has no keys.
+    pub psk_store: Arc<StoresClientPsks>,
+
+    /// The TLS1.3 PSK key exchange modes we offer, for both resumption
+    /// and external PSKs.  `PSK_KE` connections don't have forward
+    /// secrecy.
+    ///
+    /// The default is just `PSK_DHE_KE`.
+    pub psk_modes: Vec<PSKKeyExchangeMode>,
+
+    /// Whether to offer TLS1.3 post-handshake client authentication.
+    /// If the server then asks for a certificate after the handshake,
+    /// we answer using `client_auth_cert_resolver`.
+    ///
+    /// The default is false.
+    pub enable_post_handshake_auth: bool,
+
+    /// How to decompress certificate chains sent by TLS1.3 servers
+    /// using RFC8879 certificate compression.  The algorithms are
+    /// offered in this order.  If empty, we don't offer compression.
+    ///
+    /// The default is `ZlibCertificateCompression` if the `zlib`
+    /// feature is enabled, and empty otherwise.
+    pub certificate_decompressors: Vec<Arc<CertificateDecompressor>>,
+
+    /// Whether to fail the handshake if the server's certificate has
+    /// the RFC7633 "must staple" TLS feature, but the server did not
+    /// staple an OCSP response.  Stapled responses are always checked.
+    ///
+    /// The default is false.
+    pub enforce_ocsp_must_staple: bool,
+
+    /// Which Encrypted Client Hello configuration we use, if any.
+    /// See `set_ech_config_list`.
+    ech_config: Option<ech::ClientECHConfig>,
+
+    /// CRLs and pins for the verifier.  See `set_crls` and
+    /// `set_spki_pins`.
+    crls: Option<(Arc<CRLStore>, CRLPolicy)>,
+    spki_pins: Option<SPKIPinSet>,
+
     /// How to verify the server certificate chain.
     verifier: Arc<verify::ServerCertVerifier>,
 }
@@ -126,17 +351,39 @@ impl ClientConfig {
     /// The default session persistence provider stores up to 32
     /// items in memory.
     pub fn new() -> ClientConfig {
+        ClientConfig::with_provider(Arc::new(RingProvider))
+    }
+
+    /// Make a `ClientConfig` like `new`, but getting all its
+    /// cryptography from `provider`.  Its ciphersuites are the
+    /// provider's.
+    pub fn with_provider(provider: Arc<CryptoProvider>) -> ClientConfig {
         ClientConfig {
-            ciphersuites: ALL_CIPHERSUITES.to_vec(),
+            ciphersuites: provider.cipher_suites(),
+            provider: provider,
             root_store: anchors::RootCertStore::empty(),
             alpn_protocols: Vec::new(),
             session_persistence: handy::ClientSessionMemoryCache::new(32),
             mtu: None,
+            record_size_limit: None,
+            max_fragment_length: None,
             client_auth_cert_resolver: Arc::new(handy::FailResolveClientCert {}),
             enable_tickets: true,
             versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+            kx_groups: NamedGroups::supported(),
             ct_logs: None,
             enable_sni: true,
+            enable_early_data: false,
+            key_log: Arc::new(NoKeyLog {}),
+            record_padding: Arc::new(NoPadding),
+            psk_store: Arc::new(handy::NoClientPsks {}),
+            psk_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
+            enable_post_handshake_auth: false,
+            certificate_decompressors: compress::default_decompressors(),
+            enforce_ocsp_must_staple: false,
+            ech_config: None,
+            crls: None,
+            spki_pins: None,
             verifier: Arc::new(verify::WebPKIVerifier::new())
         }
     }
@@ -177,18 +424,73 @@ impl ClientConfig {
         }
     }
 
+    /// Encrypt our ClientHellos to the server using Encrypted Client
+    /// Hello.  `config_list` is the server's `ECHConfigList`, as
+    /// published in DNS or sent with `TLSError::ECHRejected`.  We use
+    /// the first configuration in it which we support.
+    ///
+    /// This hides the server name we're connecting to, and the
+    /// rest of the ClientHello, from observers.  It only works with
+    /// TLS1.3: connections fail if the server negotiates TLS1.2.
+    pub fn set_ech_config_list(&mut self, config_list: &[u8]) -> Result<(), TLSError> {
+        let config = ech::ClientECHConfig::select(config_list)
+            .ok_or_else(|| TLSError::General("no usable ECH configuration".to_string()))?;
+        self.ech_config = Some(config);
+        Ok(())
+    }
+
     /// Sets a single client authentication certificate and private key.
     /// This is blindly used for all servers that ask for client auth.
     ///
     /// `cert_chain` is a vector of DER-encoded certificates,
-    /// `key_der` is a DER-encoded RSA private key.
+    /// `key_der` is a DER-encoded RSA or ECDSA private key.
     pub fn set_single_client_cert(&mut self,
                                   cert_chain: Vec<key::Certificate>,
                                   key_der: key::PrivateKey) {
-        let resolver = handy::AlwaysResolvesClientCert::new_rsa(cert_chain, &key_der);
+        let resolver = handy::AlwaysResolvesClientCert::new(cert_chain, &key_der);
         self.client_auth_cert_resolver = Arc::new(resolver);
     }
 
+    /// Reject servers whose certificate chain includes a revoked
+    /// certificate.  Every certificate in the chain is checked against
+    /// the CRL from its issuer in `crls`, with `policy` saying what
+    /// to do if there isn't a current one.
+    ///
+    /// This replaces the certificate verifier with one that validates
+    /// chains like the default one, and then checks CRLs (and pins, if
+    /// `set_spki_pins` was called).  So any verifier set with
+    /// `dangerous().set_certificate_verifier` is discarded; setting one
+    /// afterwards discards the CRL checking instead.
+    pub fn set_crls(&mut self, crls: Arc<CRLStore>, policy: CRLPolicy) {
+        self.crls = Some((crls, policy));
+        self.reset_verifier();
+    }
+
+    /// Reject servers unless some certificate in their validated
+    /// chain, including the trust anchor, has a key in `pins`.
+    ///
+    /// Like `set_crls`, this replaces the certificate verifier with one
+    /// that validates chains like the default one, and then checks pins
+    /// (and CRLs, if any).  So any verifier set with
+    /// `dangerous().set_certificate_verifier` is discarded; setting one
+    /// afterwards discards the pin checking instead.
+    pub fn set_spki_pins(&mut self, pins: SPKIPinSet) {
+        self.spki_pins = Some(pins);
+        self.reset_verifier();
+    }
+
+    fn reset_verifier(&mut self) {
+        let verifier = match self.crls {
+            Some((ref crls, policy)) => verify::WebPKIVerifier::with_crls(crls.clone(), policy),
+            None => verify::WebPKIVerifier::new(),
+        };
+
+        self.verifier = match self.spki_pins {
+            Some(ref pins) => Arc::new(verify::SPKIPinningVerifier::new(verifier, pins.clone())),
+            None => Arc::new(verifier),
+        };
+    }
+
     /// Access configuration options whose use is dangerous and requires
     /// extra care.
     #[cfg(feature = "dangerous_configuration")]
@@ -213,6 +515,11 @@ pub mod danger {
 
     impl<'a> DangerousClientConfig<'a> {
         /// Overrides the default `ServerCertVerifier` with something else.
+        ///
+        /// This replaces any verifier made by `set_crls` or
+        /// `set_spki_pins`, so their checks are no longer done unless
+        /// `verifier` does them.  Calling either of those afterwards
+        /// replaces `verifier` in turn.
         pub fn set_certificate_verifier(&mut self,
                                         verifier: Arc<ServerCertVerifier>) {
             self.cfg.verifier = verifier;
@@ -220,6 +527,118 @@ pub mod danger {
     }
 }
 
+#[derive(Debug, PartialEq)]
+enum EarlyDataState {
+    Disabled,
+    Ready,
+    Accepted,
+    AcceptedFinished,
+    Rejected,
+}
+
+pub struct EarlyData {
+    state: EarlyDataState,
+    left: usize,
+}
+
+impl EarlyData {
+    fn new() -> EarlyData {
+        EarlyData {
+            left: 0,
+            state: EarlyDataState::Disabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        match self.state {
+            EarlyDataState::Ready | EarlyDataState::Accepted => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        match self.state {
+            EarlyDataState::Accepted | EarlyDataState::AcceptedFinished => true,
+            _ => false,
+        }
+    }
+
+    pub fn enable(&mut self, max_data: usize) {
+        assert_eq!(self.state, EarlyDataState::Disabled);
+        self.state = EarlyDataState::Ready;
+        self.left = max_data;
+    }
+
+    pub fn rejected(&mut self) {
+        trace!("EarlyData rejected");
+        self.state = EarlyDataState::Rejected;
+    }
+
+    pub fn accepted(&mut self) {
+        trace!("EarlyData accepted");
+        assert_eq!(self.state, EarlyDataState::Ready);
+        self.state = EarlyDataState::Accepted;
+    }
+
+    pub fn finished(&mut self) {
+        trace!("EarlyData finished");
+        self.state = match self.state {
+            EarlyDataState::Accepted => EarlyDataState::AcceptedFinished,
+            _ => panic!("bad EarlyData state"),
+        }
+    }
+
+    fn check_write(&mut self, sz: usize) -> io::Result<usize> {
+        match self.state {
+            EarlyDataState::Disabled => unreachable!(),
+            EarlyDataState::Ready | EarlyDataState::Accepted => {
+                let take = if self.left < sz {
+                    mem::replace(&mut self.left, 0)
+                } else {
+                    self.left -= sz;
+                    sz
+                };
+
+                Ok(take)
+            }
+            EarlyDataState::Rejected | EarlyDataState::AcceptedFinished => {
+                Err(io::Error::from(io::ErrorKind::InvalidInput))
+            }
+        }
+    }
+
+    fn bytes_left(&self) -> usize {
+        self.left
+    }
+}
+
+/// Stub that implements io::Write and dispatches to `write_early_data`.
+pub struct WriteEarlyData<'a> {
+    sess: &'a mut ClientSessionImpl,
+}
+
+impl<'a> WriteEarlyData<'a> {
+    fn new(sess: &'a mut ClientSessionImpl) -> WriteEarlyData<'a> {
+        WriteEarlyData { sess }
+    }
+
+    /// How many bytes you may send.  Writes will become short
+    /// once this reaches zero.
+    pub fn bytes_left(&self) -> usize {
+        self.sess.early_data.bytes_left()
+    }
+}
+
+impl<'a> io::Write for WriteEarlyData<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sess.write_early_data(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct ClientSessionImpl {
     pub config: Arc<ClientConfig>,
     pub alpn_protocol: Option<String>,
@@ -227,6 +646,8 @@ pub struct ClientSessionImpl {
     pub error: Option<TLSError>,
     pub state: Option<Box<hs::State + Send + Sync>>,
     pub server_cert_chain: CertificatePayload,
+    pub early_data: EarlyData,
+    pub psk_identity: Option<Vec<u8>>,
 }
 
 impl fmt::Debug for ClientSessionImpl {
@@ -236,26 +657,33 @@ impl fmt::Debug for ClientSessionImpl {
 }
 
 impl ClientSessionImpl {
-    pub fn new(config: &Arc<ClientConfig>, hostname: webpki::DNSName)
-               -> ClientSessionImpl {
-        let mut cs = ClientSessionImpl {
+    pub fn new(config: &Arc<ClientConfig>) -> ClientSessionImpl {
+        let mut common = SessionCommon::new(config.mtu, true);
+        common.record_padding = config.record_padding.clone();
+
+        ClientSessionImpl {
             config: config.clone(),
             alpn_protocol: None,
-            common: SessionCommon::new(config.mtu, true),
+            common: common,
             error: None,
             state: None,
             server_cert_chain: Vec::new(),
-        };
+            early_data: EarlyData::new(),
+            psk_identity: None,
+        }
+    }
 
-        cs.state = Some(hs::start_handshake(&mut cs, hostname));
-        cs
+    pub fn start_handshake(&mut self, server_name: ServerName) {
+        self.state = Some(hs::start_handshake(self, server_name));
     }
 
     pub fn get_cipher_suites(&self) -> Vec<CipherSuite> {
         let mut ret = Vec::new();
 
         for cs in &self.config.ciphersuites {
-            ret.push(cs.suite);
+            if self.have_kx_group_for(cs) {
+                ret.push(cs.suite);
+            }
         }
 
         // We don't do renegotation at all, in fact.
@@ -264,9 +692,32 @@ impl ClientSessionImpl {
         ret
     }
 
+    /// Our `kx_groups`, without any we can't do key exchange in.
+    pub fn get_kx_groups(&self) -> Vec<NamedGroup> {
+        self.config.kx_groups.iter()
+            .filter(|group| self.config.provider.find_kx_group(**group).is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Can we agree keys for `scs` in one of our `kx_groups`?  We
+    /// don't offer TLS1.2 suites which need groups we won't use.
+    fn have_kx_group_for(&self, scs: &SupportedCipherSuite) -> bool {
+        scs.usable_for_version(ProtocolVersion::TLSv1_3) ||
+            self.get_kx_groups().iter().any(|group| scs.usable_for_group(*group))
+    }
+
+    /// The hash function for external PSKs, if we have a suite
+    /// which can use them.
+    pub fn external_psk_hash(&self) -> Option<&'static Hash> {
+        self.config.ciphersuites.iter()
+            .find(|scs| scs.usable_for_external_psk())
+            .map(|scs| scs.get_hash())
+    }
+
     pub fn find_cipher_suite(&self, suite: CipherSuite) -> Option<&'static SupportedCipherSuite> {
         for scs in &self.config.ciphersuites {
-            if scs.suite == suite {
+            if scs.suite == suite && self.have_kx_group_for(scs) {
                 return Some(scs);
             }
         }
@@ -378,6 +829,32 @@ impl ClientSessionImpl {
         Ok(())
     }
 
+    /// Process handshake data `plaintext`, given to us by QUIC.
+    pub fn read_quic_hs(&mut self, plaintext: &[u8]) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        let msg = Message {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::new_opaque(plaintext.to_vec()),
+        };
+
+        let result = match self.common.handshake_joiner.take_message(msg) {
+            Some(_) => self.process_new_handshake_messages(),
+            None => {
+                self.common.send_fatal_alert(AlertDescription::DecodeError);
+                Err(TLSError::CorruptMessagePayload(ContentType::Handshake))
+            }
+        };
+
+        if let Err(ref err) = result {
+            self.error = Some(err.clone());
+        }
+        result
+    }
+
     pub fn process_new_packets(&mut self) -> Result<(), TLSError> {
         if let Some(ref err) = self.error {
             return Err(err.clone());
@@ -424,6 +901,16 @@ impl ClientSessionImpl {
     pub fn get_negotiated_ciphersuite(&self) -> Option<&'static SupportedCipherSuite> {
         self.common.get_suite()
     }
+
+    pub fn get_psk_identity(&self) -> Option<&[u8]> {
+        self.psk_identity.as_ref().map(|id| &id[..])
+    }
+
+    fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.early_data
+            .check_write(data.len())
+            .and_then(|sz| self.common.send_early_plaintext(&data[..sz]))
+    }
 }
 
 /// This represents a single TLS client session.
@@ -435,10 +922,79 @@ pub struct ClientSession {
 
 impl ClientSession {
     /// Make a new ClientSession.  `config` controls how
-    /// we behave in the TLS protocol, `hostname` is the
-    /// hostname of who we want to talk to.
-    pub fn new(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef) -> ClientSession {
-        ClientSession { imp: ClientSessionImpl::new(config, hostname.into()) }
+    /// we behave in the TLS protocol, `server_name` is the
+    /// DNS name or IP address of who we want to talk to.
+    pub fn new<N: Into<ServerName>>(config: &Arc<ClientConfig>, server_name: N) -> ClientSession {
+        let mut imp = ClientSessionImpl::new(config);
+        imp.start_handshake(server_name.into());
+        ClientSession { imp: imp }
+    }
+
+    /// Make a new ClientSession for QUIC, offering our transport
+    /// parameters `params`.  Rather than reading and writing TLS
+    /// records, use the `QuicExt` methods to exchange handshake
+    /// messages.
+    ///
+    /// # Panics
+    ///
+    /// QUIC requires TLS1.3: this panics if `config` allows any
+    /// other version.
+    pub fn new_quic<N: Into<ServerName>>(config: &Arc<ClientConfig>,
+                                         server_name: N,
+                                         params: Vec<u8>) -> ClientSession {
+        assert!(config.versions == [ProtocolVersion::TLSv1_3],
+                "QUIC requires TLS1.3 only");
+
+        let mut imp = ClientSessionImpl::new(config);
+        imp.common.protocol = Protocol::Quic;
+        imp.common.quic.params = params;
+        imp.start_handshake(server_name.into());
+        ClientSession { imp: imp }
+    }
+
+    /// Returns an `io::Write` implementer you can write bytes to
+    /// to send TLS1.3 early data (a.k.a. "0-RTT data") to the server.
+    ///
+    /// This returns None in many circumstances when the capability to
+    /// send early data is not available, including but not limited to:
+    ///
+    /// - The server hasn't been talked to previously.
+    /// - The server does not support resumption.
+    /// - The server does not support early data.
+    /// - The resumption data for the server has expired.
+    /// - `ClientConfig::enable_early_data` is false.
+    ///
+    /// The server specifies a maximum amount of early data.  You can
+    /// learn this limit through the returned object, and writes through
+    /// it will process only this many bytes.
+    ///
+    /// The server can choose not to accept any sent early data --
+    /// in this case the data is lost but the connection continues.  You
+    /// can tell this happened using `is_early_data_accepted`.
+    pub fn early_data(&mut self) -> Option<WriteEarlyData> {
+        if self.imp.early_data.is_enabled() {
+            Some(WriteEarlyData::new(&mut self.imp))
+        } else {
+            None
+        }
+    }
+
+    /// Returns True if the server signalled it will process early data.
+    ///
+    /// If you sent early data and this returns false at the end of the
+    /// handshake then the server will not process the data.  This
+    /// is not an error, but you may wish to resend the data.
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.imp.early_data.is_accepted()
+    }
+
+    /// Returns the identity of the external PSK the server accepted,
+    /// if the handshake was authenticated by one.
+    ///
+    /// This returns None before the handshake gets that far, and
+    /// for handshakes authenticated by certificate or resumption.
+    pub fn get_psk_identity(&self) -> Option<&[u8]> {
+        self.imp.get_psk_identity()
     }
 }
 
@@ -476,6 +1032,10 @@ impl Session for ClientSession {
         self.imp.common.send_close_notify()
     }
 
+    fn refresh_traffic_keys(&mut self, request_peer: bool) -> Result<(), TLSError> {
+        self.imp.common.refresh_traffic_keys(request_peer)
+    }
+
     fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
         self.imp.get_peer_certificates()
     }
@@ -500,6 +1060,24 @@ impl Session for ClientSession {
     }
 }
 
+impl QuicExt for ClientSession {
+    fn get_quic_transport_parameters(&self) -> Option<&[u8]> {
+        self.imp.common.quic.peer_params.as_ref().map(|params| &params[..])
+    }
+
+    fn read_hs(&mut self, plaintext: &[u8]) -> Result<(), TLSError> {
+        self.imp.read_quic_hs(plaintext)
+    }
+
+    fn write_hs(&mut self, buf: &mut Vec<u8>) -> Option<QuicSecrets> {
+        self.imp.common.quic.write_hs(buf)
+    }
+
+    fn get_alert(&self) -> Option<AlertDescription> {
+        self.imp.common.quic.alert
+    }
+}
+
 impl io::Read for ClientSession {
     /// Obtain plaintext data received from the peer over
     /// this TLS connection.
@@ -508,6 +1086,18 @@ impl io::Read for ClientSession {
     }
 }
 
+impl io::BufRead for ClientSession {
+    /// Obtain a chunk of plaintext data received from the peer
+    /// over this TLS connection, without copying it.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.imp.common.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.imp.common.consume(amt)
+    }
+}
+
 impl io::Write for ClientSession {
     /// Send the plaintext `buf` to the peer, encrypting
     /// and authenticating it.  Once this function succeeds
@@ -0,0 +1,235 @@
+use msgs::enums::CertificateCompressionAlgorithm;
+use msgs::handshake::{CertificatePayloadTLS13, CompressedCertificatePayload};
+use msgs::base::PayloadU24;
+use msgs::codec::{Codec, Reader};
+#[cfg(feature = "zlib")]
+use miniz_oxide::{deflate, inflate};
+#[cfg(feature = "zlib")]
+use miniz_oxide::inflate::core::{DecompressorOxide, inflate_flags};
+
+use std::sync::Arc;
+
+/// The largest uncompressed Certificate message we will decompress.
+/// That's a certificate list as large as we accept uncompressed,
+/// plus the largest context and their length prefixes.
+const MAX_UNCOMPRESSED_LEN: u32 = 0x10000 + 0xff + 1 + 3;
+
+/// This trait represents a way of compressing certificate chains
+/// for RFC8879 certificate compression.
+pub trait CertificateCompressor : Send + Sync {
+    /// The algorithm this implements.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+
+    /// Compress `input`.  If this fails the certificate chain is
+    /// sent uncompressed.
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, ()>;
+}
+
+/// This trait represents a way of decompressing certificate chains
+/// compressed by a peer, for RFC8879 certificate compression.
+pub trait CertificateDecompressor : Send + Sync {
+    /// The algorithm this implements.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+
+    /// Decompress `input` into `output`.  `output` is exactly as long
+    /// as the peer says the uncompressed data is: return an error if
+    /// the decompressed data would be any other length.
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), ()>;
+}
+
+/// How hard `ZlibCertificateCompression` tries: the zlib default.
+#[cfg(feature = "zlib")]
+const ZLIB_LEVEL: u8 = 6;
+
+/// Certificate compression using zlib, as implemented by the
+/// `miniz_oxide` crate.
+#[cfg(feature = "zlib")]
+pub struct ZlibCertificateCompression;
+
+#[cfg(feature = "zlib")]
+impl CertificateCompressor for ZlibCertificateCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::Zlib
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, ()> {
+        Ok(deflate::compress_to_vec_zlib(input, ZLIB_LEVEL))
+    }
+}
+
+#[cfg(feature = "zlib")]
+impl CertificateDecompressor for ZlibCertificateCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::Zlib
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), ()> {
+        // The output buffer is all there is: a stream which doesn't
+        // fit it exactly, or has trailing data, is an error.
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER |
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let mut state = Box::new(DecompressorOxide::new());
+        let (status, read, written) = inflate::core::decompress(&mut state, input,
+                                                                output, 0, flags);
+        match status {
+            inflate::TINFLStatus::Done if read == input.len() && written == output.len() => Ok(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The compressors a `ServerConfig` uses unless told otherwise.
+#[cfg(feature = "zlib")]
+pub fn default_compressors() -> Vec<Arc<CertificateCompressor>> {
+    let zlib: Arc<CertificateCompressor> = Arc::new(ZlibCertificateCompression);
+    vec![ zlib ]
+}
+
+#[cfg(not(feature = "zlib"))]
+pub fn default_compressors() -> Vec<Arc<CertificateCompressor>> {
+    Vec::new()
+}
+
+/// The decompressors a `ClientConfig` offers unless told otherwise.
+#[cfg(feature = "zlib")]
+pub fn default_decompressors() -> Vec<Arc<CertificateDecompressor>> {
+    let zlib: Arc<CertificateDecompressor> = Arc::new(ZlibCertificateCompression);
+    vec![ zlib ]
+}
+
+#[cfg(not(feature = "zlib"))]
+pub fn default_decompressors() -> Vec<Arc<CertificateDecompressor>> {
+    Vec::new()
+}
+
+/// Compress `cert` using `compressor`, or return None if that fails.
+pub fn compress_certificate(compressor: &CertificateCompressor,
+                            cert: &CertificatePayloadTLS13)
+                            -> Option<CompressedCertificatePayload> {
+    let mut uncompressed = Vec::new();
+    cert.encode(&mut uncompressed);
+
+    let compressed = try_ret!(compressor.compress(&uncompressed).ok());
+    debug!("Compressed certificate from {} to {} bytes",
+           uncompressed.len(), compressed.len());
+
+    Some(CompressedCertificatePayload {
+        alg: compressor.algorithm(),
+        uncompressed_len: uncompressed.len() as u32,
+        compressed: PayloadU24::new(compressed),
+    })
+}
+
+/// Decompress `compressed` using whichever of `decompressors` matches
+/// its algorithm.  Returns None if we don't support the algorithm,
+/// the claimed size is too large, or the result isn't a valid
+/// Certificate message.
+pub fn decompress_certificate(decompressors: &[Arc<CertificateDecompressor>],
+                              compressed: &CompressedCertificatePayload)
+                              -> Option<CertificatePayloadTLS13> {
+    let decompressor = try_ret!(decompressors.iter()
+                                .find(|d| d.algorithm() == compressed.alg));
+
+    if compressed.uncompressed_len > MAX_UNCOMPRESSED_LEN {
+        return None;
+    }
+
+    let mut uncompressed = vec![0u8; compressed.uncompressed_len as usize];
+    try_ret!(decompressor.decompress(&compressed.compressed.0, &mut uncompressed).ok());
+
+    let mut rd = Reader::init(&uncompressed);
+    let cert = try_ret!(CertificatePayloadTLS13::read(&mut rd));
+    if rd.any_left() {
+        return None;
+    }
+
+    Some(cert)
+}
+
+#[cfg(all(test, feature = "zlib"))]
+mod test {
+    use super::{CertificateCompressor, CertificateDecompressor, ZlibCertificateCompression};
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len() / 2)
+            .map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decompress(input: &[u8], output: &mut [u8]) -> Result<(), ()> {
+        ZlibCertificateCompression.decompress(input, output)
+    }
+
+    fn check_decompress(compressed: &[u8], expect: &[u8]) {
+        let mut out = vec![0u8; expect.len()];
+        decompress(compressed, &mut out).unwrap();
+        assert_eq!(out, expect.to_vec());
+    }
+
+    #[test]
+    fn zlib_decompress_fixed() {
+        check_decompress(&unhex("789ccb48cdc9c90700062c0215"), b"hello");
+    }
+
+    #[test]
+    fn zlib_decompress_stored() {
+        check_decompress(&unhex("7801010b00f4ff73746f72656420646174611ab2044c"),
+                         b"stored data");
+    }
+
+    #[test]
+    fn zlib_decompress_dynamic() {
+        let text = "subject issuer example issuer sign sign sign key rustls issuer sign \
+                    certificate key key certificate sign example rustls issuer authority \
+                    certificate certificate certificate certificate key rustls key \
+                    certificate rustls sign sign rustls authority rustls rustls sign \
+                    example certificate key issuer subject example issuer authority key \
+                    rustls example example sign key certificate sign rustls key key \
+                    subject authority authority issuer sign";
+        let compressed = unhex("78da8d505b0e80200cbb0a57433275bec346a2b73718e606fef8018ca65bbb\
+                                52ea2608ec9028417470faf55840be84c366ae192e1713f14215214064ec31\
+                                788687928fc51e924caefb7de2718fc875c39fda7869f50aacbe0ba06205b0\
+                                44f1d76ac8a225a726201d69fc0847de37bb4f2866817c4444a76a6502bf01\
+                                d9c8a7a8");
+        check_decompress(&compressed, text.as_bytes());
+    }
+
+    #[test]
+    fn zlib_decompress_refuses_wrong_size() {
+        let compressed = unhex("789ccb48cdc9c90700062c0215");
+        assert!(decompress(&compressed, &mut [0u8; 4]).is_err());
+        assert!(decompress(&compressed, &mut [0u8; 6]).is_err());
+    }
+
+    #[test]
+    fn zlib_decompress_refuses_corruption() {
+        let compressed = unhex("789ccb48cdc9c90700062c0215");
+        let mut out = [0u8; 5];
+
+        for i in 0..compressed.len() {
+            let mut bad = compressed.clone();
+            bad[i] ^= 0x01;
+            assert!(decompress(&bad, &mut out).is_err());
+        }
+
+        assert!(decompress(&compressed[..compressed.len() - 1], &mut out).is_err());
+
+        let mut trailing = compressed.clone();
+        trailing.push(0);
+        assert!(decompress(&trailing, &mut out).is_err());
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut input = Vec::new();
+        for i in 0..20000u32 {
+            input.push((i % 251) as u8 ^ (i / 1000) as u8);
+        }
+        input.extend_from_slice(&[0u8; 1000]);
+
+        for len in &[0, 1, 2, 3, 100, input.len()] {
+            let compressed = ZlibCertificateCompression.compress(&input[..*len]).unwrap();
+            check_decompress(&compressed, &input[..*len]);
+        }
+    }
+}
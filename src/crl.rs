@@ -0,0 +1,354 @@
+// Certificate revocation lists, as in RFC5280 section 5, and
+// checking certificate chains against them.  Only chains webpki
+// accepts are checked, so each CRL's issuer is one which really
+// issued the certificate.
+//
+// Only complete CRLs issued directly by the certificate's issuer are
+// supported.  Any CRL with a critical extension (eg. one which is a
+// delta CRL, or only covers some certificates) is refused.
+
+use ring::der;
+use ring::error::Unspecified;
+use untrusted;
+use webpki;
+
+use anchors::RootCertStore;
+use chain::{self, ChainCert, Usage};
+use error::TLSError;
+use key::Certificate;
+use pemfile;
+use x509;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A certificate revocation list: the serial numbers of certificates
+/// its issuer has revoked.
+///
+/// Its signature is not checked until it is used, because that needs
+/// the certificate of its issuer.
+#[derive(Debug, Clone)]
+pub struct CertRevocationList {
+    issuer: Vec<u8>,
+    this_update: u64,
+    next_update: Option<u64>,
+    revoked_serials: Vec<Vec<u8>>,
+    signed_data: Vec<u8>,
+    signature_alg: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl CertRevocationList {
+    /// Parse a DER-encoded CRL.
+    pub fn from_der(der: &[u8]) -> Result<CertRevocationList, TLSError> {
+        untrusted::Input::from(der)
+            .read_all(Unspecified, |rd| {
+                der::nested(rd, der::Tag::Sequence, Unspecified, read_crl)
+            })
+            .map_err(|_| TLSError::InvalidCertRevocationList)
+    }
+
+    fn is_revoked(&self, serial: &[u8]) -> bool {
+        self.revoked_serials
+            .iter()
+            .any(|revoked| revoked.as_slice() == serial)
+    }
+
+    fn verify_signature(&self, spki: untrusted::Input) -> Result<(), Unspecified> {
+        x509::verify_signed_data(spki,
+                                 &self.signature_alg,
+                                 untrusted::Input::from(&self.signed_data),
+                                 untrusted::Input::from(&self.signature))
+    }
+}
+
+fn read_crl(crl: &mut untrusted::Reader) -> Result<CertRevocationList, Unspecified> {
+    let start = crl.mark();
+    let tbs = der::expect_tag_and_get_value(crl, der::Tag::Sequence)?;
+    let signed_data = crl.get_input_between_marks(start, crl.mark())?;
+
+    let signature_alg = x509::read_algorithm_oid(crl)?;
+    let signature = der::bit_string_with_no_unused_bits(crl)?;
+
+    let mut ret = tbs.read_all(Unspecified, read_tbs_cert_list)?;
+
+    // The signature algorithm must match the one in the signed data.
+    if ret.signature_alg != signature_alg.as_slice_less_safe() {
+        return Err(Unspecified);
+    }
+
+    ret.signed_data = signed_data.as_slice_less_safe().to_vec();
+    ret.signature = signature.as_slice_less_safe().to_vec();
+    Ok(ret)
+}
+
+fn read_tbs_cert_list(tbs: &mut untrusted::Reader) -> Result<CertRevocationList, Unspecified> {
+    // version is optional, and only v2 is allowed if present
+    if tbs.peek(der::Tag::Integer as u8) && der::small_nonnegative_integer(tbs)? != 1 {
+        return Err(Unspecified);
+    }
+
+    let signature_alg = x509::read_algorithm_oid(tbs)?;
+    let issuer = der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?;
+    let this_update = x509::read_time(tbs)?;
+    let next_update = if tbs.peek(der::Tag::UTCTime as u8) ||
+                         tbs.peek(der::Tag::GeneralizedTime as u8) {
+        Some(x509::read_time(tbs)?)
+    } else {
+        None
+    };
+
+    let mut revoked_serials = Vec::new();
+    if tbs.peek(der::Tag::Sequence as u8) {
+        der::nested(tbs, der::Tag::Sequence, Unspecified, |revoked| {
+            while !revoked.at_end() {
+                let serial = der::nested(revoked, der::Tag::Sequence, Unspecified, |entry| {
+                    let serial = der::expect_tag_and_get_value(entry, der::Tag::Integer)?;
+                    x509::read_time(entry)?; // revocationDate
+                    if !entry.at_end() {
+                        der::nested(entry, der::Tag::Sequence, Unspecified, x509::check_extensions)?;
+                    }
+                    Ok(serial)
+                })?;
+                revoked_serials.push(serial.as_slice_less_safe().to_vec());
+            }
+            Ok(())
+        })?;
+    }
+
+    // crlExtensions are explicitly tagged [0]
+    if tbs.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+        der::nested(tbs, der::Tag::ContextSpecificConstructed0, Unspecified, |exts| {
+            der::nested(exts, der::Tag::Sequence, Unspecified, x509::check_extensions)
+        })?;
+    }
+
+    Ok(CertRevocationList {
+        issuer: issuer.as_slice_less_safe().to_vec(),
+        this_update: this_update,
+        next_update: next_update,
+        revoked_serials: revoked_serials,
+        signed_data: Vec::new(),
+        signature_alg: signature_alg.as_slice_less_safe().to_vec(),
+        signature: Vec::new(),
+    })
+}
+
+/// What to do with a certificate whose revocation status we
+/// can't find out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CRLPolicy {
+    /// Accept certificates whose issuer has no CRL in the store.
+    /// The default is false.
+    pub allow_missing: bool,
+
+    /// Accept certificates whose issuer's CRL is past its
+    /// `nextUpdate` time, unless the CRL lists them.
+    /// The default is false.
+    pub allow_expired: bool,
+}
+
+/// A set of CRLs, with at most one for each issuer.
+///
+/// The CRLs may be changed at any time, including while sessions
+/// are using them: share a `CRLStore` with the configs using it
+/// and update it in place.  Each certificate check sees either
+/// the old or the new CRLs.
+#[derive(Debug)]
+pub struct CRLStore {
+    crls: Mutex<Vec<Arc<CertRevocationList>>>,
+}
+
+impl CRLStore {
+    /// Make a new, empty `CRLStore`.
+    pub fn empty() -> CRLStore {
+        CRLStore { crls: Mutex::new(Vec::new()) }
+    }
+
+    /// Say how many CRLs are in the store.
+    pub fn len(&self) -> usize {
+        self.crls.lock().unwrap().len()
+    }
+
+    /// Return true if there are no CRLs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add `crl`, replacing any CRL from the same issuer.
+    pub fn add(&self, crl: CertRevocationList) {
+        let mut crls = self.crls.lock().unwrap();
+        crls.retain(|existing| existing.issuer != crl.issuer);
+        crls.push(Arc::new(crl));
+    }
+
+    /// Parse and add a single DER-encoded CRL, replacing any CRL
+    /// from the same issuer.
+    pub fn add_der(&self, der: &[u8]) -> Result<(), TLSError> {
+        let crl = CertRevocationList::from_der(der)?;
+        self.add(crl);
+        Ok(())
+    }
+
+    /// Parse a PEM file and add all the CRLs found inside, replacing
+    /// any CRLs from the same issuers.  Errors are non-specific; they
+    /// may be io errors in `rd` and PEM format errors.
+    ///
+    /// Returns the number of CRLs added, and the number which were
+    /// extracted from the PEM but could not be parsed.
+    pub fn add_pem_file(&self, rd: &mut io::BufRead) -> Result<(usize, usize), ()> {
+        let ders = pemfile::crls(rd)?;
+        let mut valid_count = 0;
+        let mut invalid_count = 0;
+
+        for der in ders {
+            match self.add_der(&der) {
+                Ok(_) => valid_count += 1,
+                Err(err) => {
+                    trace!("invalid crl der {:?}", der);
+                    debug!("crl parsing failed: {:?}", err);
+                    invalid_count += 1
+                }
+            }
+        }
+
+        debug!("add_pem_file processed {} valid and {} invalid crls",
+               valid_count, invalid_count);
+
+        Ok((valid_count, invalid_count))
+    }
+
+    /// Replace all the CRLs in the store with `crls`.
+    pub fn replace_all(&self, crls: Vec<CertRevocationList>) {
+        let crls = crls.into_iter()
+            .map(Arc::new)
+            .collect();
+        *self.crls.lock().unwrap() = crls;
+    }
+
+    fn find(&self, issuer: &[u8]) -> Option<Arc<CertRevocationList>> {
+        self.crls.lock()
+            .unwrap()
+            .iter()
+            .find(|crl| crl.issuer.as_slice() == issuer)
+            .cloned()
+    }
+}
+
+/// Checks certificate chains against the CRLs in a `CRLStore`.
+#[derive(Clone)]
+pub struct CRLChecker {
+    crls: Arc<CRLStore>,
+    policy: CRLPolicy,
+}
+
+impl CRLChecker {
+    pub fn new(crls: Arc<CRLStore>, policy: CRLPolicy) -> CRLChecker {
+        CRLChecker {
+            crls: crls,
+            policy: policy,
+        }
+    }
+
+    /// Check every certificate on each chain from the end-entity
+    /// certificate in `presented_certs` to a trust anchor in `roots`
+    /// which webpki accepts for `usage`.  Certificates which aren't on
+    /// such a chain are ignored, however they are named.
+    pub fn check(&self,
+                 roots: &RootCertStore,
+                 presented_certs: &[Certificate],
+                 usage: Usage) -> Result<(), TLSError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| TLSError::FailedToGetCurrentTime)?
+            .as_secs();
+
+        let chains = chain::validated_chains(roots,
+                                             presented_certs,
+                                             usage,
+                                             webpki::Time::from_seconds_since_unix_epoch(now))?;
+        if chains.is_empty() {
+            return Err(TLSError::UnknownRevocationStatus("chain does not reach a trust anchor"
+                                                         .to_string()));
+        }
+
+        // We don't know which chain webpki used, so all of them
+        // must pass.
+        for chain in &chains {
+            for pair in chain.windows(2) {
+                self.check_cert(&pair[0], &pair[1], now)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_cert(&self,
+                  cert: &ChainCert,
+                  issuer: &ChainCert,
+                  now: u64) -> Result<(), TLSError> {
+        let crl = match self.crls.find(&issuer.subject) {
+            Some(crl) => crl,
+            None if self.policy.allow_missing => return Ok(()),
+            None => return Err(TLSError::UnknownRevocationStatus("no CRL for issuer".to_string())),
+        };
+
+        crl.verify_signature(untrusted::Input::from(&issuer.spki))
+            .map_err(|_| TLSError::UnknownRevocationStatus("CRL has invalid signature"
+                                                           .to_string()))?;
+
+        if crl.this_update > now {
+            return Err(TLSError::UnknownRevocationStatus("CRL is not yet valid".to_string()));
+        }
+
+        if crl.is_revoked(cert.serial.as_ref().unwrap()) {
+            return Err(TLSError::CertRevoked);
+        }
+
+        match crl.next_update {
+            Some(next_update) if next_update < now && !self.policy.allow_expired => {
+                Err(TLSError::UnknownRevocationStatus("CRL has expired".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load(name: &str) -> CertRevocationList {
+        let path = format!("test-ca/rsa/{}", name);
+        let mut rd = io::BufReader::new(::std::fs::File::open(path).unwrap());
+        let ders = pemfile::crls(&mut rd).unwrap();
+        assert_eq!(ders.len(), 1);
+        CertRevocationList::from_der(&ders[0]).unwrap()
+    }
+
+    #[test]
+    fn parses_revoked_serials() {
+        let crl = load("inter.revoked.crl");
+        assert!(crl.is_revoked(&[0x01, 0xc8]));
+        assert!(crl.is_revoked(&[0x03, 0x15]));
+        assert!(!crl.is_revoked(&[0x7b]));
+
+        // 2019-01-01 to 2049-01-01
+        assert_eq!(crl.this_update, 1546300800);
+        assert_eq!(crl.next_update, Some(2493072000));
+    }
+
+    #[test]
+    fn parses_empty_crl() {
+        let crl = load("inter.crl");
+        assert!(crl.revoked_serials.is_empty());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let crl = load("ca.crl");
+        assert!(CertRevocationList::from_der(&[]).is_err());
+        assert!(CertRevocationList::from_der(&crl.signed_data).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+// The cryptography rustls needs, as traits.  A `CryptoProvider`
+// supplies all of it; `ring::RingProvider` is the default.
+use msgs::enums::{HashAlgorithm, NamedGroup, SignatureScheme};
+use suites::{BulkAlgorithm, SupportedCipherSuite};
+use key::Certificate;
+use error::TLSError;
+
+/// The default provider, using *ring* and webpki.
+pub mod ring;
+
+/// A source of all the cryptography used by a session.
+///
+/// A provider's `SupportedCipherSuite`s carry its `Hash` and `Aead`
+/// implementations, so those are used whenever one of its suites is.
+/// The other methods provide everything else.
+///
+/// Certificate chain validation is not done here: it has its own
+/// interfaces in `ServerCertVerifier` and `ClientCertVerifier`.
+/// Session tickets are encrypted by a `ProducesTickets`: use
+/// `Ticketer::with_provider` to get one which uses a provider.
+///
+/// These still use *ring* directly, whatever the provider:
+///
+/// - our own signatures, made by a `sign::SigningKey`;
+/// - checking signatures on CRLs and OCSP responses, and hashing
+///   public keys for `set_spki_pins`;
+/// - HPKE for Encrypted Client Hello, apart from its randomness;
+/// - constant-time comparison of Finished messages and PSK binders.
+pub trait CryptoProvider : Send + Sync {
+    /// The cipher suites this provider implements, most preferred
+    /// first.  These become the default `ciphersuites` of configs
+    /// made with this provider.
+    fn cipher_suites(&self) -> Vec<&'static SupportedCipherSuite>;
+
+    /// The key exchange groups this provider implements, in no
+    /// particular order.  Configs' `kx_groups` decide which are
+    /// used, and in which order.
+    fn kx_groups(&self) -> Vec<&'static SupportedKxGroup>;
+
+    /// How to verify our peer's handshake signatures.
+    fn signature_verifier(&self) -> &SignatureVerifier;
+
+    /// Where to get random material.
+    fn secure_random(&self) -> &SecureRandom;
+
+    /// Find our implementation of `group`, if there is one.
+    fn find_kx_group(&self, group: NamedGroup) -> Option<&'static SupportedKxGroup> {
+        self.kx_groups()
+            .into_iter()
+            .find(|kx| kx.name() == group)
+    }
+}
+
+/// A hash function, and HMAC using it.
+pub trait Hash : Send + Sync {
+    /// Which hash function this is.
+    fn algorithm(&self) -> HashAlgorithm;
+
+    /// The length of this hash function's output, in bytes.
+    fn output_len(&self) -> usize;
+
+    /// Start an incremental hash computation.
+    fn start(&self) -> Box<HashContext>;
+
+    /// Compute HMAC, keyed with `key`, over the concatenation of
+    /// the slices in `data`.
+    fn hmac_sign(&self, key: &[u8], data: &[&[u8]]) -> Vec<u8>;
+
+    /// Hash `data` in one go.
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut ctx = self.start();
+        ctx.update(data);
+        ctx.finish()
+    }
+}
+
+/// An incremental hash computation.
+pub trait HashContext : Send + Sync {
+    /// Add `data` to the input.
+    fn update(&mut self, data: &[u8]);
+
+    /// Copy this computation, so the copy can be finished or
+    /// continued separately.
+    fn fork(&self) -> Box<HashContext>;
+
+    /// Finish the computation, returning the hash of all the input.
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+/// An AEAD algorithm, with 96-bit nonces.
+pub trait Aead : Send + Sync {
+    /// Which algorithm this is.
+    fn algorithm(&self) -> BulkAlgorithm;
+
+    /// The length of this algorithm's authentication tags, in bytes.
+    fn tag_len(&self) -> usize;
+
+    /// Make a key for this algorithm from `key`, which is as long
+    /// as the cipher suite's `enc_key_len`.
+    fn new_key(&self, key: &[u8]) -> Box<AeadKey>;
+}
+
+/// An AEAD key, which can both encrypt and decrypt.
+pub trait AeadKey : Send + Sync {
+    /// Encrypt and authenticate all but the last `tag_len` bytes of
+    /// `in_out`, using `nonce` and additional data `aad`.  The tag
+    /// is written over those last bytes.  Returns the length of the
+    /// result, which is the length of `in_out`.
+    fn seal_in_place(&self,
+                     nonce: &[u8],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError>;
+
+    /// Authenticate and decrypt `in_out`, which is a ciphertext
+    /// followed by its tag, using `nonce` and additional data `aad`.
+    /// The plaintext is written at the start of `in_out`, and its
+    /// length returned.
+    fn open_in_place(&self,
+                     nonce: &[u8],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError>;
+}
+
+/// A key exchange group we implement: an elliptic curve or a
+/// finite field group.
+pub trait SupportedKxGroup : Send + Sync {
+    /// The TLS name of this group.
+    fn name(&self) -> NamedGroup;
+
+    /// Generate an ephemeral key pair using `rng`, starting a key
+    /// exchange.  `rng` is the provider's `secure_random`.
+    fn start(&self, rng: &SecureRandom) -> Result<Box<ActiveKeyExchange>, TLSError>;
+}
+
+/// Our half of a key exchange that is in progress.
+pub trait ActiveKeyExchange : Send + Sync {
+    /// The group this key exchange is in.
+    fn group(&self) -> NamedGroup;
+
+    /// Our public key, encoded as TLS1.3 key shares need it.
+    /// Finite field public values are padded to the length of
+    /// the prime.
+    fn pub_key(&self) -> &[u8];
+
+    /// Agree a shared secret with the peer's public key `peer`,
+    /// which is encoded like ours.  Returns None if `peer` is
+    /// not acceptable.
+    fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Verification of the signatures our peer makes during the
+/// handshake, using the public key in its (already verified)
+/// end-entity certificate.
+pub trait SignatureVerifier : Send + Sync {
+    /// The signature schemes we can verify, most preferred first.
+    /// These are offered to the peer.
+    fn supported_schemes(&self) -> Vec<SignatureScheme>;
+
+    /// Verify a TLS1.2 `signature` over `message` using `scheme` and
+    /// the key in `cert`.  In TLS1.2, ECDSA schemes don't fix the curve.
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError>;
+
+    /// Verify a TLS1.3 `signature` over `message` using `scheme` and
+    /// the key in `cert`.
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError>;
+}
+
+/// A cryptographically secure random number generator.
+pub trait SecureRandom : Send + Sync {
+    /// Fill all of `bytes` with random material.
+    fn fill(&self, bytes: &mut [u8]) -> Result<(), TLSError>;
+}
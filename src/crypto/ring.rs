@@ -0,0 +1,392 @@
+// The default `CryptoProvider`, using *ring* for hashing, AEADs,
+// ECDHE and randomness, and webpki for signature verification.
+// The RFC7919 groups use our own `ffdhe` module.
+use ring;
+use ring::{aead, agreement, digest, hmac};
+use ring::rand::SecureRandom as RingSecureRandom;
+use webpki;
+use untrusted;
+
+use crypto;
+use ffdhe;
+use key::Certificate;
+use msgs::enums::{HashAlgorithm, NamedGroup, SignatureScheme};
+use msgs::handshake::{SupportedSignatureSchemes, SupportedMandatedSignatureSchemes};
+use suites::{BulkAlgorithm, SupportedCipherSuite, ALL_CIPHERSUITES};
+use error::TLSError;
+use x509;
+
+/// The default `CryptoProvider`.  Its cipher suites are those in
+/// `ALL_CIPHERSUITES`.  It does key exchange with X25519, P-256,
+/// P-384 and the RFC7919 groups.
+pub struct RingProvider;
+
+impl crypto::CryptoProvider for RingProvider {
+    fn cipher_suites(&self) -> Vec<&'static SupportedCipherSuite> {
+        ALL_CIPHERSUITES.to_vec()
+    }
+
+    fn kx_groups(&self) -> Vec<&'static crypto::SupportedKxGroup> {
+        let mut groups: Vec<&'static crypto::SupportedKxGroup> = vec![&X25519, &SECP256R1, &SECP384R1];
+        for group in ffdhe::GROUPS.iter() {
+            groups.push(group);
+        }
+        groups
+    }
+
+    fn signature_verifier(&self) -> &crypto::SignatureVerifier {
+        &WebPKISignatureVerifier
+    }
+
+    fn secure_random(&self) -> &crypto::SecureRandom {
+        &RingRandom
+    }
+}
+
+/// A hash function implemented by *ring*.
+pub struct RingHash {
+    alg: &'static digest::Algorithm,
+    name: HashAlgorithm,
+}
+
+/// SHA-256.
+pub static SHA256: RingHash = RingHash { alg: &digest::SHA256, name: HashAlgorithm::SHA256 };
+
+/// SHA-384.
+pub static SHA384: RingHash = RingHash { alg: &digest::SHA384, name: HashAlgorithm::SHA384 };
+
+/// SHA-512.
+pub static SHA512: RingHash = RingHash { alg: &digest::SHA512, name: HashAlgorithm::SHA512 };
+
+struct RingHashContext(digest::Context);
+
+impl crypto::Hash for RingHash {
+    fn algorithm(&self) -> HashAlgorithm {
+        self.name
+    }
+
+    fn output_len(&self) -> usize {
+        self.alg.output_len
+    }
+
+    fn start(&self) -> Box<crypto::HashContext> {
+        Box::new(RingHashContext(digest::Context::new(self.alg)))
+    }
+
+    fn hmac_sign(&self, key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+        let key = hmac::SigningKey::new(self.alg, key);
+        let mut ctx = hmac::SigningContext::with_key(&key);
+        for d in data {
+            ctx.update(d);
+        }
+        ctx.sign().as_ref().to_vec()
+    }
+}
+
+impl crypto::HashContext for RingHashContext {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn fork(&self) -> Box<crypto::HashContext> {
+        Box::new(RingHashContext(self.0.clone()))
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+}
+
+/// An AEAD algorithm implemented by *ring*.
+pub struct RingAead {
+    alg: &'static aead::Algorithm,
+    name: BulkAlgorithm,
+}
+
+/// AES-128 in GCM mode.
+pub static AES_128_GCM: RingAead = RingAead {
+    alg: &aead::AES_128_GCM,
+    name: BulkAlgorithm::AES_128_GCM,
+};
+
+/// AES-256 in GCM mode.
+pub static AES_256_GCM: RingAead = RingAead {
+    alg: &aead::AES_256_GCM,
+    name: BulkAlgorithm::AES_256_GCM,
+};
+
+/// The RFC7539 ChaCha20-Poly1305 construction.
+pub static CHACHA20_POLY1305: RingAead = RingAead {
+    alg: &aead::CHACHA20_POLY1305,
+    name: BulkAlgorithm::CHACHA20_POLY1305,
+};
+
+struct RingAeadKey {
+    sealing: aead::SealingKey,
+    opening: aead::OpeningKey,
+    tag_len: usize,
+}
+
+impl crypto::Aead for RingAead {
+    fn algorithm(&self) -> BulkAlgorithm {
+        self.name
+    }
+
+    fn tag_len(&self) -> usize {
+        self.alg.tag_len()
+    }
+
+    fn new_key(&self, key: &[u8]) -> Box<crypto::AeadKey> {
+        Box::new(RingAeadKey {
+            sealing: aead::SealingKey::new(self.alg, key).unwrap(),
+            opening: aead::OpeningKey::new(self.alg, key).unwrap(),
+            tag_len: self.alg.tag_len(),
+        })
+    }
+}
+
+impl crypto::AeadKey for RingAeadKey {
+    fn seal_in_place(&self,
+                     nonce: &[u8],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError> {
+        aead::seal_in_place(&self.sealing, nonce, aad, in_out, self.tag_len)
+            .map_err(|_| TLSError::General("encrypt failed".to_string()))
+    }
+
+    fn open_in_place(&self,
+                     nonce: &[u8],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError> {
+        aead::open_in_place(&self.opening, nonce, aad, 0, in_out)
+            .map(|plain| plain.len())
+            .map_err(|_| TLSError::DecryptError)
+    }
+}
+
+/// An elliptic curve Diffie-Hellman group implemented by *ring*.
+///
+/// *ring* only generates these keys with its own random number
+/// generator, so `start` ignores its `rng`.
+pub struct RingKxGroup {
+    name: NamedGroup,
+    alg: &'static agreement::Algorithm,
+}
+
+/// X25519 (RFC7748).
+pub static X25519: RingKxGroup = RingKxGroup {
+    name: NamedGroup::X25519,
+    alg: &agreement::X25519,
+};
+
+/// ECDH on NIST P-256.
+pub static SECP256R1: RingKxGroup = RingKxGroup {
+    name: NamedGroup::secp256r1,
+    alg: &agreement::ECDH_P256,
+};
+
+/// ECDH on NIST P-384.
+pub static SECP384R1: RingKxGroup = RingKxGroup {
+    name: NamedGroup::secp384r1,
+    alg: &agreement::ECDH_P384,
+};
+
+struct RingKeyExchange {
+    name: NamedGroup,
+    alg: &'static agreement::Algorithm,
+    privkey: agreement::EphemeralPrivateKey,
+    pubkey: Vec<u8>,
+}
+
+impl crypto::SupportedKxGroup for RingKxGroup {
+    fn name(&self) -> NamedGroup {
+        self.name
+    }
+
+    fn start(&self, _rng: &crypto::SecureRandom) -> Result<Box<crypto::ActiveKeyExchange>, TLSError> {
+        let rng = ring::rand::SystemRandom::new();
+        let privkey = agreement::EphemeralPrivateKey::generate(self.alg, &rng)
+            .map_err(|_| TLSError::General("key generation failed".to_string()))?;
+
+        let mut pubkey = Vec::new();
+        pubkey.resize(privkey.public_key_len(), 0u8);
+        privkey.compute_public_key(pubkey.as_mut_slice()).unwrap();
+
+        Ok(Box::new(RingKeyExchange {
+            name: self.name,
+            alg: self.alg,
+            privkey: privkey,
+            pubkey: pubkey,
+        }))
+    }
+}
+
+impl crypto::ActiveKeyExchange for RingKeyExchange {
+    fn group(&self) -> NamedGroup {
+        self.name
+    }
+
+    fn pub_key(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>> {
+        agreement::agree_ephemeral(self.privkey,
+                                   self.alg,
+                                   untrusted::Input::from(peer),
+                                   (),
+                                   |v| Ok(v.to_vec()))
+            .ok()
+    }
+}
+
+/// Randomness from the operating system, via *ring*.
+pub struct RingRandom;
+
+impl crypto::SecureRandom for RingRandom {
+    fn fill(&self, bytes: &mut [u8]) -> Result<(), TLSError> {
+        ring::rand::SystemRandom::new()
+            .fill(bytes)
+            .map_err(|_| TLSError::General("no random material available".to_string()))
+    }
+}
+
+type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
+
+static ECDSA_SHA256: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
+                                             &webpki::ECDSA_P384_SHA256];
+static ECDSA_SHA384: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA384,
+                                             &webpki::ECDSA_P384_SHA384];
+
+static RSA_SHA1: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA1];
+static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
+static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
+static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
+static RSA_PSS_SHA256: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY];
+static RSA_PSS_SHA384: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY];
+static RSA_PSS_SHA512: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY];
+
+fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, TLSError> {
+    match scheme {
+        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
+        SignatureScheme::ECDSA_NISTP256_SHA256 => Ok(ECDSA_SHA256),
+        SignatureScheme::ECDSA_NISTP384_SHA384 => Ok(ECDSA_SHA384),
+
+        SignatureScheme::RSA_PKCS1_SHA1 => Ok(RSA_SHA1),
+        SignatureScheme::RSA_PKCS1_SHA256 => Ok(RSA_SHA256),
+        SignatureScheme::RSA_PKCS1_SHA384 => Ok(RSA_SHA384),
+        SignatureScheme::RSA_PKCS1_SHA512 => Ok(RSA_SHA512),
+
+        SignatureScheme::RSA_PSS_SHA256 => Ok(RSA_PSS_SHA256),
+        SignatureScheme::RSA_PSS_SHA384 => Ok(RSA_PSS_SHA384),
+        SignatureScheme::RSA_PSS_SHA512 => Ok(RSA_PSS_SHA512),
+
+        _ => {
+            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
+            Err(TLSError::PeerMisbehavedError(error_msg))
+        }
+    }
+}
+
+fn verify_sig_using_any_alg(cert: &webpki::EndEntityCert,
+                            algs: SignatureAlgorithms,
+                            message: &[u8],
+                            sig: &[u8])
+                            -> Result<(), webpki::Error> {
+    // TLS doesn't itself give us enough info to map to a single webpki::SignatureAlgorithm.
+    // Therefore, convert_algs maps to several and we try them all.
+    for alg in algs {
+        match cert.verify_signature(alg,
+                                    untrusted::Input::from(message),
+                                    untrusted::Input::from(sig)) {
+            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
+            res => return res,
+        }
+    }
+
+    Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
+}
+
+/// Verify an Ed25519 `sig` over `message` using the public key quoted
+/// in `cert`.  webpki doesn't support Ed25519, so we find the key
+/// ourselves and use ring directly.
+fn verify_ed25519(message: &[u8],
+                  cert: &Certificate,
+                  sig: &[u8]) -> Result<(), TLSError> {
+    let cert_in = untrusted::Input::from(&cert.0);
+    webpki::EndEntityCert::from(cert_in)
+        .map_err(TLSError::WebPKIError)?;
+
+    let public_key = x509::ed25519_public_key(cert_in)
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey))?;
+
+    ring::signature::verify(&ring::signature::ED25519,
+                            public_key,
+                            untrusted::Input::from(message),
+                            untrusted::Input::from(sig))
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::InvalidSignatureForPublicKey))
+}
+
+fn convert_alg_tls13(scheme: SignatureScheme)
+                     -> Result<&'static webpki::SignatureAlgorithm, TLSError> {
+    use msgs::enums::SignatureScheme::*;
+
+    match scheme {
+        ECDSA_NISTP256_SHA256 => Ok(&webpki::ECDSA_P256_SHA256),
+        ECDSA_NISTP384_SHA384 => Ok(&webpki::ECDSA_P384_SHA384),
+        RSA_PSS_SHA256 => Ok(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
+        RSA_PSS_SHA384 => Ok(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
+        RSA_PSS_SHA512 => Ok(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
+        _ => {
+            let error_msg = format!("received unsupported sig scheme {:?}", scheme);
+            Err(TLSError::PeerMisbehavedError(error_msg))
+        }
+    }
+}
+
+struct WebPKISignatureVerifier;
+
+impl crypto::SignatureVerifier for WebPKISignatureVerifier {
+    fn supported_schemes(&self) -> Vec<SignatureScheme> {
+        SupportedSignatureSchemes::supported_verify()
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError> {
+        if scheme == SignatureScheme::ED25519 {
+            return verify_ed25519(message, cert, signature);
+        }
+
+        let possible_algs = convert_scheme(scheme)?;
+        let cert_in = untrusted::Input::from(&cert.0);
+        let cert = webpki::EndEntityCert::from(cert_in)
+            .map_err(TLSError::WebPKIError)?;
+
+        verify_sig_using_any_alg(&cert, possible_algs, message, signature)
+            .map_err(TLSError::WebPKIError)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError> {
+        if scheme == SignatureScheme::ED25519 {
+            return verify_ed25519(message, cert, signature);
+        }
+
+        let alg = convert_alg_tls13(scheme)?;
+
+        let cert_in = untrusted::Input::from(&cert.0);
+        let cert = webpki::EndEntityCert::from(cert_in)
+            .map_err(TLSError::WebPKIError)?;
+
+        cert.verify_signature(alg,
+                              untrusted::Input::from(message),
+                              untrusted::Input::from(signature))
+            .map_err(TLSError::WebPKIError)
+    }
+}
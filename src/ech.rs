@@ -0,0 +1,479 @@
+use msgs::base::PayloadU16;
+use msgs::codec::Codec;
+use msgs::enums::{ExtensionType, ProtocolVersion};
+use msgs::handshake::{ClientExtension, ClientHelloPayload, ConvertServerNameList};
+use msgs::handshake::{ECHConfig, ECHConfigContents, ECHConfigList, ECHConfigPayload};
+use msgs::handshake::{ECHClientHelloOuter, EncryptedClientHello};
+#[cfg(feature = "ech")]
+use msgs::base::PayloadU8;
+#[cfg(feature = "ech")]
+use msgs::codec::{self, Reader};
+#[cfg(feature = "ech")]
+use msgs::enums::ECHVersion;
+#[cfg(feature = "ech")]
+use msgs::handshake::HpkeKeyConfig;
+use msgs::handshake::{HpkeSymmetricCipherSuite, SessionID};
+use msgs::message::{Message, MessagePayload};
+#[cfg(feature = "ech")]
+use error::TLSError;
+use hash_hs::HandshakeHash;
+use key_schedule;
+use hpke;
+use rand;
+use crypto::{Hash, SecureRandom};
+
+use untrusted;
+use webpki;
+
+#[cfg(feature = "ech")]
+use std::mem;
+
+/// Label for the confirmation in the ServerHello random.
+pub const ACCEPT_CONFIRMATION_LABEL: &[u8] = b"ech accept confirmation";
+
+/// Label for the confirmation in a HelloRetryRequest extension.
+pub const HRR_ACCEPT_CONFIRMATION_LABEL: &[u8] = b"hrr ech accept confirmation";
+
+/// The server's private key for decrypting Encrypted Client Hello
+/// offers, along with the configuration clients need to make them.
+///
+/// Clients get the configuration out-of-band, typically from DNS.
+/// `ServerConfig::get_ech_config_list` gives what to publish.
+///
+/// This needs the `ech` feature.
+#[cfg(feature = "ech")]
+#[derive(Clone)]
+pub struct ECHKey {
+    config: ECHConfig,
+    private_key: [u8; hpke::KEY_LEN],
+}
+
+#[cfg(feature = "ech")]
+impl ECHKey {
+    /// Make a new random key from `rng`, identified to clients by
+    /// `config_id`.  Use the `secure_random` of the `ServerConfig`'s
+    /// `provider`.
+    ///
+    /// Clients put `public_name` in their outer, unencrypted, ClientHello.
+    /// If we can't decrypt an offer we complete the handshake as
+    /// `public_name` and give the client our current configurations,
+    /// so a certificate for `public_name` is needed too.
+    pub fn generate(config_id: u8,
+                    public_name: webpki::DNSNameRef,
+                    rng: &SecureRandom) -> ECHKey {
+        let mut private_key = [0u8; hpke::KEY_LEN];
+        rand::fill_random(rng, &mut private_key);
+        ECHKey::from_private_key(config_id, public_name, private_key)
+    }
+
+    /// Like `generate`, but using an existing X25519 private key,
+    /// such as one from `get_private_key`.
+    pub fn new(config_id: u8,
+               public_name: webpki::DNSNameRef,
+               private_key: &[u8]) -> Result<ECHKey, TLSError> {
+        if private_key.len() != hpke::KEY_LEN {
+            return Err(TLSError::General("ECH private key must be 32 bytes".to_string()));
+        }
+
+        let mut key = [0u8; hpke::KEY_LEN];
+        key.copy_from_slice(private_key);
+        Ok(ECHKey::from_private_key(config_id, public_name, key))
+    }
+
+    fn from_private_key(config_id: u8,
+                        public_name: webpki::DNSNameRef,
+                        private_key: [u8; hpke::KEY_LEN]) -> ECHKey {
+        let public_name: &str = public_name.into();
+
+        let contents = ECHConfigContents {
+            key_config: HpkeKeyConfig {
+                config_id: config_id,
+                kem_id: hpke::KEM,
+                public_key: PayloadU16::new(hpke::public_key(&private_key).to_vec()),
+                symmetric_cipher_suites: hpke::supported_suites(),
+            },
+            maximum_name_length: 0,
+            public_name: PayloadU8::new(public_name.as_bytes().to_vec()),
+            extensions: Vec::new(),
+        };
+
+        ECHKey {
+            config: ECHConfig {
+                version: ECHVersion::V13,
+                contents: ECHConfigPayload::V13(contents),
+            },
+            private_key: private_key,
+        }
+    }
+
+    /// The X25519 private key, for storing this key.
+    pub fn get_private_key(&self) -> Vec<u8> {
+        self.private_key.to_vec()
+    }
+
+    fn get_contents(&self) -> &ECHConfigContents {
+        match self.config.contents {
+            ECHConfigPayload::V13(ref contents) => contents,
+            ECHConfigPayload::Unknown(_) => unreachable!(),
+        }
+    }
+}
+
+/// The configurations for `keys`, in `ECHConfigList` form.
+#[cfg(feature = "ech")]
+pub fn get_config_list(keys: &[ECHKey]) -> ECHConfigList {
+    keys.iter()
+        .map(|key| key.config.clone())
+        .collect()
+}
+
+fn hpke_info(config: &ECHConfig) -> Vec<u8> {
+    let mut info = b"tls ech\0".to_vec();
+    config.encode(&mut info);
+    info
+}
+
+/// How much zero padding follows an encoded inner ClientHello
+/// `encoded_len` bytes long, which carries a server name `name_len`
+/// bytes long.  This hides the length of the name, as far as
+/// `maximum_name_length` allows, and then rounds up to a multiple
+/// of 32 bytes.
+fn padding_len(maximum_name_length: u8, name_len: Option<usize>, encoded_len: usize) -> usize {
+    let maximum_name_length = maximum_name_length as usize;
+
+    let pad = match name_len {
+        Some(len) if len < maximum_name_length => maximum_name_length - len,
+        Some(_) => 0,
+        None => maximum_name_length + 9,
+    };
+
+    pad + 31 - ((encoded_len + pad + 31) % 32)
+}
+
+/// The acceptance signal a server that accepted ECH puts in `m`,
+/// a ServerHello or HelloRetryRequest.  `transcript` runs up to
+/// just before `m`.
+pub fn compute_confirmation(hash: &'static Hash,
+                            transcript: &HandshakeHash,
+                            inner_client_random: &[u8],
+                            label: &[u8],
+                            m: &Message) -> Vec<u8> {
+    let encoding = match m.payload {
+        MessagePayload::Handshake(ref hmp) => hmp.get_encoding_for_ech_confirmation(),
+        _ => unreachable!(),
+    };
+
+    let hs_hash = transcript.get_hash_given(hash, &encoding);
+    key_schedule::derive_ech_confirmation(hash, inner_client_random, label, &hs_hash)
+}
+
+/// An ECH configuration a client has chosen to use.
+#[derive(Clone)]
+pub struct ClientECHConfig {
+    config: ECHConfig,
+    suite: HpkeSymmetricCipherSuite,
+    public_name: webpki::DNSName,
+}
+
+impl ClientECHConfig {
+    /// Choose the first configuration in `config_list`, an encoded
+    /// `ECHConfigList`, which we can use.
+    pub fn select(config_list: &[u8]) -> Option<ClientECHConfig> {
+        let configs = try_ret!(ECHConfigList::read_bytes(config_list));
+
+        configs.into_iter()
+            .filter_map(ClientECHConfig::new)
+            .next()
+    }
+
+    fn new(config: ECHConfig) -> Option<ClientECHConfig> {
+        let (suite, public_name) = match config.contents {
+            ECHConfigPayload::V13(ref contents) => {
+                if contents.key_config.kem_id != hpke::KEM ||
+                   contents.has_mandatory_extension() {
+                    return None;
+                }
+
+                let suite = try_ret!(contents.key_config.symmetric_cipher_suites
+                                     .iter()
+                                     .find(|suite| hpke::is_supported(suite)));
+                let public_name = untrusted::Input::from(&contents.public_name.0);
+                let public_name = try_ret!(webpki::DNSNameRef::try_from_ascii(public_name).ok());
+                (suite.clone(), public_name.into())
+            }
+            ECHConfigPayload::Unknown(_) => return None,
+        };
+
+        Some(ClientECHConfig {
+            config: config,
+            suite: suite,
+            public_name: public_name,
+        })
+    }
+
+    fn get_contents(&self) -> &ECHConfigContents {
+        match self.config.contents {
+            ECHConfigPayload::V13(ref contents) => contents,
+            ECHConfigPayload::Unknown(_) => unreachable!(),
+        }
+    }
+}
+
+/// A client's ECH offer, until the server says what it made of it.
+pub struct ClientECHOffer {
+    config: ClientECHConfig,
+    context: hpke::Context,
+    enc: Vec<u8>,
+
+    /// The random for the outer ClientHello.
+    pub outer_random: [u8; 32],
+
+    /// The transcript of the outer handshake, in case the server
+    /// rejects the offer and continues with that.
+    pub outer_transcript: HandshakeHash,
+
+    /// The extensions we sent in the outer ClientHello.
+    pub outer_extensions: Vec<ExtensionType>,
+
+    /// The server accepted in a HelloRetryRequest, so must accept
+    /// again in the ServerHello.
+    pub accepted_at_hrr: bool,
+}
+
+impl ClientECHOffer {
+    pub fn new(config: &ClientECHConfig, rng: &SecureRandom) -> Option<ClientECHOffer> {
+        let (enc, context) = try_ret!(
+            hpke::setup_base_sender(&config.suite,
+                                    &config.get_contents().key_config.public_key.0,
+                                    &hpke_info(&config.config),
+                                    rng));
+
+        let mut outer_random = [0u8; 32];
+        rand::fill_random(rng, &mut outer_random);
+
+        Some(ClientECHOffer {
+            config: config.clone(),
+            context: context,
+            enc: enc,
+            outer_random: outer_random,
+            outer_transcript: HandshakeHash::new(),
+            outer_extensions: Vec::new(),
+            accepted_at_hrr: false,
+        })
+    }
+
+    pub fn get_public_name(&self) -> webpki::DNSNameRef {
+        self.config.public_name.as_ref()
+    }
+
+    /// Work out the outer ClientHello's extensions from `exts`,
+    /// those we'd send without ECH.  The outer hello names the
+    /// public name, and doesn't resume anything.
+    pub fn outer_extensions(&self, exts: &[ClientExtension]) -> Vec<ClientExtension> {
+        let mut outer = vec![ ClientExtension::make_sni(self.get_public_name()) ];
+
+        for ext in exts {
+            match *ext {
+                ClientExtension::ServerName(_) |
+                    ClientExtension::PresharedKey(_) => {}
+                ClientExtension::SessionTicketOffer(_) => {
+                    outer.push(ClientExtension::SessionTicketRequest);
+                }
+                _ => outer.push(ext.clone()),
+            }
+        }
+
+        outer
+    }
+
+    /// Turn `exts` into the inner ClientHello's extensions.  The inner
+    /// hello only offers TLS1.3, and says that it is the inner hello.
+    pub fn make_inner_extensions(exts: &mut Vec<ClientExtension>) {
+        for ext in exts.iter_mut() {
+            if let ClientExtension::SupportedVersions(ref mut versions) = *ext {
+                versions.retain(|version| *version != ProtocolVersion::TLSv1_2);
+            }
+        }
+
+        // The PSK extension must stay last.
+        let pos = match exts.last() {
+            Some(&ClientExtension::PresharedKey(_)) => exts.len() - 1,
+            _ => exts.len(),
+        };
+        exts.insert(pos, ClientExtension::EncryptedClientHello(EncryptedClientHello::Inner));
+    }
+
+    /// Encrypt `inner` into a new extension at the end of `outer`.
+    pub fn seal(&mut self, inner: &ClientHelloPayload, outer: &mut ClientHelloPayload) {
+        // The session_id is taken from the outer hello, rather than
+        // sending it twice.
+        let mut encoded_inner = inner.clone();
+        encoded_inner.session_id = SessionID::empty();
+        let mut encoded = encoded_inner.get_encoding();
+
+        let name_len = inner.get_sni_extension()
+            .and_then(|sni| sni.get_hostname())
+            .map(|name| {
+                let name: &str = name.into();
+                name.len()
+            });
+        let pad = padding_len(self.config.get_contents().maximum_name_length,
+                              name_len,
+                              encoded.len());
+        let padded_len = encoded.len() + pad;
+        encoded.resize(padded_len, 0u8);
+
+        // We only send our HPKE share in the first ClientHello: the
+        // second one continues the same context.
+        let enc = if self.accepted_at_hrr {
+            Vec::new()
+        } else {
+            self.enc.clone()
+        };
+
+        let payload_len = encoded.len() + hpke::overhead(&self.config.suite);
+        let ech = ECHClientHelloOuter {
+            cipher_suite: self.config.suite.clone(),
+            config_id: self.config.get_contents().key_config.config_id,
+            enc: PayloadU16::new(enc),
+            payload: PayloadU16::new(vec![0u8; payload_len]),
+        };
+        outer.extensions.push(ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(ech)));
+
+        let aad = outer.get_encoding_for_ech_aad();
+        let payload = self.context.seal(&aad, &encoded);
+        outer.set_ech_payload(payload);
+
+        self.outer_extensions = outer.extensions
+            .iter()
+            .map(|ext| ext.get_type())
+            .collect();
+    }
+}
+
+/// What a server made of a client's ECH offer.
+pub enum ServerECHState {
+    NotOffered,
+
+    /// We're using the inner ClientHello.  This is the context for
+    /// decrypting the second one, if we send a HelloRetryRequest.
+    #[cfg(feature = "ech")]
+    Accepted(hpke::Context),
+
+    /// We couldn't decrypt the offer, so are using the outer ClientHello.
+    #[cfg(feature = "ech")]
+    Rejected,
+}
+
+impl ServerECHState {
+    pub fn is_accepted(&self) -> bool {
+        match *self {
+            #[cfg(feature = "ech")]
+            ServerECHState::Accepted(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Decrypt the inner ClientHello in `ech`, an extension of `outer`,
+/// using the first of `keys` that works.  Returns the HPKE context
+/// and the encoded inner hello.
+#[cfg(feature = "ech")]
+pub fn open_inner_hello(keys: &[ECHKey],
+                        outer: &ClientHelloPayload,
+                        ech: &ECHClientHelloOuter) -> Option<(hpke::Context, Vec<u8>)> {
+    let aad = outer.get_encoding_for_ech_aad();
+
+    for key in keys {
+        if key.get_contents().key_config.config_id != ech.config_id ||
+           !key.get_contents().key_config.symmetric_cipher_suites.contains(&ech.cipher_suite) {
+            continue;
+        }
+
+        let mut context = match hpke::setup_base_recipient(&ech.cipher_suite,
+                                                           &ech.enc.0,
+                                                           &key.private_key,
+                                                           &hpke_info(&key.config)) {
+            Some(context) => context,
+            None => continue,
+        };
+
+        if let Some(encoded) = context.open(&aad, &ech.payload.0) {
+            return Some((context, encoded));
+        }
+    }
+
+    None
+}
+
+/// Decrypt the inner ClientHello sent after a HelloRetryRequest,
+/// continuing `context`.
+#[cfg(feature = "ech")]
+pub fn open_retried_inner_hello(context: &mut hpke::Context,
+                                outer: &ClientHelloPayload,
+                                ech: &ECHClientHelloOuter) -> Option<Vec<u8>> {
+    if !ech.enc.0.is_empty() {
+        return None;
+    }
+
+    context.open(&outer.get_encoding_for_ech_aad(), &ech.payload.0)
+}
+
+/// Decode an inner ClientHello, decrypted from `outer`.  This undoes
+/// what the client did to make it smaller: it has no session_id,
+/// and may refer to extensions in `outer` rather than repeating them.
+#[cfg(feature = "ech")]
+pub fn decode_inner_hello(encoded: &[u8], outer: &ClientHelloPayload) -> Option<ClientHelloPayload> {
+    let mut rd = Reader::init(encoded);
+    let mut inner = try_ret!(ClientHelloPayload::read(&mut rd));
+
+    if !rd.rest().iter().all(|x| *x == 0) || !inner.session_id.is_empty() {
+        return None;
+    }
+    inner.session_id = outer.session_id;
+
+    let exts = mem::replace(&mut inner.extensions, Vec::new());
+    for ext in exts {
+        let outer_types = match ext {
+            ClientExtension::Unknown(ref unknown) if unknown.typ == ExtensionType::ECHOuterExtensions => {
+                let mut rd = Reader::init(&unknown.payload.0);
+                try_ret!(codec::read_vec_u8::<ExtensionType>(&mut rd))
+            }
+            _ => {
+                inner.extensions.push(ext);
+                continue;
+            }
+        };
+
+        for typ in outer_types {
+            if typ == ExtensionType::EncryptedClientHello {
+                return None;
+            }
+
+            inner.extensions.push(try_ret!(outer.find_extension(typ)).clone());
+        }
+    }
+
+    match inner.get_ech_extension() {
+        Some(&EncryptedClientHello::Inner) => Some(inner),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::padding_len;
+
+    #[test]
+    fn test_padding_len() {
+        // Names shorter than the maximum are padded up to it.
+        assert_eq!(padding_len(32, Some(10), 100), 22 + 6);
+        assert_eq!(padding_len(32, Some(40), 100), 28);
+        assert_eq!(padding_len(0, Some(10), 128), 0);
+
+        // And with no name, we pad as if there's a name of the
+        // maximum length.
+        assert_eq!(padding_len(32, None, 100), 41 + 19);
+        assert_eq!(padding_len(0, None, 100), 9 + 19);
+    }
+}
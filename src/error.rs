@@ -73,6 +73,46 @@ pub enum TLSError {
 
     /// The peer sent an oversized record/fragment.
     PeerSentOversizedRecord,
+
+    /// The server didn't accept our Encrypted Client Hello offer.
+    /// The parameter is any replacement configurations it sent, in
+    /// `ECHConfigList` encoding: the connection may be retried with
+    /// those.
+    ECHRejected(Option<Vec<u8>>),
+
+    /// A certificate revocation list could not be parsed, or
+    /// uses features we don't support.
+    InvalidCertRevocationList,
+
+    /// A certificate in the presented chain has been revoked.
+    CertRevoked,
+
+    /// We could not find out whether a certificate in the presented
+    /// chain has been revoked, and policy does not allow that.
+    /// The parameter gives a hint as to why.
+    UnknownRevocationStatus(String),
+
+    /// A stapled OCSP response could not be parsed, was not
+    /// properly signed, did not cover the certificate, or is not
+    /// current.  The parameter gives a hint as to why.
+    InvalidOCSPResponse(String),
+
+    /// The server's certificate requires a stapled OCSP response
+    /// (it has the "must staple" TLS feature), but none was sent.
+    OCSPStapleRequired,
+
+    /// No certificate in the server's chain has a key pinned
+    /// by `ClientConfig::set_spki_pins`.
+    NoMatchingSPKIPin,
+
+    /// A SPIFFE ID could not be parsed, or a client certificate did
+    /// not have exactly one.  The parameter gives a hint as to why.
+    InvalidSPIFFEID(String),
+
+    /// A client's SPIFFE ID is not in the trust domain, or does not
+    /// have one of the paths, which the server allows.  The parameter
+    /// is the ID.
+    SPIFFEIDNotAllowed(String),
 }
 
 fn join<T: fmt::Debug>(items: &[T]) -> String {
@@ -110,7 +150,18 @@ impl fmt::Display for TLSError {
             TLSError::NoCertificatesPresented |
             TLSError::DecryptError |
             TLSError::PeerSentOversizedRecord |
+            TLSError::ECHRejected(_) |
+            TLSError::InvalidCertRevocationList |
+            TLSError::CertRevoked |
+            TLSError::OCSPStapleRequired |
+            TLSError::NoMatchingSPKIPin |
             TLSError::HandshakeNotComplete => write!(f, "{}", self.description()),
+            TLSError::UnknownRevocationStatus(ref why) |
+            TLSError::InvalidOCSPResponse(ref why) |
+            TLSError::InvalidSPIFFEID(ref why) |
+            TLSError::SPIFFEIDNotAllowed(ref why) => {
+                write!(f, "{}: {}", self.description(), why)
+            }
             _ => write!(f, "{}: {:?}", self.description(), self),
         }
     }
@@ -137,6 +188,15 @@ impl Error for TLSError {
             TLSError::InvalidDNSName(_) => "invalid DNS name",
             TLSError::HandshakeNotComplete => "handshake not complete",
             TLSError::PeerSentOversizedRecord => "peer sent excess record size",
+            TLSError::ECHRejected(_) => "server rejected encrypted client hello",
+            TLSError::InvalidCertRevocationList => "invalid certificate revocation list",
+            TLSError::CertRevoked => "certificate revoked",
+            TLSError::UnknownRevocationStatus(_) => "unknown certificate revocation status",
+            TLSError::InvalidOCSPResponse(_) => "invalid OCSP response",
+            TLSError::OCSPStapleRequired => "server did not staple a required OCSP response",
+            TLSError::NoMatchingSPKIPin => "no certificate key matches a pinned key",
+            TLSError::InvalidSPIFFEID(_) => "invalid SPIFFE ID",
+            TLSError::SPIFFEIDNotAllowed(_) => "SPIFFE ID not allowed",
         }
     }
 }
@@ -172,7 +232,16 @@ mod tests {
                        TLSError::FailedToGetCurrentTime,
                        TLSError::InvalidDNSName("dns something".to_string()),
                        TLSError::HandshakeNotComplete,
-                       TLSError::PeerSentOversizedRecord];
+                       TLSError::PeerSentOversizedRecord,
+                       TLSError::ECHRejected(None),
+                       TLSError::InvalidCertRevocationList,
+                       TLSError::CertRevoked,
+                       TLSError::UnknownRevocationStatus("no CRL".to_string()),
+                       TLSError::InvalidOCSPResponse("expired".to_string()),
+                       TLSError::OCSPStapleRequired,
+                       TLSError::NoMatchingSPKIPin,
+                       TLSError::InvalidSPIFFEID("no URI".to_string()),
+                       TLSError::SPIFFEIDNotAllowed("spiffe://example.org/x".to_string())];
 
         for err in all {
             println!("{:?}:", err);
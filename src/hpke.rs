@@ -0,0 +1,331 @@
+// Hybrid public key encryption (RFC9180), as needed for
+// Encrypted Client Hello.
+//
+// We only do the base mode, with DHKEM(X25519, HKDF-SHA256) and
+// HKDF-SHA256.  Any of the AEADs *ring* supports can be used.
+//
+// *ring* only does X25519 with ephemeral keys, which is enough
+// for senders.  Recipients hold a long-lived private key, so
+// they use x25519-dalek, behind the `ech` feature.
+
+use ring::{aead, digest, hkdf, hmac};
+use msgs::enums::{HpkeKem, HpkeKdf, HpkeAead};
+use msgs::handshake::HpkeSymmetricCipherSuite;
+use msgs::codec::{self, Codec};
+use crypto;
+use crypto::{SecureRandom, SupportedKxGroup};
+
+#[cfg(feature = "ech")]
+use x25519_dalek;
+
+const MODE_BASE: u8 = 0x00;
+const NONCE_LEN: usize = 12;
+
+/// The length of our KEM's private and public keys.
+#[cfg(feature = "ech")]
+pub const KEY_LEN: usize = 32;
+
+/// The KEM we support.
+pub const KEM: HpkeKem = HpkeKem::DHKEM_X25519_HKDF_SHA256;
+
+/// The KDF/AEAD pairs we support, most preferred first.
+#[cfg(feature = "ech")]
+pub fn supported_suites() -> Vec<HpkeSymmetricCipherSuite> {
+    vec![
+        HpkeSymmetricCipherSuite { kdf_id: HpkeKdf::HKDF_SHA256, aead_id: HpkeAead::AES_128_GCM },
+        HpkeSymmetricCipherSuite { kdf_id: HpkeKdf::HKDF_SHA256, aead_id: HpkeAead::AES_256_GCM },
+        HpkeSymmetricCipherSuite { kdf_id: HpkeKdf::HKDF_SHA256, aead_id: HpkeAead::CHACHA20_POLY1305 },
+    ]
+}
+
+fn aead_for(suite: &HpkeSymmetricCipherSuite) -> Option<&'static aead::Algorithm> {
+    if suite.kdf_id != HpkeKdf::HKDF_SHA256 {
+        return None;
+    }
+
+    match suite.aead_id {
+        HpkeAead::AES_128_GCM => Some(&aead::AES_128_GCM),
+        HpkeAead::AES_256_GCM => Some(&aead::AES_256_GCM),
+        HpkeAead::CHACHA20_POLY1305 => Some(&aead::CHACHA20_POLY1305),
+        _ => None,
+    }
+}
+
+/// Can we use `suite`?
+pub fn is_supported(suite: &HpkeSymmetricCipherSuite) -> bool {
+    aead_for(suite).is_some()
+}
+
+/// How much longer `seal` makes a plaintext.
+pub fn overhead(suite: &HpkeSymmetricCipherSuite) -> usize {
+    aead_for(suite).map_or(0, |alg| alg.tag_len())
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let mut labeled_ikm = Vec::new();
+    labeled_ikm.extend_from_slice(b"HPKE-v1");
+    labeled_ikm.extend_from_slice(suite_id);
+    labeled_ikm.extend_from_slice(label);
+    labeled_ikm.extend_from_slice(ikm);
+
+    let salt = hmac::SigningKey::new(&digest::SHA256, salt);
+    hmac::sign(&salt, &labeled_ikm).as_ref().to_vec()
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let mut labeled_info = Vec::new();
+    codec::encode_u16(len as u16, &mut labeled_info);
+    labeled_info.extend_from_slice(b"HPKE-v1");
+    labeled_info.extend_from_slice(suite_id);
+    labeled_info.extend_from_slice(label);
+    labeled_info.extend_from_slice(info);
+
+    let mut out = vec![0u8; len];
+    hkdf::expand(&hmac::SigningKey::new(&digest::SHA256, prk), &labeled_info, &mut out);
+    out
+}
+
+fn kem_suite_id() -> Vec<u8> {
+    let mut suite_id = b"KEM".to_vec();
+    KEM.encode(&mut suite_id);
+    suite_id
+}
+
+/// DHKEM's ExtractAndExpand.
+fn shared_secret(dh: &[u8], enc: &[u8], public_key: &[u8]) -> Vec<u8> {
+    let suite_id = kem_suite_id();
+    let mut kem_context = Vec::new();
+    kem_context.extend_from_slice(enc);
+    kem_context.extend_from_slice(public_key);
+
+    let eae_prk = labeled_extract(&suite_id, b"", b"eae_prk", dh);
+    labeled_expand(&suite_id, &eae_prk, b"shared_secret", &kem_context, digest::SHA256.output_len)
+}
+
+/// An HPKE encryption context, for one direction.
+pub struct Context {
+    alg: &'static aead::Algorithm,
+    key: Vec<u8>,
+    base_nonce: Vec<u8>,
+    seq: u64,
+}
+
+impl Context {
+    fn new(suite: &HpkeSymmetricCipherSuite,
+           shared_secret: &[u8],
+           info: &[u8]) -> Option<Context> {
+        let alg = try_ret!(aead_for(suite));
+
+        let mut suite_id = b"HPKE".to_vec();
+        KEM.encode(&mut suite_id);
+        suite.encode(&mut suite_id);
+
+        let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+        let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+        let mut context = vec![ MODE_BASE ];
+        context.extend_from_slice(&psk_id_hash);
+        context.extend_from_slice(&info_hash);
+
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+
+        Some(Context {
+            alg: alg,
+            key: labeled_expand(&suite_id, &secret, b"key", &context, alg.key_len()),
+            base_nonce: labeled_expand(&suite_id, &secret, b"base_nonce", &context, NONCE_LEN),
+            seq: 0,
+        })
+    }
+
+    fn next_nonce(&mut self) -> Vec<u8> {
+        let mut nonce = self.base_nonce.clone();
+        let mut seq = Vec::new();
+        codec::encode_u64(self.seq, &mut seq);
+        for (n, s) in nonce[NONCE_LEN - 8..].iter_mut().zip(seq.iter()) {
+            *n ^= *s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    /// Encrypt `plaintext`, authenticating `aad` too.
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        let key = aead::SealingKey::new(self.alg, &self.key).unwrap();
+        let tag_len = self.alg.tag_len();
+
+        let mut buf = plaintext.to_vec();
+        buf.resize(plaintext.len() + tag_len, 0u8);
+        let len = aead::seal_in_place(&key, &nonce, aad, &mut buf, tag_len).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    /// Decrypt and authenticate `ciphertext` and `aad`.
+    #[cfg(feature = "ech")]
+    pub fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce();
+        let key = aead::OpeningKey::new(self.alg, &self.key).unwrap();
+
+        let mut buf = ciphertext.to_vec();
+        let len = try_ret!(aead::open_in_place(&key, &nonce, aad, 0, &mut buf).ok()).len();
+        buf.truncate(len);
+        Some(buf)
+    }
+}
+
+/// Start encrypting to `public_key`, with an ephemeral key from
+/// `rng`.  Returns the encapsulated key to send to the recipient,
+/// and the context.
+pub fn setup_base_sender(suite: &HpkeSymmetricCipherSuite,
+                         public_key: &[u8],
+                         info: &[u8],
+                         rng: &SecureRandom) -> Option<(Vec<u8>, Context)> {
+    let kx = try_ret!(crypto::ring::X25519.start(rng).ok());
+    let our_public = kx.pub_key().to_vec();
+    let dh = try_ret!(kx.complete(public_key));
+
+    let secret = shared_secret(&dh, &our_public, public_key);
+    let context = try_ret!(Context::new(suite, &secret, info));
+    Some((our_public, context))
+}
+
+/// The public key for our KEM private key `private_key`.
+#[cfg(feature = "ech")]
+pub fn public_key(private_key: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let secret = x25519_dalek::StaticSecret::from(*private_key);
+    *x25519_dalek::PublicKey::from(&secret).as_bytes()
+}
+
+/// Start decrypting things sent to us, where our private key
+/// is `private_key` and the sender's encapsulated key is `enc`.
+#[cfg(feature = "ech")]
+pub fn setup_base_recipient(suite: &HpkeSymmetricCipherSuite,
+                            enc: &[u8],
+                            private_key: &[u8; KEY_LEN],
+                            info: &[u8]) -> Option<Context> {
+    if enc.len() != KEY_LEN {
+        return None;
+    }
+
+    let mut their_public = [0u8; KEY_LEN];
+    their_public.copy_from_slice(enc);
+    let secret = x25519_dalek::StaticSecret::from(*private_key);
+    let dh = secret.diffie_hellman(&x25519_dalek::PublicKey::from(their_public));
+
+    // Reject small-order points, like ring does for the sender.
+    if !dh.was_contributory() {
+        return None;
+    }
+
+    let our_public = public_key(private_key);
+    let secret = shared_secret(dh.as_bytes(), enc, &our_public);
+    Context::new(suite, &secret, info)
+}
+
+#[cfg(all(test, feature = "ech"))]
+mod test {
+    use super::*;
+    use crypto::ring::RingRandom;
+    use msgs::enums::{HpkeKdf, HpkeAead};
+    use msgs::handshake::HpkeSymmetricCipherSuite;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len() / 2)
+            .map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn private_key() -> [u8; 32] {
+        let mut key = [0u8; 32];
+        for (i, x) in key.iter_mut().enumerate() {
+            *x = i as u8 + 1;
+        }
+        key
+    }
+
+    fn suite(aead_id: HpkeAead) -> HpkeSymmetricCipherSuite {
+        HpkeSymmetricCipherSuite { kdf_id: HpkeKdf::HKDF_SHA256, aead_id: aead_id }
+    }
+
+    // These were made by another HPKE implementation, encrypting
+    // "hello world" with info "tls ech" and no aad.
+    #[test]
+    fn opens_known_answers() {
+        let vectors = [
+            (HpkeAead::AES_128_GCM,
+             "e122365dd089ee0c6e0b8ca46e0a9ce6f669f81b7684258f6d29e75e722c5764\
+              c0689ba63758da0fd7d10824e488543a4b85b2a1632e7325b0b84c"),
+            (HpkeAead::AES_256_GCM,
+             "6ad70a8614a4eb290d94425fe91fbe383b2f2c1db410bf193b38b2374194c96b\
+              b319774e0b6332dc2d56b908bff946b6c8cadb5fcf54e7e11f57d5"),
+            (HpkeAead::CHACHA20_POLY1305,
+             "31af64a57902355571df46128839aabab6b309190de3524e7e8408cac7437479\
+              02ca76d1eac3b09e9a2e13cbd33654b0dc3aac8a18977a96e6a3fc"),
+        ];
+
+        for &(aead_id, message) in vectors.iter() {
+            let message = unhex(message);
+            let (enc, ciphertext) = message.split_at(32);
+            let mut ctx = setup_base_recipient(&suite(aead_id), enc, &private_key(), b"tls ech")
+                .unwrap();
+            assert_eq!(ctx.open(b"", ciphertext), Some(b"hello world".to_vec()));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let public_key = public_key(&private_key());
+
+        for suite in supported_suites() {
+            let (enc, mut sender) = setup_base_sender(&suite, &public_key, b"info", &RingRandom)
+                .unwrap();
+            let mut recipient = setup_base_recipient(&suite, &enc, &private_key(), b"info")
+                .unwrap();
+
+            for i in 0..3 {
+                let ciphertext = sender.seal(b"aad", &[i; 20]);
+                assert_eq!(ciphertext.len(), 20 + overhead(&suite));
+                assert_eq!(recipient.open(b"aad", &ciphertext), Some(vec![i; 20]));
+            }
+
+            let ciphertext = sender.seal(b"aad", b"hello");
+            assert_eq!(recipient.open(b"bad", &ciphertext), None);
+        }
+    }
+
+    #[test]
+    fn wrong_info_fails() {
+        let public_key = public_key(&private_key());
+        let suite = suite(HpkeAead::AES_128_GCM);
+        let (enc, mut sender) = setup_base_sender(&suite, &public_key, b"info", &RingRandom)
+            .unwrap();
+        let mut recipient = setup_base_recipient(&suite, &enc, &private_key(), b"other")
+            .unwrap();
+        let ciphertext = sender.seal(b"", b"hello");
+        assert_eq!(recipient.open(b"", &ciphertext), None);
+    }
+
+    #[test]
+    fn rejects_bad_enc() {
+        let suite = suite(HpkeAead::AES_128_GCM);
+        assert!(setup_base_recipient(&suite, &[0u8; 31], &private_key(), b"").is_none());
+        assert!(setup_base_recipient(&suite, &[0u8; 32], &private_key(), b"").is_none());
+        assert!(!is_supported(&HpkeSymmetricCipherSuite {
+            kdf_id: HpkeKdf::HKDF_SHA384,
+            aead_id: HpkeAead::AES_128_GCM,
+        }));
+    }
+
+    #[test]
+    fn rfc7748_public_keys() {
+        let mut alice = [0u8; KEY_LEN];
+        alice.copy_from_slice(&unhex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a"));
+        let mut bob = [0u8; KEY_LEN];
+        bob.copy_from_slice(&unhex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb"));
+
+        assert_eq!(public_key(&alice).to_vec(),
+                   unhex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"));
+        assert_eq!(public_key(&bob).to_vec(),
+                   unhex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"));
+    }
+}
@@ -1,13 +1,16 @@
 /// Key schedule maintenance for TLS1.3
 
-use ring::{hmac, digest, hkdf};
+use crypto::Hash;
 use msgs::codec;
 use error::TLSError;
+use keylog::KeyLog;
 
 /// The kinds of secret we can extract from `KeySchedule`.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SecretKind {
     ResumptionPSKBinderKey,
+    ExternalPSKBinderKey,
+    ClientEarlyTrafficSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
     ClientApplicationTrafficSecret,
@@ -21,6 +24,8 @@ impl SecretKind {
     fn to_bytes(&self) -> &'static [u8] {
         match *self {
             SecretKind::ResumptionPSKBinderKey => b"res binder",
+            SecretKind::ExternalPSKBinderKey => b"ext binder",
+            SecretKind::ClientEarlyTrafficSecret => b"c e traffic",
             SecretKind::ClientHandshakeTrafficSecret => b"c hs traffic",
             SecretKind::ServerHandshakeTrafficSecret => b"s hs traffic",
             SecretKind::ClientApplicationTrafficSecret => b"c ap traffic",
@@ -30,34 +35,42 @@ impl SecretKind {
             SecretKind::DerivedSecret => b"derived",
         }
     }
+
+    /// The NSS key log label for this kind of secret, if it
+    /// is one that is logged.
+    fn log_label(&self) -> Option<&'static str> {
+        match *self {
+            SecretKind::ClientEarlyTrafficSecret => Some("CLIENT_EARLY_TRAFFIC_SECRET"),
+            SecretKind::ClientHandshakeTrafficSecret => Some("CLIENT_HANDSHAKE_TRAFFIC_SECRET"),
+            SecretKind::ServerHandshakeTrafficSecret => Some("SERVER_HANDSHAKE_TRAFFIC_SECRET"),
+            SecretKind::ClientApplicationTrafficSecret => Some("CLIENT_TRAFFIC_SECRET_0"),
+            SecretKind::ServerApplicationTrafficSecret => Some("SERVER_TRAFFIC_SECRET_0"),
+            SecretKind::ExporterMasterSecret => Some("EXPORTER_SECRET"),
+            _ => None,
+        }
+    }
 }
 
 /// This is the TLS1.3 key schedule.  It stores the current secret,
 /// the type of hash, plus the two current traffic keys which form their
 /// own lineage of keys over successive key updates.
 pub struct KeySchedule {
-    current: hmac::SigningKey,
+    current: Vec<u8>,
     need_derive_for_extract: bool,
-    hash: &'static digest::Algorithm,
-    hash_of_empty_message: [u8; digest::MAX_OUTPUT_LEN],
+    hash: &'static Hash,
+    hash_of_empty_message: Vec<u8>,
     pub current_client_traffic_secret: Vec<u8>,
     pub current_server_traffic_secret: Vec<u8>,
     pub current_exporter_secret: Vec<u8>,
 }
 
 impl KeySchedule {
-    pub fn new(hash: &'static digest::Algorithm) -> KeySchedule {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-
-        let mut empty_hash = [0u8; digest::MAX_OUTPUT_LEN];
-        empty_hash[..hash.output_len]
-            .clone_from_slice(digest::digest(hash, &[]).as_ref());
-
+    pub fn new(hash: &'static Hash) -> KeySchedule {
         KeySchedule {
-            current: hmac::SigningKey::new(hash, &zeroes[..hash.output_len]),
+            current: vec![0u8; hash.output_len()],
             need_derive_for_extract: false,
             hash: hash,
-            hash_of_empty_message: empty_hash,
+            hash_of_empty_message: hash.hash(&[]),
             current_server_traffic_secret: Vec::new(),
             current_client_traffic_secret: Vec::new(),
             current_exporter_secret: Vec::new(),
@@ -65,14 +78,13 @@ impl KeySchedule {
     }
 
     pub fn get_hash_of_empty_message(&self) -> &[u8] {
-        &self.hash_of_empty_message[..self.hash.output_len]
+        &self.hash_of_empty_message
     }
 
     /// Input the empty secret.
     pub fn input_empty(&mut self) {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        let hash_len = self.hash.output_len;
-        self.input_secret(&zeroes[..hash_len]);
+        let zeroes = vec![0u8; self.hash.output_len()];
+        self.input_secret(&zeroes);
     }
 
     /// Input the given secret.
@@ -80,21 +92,36 @@ impl KeySchedule {
         if self.need_derive_for_extract {
             let derived = self.derive(SecretKind::DerivedSecret,
                                       self.get_hash_of_empty_message());
-            self.current = hmac::SigningKey::new(self.hash, &derived);
+            self.current = derived;
         }
         self.need_derive_for_extract = true;
-        let new = hkdf::extract(&self.current, secret);
-        self.current = new
+        self.current = hkdf_extract(self.hash, &self.current, secret);
     }
 
     /// Derive a secret of given `kind`, using current handshake hash `hs_hash`.
     pub fn derive(&self, kind: SecretKind, hs_hash: &[u8]) -> Vec<u8> {
-        debug_assert_eq!(hs_hash.len(), self.hash.output_len);
+        debug_assert_eq!(hs_hash.len(), self.hash.output_len());
 
-        _hkdf_expand_label_vec(&self.current,
+        _hkdf_expand_label_vec(self.hash,
+                               &self.current,
                                kind.to_bytes(),
                                hs_hash,
-                               self.hash.output_len)
+                               self.hash.output_len())
+    }
+
+    /// Derive a secret of given `kind` using `hs_hash`, like `derive`,
+    /// and then tell `key_log` about it.  `client_random` identifies
+    /// the session in the log.
+    pub fn derive_logged_secret(&self,
+                                kind: SecretKind,
+                                hs_hash: &[u8],
+                                key_log: &KeyLog,
+                                client_random: &[u8]) -> Vec<u8> {
+        let secret = self.derive(kind, hs_hash);
+        if let Some(label) = kind.log_label() {
+            key_log.log(label, client_random, &secret);
+        }
+        secret
     }
 
     /// Return the current traffic secret, of given `kind`.
@@ -118,35 +145,36 @@ impl KeySchedule {
     /// Sign the finished message consisting of `hs_hash` using the key material
     /// `base_key`.
     pub fn sign_verify_data(&self, base_key: &[u8], hs_hash: &[u8]) -> Vec<u8> {
-        debug_assert_eq!(hs_hash.len(), self.hash.output_len);
+        debug_assert_eq!(hs_hash.len(), self.hash.output_len());
 
-        let hmac_key = _hkdf_expand_label_vec(&hmac::SigningKey::new(self.hash, base_key),
+        let hmac_key = _hkdf_expand_label_vec(self.hash,
+                                              base_key,
                                               b"finished",
                                               &[],
-                                              self.hash.output_len);
+                                              self.hash.output_len());
 
-        hmac::sign(&hmac::SigningKey::new(self.hash, &hmac_key), hs_hash)
-            .as_ref()
-            .to_vec()
+        self.hash.hmac_sign(&hmac_key, &[hs_hash])
     }
 
     /// Derive the next application traffic secret of given `kind`, returning
     /// it.
     pub fn derive_next(&self, kind: SecretKind) -> Vec<u8> {
         let base_key = self.current_traffic_secret(kind);
-        _hkdf_expand_label_vec(&hmac::SigningKey::new(self.hash, base_key),
+        _hkdf_expand_label_vec(self.hash,
+                               base_key,
                                b"traffic upd",
                                &[],
-                               self.hash.output_len)
+                               self.hash.output_len())
     }
 
     /// Derive the PSK to use given a resumption_master_secret and
     /// ticket_nonce.
     pub fn derive_ticket_psk(&self, rms: &[u8], nonce: &[u8]) -> Vec<u8> {
-        _hkdf_expand_label_vec(&hmac::SigningKey::new(self.hash, rms),
+        _hkdf_expand_label_vec(self.hash,
+                               rms,
                                b"resumption",
                                nonce,
-                               self.hash.output_len)
+                               self.hash.output_len())
     }
 
     pub fn export_keying_material(&self, out: &mut [u8],
@@ -156,35 +184,49 @@ impl KeySchedule {
             return Err(TLSError::HandshakeNotComplete);
         }
 
-        let h_empty = digest::digest(self.hash, &[]);
-        let mut secret = [0u8; digest::MAX_OUTPUT_LEN];
-        _hkdf_expand_label(&mut secret[..self.hash.output_len],
-                           &hmac::SigningKey::new(self.hash,
-                                                  &self.current_exporter_secret),
-                           label,
-                           h_empty.as_ref());
+        let secret = _hkdf_expand_label_vec(self.hash,
+                                            &self.current_exporter_secret,
+                                            label,
+                                            &self.hash_of_empty_message,
+                                            self.hash.output_len());
 
-        let mut h_context = [0u8; digest::MAX_OUTPUT_LEN];
-        h_context[..self.hash.output_len]
-            .clone_from_slice(digest::digest(self.hash,
-                                             context.unwrap_or(&[]))
-                              .as_ref());
+        let h_context = self.hash.hash(context.unwrap_or(&[]));
 
         _hkdf_expand_label(out,
-                           &hmac::SigningKey::new(self.hash, &secret[..self.hash.output_len]),
+                           self.hash,
+                           &secret,
                            b"exporter",
-                           &h_context[..self.hash.output_len]);
+                           &h_context);
         Ok(())
     }
 }
 
-fn _hkdf_expand_label_vec(secret: &hmac::SigningKey,
+/// RFC5869 HKDF-Extract: the pseudorandom key from input keying
+/// material `ikm` and `salt`.
+fn hkdf_extract(hash: &Hash, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    hash.hmac_sign(salt, &[ikm])
+}
+
+/// RFC5869 HKDF-Expand: fill `output` from pseudorandom key `prk`
+/// and `info`.
+fn hkdf_expand(hash: &Hash, prk: &[u8], info: &[u8], output: &mut [u8]) {
+    let mut t = Vec::new();
+
+    for (i, chunk) in output.chunks_mut(hash.output_len()).enumerate() {
+        t = hash.hmac_sign(prk, &[&t, info, &[(i + 1) as u8]]);
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+fn _hkdf_expand_label_vec(hash: &Hash,
+                          secret: &[u8],
                           label: &[u8],
                           context: &[u8],
                           len: usize) -> Vec<u8> {
     let mut v = Vec::new();
     v.resize(len, 0u8);
     _hkdf_expand_label(&mut v,
+                       hash,
                        secret,
                        label,
                        context);
@@ -192,7 +234,8 @@ fn _hkdf_expand_label_vec(secret: &hmac::SigningKey,
 }
 
 fn _hkdf_expand_label(output: &mut [u8],
-                      secret: &hmac::SigningKey,
+                      hash: &Hash,
+                      secret: &[u8],
                       label: &[u8],
                       context: &[u8]) {
     let label_prefix = b"tls13 ";
@@ -205,27 +248,39 @@ fn _hkdf_expand_label(output: &mut [u8],
     codec::encode_u8(context.len() as u8, &mut hkdflabel);
     hkdflabel.extend_from_slice(context);
 
-    hkdf::expand(secret, &hkdflabel, output)
+    hkdf_expand(hash, secret, &hkdflabel, output)
+}
+
+pub fn derive_traffic_key(hash: &Hash, secret: &[u8], len: usize) -> Vec<u8> {
+    _hkdf_expand_label_vec(hash, secret, b"key", &[], len)
 }
 
-pub fn derive_traffic_key(hash: &'static digest::Algorithm, secret: &[u8], len: usize) -> Vec<u8> {
-    _hkdf_expand_label_vec(&hmac::SigningKey::new(hash, secret), b"key", &[], len)
+pub fn derive_traffic_iv(hash: &Hash, secret: &[u8], len: usize) -> Vec<u8> {
+    _hkdf_expand_label_vec(hash, secret, b"iv", &[], len)
 }
 
-pub fn derive_traffic_iv(hash: &'static digest::Algorithm, secret: &[u8], len: usize) -> Vec<u8> {
-    _hkdf_expand_label_vec(&hmac::SigningKey::new(hash, secret), b"iv", &[], len)
+/// Derive the signal a server uses to say it accepted Encrypted
+/// Client Hello.  `hs_hash` is the hash of the transcript with the
+/// signal itself zeroed out.
+pub fn derive_ech_confirmation(hash: &'static Hash,
+                               inner_client_random: &[u8],
+                               label: &[u8],
+                               hs_hash: &[u8]) -> Vec<u8> {
+    let mut ks = KeySchedule::new(hash);
+    ks.input_secret(inner_client_random);
+    _hkdf_expand_label_vec(hash, &ks.current, label, hs_hash, 8)
 }
 
 #[cfg(test)]
 mod test {
     use super::{KeySchedule, SecretKind, derive_traffic_key, derive_traffic_iv};
-    use ring::digest;
+    use crypto::ring::SHA256;
 
     #[test]
     fn smoke_test() {
         let fake_handshake_hash = [0u8; 32];
 
-        let mut ks = KeySchedule::new(&digest::SHA256);
+        let mut ks = KeySchedule::new(&SHA256);
         ks.input_empty(); // no PSK
         ks.derive(SecretKind::ResumptionPSKBinderKey, &fake_handshake_hash);
         ks.input_secret(&[1u8, 2u8, 3u8, 4u8]);
@@ -322,7 +377,7 @@ mod test {
             0x0d, 0xb2, 0x8f, 0x98, 0x85, 0x86, 0xa1, 0xb7, 0xe4, 0xd5, 0xc6, 0x9c
         ];
 
-        let hash = &digest::SHA256;
+        let hash = &SHA256;
         let mut ks = KeySchedule::new(hash);
         ks.input_empty();
         ks.input_secret(&ecdhe_secret);
@@ -6,31 +6,34 @@
 //! ## Current features
 //!
 //! * TLS1.2 and TLS1.3 (draft 23) only.
-//! * ECDSA or RSA server authentication by clients.
-//! * RSA server authentication by servers.
+//! * ECDSA, Ed25519 or RSA server authentication by clients.
+//! * ECDSA, Ed25519 or RSA server authentication by servers.
 //! * Forward secrecy using ECDHE; with curve25519, nistp256 or nistp384 curves.
+//! * Optional forward secrecy using finite field DHE, in the RFC7919 groups.
 //! * AES128-GCM and AES256-GCM bulk encryption, with safe nonces.
 //! * Chacha20Poly1305 bulk encryption.
 //! * ALPN support.
 //! * SNI support.
+//! * Choosing server configuration per connection from the ClientHello.
 //! * Tunable MTU to make TLS messages match size of underlying transport.
+//! * Record size limits (RFC8449) and maximum fragment length (RFC6066) negotiation.
+//! * TLS1.3 certificate compression (RFC8879).
+//! * Encrypted Client Hello (draft-ietf-tls-esni-13).
 //! * TLS1.2 session resumption.
 //! * TLS1.2 resumption via tickets (RFC5077).
 //! * TLS1.3 resumption via tickets.
+//! * TLS1.3 early data (0-RTT) by clients and servers.
+//! * TLS1.3 external PSK authentication, with or without ECDHE.
 //! * Client authentication by clients.
-//! * Client authentication by servers.
+//! * Client authentication by servers, including by SPIFFE ID.
+//! * TLS1.3 post-handshake client authentication.
 //! * Extended master secret support (RFC7627).
 //! * Exporters (RFC5705).
 //! * OCSP stapling by servers.
 //! * SCT stapling by servers.
 //! * SCT verification by clients.
-//!
-//! ## Possible future features
-//!
-//! * ECDSA server authentication by servers.
-//! * PSK support.
-//! * OCSP verification by clients.
-//! * Certificate pinning.
+//! * OCSP verification by clients, including OCSP Must-Staple (RFC7633).
+//! * Server public key pinning by clients.
 //!
 //! ## Non-features
 //!
@@ -46,7 +49,7 @@
 //! * Renegotiation.
 //! * Kerberos.
 //! * Compression.
-//! * Discrete-log Diffie-Hellman.
+//! * Discrete-log Diffie-Hellman in groups other than those of RFC7919.
 //! * Automatic protocol version downgrade.
 //! * AES-GCM with unsafe nonces.
 //!
@@ -103,7 +106,8 @@
 //! ```
 //!
 //! Now we can make a session.  You need to provide the server's hostname so we
-//! know what to expect to find in the server's certificate.
+//! know what to expect to find in the server's certificate.  Servers can
+//! also be named by IP address, using `rustls::ServerName`.
 //!
 //! ```no_run
 //! # extern crate rustls;
@@ -179,6 +183,19 @@
 //!   such as replacing the certificate verification process.  Applications
 //!   requesting this feature should be reviewed carefully.
 //!
+//! - `zlib`: this adds `ZlibCertificateCompression`, using the `miniz_oxide`
+//!   crate, and makes it the default for RFC8879 TLS1.3 certificate
+//!   compression in `ClientConfig` and `ServerConfig`.
+//!
+//! - `async`: this adds `AsyncStream`, which implements the `futures-io`
+//!   crate's `AsyncRead` and `AsyncWrite` traits over any async transport,
+//!   and the `connect` and `accept` functions to drive handshakes with it.
+//!
+//! - `ech`: this adds `ECHKey` and `ServerConfig::ech_keys`, so servers
+//!   can decrypt Encrypted Client Hello offers.  Server keys are long-lived
+//!   X25519 keys, which *ring* can't use, so this depends on the
+//!   `x25519-dalek` crate.  Clients can offer ECH without this feature.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code,
@@ -207,6 +224,18 @@ extern crate sct;
 // rust-base64 for pemfile module.
 extern crate base64;
 
+// miniz_oxide for zlib certificate compression (optional).
+#[cfg(feature = "zlib")]
+extern crate miniz_oxide;
+
+// futures-io for AsyncStream (optional).
+#[cfg(feature = "async")]
+extern crate futures_io;
+
+// x25519-dalek for Encrypted Client Hello server keys (optional).
+#[cfg(feature = "ech")]
+extern crate x25519_dalek;
+
 // log for logging (optional).
 #[cfg(feature = "logging")]
 #[macro_use]
@@ -233,11 +262,25 @@ mod vecbuf;
 mod prf;
 mod cipher;
 mod key_schedule;
+mod keylog;
+mod padding;
+mod compress;
+mod ffdhe;
+mod hpke;
+mod ech;
 mod session;
+mod quic;
 mod stream;
+#[cfg(feature = "async")]
+mod async_io;
 mod pemfile;
 mod x509;
 mod anchors;
+mod chain;
+mod crl;
+mod ocsp;
+mod pinning;
+mod spiffe;
 mod verify;
 #[cfg(test)]
 mod verifybench;
@@ -254,7 +297,7 @@ mod bs_debug;
 pub mod internal {
     /// Functions for parsing PEM files containing certificates/keys.
     pub mod pemfile {
-        pub use pemfile::{certs, rsa_private_keys, pkcs8_private_keys};
+        pub use pemfile::{certs, crls, rsa_private_keys, pkcs8_private_keys};
     }
 
     /// Low-level TLS message parsing and encoding functions.
@@ -266,30 +309,61 @@ pub mod internal {
 // The public interface is:
 pub use msgs::enums::ProtocolVersion;
 pub use msgs::enums::SignatureScheme;
+pub use msgs::enums::NamedGroup;
 pub use msgs::enums::CipherSuite;
+pub use msgs::enums::HashAlgorithm;
+pub use msgs::enums::PSKKeyExchangeMode;
+pub use msgs::enums::MaxFragmentLength;
+pub use msgs::enums::CertificateCompressionAlgorithm;
+pub use msgs::enums::AlertDescription;
+pub use msgs::enums::ContentType;
 pub use error::TLSError;
 pub use session::Session;
-pub use stream::Stream;
+pub use stream::{Stream, StreamOwned};
+#[cfg(feature = "async")]
+pub use async_io::{AsyncStream, Handshake, connect, accept};
 pub use anchors::{DistinguishedNames, RootCertStore};
+pub use crl::{CertRevocationList, CRLStore, CRLPolicy};
+pub use pinning::{SPKIPinSet, SPKI_PIN_LEN};
+pub use spiffe::SPIFFEID;
 pub use client::StoresClientSessions;
 pub use client::handy::{NoClientSessionStorage, ClientSessionMemoryCache};
-pub use client::{ClientConfig, ClientSession};
+pub use client::{ClientConfig, ClientSession, WriteEarlyData, ServerName};
 pub use client::ResolvesClientCert;
+pub use client::{StoresClientPsks, ExternalPsk};
+pub use client::handy::ClientPskStore;
 pub use server::StoresServerSessions;
 pub use server::handy::{NoServerSessionStorage, ServerSessionMemoryCache};
-pub use server::{ServerConfig, ServerSession};
+pub use server::DetectsEarlyDataReplay;
+pub use server::handy::EarlyDataReplayCache;
+pub use server::{ServerConfig, ServerSession, ReadEarlyData};
+pub use server::{Acceptor, Accepted};
 pub use server::handy::ResolvesServerCertUsingSNI;
 pub use server::ResolvesServerCert;
+pub use server::ResolvesServerAlpn;
 pub use server::ProducesTickets;
+pub use server::ResolvesPsk;
+pub use server::handy::ServerPskStore;
 pub use ticketer::Ticketer;
 pub use verify::{NoClientAuth, AllowAnyAuthenticatedClient,
-                 AllowAnyAnonymousOrAuthenticatedClient};
-pub use suites::{ALL_CIPHERSUITES, SupportedCipherSuite};
+                 AllowAnyAnonymousOrAuthenticatedClient, AllowSPIFFEClient};
+pub use suites::{ALL_CIPHERSUITES, SupportedCipherSuite, BulkAlgorithm};
 pub use key::{Certificate, PrivateKey};
+pub use keylog::{KeyLog, NoKeyLog, KeyLogFile};
+pub use padding::{RecordPadding, NoPadding, PadToBlockSize, PadToFullRecord};
+pub use compress::{CertificateCompressor, CertificateDecompressor};
+#[cfg(feature = "ech")]
+pub use ech::ECHKey;
+pub use quic::{QuicExt, QuicSecrets};
+#[cfg(feature = "zlib")]
+pub use compress::ZlibCertificateCompression;
 
 /// Message signing interfaces and implementations.
 pub mod sign;
 
+/// Cryptography interfaces, and the default implementation using *ring*.
+pub mod crypto;
+
 #[cfg(feature = "dangerous_configuration")]
 pub use verify::{ServerCertVerifier, ServerCertVerified,
     ClientCertVerifier, ClientCertVerified};
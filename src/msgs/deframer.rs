@@ -13,6 +13,11 @@ const HEADER_SIZE: usize = 1 + 2 + 2;
 /// for ciphertext overheads.
 const MAX_MESSAGE: usize = 16384 + 2048 + HEADER_SIZE;
 
+/// This is how much larger than its plaintext we allow a
+/// TLSCiphertext to be, once a smaller record size has been
+/// negotiated.
+const MAX_CIPHERTEXT_EXPANSION: usize = 256;
+
 /// This deframer works to reconstruct TLS messages
 /// from arbitrary-sized reads, buffering as necessary.
 /// The input is `read()`, the output is the `frames` deque.
@@ -28,6 +33,9 @@ pub struct MessageDeframer {
     /// A variable-size buffer containing the currently-
     /// accumulating TLS message.
     buf: Vec<u8>,
+
+    /// Messages with a payload this large or larger are refused.
+    max_payload: usize,
 }
 
 impl MessageDeframer {
@@ -36,6 +44,17 @@ impl MessageDeframer {
             frames: VecDeque::new(),
             desynced: false,
             buf: Vec::with_capacity(MAX_MESSAGE),
+            max_payload: MAX_MESSAGE - HEADER_SIZE,
+        }
+    }
+
+    /// Refuse any further messages which could not hold a protected
+    /// record of no more than `max_fragment_len` plaintext bytes.
+    /// This is used once a limit has been negotiated with the peer.
+    pub fn limit_fragment_len(&mut self, max_fragment_len: usize) {
+        let max_payload = max_fragment_len + MAX_CIPHERTEXT_EXPANSION + 1;
+        if max_payload < self.max_payload {
+            self.max_payload = max_payload;
         }
     }
 
@@ -101,7 +120,7 @@ impl MessageDeframer {
         let len = len_maybe.unwrap();
 
         // This is just too large.
-        if len >= MAX_MESSAGE - HEADER_SIZE {
+        if len >= self.max_payload {
             return None;
         }
 
@@ -248,4 +267,16 @@ mod tests {
         pop_second(&mut d);
         assert_eq!(d.has_pending(), false);
     }
+
+    #[test]
+    fn check_limited() {
+        let mut d = MessageDeframer::new();
+        d.limit_fragment_len(64);
+        input_bytes(&mut d, SECOND_MESSAGE).unwrap();
+        assert_eq!(d.desynced, false);
+        pop_second(&mut d);
+        input_bytes(&mut d, FIRST_MESSAGE).unwrap();
+        assert_eq!(d.desynced, true);
+        assert_eq!(d.frames.len(), 0);
+    }
 }
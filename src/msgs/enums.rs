@@ -42,7 +42,9 @@ enum_builder! {@U8
         Anonymous => 0x00,
         RSA => 0x01,
         DSA => 0x02,
-        ECDSA => 0x03
+        ECDSA => 0x03,
+        ED25519 => 0x07,
+        ED448 => 0x08
     }
 }
 
@@ -114,6 +116,7 @@ enum_builder! {@U8
         CertificateURL => 0x15,
         CertificateStatus => 0x16,
         KeyUpdate => 0x18,
+        CompressedCertificate => 0x19,
         MessageHash => 0xfe
     }
 }
@@ -168,7 +171,8 @@ enum_builder! {@U8
         BadCertificateHashValue => 0x72,
         UnknownPSKIdentity => 0x73,
         CertificateRequired => 0x74,
-        NoApplicationProtocol => 0x78
+        NoApplicationProtocol => 0x78,
+        ECHRequired => 0x79
     }
 }
 
@@ -209,6 +213,8 @@ enum_builder! {@U16
         SCT => 0x0012,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
+        CompressCertificate => 0x001b,
+        RecordSizeLimit => 0x001c,
         SessionTicket => 0x0023,
         PreSharedKey => 0x0029,
         EarlyData => 0x002a,
@@ -221,8 +227,11 @@ enum_builder! {@U16
         PostHandshakeAuth => 0x0031,
         SignatureAlgorithmsCert => 0x0032,
         KeyShare => 0x0033,
+        TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
         ChannelId => 0x754f,
+        ECHOuterExtensions => 0xfd00,
+        EncryptedClientHello => 0xfe0d,
         RenegotiationInfo => 0xff01
     }
 }
@@ -766,3 +775,88 @@ enum_builder! {@U8
         OCSP => 0x01
     }
 }
+
+/// The `MaxFragmentLength` TLS protocol enum.  Values in this enum are taken
+/// from the various RFCs covering TLS, and are listed by IANA.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U8
+    EnumName: MaxFragmentLength;
+    EnumVal{
+        Len512 => 0x01,
+        Len1024 => 0x02,
+        Len2048 => 0x03,
+        Len4096 => 0x04
+    }
+}
+
+/// The `CertificateCompressionAlgorithm` TLS protocol enum.  Values in this
+/// enum are taken from the various RFCs covering TLS, and are listed by IANA.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U16
+    EnumName: CertificateCompressionAlgorithm;
+    EnumVal{
+        Zlib => 0x0001,
+        Brotli => 0x0002,
+        Zstd => 0x0003
+    }
+}
+
+/// The `ECHClientHelloType` TLS protocol enum.  Values in this enum are taken
+/// from draft-ietf-tls-esni-13.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U8
+    EnumName: ECHClientHelloType;
+    EnumVal{
+        ClientHelloOuter => 0x00,
+        ClientHelloInner => 0x01
+    }
+}
+
+/// The `ECHVersion` TLS protocol enum.  Values in this enum are taken
+/// from draft-ietf-tls-esni-13.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U16
+    EnumName: ECHVersion;
+    EnumVal{
+        V13 => 0xfe0d
+    }
+}
+
+/// The `HpkeKem` protocol enum.  Values in this enum are taken
+/// from RFC9180, and are listed by IANA.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U16
+    EnumName: HpkeKem;
+    EnumVal{
+        DHKEM_P256_HKDF_SHA256 => 0x0010,
+        DHKEM_P384_HKDF_SHA384 => 0x0011,
+        DHKEM_P521_HKDF_SHA512 => 0x0012,
+        DHKEM_X25519_HKDF_SHA256 => 0x0020,
+        DHKEM_X448_HKDF_SHA512 => 0x0021
+    }
+}
+
+/// The `HpkeKdf` protocol enum.  Values in this enum are taken
+/// from RFC9180, and are listed by IANA.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U16
+    EnumName: HpkeKdf;
+    EnumVal{
+        HKDF_SHA256 => 0x0001,
+        HKDF_SHA384 => 0x0002,
+        HKDF_SHA512 => 0x0003
+    }
+}
+
+/// The `HpkeAead` protocol enum.  Values in this enum are taken
+/// from RFC9180, and are listed by IANA.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U16
+    EnumName: HpkeAead;
+    EnumVal{
+        AES_128_GCM => 0x0001,
+        AES_256_GCM => 0x0002,
+        CHACHA20_POLY1305 => 0x0003,
+        EXPORT_ONLY => 0xffff
+    }
+}
@@ -48,13 +48,13 @@ fn test_enum8<T: Codec>(first: T, last: T) {
 fn test_enums() {
     test_enum16::<ProtocolVersion>(ProtocolVersion::SSLv2, ProtocolVersion::TLSv1_3);
     test_enum8::<HashAlgorithm>(HashAlgorithm::NONE, HashAlgorithm::SHA512);
-    test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ECDSA);
+    test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ED448);
     test_enum8::<ClientCertificateType>(ClientCertificateType::RSASign, ClientCertificateType::ECDSAFixedECDH);
     test_enum8::<Compression>(Compression::Null, Compression::LSZ);
     test_enum8::<ContentType>(ContentType::ChangeCipherSpec, ContentType::Heartbeat);
     test_enum8::<HandshakeType>(HandshakeType::HelloRequest, HandshakeType::MessageHash);
     test_enum8::<AlertLevel>(AlertLevel::Warning, AlertLevel::Fatal);
-    test_enum8::<AlertDescription>(AlertDescription::CloseNotify, AlertDescription::NoApplicationProtocol);
+    test_enum8::<AlertDescription>(AlertDescription::CloseNotify, AlertDescription::ECHRequired);
     test_enum8::<HeartbeatMessageType>(HeartbeatMessageType::Request, HeartbeatMessageType::Response);
     test_enum16::<ExtensionType>(ExtensionType::ServerName, ExtensionType::RenegotiationInfo);
     test_enum8::<ServerNameType>(ServerNameType::HostName, ServerNameType::HostName);
@@ -68,4 +68,12 @@ fn test_enums() {
     test_enum8::<PSKKeyExchangeMode>(PSKKeyExchangeMode::PSK_KE, PSKKeyExchangeMode::PSK_DHE_KE);
     test_enum8::<KeyUpdateRequest>(KeyUpdateRequest::UpdateNotRequested, KeyUpdateRequest::UpdateRequested);
     test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
+    test_enum8::<MaxFragmentLength>(MaxFragmentLength::Len512, MaxFragmentLength::Len4096);
+    test_enum16::<CertificateCompressionAlgorithm>(CertificateCompressionAlgorithm::Zlib,
+                                                   CertificateCompressionAlgorithm::Zstd);
+    test_enum8::<ECHClientHelloType>(ECHClientHelloType::ClientHelloOuter,
+                                     ECHClientHelloType::ClientHelloInner);
+    test_enum16::<HpkeKem>(HpkeKem::DHKEM_P256_HKDF_SHA256, HpkeKem::DHKEM_X448_HKDF_SHA512);
+    test_enum16::<HpkeKdf>(HpkeKdf::HKDF_SHA256, HpkeKdf::HKDF_SHA512);
+    test_enum16::<HpkeAead>(HpkeAead::AES_128_GCM, HpkeAead::CHACHA20_POLY1305);
 }
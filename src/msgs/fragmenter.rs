@@ -1,11 +1,46 @@
 
+use std::cmp;
 use std::collections::VecDeque;
 use msgs::message::{BorrowMessage, Message, MessagePayload};
-use msgs::enums::{ContentType, ProtocolVersion};
+use msgs::enums::{ContentType, ProtocolVersion, MaxFragmentLength};
 
 pub const MAX_FRAGMENT_LEN: usize = 16384;
 pub const PACKET_OVERHEAD: usize = 1 + 2 + 2;
 
+/// The smallest `record_size_limit` anyone may ask for (RFC8449).
+pub const MIN_RECORD_SIZE_LIMIT: u16 = 64;
+
+/// The largest `record_size_limit` which means anything.  In TLS1.3
+/// the limit includes the inner content type byte.
+pub const MAX_RECORD_SIZE_LIMIT: u16 = MAX_FRAGMENT_LEN as u16 + 1;
+
+/// Clamps a configured `record_size_limit` into the range which
+/// may be sent.
+pub fn clamp_record_size_limit(limit: u16) -> u16 {
+    let limit = cmp::max(limit, MIN_RECORD_SIZE_LIMIT);
+    cmp::min(limit, MAX_RECORD_SIZE_LIMIT)
+}
+
+/// Returns the largest fragment of content allowed by a
+/// `record_size_limit` of `limit`.
+pub fn record_size_limit_to_fragment_len(limit: u16, tls13: bool) -> usize {
+    let limit = limit as usize;
+    let len = if tls13 { limit - 1 } else { limit };
+    cmp::min(len, MAX_FRAGMENT_LEN)
+}
+
+/// Returns the fragment length asked for with `max_fragment_length`,
+/// or None if `mfl` is not a valid request.
+pub fn max_fragment_length_to_len(mfl: MaxFragmentLength) -> Option<usize> {
+    match mfl {
+        MaxFragmentLength::Len512 => Some(512),
+        MaxFragmentLength::Len1024 => Some(1024),
+        MaxFragmentLength::Len2048 => Some(2048),
+        MaxFragmentLength::Len4096 => Some(4096),
+        MaxFragmentLength::Unknown(_) => None,
+    }
+}
+
 pub struct MessageFragmenter {
     max_frag: usize,
 }
@@ -20,6 +55,20 @@ impl MessageFragmenter {
         MessageFragmenter { max_frag: max_fragment_len }
     }
 
+    /// Lower the maximum fragment size to `max_fragment_len`, for
+    /// example once the peer has told us the largest record it will
+    /// accept.  This never raises the current maximum.
+    pub fn limit_fragment_len(&mut self, max_fragment_len: usize) {
+        if max_fragment_len < self.max_frag {
+            self.max_frag = max_fragment_len;
+        }
+    }
+
+    /// Returns the largest fragment we currently produce.
+    pub fn get_max_fragment_len(&self) -> usize {
+        self.max_frag
+    }
+
     /// Take the Message `msg` and re-fragment it into new
     /// messages whose fragment is no more than max_frag.
     /// The new messages are appended to the `out` deque.
@@ -65,7 +114,8 @@ impl MessageFragmenter {
 
 #[cfg(test)]
 mod tests {
-    use super::{MessageFragmenter, PACKET_OVERHEAD};
+    use super::{MessageFragmenter, PACKET_OVERHEAD, MAX_FRAGMENT_LEN};
+    use super::{MAX_RECORD_SIZE_LIMIT, record_size_limit_to_fragment_len};
     use msgs::message::{MessagePayload, Message};
     use msgs::enums::{ContentType, ProtocolVersion};
     use msgs::codec::Codec;
@@ -137,4 +187,24 @@ mod tests {
                b"\x01\x02\x03\x04\x05\x06\x07\x08");
         assert_eq!(q.len(), 0);
     }
+
+    #[test]
+    fn record_size_limits() {
+        assert_eq!(record_size_limit_to_fragment_len(64, false), 64);
+        assert_eq!(record_size_limit_to_fragment_len(64, true), 63);
+        assert_eq!(record_size_limit_to_fragment_len(MAX_RECORD_SIZE_LIMIT, false),
+                   MAX_FRAGMENT_LEN);
+        assert_eq!(record_size_limit_to_fragment_len(MAX_RECORD_SIZE_LIMIT, true),
+                   MAX_FRAGMENT_LEN);
+        assert_eq!(record_size_limit_to_fragment_len(0xffff, true), MAX_FRAGMENT_LEN);
+    }
+
+    #[test]
+    fn limit_only_lowers() {
+        let mut frag = MessageFragmenter::new(8);
+        frag.limit_fragment_len(16);
+        assert_eq!(frag.max_frag, 8);
+        frag.limit_fragment_len(4);
+        assert_eq!(frag.max_frag, 4);
+    }
 }
@@ -5,6 +5,8 @@ use msgs::enums::{SignatureScheme, KeyUpdateRequest, NamedGroup};
 use msgs::enums::{ClientCertificateType, CertificateStatusType};
 use msgs::enums::ECCurveType;
 use msgs::enums::PSKKeyExchangeMode;
+use msgs::enums::{MaxFragmentLength, CertificateCompressionAlgorithm};
+use msgs::enums::{ECHVersion, ECHClientHelloType, HpkeKem, HpkeKdf, HpkeAead};
 use msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
 use msgs::codec;
 use msgs::codec::{Codec, Reader};
@@ -176,7 +178,7 @@ impl SessionID {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnknownExtension {
     pub typ: ExtensionType,
     pub payload: Payload,
@@ -240,6 +242,8 @@ impl DecomposedSignatureScheme for SignatureScheme {
             SignatureScheme::ECDSA_NISTP256_SHA256 |
                 SignatureScheme::ECDSA_NISTP384_SHA384 |
                 SignatureScheme::ECDSA_NISTP521_SHA512 => SignatureAlgorithm::ECDSA,
+            SignatureScheme::ED25519 => SignatureAlgorithm::ED25519,
+            SignatureScheme::ED448 => SignatureAlgorithm::ED448,
             _ => SignatureAlgorithm::Unknown(0),
         }
     }
@@ -274,6 +278,8 @@ impl SupportedMandatedSignatureSchemes for SupportedSignatureSchemes {
             SignatureScheme::ECDSA_NISTP384_SHA384,
             SignatureScheme::ECDSA_NISTP256_SHA256,
 
+            SignatureScheme::ED25519,
+
             SignatureScheme::RSA_PSS_SHA512,
             SignatureScheme::RSA_PSS_SHA384,
             SignatureScheme::RSA_PSS_SHA256,
@@ -291,6 +297,8 @@ impl SupportedMandatedSignatureSchemes for SupportedSignatureSchemes {
             SignatureScheme::ECDSA_NISTP384_SHA384,
             SignatureScheme::ECDSA_NISTP256_SHA256,
 
+            SignatureScheme::ED25519,
+
             SignatureScheme::RSA_PSS_SHA512,
             SignatureScheme::RSA_PSS_SHA384,
             SignatureScheme::RSA_PSS_SHA256,
@@ -411,7 +419,7 @@ impl ConvertProtocolNameList for ProtocolNameList {
 }
 
 // --- TLS 1.3 Key shares ---
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KeyShareEntry {
     pub group: NamedGroup,
     pub payload: PayloadU16,
@@ -444,7 +452,7 @@ impl Codec for KeyShareEntry {
 }
 
 // --- TLS 1.3 PresharedKey offers ---
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PresharedKeyIdentity {
     pub identity: PayloadU16,
     pub obfuscated_ticket_age: u32,
@@ -477,7 +485,7 @@ declare_u16_vec!(PresharedKeyIdentities, PresharedKeyIdentity);
 pub type PresharedKeyBinder = PayloadU8;
 pub type PresharedKeyBinders = VecU16OfPayloadU8;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PresharedKeyOffer {
     pub identities: PresharedKeyIdentities,
     pub binders: PresharedKeyBinders,
@@ -510,7 +518,7 @@ impl Codec for PresharedKeyOffer {
 // --- RFC6066 certificate status request ---
 type ResponderIDs = VecU16OfPayloadU16;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OCSPCertificateStatusRequest {
     pub responder_ids: ResponderIDs,
     pub extensions: PayloadU16,
@@ -531,7 +539,7 @@ impl Codec for OCSPCertificateStatusRequest {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CertificateStatusRequest {
     OCSP(OCSPCertificateStatusRequest),
     Unknown((CertificateStatusType, Payload))
@@ -574,6 +582,218 @@ impl CertificateStatusRequest {
     }
 }
 
+// --- draft-ietf-tls-esni-13 encrypted client hello ---
+#[derive(Clone, Debug, PartialEq)]
+pub struct HpkeSymmetricCipherSuite {
+    pub kdf_id: HpkeKdf,
+    pub aead_id: HpkeAead,
+}
+
+impl Codec for HpkeSymmetricCipherSuite {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.kdf_id.encode(bytes);
+        self.aead_id.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<HpkeSymmetricCipherSuite> {
+        Some(HpkeSymmetricCipherSuite {
+            kdf_id: try_ret!(HpkeKdf::read(r)),
+            aead_id: try_ret!(HpkeAead::read(r)),
+        })
+    }
+}
+
+declare_u16_vec!(HpkeSymmetricCipherSuites, HpkeSymmetricCipherSuite);
+
+#[derive(Clone, Debug)]
+pub struct HpkeKeyConfig {
+    pub config_id: u8,
+    pub kem_id: HpkeKem,
+    pub public_key: PayloadU16,
+    pub symmetric_cipher_suites: HpkeSymmetricCipherSuites,
+}
+
+impl Codec for HpkeKeyConfig {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        codec::encode_u8(self.config_id, bytes);
+        self.kem_id.encode(bytes);
+        self.public_key.encode(bytes);
+        self.symmetric_cipher_suites.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<HpkeKeyConfig> {
+        Some(HpkeKeyConfig {
+            config_id: try_ret!(codec::read_u8(r)),
+            kem_id: try_ret!(HpkeKem::read(r)),
+            public_key: try_ret!(PayloadU16::read(r)),
+            symmetric_cipher_suites: try_ret!(HpkeSymmetricCipherSuites::read(r)),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ECHConfigExtension {
+    pub typ: ExtensionType,
+    pub payload: PayloadU16,
+}
+
+impl Codec for ECHConfigExtension {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.typ.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHConfigExtension> {
+        Some(ECHConfigExtension {
+            typ: try_ret!(ExtensionType::read(r)),
+            payload: try_ret!(PayloadU16::read(r)),
+        })
+    }
+}
+
+declare_u16_vec!(ECHConfigExtensions, ECHConfigExtension);
+
+#[derive(Clone, Debug)]
+pub struct ECHConfigContents {
+    pub key_config: HpkeKeyConfig,
+    pub maximum_name_length: u8,
+    pub public_name: PayloadU8,
+    pub extensions: ECHConfigExtensions,
+}
+
+impl Codec for ECHConfigContents {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.key_config.encode(bytes);
+        codec::encode_u8(self.maximum_name_length, bytes);
+        self.public_name.encode(bytes);
+        self.extensions.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHConfigContents> {
+        Some(ECHConfigContents {
+            key_config: try_ret!(HpkeKeyConfig::read(r)),
+            maximum_name_length: try_ret!(codec::read_u8(r)),
+            public_name: try_ret!(PayloadU8::read(r)),
+            extensions: try_ret!(ECHConfigExtensions::read(r)),
+        })
+    }
+}
+
+impl ECHConfigContents {
+    /// Mandatory extensions have the high bit of their type set.
+    /// We don't know any, so must skip configs which have them.
+    pub fn has_mandatory_extension(&self) -> bool {
+        self.extensions
+            .iter()
+            .any(|ext| ext.typ.get_u16() & 0x8000 != 0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ECHConfigPayload {
+    V13(ECHConfigContents),
+    Unknown(Payload),
+}
+
+#[derive(Clone, Debug)]
+pub struct ECHConfig {
+    pub version: ECHVersion,
+    pub contents: ECHConfigPayload,
+}
+
+impl Codec for ECHConfig {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.version.encode(bytes);
+
+        let mut sub: Vec<u8> = Vec::new();
+        match self.contents {
+            ECHConfigPayload::V13(ref r) => r.encode(&mut sub),
+            ECHConfigPayload::Unknown(ref r) => r.encode(&mut sub),
+        }
+
+        codec::encode_u16(sub.len() as u16, bytes);
+        bytes.append(&mut sub);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHConfig> {
+        let version = try_ret!(ECHVersion::read(r));
+        let len = try_ret!(codec::read_u16(r)) as usize;
+        let mut sub = try_ret!(r.sub(len));
+
+        let contents = match version {
+            ECHVersion::V13 => {
+                let contents = try_ret!(ECHConfigContents::read(&mut sub));
+                if sub.any_left() {
+                    return None;
+                }
+                ECHConfigPayload::V13(contents)
+            }
+            _ => ECHConfigPayload::Unknown(try_ret!(Payload::read(&mut sub))),
+        };
+
+        Some(ECHConfig {
+            version: version,
+            contents: contents,
+        })
+    }
+}
+
+declare_u16_vec!(ECHConfigList, ECHConfig);
+
+#[derive(Clone, Debug)]
+pub struct ECHClientHelloOuter {
+    pub cipher_suite: HpkeSymmetricCipherSuite,
+    pub config_id: u8,
+    pub enc: PayloadU16,
+    pub payload: PayloadU16,
+}
+
+impl Codec for ECHClientHelloOuter {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.cipher_suite.encode(bytes);
+        codec::encode_u8(self.config_id, bytes);
+        self.enc.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHClientHelloOuter> {
+        Some(ECHClientHelloOuter {
+            cipher_suite: try_ret!(HpkeSymmetricCipherSuite::read(r)),
+            config_id: try_ret!(codec::read_u8(r)),
+            enc: try_ret!(PayloadU16::read(r)),
+            payload: try_ret!(PayloadU16::read(r)),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum EncryptedClientHello {
+    Outer(ECHClientHelloOuter),
+    Inner,
+}
+
+impl Codec for EncryptedClientHello {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            EncryptedClientHello::Outer(ref r) => {
+                ECHClientHelloType::ClientHelloOuter.encode(bytes);
+                r.encode(bytes);
+            }
+            EncryptedClientHello::Inner => ECHClientHelloType::ClientHelloInner.encode(bytes),
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<EncryptedClientHello> {
+        match try_ret!(ECHClientHelloType::read(r)) {
+            ECHClientHelloType::ClientHelloOuter => {
+                Some(EncryptedClientHello::Outer(try_ret!(ECHClientHelloOuter::read(r))))
+            }
+            ECHClientHelloType::ClientHelloInner => Some(EncryptedClientHello::Inner),
+            _ => None,
+        }
+    }
+}
+
 // ---
 // SCTs
 
@@ -584,8 +804,9 @@ pub type SCTList = VecU16OfPayloadU16;
 declare_u8_vec!(PSKKeyExchangeModes, PSKKeyExchangeMode);
 declare_u16_vec!(KeyShareEntries, KeyShareEntry);
 declare_u8_vec!(ProtocolVersions, ProtocolVersion);
+declare_u8_vec!(CertificateCompressionAlgorithms, CertificateCompressionAlgorithm);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ClientExtension {
     ECPointFormats(ECPointFormatList),
     NamedGroups(NamedGroups),
@@ -602,6 +823,13 @@ pub enum ClientExtension {
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
     SignedCertificateTimestampRequest,
+    EarlyData,
+    PostHandshakeAuth,
+    MaxFragmentLength(MaxFragmentLength),
+    RecordSizeLimit(u16),
+    CertificateCompressionAlgorithms(CertificateCompressionAlgorithms),
+    EncryptedClientHello(EncryptedClientHello),
+    TransportParameters(Payload),
     Unknown(UnknownExtension),
 }
 
@@ -623,6 +851,15 @@ impl ClientExtension {
             ClientExtension::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             ClientExtension::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
             ClientExtension::SignedCertificateTimestampRequest => ExtensionType::SCT,
+            ClientExtension::EarlyData => ExtensionType::EarlyData,
+            ClientExtension::PostHandshakeAuth => ExtensionType::PostHandshakeAuth,
+            ClientExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
+            ClientExtension::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
+            ClientExtension::CertificateCompressionAlgorithms(_) => {
+                ExtensionType::CompressCertificate
+            }
+            ClientExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            ClientExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ClientExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -640,7 +877,9 @@ impl Codec for ClientExtension {
             ClientExtension::ServerName(ref r) => r.encode(&mut sub),
             ClientExtension::SessionTicketRequest |
                 ClientExtension::ExtendedMasterSecretRequest |
-                ClientExtension::SignedCertificateTimestampRequest => (),
+                ClientExtension::SignedCertificateTimestampRequest |
+                ClientExtension::EarlyData |
+                ClientExtension::PostHandshakeAuth => (),
             ClientExtension::SessionTicketOffer(ref r) => r.encode(&mut sub),
             ClientExtension::Protocols(ref r) => r.encode(&mut sub),
             ClientExtension::SupportedVersions(ref r) => r.encode(&mut sub),
@@ -649,6 +888,11 @@ impl Codec for ClientExtension {
             ClientExtension::PresharedKey(ref r) => r.encode(&mut sub),
             ClientExtension::Cookie(ref r) => r.encode(&mut sub),
             ClientExtension::CertificateStatusRequest(ref r) => r.encode(&mut sub),
+            ClientExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
+            ClientExtension::RecordSizeLimit(r) => codec::encode_u16(r, &mut sub),
+            ClientExtension::CertificateCompressionAlgorithms(ref r) => r.encode(&mut sub),
+            ClientExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            ClientExtension::TransportParameters(ref r) => r.encode(&mut sub),
             ClientExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -708,6 +952,27 @@ impl Codec for ClientExtension {
             ExtensionType::SCT if !sub.any_left() => {
                 ClientExtension::SignedCertificateTimestampRequest
             }
+            ExtensionType::EarlyData if !sub.any_left() => ClientExtension::EarlyData,
+            ExtensionType::PostHandshakeAuth if !sub.any_left() => {
+                ClientExtension::PostHandshakeAuth
+            }
+            ExtensionType::MaxFragmentLength => {
+                ClientExtension::MaxFragmentLength(try_ret!(MaxFragmentLength::read(&mut sub)))
+            }
+            ExtensionType::RecordSizeLimit => {
+                ClientExtension::RecordSizeLimit(try_ret!(codec::read_u16(&mut sub)))
+            }
+            ExtensionType::CompressCertificate => {
+                let algs = try_ret!(CertificateCompressionAlgorithms::read(&mut sub));
+                ClientExtension::CertificateCompressionAlgorithms(algs)
+            }
+            ExtensionType::EncryptedClientHello => {
+                let ech = try_ret!(EncryptedClientHello::read(&mut sub));
+                ClientExtension::EncryptedClientHello(ech)
+            }
+            ExtensionType::TransportParameters => {
+                ClientExtension::TransportParameters(try_ret!(Payload::read(&mut sub)))
+            }
             _ => ClientExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub))),
         })
     }
@@ -738,6 +1003,11 @@ pub enum ServerExtension {
     CertificateStatusAck,
     SignedCertificateTimestamp(SCTList),
     SupportedVersions(ProtocolVersion),
+    EarlyData,
+    MaxFragmentLength(MaxFragmentLength),
+    RecordSizeLimit(u16),
+    EncryptedClientHello(ECHConfigList),
+    TransportParameters(Payload),
     Unknown(UnknownExtension),
 }
 
@@ -755,6 +1025,11 @@ impl ServerExtension {
             ServerExtension::CertificateStatusAck => ExtensionType::StatusRequest,
             ServerExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             ServerExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            ServerExtension::EarlyData => ExtensionType::EarlyData,
+            ServerExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
+            ServerExtension::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
+            ServerExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            ServerExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ServerExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -770,13 +1045,18 @@ impl Codec for ServerExtension {
             ServerExtension::ServerNameAck |
                 ServerExtension::SessionTicketAck |
                 ServerExtension::ExtendedMasterSecretAck |
-                ServerExtension::CertificateStatusAck => (),
+                ServerExtension::CertificateStatusAck |
+                ServerExtension::EarlyData => (),
             ServerExtension::RenegotiationInfo(ref r) => r.encode(&mut sub),
             ServerExtension::Protocols(ref r) => r.encode(&mut sub),
             ServerExtension::KeyShare(ref r) => r.encode(&mut sub),
             ServerExtension::PresharedKey(r) => codec::encode_u16(r, &mut sub),
             ServerExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
             ServerExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            ServerExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
+            ServerExtension::RecordSizeLimit(r) => codec::encode_u16(r, &mut sub),
+            ServerExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            ServerExtension::TransportParameters(ref r) => r.encode(&mut sub),
             ServerExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -816,6 +1096,19 @@ impl Codec for ServerExtension {
             ExtensionType::SupportedVersions => {
                 ServerExtension::SupportedVersions(try_ret!(ProtocolVersion::read(&mut sub)))
             }
+            ExtensionType::EarlyData if !sub.any_left() => ServerExtension::EarlyData,
+            ExtensionType::MaxFragmentLength => {
+                ServerExtension::MaxFragmentLength(try_ret!(MaxFragmentLength::read(&mut sub)))
+            }
+            ExtensionType::RecordSizeLimit => {
+                ServerExtension::RecordSizeLimit(try_ret!(codec::read_u16(&mut sub)))
+            }
+            ExtensionType::EncryptedClientHello => {
+                ServerExtension::EncryptedClientHello(try_ret!(ECHConfigList::read(&mut sub)))
+            }
+            ExtensionType::TransportParameters => {
+                ServerExtension::TransportParameters(try_ret!(Payload::read(&mut sub)))
+            }
             _ => ServerExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub))),
         })
     }
@@ -838,7 +1131,7 @@ impl ServerExtension {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientHelloPayload {
     pub client_version: ProtocolVersion,
     pub random: Random,
@@ -1013,10 +1306,10 @@ impl ClientHelloPayload {
     }
 
 
-    pub fn set_psk_binder(&mut self, binder: Vec<u8>) {
+    pub fn set_psk_binder(&mut self, index: usize, binder: Vec<u8>) {
         let last_extension = self.extensions.last_mut().unwrap();
         if let ClientExtension::PresharedKey(ref mut offer) = *last_extension {
-            offer.binders[0] = PresharedKeyBinder::new(binder);
+            offer.binders[index] = PresharedKeyBinder::new(binder);
         }
     }
 
@@ -1024,6 +1317,81 @@ impl ClientHelloPayload {
         self.find_extension(ExtensionType::ExtendedMasterSecret)
             .is_some()
     }
+
+    pub fn early_data_extension_offered(&self) -> bool {
+        self.find_extension(ExtensionType::EarlyData)
+            .is_some()
+    }
+
+    pub fn post_handshake_auth_offered(&self) -> bool {
+        self.find_extension(ExtensionType::PostHandshakeAuth)
+            .is_some()
+    }
+
+    pub fn get_max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        let ext = try_ret!(self.find_extension(ExtensionType::MaxFragmentLength));
+        match *ext {
+            ClientExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
+
+    pub fn get_record_size_limit(&self) -> Option<u16> {
+        let ext = try_ret!(self.find_extension(ExtensionType::RecordSizeLimit));
+        match *ext {
+            ClientExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    pub fn get_certificate_compression_extension(&self)
+                                                 -> Option<&CertificateCompressionAlgorithms> {
+        let ext = try_ret!(self.find_extension(ExtensionType::CompressCertificate));
+        match *ext {
+            ClientExtension::CertificateCompressionAlgorithms(ref algs) => Some(algs),
+            _ => None,
+        }
+    }
+
+    pub fn get_ech_extension(&self) -> Option<&EncryptedClientHello> {
+        let ext = try_ret!(self.find_extension(ExtensionType::EncryptedClientHello));
+        match *ext {
+            ClientExtension::EncryptedClientHello(ref ech) => Some(ech),
+            _ => None,
+        }
+    }
+
+    pub fn get_quic_params_extension(&self) -> Option<&[u8]> {
+        let ext = try_ret!(self.find_extension(ExtensionType::TransportParameters));
+        match *ext {
+            ClientExtension::TransportParameters(ref params) => Some(&params.0),
+            _ => None,
+        }
+    }
+
+    pub fn set_ech_payload(&mut self, payload: Vec<u8>) {
+        let last_extension = self.extensions.last_mut().unwrap();
+        if let ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(ref mut outer)) =
+            *last_extension {
+            outer.payload = PayloadU16::new(payload);
+        }
+    }
+
+    /// Our encoding with the encrypted ECH payload replaced with zeroes.
+    /// This is the additional data for the ECH encryption.
+    pub fn get_encoding_for_ech_aad(&self) -> Vec<u8> {
+        let mut aad = self.clone();
+
+        for ext in &mut aad.extensions {
+            if let ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(ref mut outer)) =
+                *ext {
+                let len = outer.payload.0.len();
+                outer.payload = PayloadU16::new(vec![0u8; len]);
+            }
+        }
+
+        aad.get_encoding()
+    }
 }
 
 #[derive(Debug)]
@@ -1031,6 +1399,7 @@ pub enum HelloRetryExtension {
     KeyShare(NamedGroup),
     Cookie(PayloadU16),
     SupportedVersions(ProtocolVersion),
+    EncryptedClientHello(Payload),
     Unknown(UnknownExtension),
 }
 
@@ -1040,6 +1409,7 @@ impl HelloRetryExtension {
             HelloRetryExtension::KeyShare(_) => ExtensionType::KeyShare,
             HelloRetryExtension::Cookie(_) => ExtensionType::Cookie,
             HelloRetryExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            HelloRetryExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             HelloRetryExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1054,6 +1424,7 @@ impl Codec for HelloRetryExtension {
             HelloRetryExtension::KeyShare(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Cookie(ref r) => r.encode(&mut sub),
             HelloRetryExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            HelloRetryExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1076,6 +1447,9 @@ impl Codec for HelloRetryExtension {
             ExtensionType::SupportedVersions => {
                 HelloRetryExtension::SupportedVersions(try_ret!(ProtocolVersion::read(&mut sub)))
             }
+            ExtensionType::EncryptedClientHello => {
+                HelloRetryExtension::EncryptedClientHello(try_ret!(Payload::read(&mut sub)))
+            }
             _ => HelloRetryExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub))),
         })
     }
@@ -1141,7 +1515,8 @@ impl HelloRetryRequest {
             .any(|ext| {
                  ext.get_type() != ExtensionType::KeyShare &&
                  ext.get_type() != ExtensionType::SupportedVersions &&
-                 ext.get_type() != ExtensionType::Cookie
+                 ext.get_type() != ExtensionType::Cookie &&
+                 ext.get_type() != ExtensionType::EncryptedClientHello
                  })
     }
 
@@ -1172,6 +1547,14 @@ impl HelloRetryRequest {
             _ => None,
         }
     }
+
+    pub fn get_ech_confirmation(&self) -> Option<&[u8]> {
+        let ext = try_ret!(self.find_extension(ExtensionType::EncryptedClientHello));
+        match *ext {
+            HelloRetryExtension::EncryptedClientHello(ref conf) => Some(&conf.0),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1511,6 +1894,30 @@ impl CertificatePayloadTLS13 {
     }
 }
 
+// -- CompressedCertificate (RFC8879) --
+#[derive(Debug)]
+pub struct CompressedCertificatePayload {
+    pub alg: CertificateCompressionAlgorithm,
+    pub uncompressed_len: u32,
+    pub compressed: PayloadU24,
+}
+
+impl Codec for CompressedCertificatePayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.alg.encode(bytes);
+        codec::encode_u24(self.uncompressed_len, bytes);
+        self.compressed.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<CompressedCertificatePayload> {
+        Some(CompressedCertificatePayload {
+            alg: try_ret!(CertificateCompressionAlgorithm::read(r)),
+            uncompressed_len: try_ret!(codec::read_u24(r)),
+            compressed: try_ret!(PayloadU24::read(r)),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum KeyExchangeAlgorithm {
     BulkOnly,
@@ -1635,6 +2042,59 @@ impl Codec for ServerECDHParams {
     }
 }
 
+#[derive(Debug)]
+pub struct ClientDHParams {
+    pub public: PayloadU16,
+}
+
+impl Codec for ClientDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.public.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ClientDHParams> {
+        let pb = try_ret!(PayloadU16::read(r));
+        Some(ClientDHParams { public: pb })
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerDHParams {
+    pub dh_p: PayloadU16,
+    pub dh_g: PayloadU16,
+    pub dh_ys: PayloadU16,
+}
+
+impl ServerDHParams {
+    pub fn new(p: &[u8], g: &[u8], pubkey: &[u8]) -> ServerDHParams {
+        ServerDHParams {
+            dh_p: PayloadU16::new(p.to_vec()),
+            dh_g: PayloadU16::new(g.to_vec()),
+            dh_ys: PayloadU16::new(pubkey.to_vec()),
+        }
+    }
+}
+
+impl Codec for ServerDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.dh_p.encode(bytes);
+        self.dh_g.encode(bytes);
+        self.dh_ys.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ServerDHParams> {
+        let p = try_ret!(PayloadU16::read(r));
+        let g = try_ret!(PayloadU16::read(r));
+        let ys = try_ret!(PayloadU16::read(r));
+
+        Some(ServerDHParams {
+            dh_p: p,
+            dh_g: g,
+            dh_ys: ys,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct ECDHEServerKeyExchange {
     pub params: ServerECDHParams,
@@ -1658,9 +2118,33 @@ impl Codec for ECDHEServerKeyExchange {
     }
 }
 
+#[derive(Debug)]
+pub struct DHEServerKeyExchange {
+    pub params: ServerDHParams,
+    pub dss: DigitallySignedStruct,
+}
+
+impl Codec for DHEServerKeyExchange {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.params.encode(bytes);
+        self.dss.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<DHEServerKeyExchange> {
+        let params = try_ret!(ServerDHParams::read(r));
+        let dss = try_ret!(DigitallySignedStruct::read(r));
+
+        Some(DHEServerKeyExchange {
+            params: params,
+            dss: dss,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum ServerKeyExchangePayload {
     ECDHE(ECDHEServerKeyExchange),
+    DHE(DHEServerKeyExchange),
     Unknown(Payload),
 }
 
@@ -1668,6 +2152,7 @@ impl Codec for ServerKeyExchangePayload {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => x.encode(bytes),
+            ServerKeyExchangePayload::DHE(ref x) => x.encode(bytes),
             ServerKeyExchangePayload::Unknown(ref x) => x.encode(bytes),
         }
     }
@@ -1689,6 +2174,10 @@ impl ServerKeyExchangePayload {
                     ECDHEServerKeyExchange::read(&mut rd)
                         .and_then(|x| Some(ServerKeyExchangePayload::ECDHE(x)))
                 }
+                KeyExchangeAlgorithm::DHE => {
+                    DHEServerKeyExchange::read(&mut rd)
+                        .and_then(|x| Some(ServerKeyExchangePayload::DHE(x)))
+                }
                 _ => None,
             };
 
@@ -1705,6 +2194,7 @@ impl ServerKeyExchangePayload {
 
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => x.params.encode(bytes),
+            ServerKeyExchangePayload::DHE(ref x) => x.params.encode(bytes),
             _ => (),
         };
     }
@@ -1712,6 +2202,7 @@ impl ServerKeyExchangePayload {
     pub fn get_sig(&self) -> Option<DigitallySignedStruct> {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => Some(x.dss.clone()),
+            ServerKeyExchangePayload::DHE(ref x) => Some(x.dss.clone()),
             _ => None,
         }
     }
@@ -1751,6 +2242,42 @@ pub trait HasServerExtensions {
             _ => None,
         }
     }
+
+    fn early_data_extension_offered(&self) -> bool {
+        self.find_extension(ExtensionType::EarlyData).is_some()
+    }
+
+    fn get_max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        let ext = try_ret!(self.find_extension(ExtensionType::MaxFragmentLength));
+        match *ext {
+            ServerExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
+
+    fn get_record_size_limit(&self) -> Option<u16> {
+        let ext = try_ret!(self.find_extension(ExtensionType::RecordSizeLimit));
+        match *ext {
+            ServerExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    fn get_ech_retry_configs(&self) -> Option<&ECHConfigList> {
+        let ext = try_ret!(self.find_extension(ExtensionType::EncryptedClientHello));
+        match *ext {
+            ServerExtension::EncryptedClientHello(ref configs) => Some(configs),
+            _ => None,
+        }
+    }
+
+    fn get_quic_params_extension(&self) -> Option<&[u8]> {
+        let ext = try_ret!(self.find_extension(ExtensionType::TransportParameters));
+        match *ext {
+            ServerExtension::TransportParameters(ref params) => Some(&params.0),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for EncryptedExtensions {
@@ -1925,12 +2452,14 @@ impl Codec for NewSessionTicketPayload {
 // -- NewSessionTicket electric boogaloo --
 #[derive(Debug)]
 pub enum NewSessionTicketExtension {
+    EarlyData(u32),
     Unknown(UnknownExtension),
 }
 
 impl NewSessionTicketExtension {
     pub fn get_type(&self) -> ExtensionType {
         match *self {
+            NewSessionTicketExtension::EarlyData(_) => ExtensionType::EarlyData,
             NewSessionTicketExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1942,6 +2471,7 @@ impl Codec for NewSessionTicketExtension {
 
         let mut sub: Vec<u8> = Vec::new();
         match *self {
+            NewSessionTicketExtension::EarlyData(r) => codec::encode_u32(r, &mut sub),
             NewSessionTicketExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1955,6 +2485,9 @@ impl Codec for NewSessionTicketExtension {
         let mut sub = try_ret!(r.sub(len));
 
         Some(match typ {
+            ExtensionType::EarlyData => {
+                NewSessionTicketExtension::EarlyData(try_ret!(codec::read_u32(&mut sub)))
+            }
             _ => {
                 NewSessionTicketExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
             }
@@ -1986,6 +2519,18 @@ impl NewSessionTicketPayloadTLS13 {
             exts: vec![],
         }
     }
+
+    pub fn find_extension(&self, ext: ExtensionType) -> Option<&NewSessionTicketExtension> {
+        self.exts.iter().find(|x| x.get_type() == ext)
+    }
+
+    pub fn get_max_early_data_size(&self) -> Option<u32> {
+        let ext = try_ret!(self.find_extension(ExtensionType::EarlyData));
+        match *ext {
+            NewSessionTicketExtension::EarlyData(ref sz) => Some(*sz),
+            _ => None,
+        }
+    }
 }
 
 impl Codec for NewSessionTicketPayloadTLS13 {
@@ -2061,11 +2606,13 @@ pub enum HandshakePayload {
     HelloRetryRequest(HelloRetryRequest),
     Certificate(CertificatePayload),
     CertificateTLS13(CertificatePayloadTLS13),
+    CompressedCertificate(CompressedCertificatePayload),
     ServerKeyExchange(ServerKeyExchangePayload),
     CertificateRequest(CertificateRequestPayload),
     CertificateRequestTLS13(CertificateRequestPayloadTLS13),
     CertificateVerify(DigitallySignedStruct),
     ServerHelloDone,
+    EndOfEarlyData,
     ClientKeyExchange(Payload),
     NewSessionTicket(NewSessionTicketPayload),
     NewSessionTicketTLS13(NewSessionTicketPayloadTLS13),
@@ -2081,12 +2628,14 @@ impl HandshakePayload {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match *self {
             HandshakePayload::HelloRequest |
-                HandshakePayload::ServerHelloDone => {}
+                HandshakePayload::ServerHelloDone |
+                HandshakePayload::EndOfEarlyData => {}
             HandshakePayload::ClientHello(ref x) => x.encode(bytes),
             HandshakePayload::ServerHello(ref x) => x.encode(bytes),
             HandshakePayload::HelloRetryRequest(ref x) => x.encode(bytes),
             HandshakePayload::Certificate(ref x) => x.encode(bytes),
             HandshakePayload::CertificateTLS13(ref x) => x.encode(bytes),
+            HandshakePayload::CompressedCertificate(ref x) => x.encode(bytes),
             HandshakePayload::ServerKeyExchange(ref x) => x.encode(bytes),
             HandshakePayload::ClientKeyExchange(ref x) => x.encode(bytes),
             HandshakePayload::CertificateRequest(ref x) => x.encode(bytes),
@@ -2170,6 +2719,10 @@ impl HandshakeMessagePayload {
             HandshakeType::Certificate => {
                 HandshakePayload::Certificate(try_ret!(CertificatePayload::read(&mut sub)))
             }
+            HandshakeType::CompressedCertificate => {
+                let p = try_ret!(CompressedCertificatePayload::read(&mut sub));
+                HandshakePayload::CompressedCertificate(p)
+            }
             HandshakeType::ServerKeyExchange => {
                 let p = try_ret!(ServerKeyExchangePayload::read(&mut sub));
                 HandshakePayload::ServerKeyExchange(p)
@@ -2180,6 +2733,12 @@ impl HandshakeMessagePayload {
                 }
                 HandshakePayload::ServerHelloDone
             }
+            HandshakeType::EndOfEarlyData => {
+                if sub.any_left() {
+                    return None;
+                }
+                HandshakePayload::EndOfEarlyData
+            }
             HandshakeType::ClientKeyExchange => {
                 HandshakePayload::ClientKeyExchange(try_ret!(Payload::read(&mut sub)))
             }
@@ -2242,6 +2801,13 @@ impl HandshakeMessagePayload {
         }
     }
 
+    pub fn build_key_update_request() -> HandshakeMessagePayload {
+        HandshakeMessagePayload {
+            typ: HandshakeType::KeyUpdate,
+            payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateRequested),
+        }
+    }
+
     pub fn get_encoding_for_binder_signing(&self) -> Vec<u8> {
         let mut ret = self.get_encoding();
 
@@ -2261,6 +2827,44 @@ impl HandshakeMessagePayload {
         ret
     }
 
+    /// Our encoding, with the ECH acceptance signal replaced with
+    /// zeroes.  For a ServerHello this is the last eight bytes of
+    /// the random; for a HelloRetryRequest it is the payload of its
+    /// encrypted_client_hello extension.
+    pub fn get_encoding_for_ech_confirmation(&self) -> Vec<u8> {
+        let mut ret = self.get_encoding();
+
+        // handshake header, then legacy_version.
+        let random_start = 4 + 2;
+
+        let (start, len) = match self.payload {
+            HandshakePayload::ServerHello(_) => (random_start + 24, 8),
+            HandshakePayload::HelloRetryRequest(ref hrr) => {
+                // random, session_id, cipher_suite, compression
+                // and extensions length.
+                let mut offs = random_start + 32 + 1 + hrr.session_id.len() + 2 + 1 + 2;
+                let mut found = (0, 0);
+
+                for ext in &hrr.extensions {
+                    let encoding = ext.get_encoding();
+                    if let HelloRetryExtension::EncryptedClientHello(ref conf) = *ext {
+                        found = (offs + 4, conf.len());
+                        break;
+                    }
+                    offs += encoding.len();
+                }
+
+                found
+            }
+            _ => (0, 0),
+        };
+
+        for x in &mut ret[start..start + len] {
+            *x = 0;
+        }
+        ret
+    }
+
     pub fn build_handshake_hash(hash: &[u8]) -> HandshakeMessagePayload {
         HandshakeMessagePayload {
             typ: HandshakeType::MessageHash,
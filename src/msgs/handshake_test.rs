@@ -345,6 +345,15 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::SignedCertificateTimestampRequest,
+            ClientExtension::PostHandshakeAuth,
+            ClientExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
+            ClientExtension::RecordSizeLimit(1024),
+            ClientExtension::CertificateCompressionAlgorithms(vec![
+                CertificateCompressionAlgorithm::Zlib,
+                CertificateCompressionAlgorithm::Brotli,
+            ]),
+            ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(get_sample_echouter())),
+            ClientExtension::TransportParameters(Payload(vec![ 1, 2, 3 ])),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -358,6 +367,81 @@ fn can_print_all_clientextensions() {
     println!("client hello {:?}", get_sample_clienthellopayload());
 }
 
+fn get_sample_echouter() -> ECHClientHelloOuter {
+    ECHClientHelloOuter {
+        cipher_suite: HpkeSymmetricCipherSuite {
+            kdf_id: HpkeKdf::HKDF_SHA256,
+            aead_id: HpkeAead::AES_128_GCM,
+        },
+        config_id: 1,
+        enc: PayloadU16(vec![ 1, 2, 3 ]),
+        payload: PayloadU16(vec![ 4, 5, 6 ]),
+    }
+}
+
+fn get_sample_echconfiglist() -> ECHConfigList {
+    vec![
+        ECHConfig {
+            version: ECHVersion::V13,
+            contents: ECHConfigPayload::V13(ECHConfigContents {
+                key_config: HpkeKeyConfig {
+                    config_id: 1,
+                    kem_id: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+                    public_key: PayloadU16(vec![ 1, 2, 3 ]),
+                    symmetric_cipher_suites: vec![
+                        HpkeSymmetricCipherSuite {
+                            kdf_id: HpkeKdf::HKDF_SHA256,
+                            aead_id: HpkeAead::CHACHA20_POLY1305,
+                        },
+                    ],
+                },
+                maximum_name_length: 0,
+                public_name: PayloadU8(b"example.com".to_vec()),
+                extensions: vec![
+                    ECHConfigExtension {
+                        typ: ExtensionType::Unknown(0x8001),
+                        payload: PayloadU16(vec![ 1 ]),
+                    },
+                ],
+            }),
+        },
+        ECHConfig {
+            version: ECHVersion::Unknown(0xfe0a),
+            contents: ECHConfigPayload::Unknown(Payload(vec![ 1, 2, 3 ])),
+        },
+    ]
+}
+
+#[test]
+fn ech_config_list_roundtrip() {
+    let list = get_sample_echconfiglist();
+    let mut enc = Vec::new();
+    list.encode(&mut enc);
+
+    let mut rd = Reader::init(&enc);
+    let other = ECHConfigList::read(&mut rd).unwrap();
+    assert!(!rd.any_left());
+    assert_eq!(other.get_encoding(), enc);
+
+    match other[0].contents {
+        ECHConfigPayload::V13(ref contents) => assert!(contents.has_mandatory_extension()),
+        _ => panic!("unexpected config version"),
+    }
+    match other[1].contents {
+        ECHConfigPayload::Unknown(ref payload) => assert_eq!(payload.0, vec![ 1, 2, 3 ]),
+        _ => panic!("unexpected config version"),
+    }
+}
+
+#[test]
+fn ech_inner_extension_roundtrip() {
+    let enc = EncryptedClientHello::Inner.get_encoding();
+    assert_eq!(enc, vec![ 0x01 ]);
+
+    let mut rd = Reader::init(&[ 0x02 ]);
+    assert!(EncryptedClientHello::read(&mut rd).is_none());
+}
+
 fn get_sample_serverhellopayload() -> ServerHelloPayload {
     ServerHelloPayload {
         legacy_version: ProtocolVersion::TLSv1_2,
@@ -377,6 +461,10 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::CertificateStatusAck,
             ServerExtension::SignedCertificateTimestamp(vec![ PayloadU16(vec![0]) ]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            ServerExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
+            ServerExtension::RecordSizeLimit(1024),
+            ServerExtension::EncryptedClientHello(get_sample_echconfiglist()),
+            ServerExtension::TransportParameters(Payload(vec![ 1, 2, 3 ])),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -394,6 +482,7 @@ fn get_sample_helloretryrequest() -> HelloRetryRequest {
             HelloRetryExtension::KeyShare(NamedGroup::X25519),
             HelloRetryExtension::Cookie(PayloadU16(vec![ 0 ])),
             HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            HelloRetryExtension::EncryptedClientHello(Payload(vec![ 0; 8 ])),
             HelloRetryExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -607,6 +696,14 @@ fn can_roundtrip_all_tls13_handshake_payloads() {
             typ: HandshakeType::Certificate,
             payload: HandshakePayload::CertificateTLS13(get_sample_certificatepayloadtls13()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::CompressedCertificate,
+            payload: HandshakePayload::CompressedCertificate(CompressedCertificatePayload {
+                alg: CertificateCompressionAlgorithm::Zlib,
+                uncompressed_len: 1234,
+                compressed: PayloadU24(vec![ 1, 2, 3 ]),
+            }),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(get_sample_serverkeyexchangepayload_ecdhe()),
@@ -4,6 +4,8 @@ use msgs::codec::{Reader, Codec};
 use msgs::handshake::CertificatePayload;
 use msgs::base::{PayloadU8, PayloadU16};
 use msgs::codec;
+use client::ServerName;
+use spiffe::SPIFFEID;
 
 use webpki;
 use untrusted;
@@ -19,13 +21,13 @@ use std::cmp;
 #[derive(Debug)]
 pub struct ClientSessionKey {
     kind: &'static [u8],
-    dns_name: PayloadU8,
+    server_name: PayloadU8,
 }
 
 impl Codec for ClientSessionKey {
     fn encode(&self, bytes: &mut Vec<u8>) {
         bytes.extend_from_slice(self.kind);
-        self.dns_name.encode(bytes);
+        self.server_name.encode(bytes);
     }
 
     // Don't need to read these.
@@ -35,20 +37,33 @@ impl Codec for ClientSessionKey {
 }
 
 impl ClientSessionKey {
-    pub fn session_for_dns_name(dns_name: webpki::DNSNameRef) -> ClientSessionKey {
-        let dns_name_str: &str = dns_name.into();
+    fn new(kind: &'static [u8], server_name: &ServerName) -> ClientSessionKey {
+        // IP addresses are bracketed, so they can't be confused
+        // with DNS names.
+        let name = match *server_name {
+            ServerName::DNSName(ref dns_name) => {
+                let dns_name_str: &str = dns_name.as_ref().into();
+                dns_name_str.to_string()
+            }
+            ServerName::IPAddress(ref ip) => format!("[{}]", ip),
+        };
+
         ClientSessionKey {
-            kind: b"session",
-            dns_name: PayloadU8::new(dns_name_str.as_bytes().to_vec()),
+            kind: kind,
+            server_name: PayloadU8::new(name.into_bytes()),
         }
     }
 
-    pub fn hint_for_dns_name(dns_name: webpki::DNSNameRef) -> ClientSessionKey {
-        let dns_name_str: &str = dns_name.into();
-        ClientSessionKey {
-            kind: b"kx-hint",
-            dns_name: PayloadU8::new(dns_name_str.as_bytes().to_vec()),
-        }
+    pub fn session_for_server_name(server_name: &ServerName) -> ClientSessionKey {
+        ClientSessionKey::new(b"session", server_name)
+    }
+
+    pub fn tls13_tickets_for_server_name(server_name: &ServerName) -> ClientSessionKey {
+        ClientSessionKey::new(b"tls13-tickets", server_name)
+    }
+
+    pub fn hint_for_server_name(server_name: &ServerName) -> ClientSessionKey {
+        ClientSessionKey::new(b"kx-hint", server_name)
     }
 }
 
@@ -63,6 +78,7 @@ pub struct ClientSessionValue {
     pub lifetime: u32,
     pub age_add: u32,
     pub extended_ms: bool,
+    pub max_early_data_size: u32,
 }
 
 impl Codec for ClientSessionValue {
@@ -76,6 +92,7 @@ impl Codec for ClientSessionValue {
         codec::encode_u32(self.lifetime, bytes);
         codec::encode_u32(self.age_add, bytes);
         codec::encode_u8(if self.extended_ms { 1u8 } else { 0u8 }, bytes);
+        codec::encode_u32(self.max_early_data_size, bytes);
     }
 
     fn read(r: &mut Reader) -> Option<ClientSessionValue> {
@@ -88,6 +105,7 @@ impl Codec for ClientSessionValue {
         let lifetime = try_ret!(codec::read_u32(r));
         let age_add = try_ret!(codec::read_u32(r));
         let extended_ms = try_ret!(codec::read_u8(r));
+        let max_early_data_size = try_ret!(codec::read_u32(r));
 
         Some(ClientSessionValue {
             version: v,
@@ -99,6 +117,7 @@ impl Codec for ClientSessionValue {
             lifetime: lifetime,
             age_add: age_add,
             extended_ms: extended_ms == 1u8,
+            max_early_data_size: max_early_data_size,
         })
     }
 }
@@ -122,6 +141,7 @@ impl ClientSessionValue {
             lifetime: 0,
             age_add: 0,
             extended_ms: false,
+            max_early_data_size: 0,
         }
     }
 
@@ -129,6 +149,10 @@ impl ClientSessionValue {
         self.extended_ms = true;
     }
 
+    pub fn set_max_early_data_size(&mut self, sz: u32) {
+        self.max_early_data_size = sz;
+    }
+
     pub fn set_times(&mut self, receipt_time_secs: u64,
                      lifetime_secs: u32, age_add: u32) {
         self.epoch = receipt_time_secs;
@@ -143,7 +167,7 @@ impl ClientSessionValue {
     pub fn get_obfuscated_ticket_age(&self, time_now: u64) -> u32 {
         let age_secs = time_now.saturating_sub(self.epoch);
         let age_millis = age_secs as u32 * 1000;
-        age_millis.wrapping_sub(self.age_add)
+        age_millis.wrapping_add(self.age_add)
     }
 
     pub fn take_ticket(&mut self) -> Vec<u8> {
@@ -163,7 +187,11 @@ pub struct ServerSessionValue {
     pub cipher_suite: CipherSuite,
     pub master_secret: PayloadU8,
     pub extended_ms: bool,
+    pub alpn: Option<PayloadU8>,
+    pub creation_time_sec: u64,
+    pub age_obfuscation_offset: u32,
     pub client_cert_chain: Option<CertificatePayload>,
+    pub client_spiffe_id: Option<SPIFFEID>,
 }
 
 impl Codec for ServerSessionValue {
@@ -179,8 +207,20 @@ impl Codec for ServerSessionValue {
         self.cipher_suite.encode(bytes);
         self.master_secret.encode(bytes);
         codec::encode_u8(if self.extended_ms { 1u8 } else { 0u8 }, bytes);
+        if let Some(ref alpn) = self.alpn {
+            codec::encode_u8(1, bytes);
+            alpn.encode(bytes);
+        } else {
+            codec::encode_u8(0, bytes);
+        }
+        codec::encode_u64(self.creation_time_sec, bytes);
+        codec::encode_u32(self.age_obfuscation_offset, bytes);
         if self.client_cert_chain.is_some() {
             self.client_cert_chain.as_ref().unwrap().encode(bytes);
+
+            if let Some(ref id) = self.client_spiffe_id {
+                PayloadU16::new(id.to_string().into_bytes()).encode(bytes);
+            }
         }
     }
 
@@ -198,11 +238,26 @@ impl Codec for ServerSessionValue {
         let cs = try_ret!(CipherSuite::read(r));
         let ms = try_ret!(PayloadU8::read(r));
         let ems = try_ret!(codec::read_u8(r));
+        let has_alpn = try_ret!(codec::read_u8(r));
+        let alpn = if has_alpn == 1 {
+            Some(try_ret!(PayloadU8::read(r)))
+        } else {
+            None
+        };
+        let creation_time_sec = try_ret!(codec::read_u64(r));
+        let age_obfuscation_offset = try_ret!(codec::read_u32(r));
         let ccert = if r.any_left() {
             CertificatePayload::read(r)
         } else {
             None
         };
+        let spiffe_id = if ccert.is_some() && r.any_left() {
+            let uri = try_ret!(PayloadU16::read(r));
+            let uri = try_ret!(String::from_utf8(uri.0).ok());
+            Some(try_ret!(SPIFFEID::from_uri(&uri).ok()))
+        } else {
+            None
+        };
 
         Some(ServerSessionValue {
             sni: sni,
@@ -210,7 +265,11 @@ impl Codec for ServerSessionValue {
             cipher_suite: cs,
             master_secret: ms,
             extended_ms: ems == 1u8,
+            alpn: alpn,
+            creation_time_sec: creation_time_sec,
+            age_obfuscation_offset: age_obfuscation_offset,
             client_cert_chain: ccert,
+            client_spiffe_id: spiffe_id,
         })
     }
 }
@@ -220,7 +279,9 @@ impl ServerSessionValue {
                v: ProtocolVersion,
                cs: CipherSuite,
                ms: Vec<u8>,
-               cert_chain: &Option<CertificatePayload>)
+               cert_chain: &Option<CertificatePayload>,
+               spiffe_id: &Option<SPIFFEID>,
+               alpn: Option<Vec<u8>>)
                -> ServerSessionValue {
         ServerSessionValue {
             sni: sni.map(|sni| sni.clone()),
@@ -228,11 +289,20 @@ impl ServerSessionValue {
             cipher_suite: cs,
             master_secret: PayloadU8::new(ms),
             extended_ms: false,
+            alpn: alpn.map(PayloadU8::new),
+            creation_time_sec: 0,
+            age_obfuscation_offset: 0,
             client_cert_chain: cert_chain.clone(),
+            client_spiffe_id: spiffe_id.clone(),
         }
     }
 
     pub fn set_extended_ms_used(&mut self) {
         self.extended_ms = true;
     }
+
+    pub fn set_freshness_params(&mut self, creation_time_sec: u64, age_obfuscation_offset: u32) {
+        self.creation_time_sec = creation_time_sec;
+        self.age_obfuscation_offset = age_obfuscation_offset;
+    }
 }
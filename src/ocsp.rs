@@ -0,0 +1,512 @@
+// Checking of stapled OCSP responses, as in RFC6960.
+//
+// A response must be a BasicOCSPResponse, signed either by the
+// certificate's issuer, or by a responder certificate the issuer
+// has delegated that to.  The issuer is taken from a chain webpki
+// accepts.
+
+use ring::der;
+use ring::digest;
+use ring::error::Unspecified;
+use untrusted;
+use webpki;
+
+use anchors::RootCertStore;
+use chain::{self, Usage};
+use error::TLSError;
+use key::Certificate;
+use x509;
+
+/// id-pkix-ocsp-basic
+static OCSP_BASIC: &'static [u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// id-kp-OCSPSigning
+static OCSP_SIGNING: &'static [u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+/// id-sha1 and id-sha256, for CertIDs
+static SHA1: &'static [u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+static SHA256: &'static [u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// How long after its thisUpdate time a response without a
+/// nextUpdate time is accepted: otherwise, an old good response
+/// could be replayed forever.
+const MAX_AGE_WITHOUT_NEXT_UPDATE: u64 = 7 * 24 * 60 * 60;
+
+// Tags which ring doesn't name.
+const ENUMERATED: u8 = 0x0a;
+const CONTEXT_SPECIFIC_PRIMITIVE_0: u8 = 0x80;
+const CONTEXT_SPECIFIC_CONSTRUCTED_1: u8 = 0xa1;
+const CONTEXT_SPECIFIC_CONSTRUCTED_2: u8 = 0xa2;
+const CONTEXT_SPECIFIC_PRIMITIVE_2: u8 = 0x82;
+
+#[derive(Debug, PartialEq)]
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+enum ResponderID<'a> {
+    ByName(untrusted::Input<'a>),
+    ByKey(untrusted::Input<'a>),
+}
+
+struct CertID<'a> {
+    hash_alg: untrusted::Input<'a>,
+    issuer_name_hash: untrusted::Input<'a>,
+    issuer_key_hash: untrusted::Input<'a>,
+    serial: untrusted::Input<'a>,
+}
+
+struct SingleResponse<'a> {
+    cert_id: CertID<'a>,
+    status: CertStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+}
+
+struct BasicResponse<'a> {
+    responder: ResponderID<'a>,
+    responses: Vec<SingleResponse<'a>>,
+    signed_data: untrusted::Input<'a>,
+    signature_alg: untrusted::Input<'a>,
+    signature: untrusted::Input<'a>,
+    certs: Vec<untrusted::Input<'a>>,
+}
+
+fn invalid(why: &str) -> TLSError {
+    TLSError::InvalidOCSPResponse(why.to_string())
+}
+
+/// Check the DER-encoded OCSP `response` stapled alongside
+/// `presented_certs` says the end-entity certificate is not
+/// revoked at time `now`.  The issuer is the next certificate on
+/// a chain to `roots` which webpki accepts: a certificate which only
+/// has the issuer's name is never trusted to sign responses.
+pub fn check_stapled(response: &[u8],
+                     roots: &RootCertStore,
+                     presented_certs: &[Certificate],
+                     now: webpki::Time) -> Result<(), TLSError> {
+    let chains = chain::validated_chains(roots, presented_certs, Usage::TLSServer, now)?;
+
+    // Cross-signing can give the end-entity certificate more than
+    // one issuer.  A response from any of them will do, but one
+    // saying the certificate is revoked is final.
+    let mut result = Err(invalid("no issuer found"));
+    for chain in &chains {
+        let (cert, issuer) = (&chain[0], &chain[1]);
+        result = check_response(response,
+                                cert.serial.as_ref().unwrap(),
+                                untrusted::Input::from(&issuer.subject),
+                                untrusted::Input::from(&issuer.spki),
+                                now);
+        match result {
+            Ok(()) | Err(TLSError::CertRevoked) => return result,
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// Check the DER-encoded OCSP `response` says the certificate with
+/// `serial` is not revoked at time `now`.  The certificate was issued
+/// by the certificate with subject `issuer_name` and key `issuer_spki`.
+fn check_response(response: &[u8],
+                  serial: &[u8],
+                  issuer_name: untrusted::Input,
+                  issuer_spki: untrusted::Input,
+                  now: webpki::Time) -> Result<(), TLSError> {
+    let basic = untrusted::Input::from(response)
+        .read_all(Unspecified, read_ocsp_response)
+        .map_err(|_| invalid("cannot parse response"))?
+        .ok_or_else(|| invalid("responder did not succeed"))?;
+    let basic = basic.read_all(Unspecified, read_basic_response)
+        .map_err(|_| invalid("cannot parse response"))?;
+
+    let signer_spki = find_signer(&basic, issuer_name, issuer_spki, now)?;
+    x509::verify_signed_data(signer_spki,
+                             basic.signature_alg.as_slice_less_safe(),
+                             basic.signed_data,
+                             basic.signature)
+        .map_err(|_| invalid("bad signature"))?;
+
+    let single = basic.responses
+        .iter()
+        .find(|single| cert_id_matches(&single.cert_id, serial, issuer_name, issuer_spki))
+        .ok_or_else(|| invalid("no response for certificate"))?;
+
+    let at = webpki::Time::from_seconds_since_unix_epoch;
+    if at(single.this_update) > now {
+        return Err(invalid("response is not yet valid"));
+    }
+
+    let next_update = single.next_update
+        .unwrap_or_else(|| single.this_update.saturating_add(MAX_AGE_WITHOUT_NEXT_UPDATE));
+    if at(next_update) < now {
+        return Err(invalid("response has expired"));
+    }
+
+    match single.status {
+        CertStatus::Good => Ok(()),
+        CertStatus::Revoked => Err(TLSError::CertRevoked),
+        CertStatus::Unknown => Err(invalid("certificate status is unknown")),
+    }
+}
+
+/// Find the key which should have signed `basic`.  That's
+/// `issuer_spki`, unless the response names a delegated responder
+/// certificate issued by the issuer.
+fn find_signer<'a>(basic: &BasicResponse<'a>,
+                   issuer_name: untrusted::Input<'a>,
+                   issuer_spki: untrusted::Input<'a>,
+                   now: webpki::Time) -> Result<untrusted::Input<'a>, TLSError> {
+    if responder_is(&basic.responder, issuer_name, issuer_spki) {
+        return Ok(issuer_spki);
+    }
+
+    for cert in &basic.certs {
+        let responder = match x509::cert_fields(*cert) {
+            Ok(responder) => responder,
+            Err(_) => return Err(invalid("cannot parse responder certificate")),
+        };
+
+        if !responder_is(&basic.responder, responder.subject, responder.spki) {
+            continue;
+        }
+
+        let at = webpki::Time::from_seconds_since_unix_epoch;
+        if responder.issuer.as_slice_less_safe() != issuer_name.as_slice_less_safe() ||
+            x509::verify_signed_data(issuer_spki,
+                                     responder.signature_alg.as_slice_less_safe(),
+                                     responder.signed_data,
+                                     responder.signature).is_err() {
+            return Err(invalid("responder certificate not issued by issuer"));
+        }
+
+        if !responder.has_extended_key_usage(OCSP_SIGNING) {
+            return Err(invalid("responder certificate not valid for OCSP signing"));
+        }
+
+        if at(responder.not_before) > now || at(responder.not_after) < now {
+            return Err(invalid("responder certificate expired or not yet valid"));
+        }
+
+        return Ok(responder.spki);
+    }
+
+    Err(invalid("unknown responder"))
+}
+
+/// Does `responder` identify the certificate with `subject` and `spki`?
+fn responder_is(responder: &ResponderID,
+                subject: untrusted::Input,
+                spki: untrusted::Input) -> bool {
+    match *responder {
+        ResponderID::ByName(name) => name.as_slice_less_safe() == subject.as_slice_less_safe(),
+        ResponderID::ByKey(hash) => {
+            hash_key(&digest::SHA1, spki)
+                .map_or(false, |key_hash| key_hash.as_ref() == hash.as_slice_less_safe())
+        }
+    }
+}
+
+/// Hash the public key in `spki`, without its BIT STRING wrapping.
+fn hash_key(alg: &'static digest::Algorithm,
+            spki: untrusted::Input) -> Result<digest::Digest, Unspecified> {
+    let key = spki.read_all(Unspecified, |spki| {
+        der::expect_tag_and_get_value(spki, der::Tag::Sequence)?;
+        der::bit_string_with_no_unused_bits(spki)
+    })?;
+    Ok(digest::digest(alg, key.as_slice_less_safe()))
+}
+
+fn cert_id_matches(cert_id: &CertID,
+                   serial: &[u8],
+                   issuer_name: untrusted::Input,
+                   issuer_spki: untrusted::Input) -> bool {
+    let alg = match cert_id.hash_alg.as_slice_less_safe() {
+        oid if oid == SHA1 => &digest::SHA1,
+        oid if oid == SHA256 => &digest::SHA256,
+        _ => return false,
+    };
+
+    let mut name = issuer_name.as_slice_less_safe().to_vec();
+    x509::wrap_in_sequence(&mut name);
+    let name_hash = digest::digest(alg, &name);
+    let key_hash = match hash_key(alg, issuer_spki) {
+        Ok(key_hash) => key_hash,
+        Err(_) => return false,
+    };
+
+    cert_id.serial.as_slice_less_safe() == serial &&
+        cert_id.issuer_name_hash.as_slice_less_safe() == name_hash.as_ref() &&
+        cert_id.issuer_key_hash.as_slice_less_safe() == key_hash.as_ref()
+}
+
+/// Read an OCSPResponse, returning the BasicOCSPResponse inside
+/// if it is successful.
+fn read_ocsp_response<'a>(rd: &mut untrusted::Reader<'a>)
+                          -> Result<Option<untrusted::Input<'a>>, Unspecified> {
+    der::nested(rd, der::Tag::Sequence, Unspecified, |resp| {
+        let (tag, status) = der::read_tag_and_get_value(resp)?;
+        if tag != ENUMERATED {
+            return Err(Unspecified);
+        }
+
+        if status.as_slice_less_safe() != [0] {
+            resp.skip_to_end();
+            return Ok(None);
+        }
+
+        // responseBytes are explicitly tagged [0]
+        let basic = der::nested(resp, der::Tag::ContextSpecificConstructed0, Unspecified, |bytes| {
+            der::nested(bytes, der::Tag::Sequence, Unspecified, |bytes| {
+                let typ = der::expect_tag_and_get_value(bytes, der::Tag::OID)?;
+                if typ.as_slice_less_safe() != OCSP_BASIC {
+                    return Err(Unspecified);
+                }
+                der::expect_tag_and_get_value(bytes, der::Tag::OctetString)
+            })
+        })?;
+        Ok(Some(basic))
+    })
+}
+
+fn read_basic_response<'a>(rd: &mut untrusted::Reader<'a>)
+                           -> Result<BasicResponse<'a>, Unspecified> {
+    der::nested(rd, der::Tag::Sequence, Unspecified, |basic| {
+        let start = basic.mark();
+        let tbs = der::expect_tag_and_get_value(basic, der::Tag::Sequence)?;
+        let signed_data = basic.get_input_between_marks(start, basic.mark())?;
+        let signature_alg = x509::read_algorithm_oid(basic)?;
+        let signature = der::bit_string_with_no_unused_bits(basic)?;
+
+        // certs are explicitly tagged [0]
+        let mut certs = Vec::new();
+        if basic.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+            der::nested(basic, der::Tag::ContextSpecificConstructed0, Unspecified, |seq| {
+                der::nested(seq, der::Tag::Sequence, Unspecified, |seq| {
+                    while !seq.at_end() {
+                        let start = seq.mark();
+                        der::expect_tag_and_get_value(seq, der::Tag::Sequence)?;
+                        certs.push(seq.get_input_between_marks(start, seq.mark())?);
+                    }
+                    Ok(())
+                })
+            })?;
+        }
+
+        let (responder, responses) = tbs.read_all(Unspecified, read_response_data)?;
+
+        Ok(BasicResponse {
+            responder: responder,
+            responses: responses,
+            signed_data: signed_data,
+            signature_alg: signature_alg,
+            signature: signature,
+            certs: certs,
+        })
+    })
+}
+
+fn read_response_data<'a>(tbs: &mut untrusted::Reader<'a>)
+                          -> Result<(ResponderID<'a>, Vec<SingleResponse<'a>>), Unspecified> {
+    // version is optional, explicitly tagged [0], and must be v1
+    if tbs.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+        let version = der::nested(tbs, der::Tag::ContextSpecificConstructed0, Unspecified,
+                                  der::small_nonnegative_integer)?;
+        if version != 0 {
+            return Err(Unspecified);
+        }
+    }
+
+    let (tag, id) = der::read_tag_and_get_value(tbs)?;
+    let responder = match tag {
+        CONTEXT_SPECIFIC_CONSTRUCTED_1 => {
+            ResponderID::ByName(id.read_all(Unspecified, |name| {
+                der::expect_tag_and_get_value(name, der::Tag::Sequence)
+            })?)
+        }
+        CONTEXT_SPECIFIC_CONSTRUCTED_2 => {
+            ResponderID::ByKey(id.read_all(Unspecified, |key| {
+                der::expect_tag_and_get_value(key, der::Tag::OctetString)
+            })?)
+        }
+        _ => return Err(Unspecified),
+    };
+
+    x509::read_time(tbs)?; // producedAt
+
+    let mut responses = Vec::new();
+    der::nested(tbs, der::Tag::Sequence, Unspecified, |seq| {
+        while !seq.at_end() {
+            responses.push(der::nested(seq, der::Tag::Sequence, Unspecified,
+                                       read_single_response)?);
+        }
+        Ok(())
+    })?;
+
+    // responseExtensions are explicitly tagged [1]
+    if tbs.peek(der::Tag::ContextSpecificConstructed1 as u8) {
+        der::nested(tbs, der::Tag::ContextSpecificConstructed1, Unspecified, |exts| {
+            der::nested(exts, der::Tag::Sequence, Unspecified, x509::check_extensions)
+        })?;
+    }
+
+    Ok((responder, responses))
+}
+
+fn read_single_response<'a>(single: &mut untrusted::Reader<'a>)
+                            -> Result<SingleResponse<'a>, Unspecified> {
+    let cert_id = der::nested(single, der::Tag::Sequence, Unspecified, |id| {
+        Ok(CertID {
+            hash_alg: x509::read_algorithm_oid(id)?,
+            issuer_name_hash: der::expect_tag_and_get_value(id, der::Tag::OctetString)?,
+            issuer_key_hash: der::expect_tag_and_get_value(id, der::Tag::OctetString)?,
+            serial: der::expect_tag_and_get_value(id, der::Tag::Integer)?,
+        })
+    })?;
+
+    // good [0] and unknown [2] are implicitly tagged NULLs;
+    // revoked [1] is implicitly tagged RevokedInfo.
+    let (tag, _) = der::read_tag_and_get_value(single)?;
+    let status = match tag {
+        CONTEXT_SPECIFIC_PRIMITIVE_0 => CertStatus::Good,
+        CONTEXT_SPECIFIC_CONSTRUCTED_1 => CertStatus::Revoked,
+        CONTEXT_SPECIFIC_PRIMITIVE_2 => CertStatus::Unknown,
+        _ => return Err(Unspecified),
+    };
+
+    let this_update = x509::read_time(single)?;
+
+    // nextUpdate is explicitly tagged [0]
+    let next_update = if single.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+        Some(der::nested(single, der::Tag::ContextSpecificConstructed0, Unspecified,
+                         x509::read_time)?)
+    } else {
+        None
+    };
+
+    // singleExtensions are explicitly tagged [1]
+    if single.peek(der::Tag::ContextSpecificConstructed1 as u8) {
+        der::nested(single, der::Tag::ContextSpecificConstructed1, Unspecified, |exts| {
+            der::nested(exts, der::Tag::Sequence, Unspecified, x509::check_extensions)
+        })?;
+    }
+
+    Ok(SingleResponse {
+        cert_id: cert_id,
+        status: status,
+        this_update: this_update,
+        next_update: next_update,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pemfile;
+    use std::fs;
+    use std::io::{self, Read};
+
+    fn load(name: &str) -> Vec<u8> {
+        let mut der = Vec::new();
+        fs::File::open(format!("test-ca/rsa/{}", name))
+            .unwrap()
+            .read_to_end(&mut der)
+            .unwrap();
+        der
+    }
+
+    fn load_certs(name: &str) -> Vec<Certificate> {
+        let path = format!("test-ca/rsa/{}", name);
+        pemfile::certs(&mut io::BufReader::new(fs::File::open(path).unwrap())).unwrap()
+    }
+
+    fn now() -> webpki::Time {
+        // 2020-01-01, when the test certificates are valid.
+        webpki::Time::from_seconds_since_unix_epoch(1_577_836_800)
+    }
+
+    fn parse<'a>(der: &'a [u8]) -> BasicResponse<'a> {
+        untrusted::Input::from(der)
+            .read_all(Unspecified, read_ocsp_response)
+            .unwrap()
+            .unwrap()
+            .read_all(Unspecified, read_basic_response)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_response() {
+        let der = load("end.revoked.ocsp");
+        let basic = parse(&der);
+        assert!(basic.certs.is_empty());
+        assert_eq!(basic.responses.len(), 1);
+
+        let single = &basic.responses[0];
+        assert_eq!(single.status, CertStatus::Revoked);
+        assert_eq!(single.cert_id.hash_alg.as_slice_less_safe(), SHA1);
+
+        // 2019-01-01 to 2048-12-24
+        assert_eq!(single.this_update, 1546300800);
+        assert_eq!(single.next_update, Some(2492380800));
+    }
+
+    #[test]
+    fn parses_delegated_response() {
+        let der = load("end.delegated.ocsp");
+        let basic = parse(&der);
+        assert_eq!(basic.certs.len(), 1);
+        match basic.responder {
+            ResponderID::ByKey(hash) => assert_eq!(hash.len(), 20),
+            ResponderID::ByName(_) => panic!("expected responder by key"),
+        }
+    }
+
+    #[test]
+    fn unsuccessful_response_has_no_body() {
+        // OCSPResponse { responseStatus: tryLater }
+        let der = [0x30, 0x03, 0x0a, 0x01, 0x03];
+        assert!(untrusted::Input::from(&der)
+                .read_all(Unspecified, read_ocsp_response)
+                .unwrap()
+                .is_none());
+    }
+
+    #[test]
+    fn issuer_must_be_on_validated_chain() {
+        // The server sends a decoy with the intermediate's name but its
+        // own key ahead of the real intermediate.  The decoy signed a
+        // good response, but didn't issue anything webpki accepts.
+        let chain = load_certs("end.fullchain");
+        let decoy = load_certs("inter.decoy.cert");
+        let presented = vec![chain[0].clone(), decoy[0].clone(), chain[1].clone()];
+        let mut roots = RootCertStore::empty();
+        roots.add(&chain[2]).unwrap();
+
+        assert_eq!(check_stapled(&load("end.ocsp"), &roots, &presented, now()), Ok(()));
+        assert_eq!(check_stapled(&load("end.revoked.ocsp"), &roots, &presented, now()),
+                   Err(TLSError::CertRevoked));
+        assert_eq!(check_stapled(&load("end.decoy.ocsp"), &roots, &presented, now()),
+                   Err(invalid("bad signature")));
+    }
+
+    #[test]
+    fn response_without_next_update_expires() {
+        let chain = load_certs("end.fullchain");
+        let mut roots = RootCertStore::empty();
+        roots.add(&chain[2]).unwrap();
+        let response = load("end.no-next-update.ocsp");
+        assert_eq!(parse(&response).responses[0].next_update, None);
+
+        // thisUpdate is 2019-01-01.
+        let day = 24 * 60 * 60;
+        let at = |secs| webpki::Time::from_seconds_since_unix_epoch(secs);
+        assert_eq!(check_stapled(&response, &roots, &chain[..2], at(1546300800 + 6 * day)),
+                   Ok(()));
+        assert_eq!(check_stapled(&response, &roots, &chain[..2], at(1546300800 + 8 * day)),
+                   Err(invalid("response has expired")));
+    }
+}
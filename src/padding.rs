@@ -0,0 +1,93 @@
+// TLS1.3 records can carry zero padding after the inner content
+// type, to hide how long the plaintext really is.
+use msgs::enums::ContentType;
+
+/// This trait decides how much padding goes into each TLS1.3 record
+/// we send.
+///
+/// It's given the record's content type, the length of its content,
+/// and the largest the padded content may be.  It returns the number
+/// of padding bytes to add, which is reduced if it would take the
+/// record past that limit.  `max_len` already allows for the peer's
+/// `record_size_limit` or `max_fragment_length`, if any.
+///
+/// TLS1.2 records can't be padded, so this is not used for them.
+///
+/// Closures taking the content type and length, and returning the
+/// padding length, implement this too.
+pub trait RecordPadding : Send + Sync {
+    /// Return the number of padding bytes for a record of type `typ`
+    /// containing `len` bytes.
+    fn padding_len(&self, typ: ContentType, len: usize, max_len: usize) -> usize;
+}
+
+/// `RecordPadding` which never pads.  This is the default.
+pub struct NoPadding;
+
+impl RecordPadding for NoPadding {
+    fn padding_len(&self, _: ContentType, _: usize, _: usize) -> usize {
+        0
+    }
+}
+
+/// `RecordPadding` which pads record contents up to a multiple
+/// of the given block size.
+pub struct PadToBlockSize(pub usize);
+
+impl RecordPadding for PadToBlockSize {
+    fn padding_len(&self, _: ContentType, len: usize, _: usize) -> usize {
+        if self.0 == 0 {
+            return 0;
+        }
+
+        (self.0 - len % self.0) % self.0
+    }
+}
+
+/// `RecordPadding` which makes every record as large as it can be.
+/// This hides the most, but costs the most bandwidth.
+pub struct PadToFullRecord;
+
+impl RecordPadding for PadToFullRecord {
+    fn padding_len(&self, _: ContentType, len: usize, max_len: usize) -> usize {
+        max_len.saturating_sub(len)
+    }
+}
+
+impl<F> RecordPadding for F
+    where F: Fn(ContentType, usize) -> usize + Send + Sync
+{
+    fn padding_len(&self, typ: ContentType, len: usize, _: usize) -> usize {
+        self(typ, len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RecordPadding, NoPadding, PadToBlockSize, PadToFullRecord};
+    use msgs::enums::ContentType;
+
+    #[test]
+    fn test_block_size_padding() {
+        let pad = PadToBlockSize(64);
+        assert_eq!(pad.padding_len(ContentType::ApplicationData, 0, 16384), 0);
+        assert_eq!(pad.padding_len(ContentType::ApplicationData, 1, 16384), 63);
+        assert_eq!(pad.padding_len(ContentType::ApplicationData, 64, 16384), 0);
+        assert_eq!(pad.padding_len(ContentType::ApplicationData, 100, 16384), 28);
+        assert_eq!(PadToBlockSize(0).padding_len(ContentType::Alert, 2, 16384), 0);
+    }
+
+    #[test]
+    fn test_full_record_padding() {
+        assert_eq!(PadToFullRecord.padding_len(ContentType::Handshake, 100, 512), 412);
+        assert_eq!(PadToFullRecord.padding_len(ContentType::Handshake, 512, 512), 0);
+    }
+
+    #[test]
+    fn test_closure_padding() {
+        let pad = |typ, len| if typ == ContentType::Alert { 10 } else { len };
+        assert_eq!(pad.padding_len(ContentType::Alert, 2, 16384), 10);
+        assert_eq!(pad.padding_len(ContentType::Handshake, 20, 16384), 20);
+        assert_eq!(NoPadding.padding_len(ContentType::Handshake, 20, 16384), 0);
+    }
+}
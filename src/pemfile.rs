@@ -55,6 +55,15 @@ pub fn certs(rd: &mut io::BufRead) -> Result<Vec<key::Certificate>, ()> {
             &|v| key::Certificate(v))
 }
 
+/// Extract all the certificate revocation lists from rd, and return a vec
+/// of their der-format contents.
+pub fn crls(rd: &mut io::BufRead) -> Result<Vec<Vec<u8>>, ()> {
+    extract(rd,
+            "-----BEGIN X509 CRL-----",
+            "-----END X509 CRL-----",
+            &|v| v)
+}
+
 /// Extract all RSA private keys from rd, and return a vec of `key::PrivateKey`s
 /// containing the der-format contents.
 pub fn rsa_private_keys(rd: &mut io::BufRead) -> Result<Vec<key::PrivateKey>, ()> {
@@ -0,0 +1,176 @@
+// Pinning of server public keys: the SHA-256 hash of the
+// SubjectPublicKeyInfo of some certificate in the server's chain
+// must be one we were configured with.
+//
+// Pins are only checked against chains webpki accepts: a pinned
+// certificate which the server sends, but which is expired or
+// didn't issue anything in the chain, doesn't count.
+
+use ring::digest;
+use untrusted;
+use webpki;
+
+use anchors::RootCertStore;
+use chain::{self, Usage};
+use error::TLSError;
+use key::Certificate;
+use x509;
+
+use std::time::SystemTime;
+
+/// The length of a pin: a SHA-256 hash.
+pub const SPKI_PIN_LEN: usize = 32;
+
+/// A set of public keys, identified by the SHA-256 hash of their
+/// DER-encoded SubjectPublicKeyInfo, one of which must appear in
+/// a server's certificate chain.  These are the same hashes as
+/// used by HTTP public key pinning (RFC7469).
+///
+/// Backup pins are for keys not yet in use, which a server might
+/// move to.  They are accepted just like the others, but a server
+/// using one is logged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SPKIPinSet {
+    pins: Vec<[u8; SPKI_PIN_LEN]>,
+    backup_pins: Vec<[u8; SPKI_PIN_LEN]>,
+    expiry: Option<SystemTime>,
+}
+
+impl SPKIPinSet {
+    /// Make a new, empty `SPKIPinSet`.  No key matches an
+    /// empty set.
+    pub fn new() -> SPKIPinSet {
+        SPKIPinSet::default()
+    }
+
+    /// Add `pin`, the SHA-256 hash of a SubjectPublicKeyInfo.
+    pub fn add_pin(&mut self, pin: [u8; SPKI_PIN_LEN]) {
+        self.pins.push(pin);
+    }
+
+    /// Add `pin` as a backup pin.
+    pub fn add_backup_pin(&mut self, pin: [u8; SPKI_PIN_LEN]) {
+        self.backup_pins.push(pin);
+    }
+
+    /// Stop enforcing the pins after `expiry`.  Then, servers
+    /// are checked like they would be without pinning.
+    ///
+    /// By default pins don't expire.
+    pub fn set_expiry(&mut self, expiry: SystemTime) {
+        self.expiry = Some(expiry);
+    }
+
+    /// Compute the pin for the key in `cert`.
+    pub fn pin_for(cert: &Certificate) -> Result<[u8; SPKI_PIN_LEN], TLSError> {
+        let fields = x509::cert_fields(untrusted::Input::from(&cert.0))
+            .map_err(|_| TLSError::General("cannot parse certificate".to_string()))?;
+        Ok(pin_for_spki(fields.spki))
+    }
+
+    /// How many pins, including backup pins, the set has.
+    pub fn len(&self) -> usize {
+        self.pins.len() + self.backup_pins.len()
+    }
+
+    /// Whether the set has no pins.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check some certificate in a chain from the end-entity
+    /// certificate in `presented_certs` to a trust anchor in `roots`
+    /// has a pinned key, unless the pins have expired by `now`.
+    /// Only chains webpki accepts at `now` count.
+    pub fn check(&self,
+                 roots: &RootCertStore,
+                 presented_certs: &[Certificate],
+                 now: webpki::Time) -> Result<(), TLSError> {
+        if let Some(expiry) = self.expiry {
+            let expiry = webpki::Time::try_from(expiry)
+                .map_err(|_| TLSError::FailedToGetCurrentTime)?;
+            if now > expiry {
+                debug!("SPKI pins expired, not checking them");
+                return Ok(());
+            }
+        }
+
+        for chain in chain::validated_chains(roots, presented_certs, Usage::TLSServer, now)? {
+            for cert in chain {
+                let pin = pin_for_spki(untrusted::Input::from(&cert.spki));
+                if self.pins.contains(&pin) {
+                    return Ok(());
+                }
+
+                if self.backup_pins.contains(&pin) {
+                    warn!("Server key matched a backup SPKI pin");
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(TLSError::NoMatchingSPKIPin)
+    }
+}
+
+/// The pin for `spki`, the contents of a SubjectPublicKeyInfo.
+fn pin_for_spki(spki: untrusted::Input) -> [u8; SPKI_PIN_LEN] {
+    let mut der = spki.as_slice_less_safe().to_vec();
+    x509::wrap_in_sequence(&mut der);
+
+    let mut pin = [0u8; SPKI_PIN_LEN];
+    pin.copy_from_slice(digest::digest(&digest::SHA256, &der).as_ref());
+    pin
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pemfile;
+    use std::fs;
+    use std::io;
+
+    fn load(name: &str) -> Vec<Certificate> {
+        let path = format!("test-ca/{}", name);
+        pemfile::certs(&mut io::BufReader::new(fs::File::open(path).unwrap())).unwrap()
+    }
+
+    fn now() -> webpki::Time {
+        // 2020-01-01, when the test certificates are valid.
+        webpki::Time::from_seconds_since_unix_epoch(1_577_836_800)
+    }
+
+    #[test]
+    fn pin_is_hash_of_spki() {
+        // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl sha256
+        let expect = [
+            0xd9, 0x98, 0x3b, 0xc2, 0x2d, 0x3e, 0x99, 0xbb, 0xf3, 0x91, 0x2d, 0x16,
+            0x22, 0xaa, 0x0c, 0x76, 0x2a, 0xf0, 0x53, 0xff, 0xe0, 0x62, 0x7a, 0x0d,
+            0x38, 0xeb, 0x2a, 0xca, 0x18, 0x7f, 0x27, 0x1d,
+        ];
+        assert_eq!(SPKIPinSet::pin_for(&load("rsa/end.cert")[0]), Ok(expect));
+    }
+
+    #[test]
+    fn certs_off_validated_chains_are_ignored() {
+        // The server also sends its intermediate cross-signed by
+        // the ECDSA CA.  That cross-signature has expired, so only
+        // the chain to the RSA CA is valid.
+        let rsa = load("rsa/end.fullchain");
+        let ecdsa_ca = load("ecdsa/ca.cert");
+        let cross = load("rsa/inter.expired-cross.cert");
+        let presented = vec![rsa[0].clone(), cross[0].clone(), rsa[1].clone()];
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&ecdsa_ca[0]).unwrap();
+        roots.add(&rsa[2]).unwrap();
+
+        let mut pins = SPKIPinSet::new();
+        pins.add_pin(SPKIPinSet::pin_for(&ecdsa_ca[0]).unwrap());
+        assert_eq!(pins.check(&roots, &presented, now()),
+                   Err(TLSError::NoMatchingSPKIPin));
+
+        pins.add_pin(SPKIPinSet::pin_for(&rsa[2]).unwrap());
+        assert_eq!(pins.check(&roots, &presented, now()), Ok(()));
+    }
+}
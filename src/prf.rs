@@ -1,30 +1,20 @@
-use ring::digest;
-use ring::hmac;
+use crypto::Hash;
 
 use std::io::Write;
 
-fn concat_sign(key: &hmac::SigningKey, a: &[u8], b: &[u8]) -> hmac::Signature {
-    let mut ctx = hmac::SigningContext::with_key(key);
-    ctx.update(a);
-    ctx.update(b);
-    ctx.sign()
-}
-
-fn p(out: &mut [u8], hashalg: &'static digest::Algorithm, secret: &[u8], seed: &[u8]) {
-    let hmac_key = hmac::SigningKey::new(hashalg, secret);
-
+fn p(out: &mut [u8], hashalg: &Hash, secret: &[u8], seed: &[u8]) {
     // A(1)
-    let mut current_a = hmac::sign(&hmac_key, seed);
+    let mut current_a = hashalg.hmac_sign(secret, &[seed]);
 
     let mut offs = 0;
 
     while offs < out.len() {
         // P_hash[i] = HMAC_hash(secret, A(i) + seed)
-        let p_term = concat_sign(&hmac_key, current_a.as_ref(), seed);
-        offs += out[offs..].as_mut().write(p_term.as_ref()).unwrap();
+        let p_term = hashalg.hmac_sign(secret, &[&current_a, seed]);
+        offs += out[offs..].as_mut().write(&p_term).unwrap();
 
         // A(i+1) = HMAC_hash(secret, A(i))
-        current_a = hmac::sign(&hmac_key, current_a.as_ref());
+        current_a = hashalg.hmac_sign(secret, &[&current_a]);
     }
 }
 
@@ -36,7 +26,7 @@ fn concat(a: &[u8], b: &[u8]) -> Vec<u8> {
 }
 
 pub fn prf(out: &mut [u8],
-           hashalg: &'static digest::Algorithm,
+           hashalg: &Hash,
            secret: &[u8],
            label: &[u8],
            seed: &[u8]) {
@@ -46,7 +36,7 @@ pub fn prf(out: &mut [u8],
 
 #[cfg(test)]
 mod tests {
-    use ring::digest::{SHA256, SHA512};
+    use crypto::ring::{SHA256, SHA512};
 
     #[test]
     fn check_sha256() {
@@ -0,0 +1,105 @@
+// QUIC carries the TLS1.3 handshake in its own frames, and protects
+// packets itself.  In QUIC mode we exchange bare handshake messages
+// with the caller, and give them the traffic secrets as we derive them.
+use msgs::enums::AlertDescription;
+use error::TLSError;
+
+use std::collections::VecDeque;
+
+/// Secrets used to protect QUIC packets at one encryption level.
+///
+/// These are the TLS1.3 traffic secrets: QUIC derives its own packet
+/// protection keys from them, using the hash of the negotiated
+/// ciphersuite.
+#[derive(Clone, Debug)]
+pub struct QuicSecrets {
+    /// The secret protecting packets sent by the client.
+    pub client: Vec<u8>,
+    /// The secret protecting packets sent by the server.
+    pub server: Vec<u8>,
+}
+
+/// Methods common to QUIC sessions, implemented by `ClientSession`
+/// and `ServerSession` made with their `new_quic` constructors.
+pub trait QuicExt {
+    /// Return the transport parameters sent by the peer, once we
+    /// have received them.
+    fn get_quic_transport_parameters(&self) -> Option<&[u8]>;
+
+    /// Consume handshake data `plaintext`, received from the peer
+    /// at the current encryption level.
+    fn read_hs(&mut self, plaintext: &[u8]) -> Result<(), TLSError>;
+
+    /// Append handshake data for the peer to `buf`.
+    ///
+    /// All of it goes at the current encryption level.  If this
+    /// returns `Some`, the caller should send it and then move to the
+    /// returned secrets: data from later calls goes at the next level.
+    /// The first secrets are for the handshake, and the second for
+    /// 1-RTT traffic.
+    fn write_hs(&mut self, buf: &mut Vec<u8>) -> Option<QuicSecrets>;
+
+    /// Return the TLS alert we would have sent the peer, if the
+    /// handshake failed.  QUIC sends it in a CONNECTION_CLOSE frame.
+    fn get_alert(&self) -> Option<AlertDescription>;
+}
+
+/// Per-session QUIC state.
+pub struct Quic {
+    /// Our transport parameters.
+    pub params: Vec<u8>,
+    /// The peer's transport parameters.
+    pub peer_params: Option<Vec<u8>>,
+    pub alert: Option<AlertDescription>,
+    /// Handshake messages for the caller, each with the number of
+    /// key changes which came before it.
+    hs_queue: VecDeque<(usize, Vec<u8>)>,
+    /// Secrets we've derived, but not yet given the caller.
+    secrets: VecDeque<QuicSecrets>,
+    /// How many secrets the caller has had.
+    secrets_taken: usize,
+}
+
+impl Quic {
+    pub fn new() -> Quic {
+        Quic {
+            params: Vec::new(),
+            peer_params: None,
+            alert: None,
+            hs_queue: VecDeque::new(),
+            secrets: VecDeque::new(),
+            secrets_taken: 0,
+        }
+    }
+
+    /// Queue an encoded handshake message for the caller.
+    pub fn send_hs(&mut self, encoding: Vec<u8>) {
+        let key_changes = self.secrets_taken + self.secrets.len();
+        self.hs_queue.push_back((key_changes, encoding));
+    }
+
+    /// Note new secrets: anything we send after this is protected by
+    /// them.
+    pub fn set_secrets(&mut self, client: Vec<u8>, server: Vec<u8>) {
+        self.secrets.push_back(QuicSecrets { client: client, server: server });
+    }
+
+    /// Implements `QuicExt::write_hs`.
+    pub fn write_hs(&mut self, buf: &mut Vec<u8>) -> Option<QuicSecrets> {
+        // Write only what goes at the caller's current level.
+        while let Some(&(key_changes, _)) = self.hs_queue.front() {
+            if key_changes > self.secrets_taken {
+                break;
+            }
+
+            let (_, encoding) = self.hs_queue.pop_front().unwrap();
+            buf.extend_from_slice(&encoding);
+        }
+
+        let secrets = self.secrets.pop_front();
+        if secrets.is_some() {
+            self.secrets_taken += 1;
+        }
+        secrets
+    }
+}
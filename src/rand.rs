@@ -3,29 +3,28 @@
 /// for our own use.  These functions never fail,
 /// they panic on error.
 
-use ring::rand::{SystemRandom, SecureRandom};
+use crypto::SecureRandom;
 use msgs::codec;
 
-/// Fill the whole slice with random material.
-pub fn fill_random(bytes: &mut [u8]) {
-    SystemRandom::new()
-        .fill(bytes)
+/// Fill the whole slice with random material from `rng`.
+pub fn fill_random(rng: &SecureRandom, bytes: &mut [u8]) {
+    rng.fill(bytes)
         .unwrap();
 }
 
 /// Make a Vec<u8> of the given size
-/// containing random material.
-pub fn random_vec(len: usize) -> Vec<u8> {
+/// containing random material from `rng`.
+pub fn random_vec(rng: &SecureRandom, len: usize) -> Vec<u8> {
     let mut v = Vec::with_capacity(len);
     v.resize(len, 0u8);
-    fill_random(&mut v);
+    fill_random(rng, &mut v);
     v
 }
 
-/// Return a uniformly random u32.
-pub fn random_u32() -> u32 {
+/// Return a uniformly random u32 from `rng`.
+pub fn random_u32(rng: &SecureRandom) -> u32 {
     let mut buf = [0u8; 4];
-    fill_random(&mut buf);
+    fill_random(rng, &mut buf);
     codec::decode_u32(&buf)
         .unwrap()
 }
@@ -0,0 +1,144 @@
+use msgs::enums::{ContentType, HandshakeType, CipherSuite, SignatureScheme};
+use msgs::deframer::MessageDeframer;
+use msgs::hsjoiner::HandshakeJoiner;
+use msgs::handshake::{HandshakePayload, ClientHelloPayload};
+use msgs::handshake::{ConvertServerNameList, ConvertProtocolNameList};
+use msgs::message::MessagePayload;
+use handshake::check_handshake_message;
+use server::{ServerConfig, ServerSession};
+use error::TLSError;
+
+use std::sync::Arc;
+use std::mem;
+use std::io;
+
+/// Reads a client's first ClientHello before any `ServerConfig`
+/// is chosen.
+///
+/// This lets one listener use different configurations -- for example,
+/// ciphersuites, versions or client authentication policies -- depending
+/// on what the client asked for.  Feed it TLS data with `read_tls`, then
+/// call `accept` until it returns an `Accepted`.  Choose a `ServerConfig`
+/// by inspecting that, and continue the handshake with the `ServerSession`
+/// from `Accepted::into_session`.
+///
+/// Nothing is written to the client until then, so errors returned
+/// by `accept` are not reported to the client.
+pub struct Acceptor {
+    deframer: MessageDeframer,
+    joiner: HandshakeJoiner,
+}
+
+impl Acceptor {
+    /// Make a new `Acceptor`, with no data read yet.
+    pub fn new() -> Acceptor {
+        Acceptor {
+            deframer: MessageDeframer::new(),
+            joiner: HandshakeJoiner::new(),
+        }
+    }
+
+    /// Read TLS content from `rd`.  This works like
+    /// `Session::read_tls`.
+    pub fn read_tls(&mut self, rd: &mut io::Read) -> io::Result<usize> {
+        self.deframer.read(rd)
+    }
+
+    /// Returns the client's ClientHello once all of it has been
+    /// read, or None if more data is needed.
+    ///
+    /// Once this has returned an `Accepted`, this `Acceptor` has no
+    /// data left and shouldn't be used further.
+    pub fn accept(&mut self) -> Result<Option<Accepted>, TLSError> {
+        if self.deframer.desynced {
+            return Err(TLSError::CorruptMessage);
+        }
+
+        while self.joiner.frames.is_empty() {
+            let msg = match self.deframer.frames.pop_front() {
+                Some(msg) => msg,
+                None => return Ok(None),
+            };
+
+            if !self.joiner.want_message(&msg) {
+                return Err(TLSError::InappropriateMessage {
+                    expect_types: vec![ ContentType::Handshake ],
+                    got_type: msg.typ,
+                });
+            }
+
+            self.joiner.take_message(msg)
+                .ok_or(TLSError::CorruptMessagePayload(ContentType::Handshake))?;
+        }
+
+        check_handshake_message(&self.joiner.frames[0], &[HandshakeType::ClientHello])?;
+
+        Ok(Some(Accepted {
+            deframer: mem::replace(&mut self.deframer, MessageDeframer::new()),
+            joiner: mem::replace(&mut self.joiner, HandshakeJoiner::new()),
+        }))
+    }
+}
+
+/// A ClientHello read by an `Acceptor`, waiting for a `ServerConfig`.
+pub struct Accepted {
+    deframer: MessageDeframer,
+    joiner: HandshakeJoiner,
+}
+
+impl Accepted {
+    /// Returns the whole ClientHello the client sent.
+    pub fn client_hello(&self) -> &ClientHelloPayload {
+        match self.joiner.frames[0].payload {
+            MessagePayload::Handshake(ref hsp) => match hsp.payload {
+                HandshakePayload::ClientHello(ref ch) => ch,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the DNS name the client gave via SNI, if any.
+    pub fn get_sni_hostname(&self) -> Option<&str> {
+        self.client_hello()
+            .get_sni_extension()
+            .and_then(|sni| sni.get_hostname())
+            .map(|name| name.into())
+    }
+
+    /// Returns the ALPN protocols the client offered, in its order
+    /// of preference.  This is empty if it didn't offer any.
+    pub fn get_alpn_protocols(&self) -> Vec<String> {
+        self.client_hello()
+            .get_alpn_extension()
+            .map(|protocols| protocols.to_strings())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Returns the ciphersuites the client offered, in its order
+    /// of preference.
+    pub fn get_cipher_suites(&self) -> &[CipherSuite] {
+        &self.client_hello().cipher_suites
+    }
+
+    /// Returns the signature schemes the client offered.  This is
+    /// empty if it didn't send the `signature_algorithms` extension.
+    pub fn get_sigschemes(&self) -> &[SignatureScheme] {
+        self.client_hello()
+            .get_sigalgs_extension()
+            .map(|schemes| &schemes[..])
+            .unwrap_or(&[])
+    }
+
+    /// Continue the handshake using `config`.  The ClientHello is
+    /// processed straight away.
+    ///
+    /// Any error doing that is returned by the session's next
+    /// `process_new_packets` call, and the alert describing it
+    /// is ready for `write_tls`.
+    pub fn into_session(self, config: &Arc<ServerConfig>) -> ServerSession {
+        let mut sess = ServerSession::new(config);
+        sess.imp.process_accepted(self.deframer, self.joiner);
+        sess
+    }
+}
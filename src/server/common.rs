@@ -1,8 +1,11 @@
 use session::SessionRandoms;
+use crypto::SecureRandom;
 use msgs::handshake::SessionID;
 use hash_hs;
 use suites;
 use key;
+use spiffe::SPIFFEID;
+use verify::ClientCertVerified;
 
 use std::mem;
 
@@ -12,16 +15,20 @@ pub struct HandshakeDetails {
     pub session_id: SessionID,
     pub randoms: SessionRandoms,
     pub using_ems: bool,
+    pub post_handshake_auth_offered: bool,
+    pub deferred_fragment_limit: Option<usize>,
 }
 
 impl HandshakeDetails {
-    pub fn new() -> HandshakeDetails {
+    pub fn new(rng: &SecureRandom) -> HandshakeDetails {
         HandshakeDetails {
             transcript: hash_hs::HandshakeHash::new(),
             hash_at_server_fin: Vec::new(),
             session_id: SessionID::empty(),
-            randoms: SessionRandoms::for_server(),
+            randoms: SessionRandoms::for_server(rng),
             using_ems: false,
+            post_handshake_auth_offered: false,
+            deferred_fragment_limit: None,
         }
     }
 }
@@ -44,16 +51,23 @@ impl ServerKXDetails {
 
 pub struct ClientCertDetails {
     pub cert_chain: Vec<key::Certificate>,
+    pub spiffe_id: Option<SPIFFEID>,
 }
 
 impl ClientCertDetails {
-    pub fn new(chain: Vec<key::Certificate>) -> ClientCertDetails {
+    pub fn new(chain: Vec<key::Certificate>,
+               verified: &ClientCertVerified) -> ClientCertDetails {
         ClientCertDetails {
             cert_chain: chain,
+            spiffe_id: verified.spiffe_id().cloned(),
         }
     }
 
     pub fn take_chain(&mut self) -> Vec<key::Certificate> {
         mem::replace(&mut self.cert_chain, Vec::new())
     }
+
+    pub fn take_spiffe_id(&mut self) -> Option<SPIFFEID> {
+        self.spiffe_id.take()
+    }
 }
@@ -1,10 +1,14 @@
 use msgs::enums::SignatureScheme;
 use msgs::handshake::SessionID;
 use rand;
+use crypto::CryptoProvider;
+use crypto::ring::RingProvider;
 use sign;
 use key;
 use webpki;
 use server;
+use server::hs::MAX_EARLY_DATA_TICKET_AGE_SKEW_MS;
+use ticketer;
 use error::TLSError;
 
 use std::collections;
@@ -31,16 +35,26 @@ impl server::StoresServerSessions for NoServerSessionStorage {
 pub struct ServerSessionMemoryCache {
     cache: Mutex<collections::HashMap<Vec<u8>, Vec<u8>>>,
     max_entries: usize,
+    provider: Arc<CryptoProvider>,
 }
 
 impl ServerSessionMemoryCache {
     /// Make a new ServerSessionMemoryCache.  `size` is the maximum
-    /// number of stored sessions.
+    /// number of stored sessions.  Session IDs are generated with
+    /// *ring*.
     pub fn new(size: usize) -> Arc<ServerSessionMemoryCache> {
+        ServerSessionMemoryCache::with_provider(size, Arc::new(RingProvider))
+    }
+
+    /// Like `new`, but generating session IDs with `provider`'s
+    /// `secure_random`.
+    pub fn with_provider(size: usize,
+                         provider: Arc<CryptoProvider>) -> Arc<ServerSessionMemoryCache> {
         debug_assert!(size > 0);
         Arc::new(ServerSessionMemoryCache {
             cache: Mutex::new(collections::HashMap::new()),
             max_entries: size,
+            provider: provider,
         })
     }
 
@@ -56,7 +70,7 @@ impl ServerSessionMemoryCache {
 impl server::StoresServerSessions for ServerSessionMemoryCache {
     fn generate(&self) -> SessionID {
         let mut v = [0u8; 32];
-        rand::fill_random(&mut v);
+        rand::fill_random(self.provider.secure_random(), &mut v);
         SessionID::new(&v)
     }
 
@@ -75,6 +89,51 @@ impl server::StoresServerSessions for ServerSessionMemoryCache {
     }
 }
 
+/// An implementor of `DetectsEarlyDataReplay` that records ClientHellos
+/// in memory, for as long as a replay of them could be accepted.  It
+/// holds a limited number: if that many are still needed, early data
+/// is refused until some expire.
+pub struct EarlyDataReplayCache {
+    seen: Mutex<collections::HashMap<Vec<u8>, u64>>,
+    max_entries: usize,
+}
+
+impl EarlyDataReplayCache {
+    /// Make a new EarlyDataReplayCache.  `size` is the maximum
+    /// number of recorded ClientHellos.
+    pub fn new(size: usize) -> Arc<EarlyDataReplayCache> {
+        debug_assert!(size > 0);
+        Arc::new(EarlyDataReplayCache {
+            seen: Mutex::new(collections::HashMap::new()),
+            max_entries: size,
+        })
+    }
+}
+
+impl server::DetectsEarlyDataReplay for EarlyDataReplayCache {
+    fn record(&self, binder: &[u8]) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains_key(binder) {
+            return false;
+        }
+
+        // A replay passes the ticket freshness check for at most
+        // twice the allowed skew after the original.
+        let now = ticketer::timebase();
+        let lifetime = 2 * MAX_EARLY_DATA_TICKET_AGE_SKEW_MS / 1000 + 1;
+        if seen.len() >= self.max_entries {
+            seen.retain(|_, recorded| now.saturating_sub(*recorded) <= lifetime);
+        }
+
+        if seen.len() >= self.max_entries {
+            return false;
+        }
+
+        seen.insert(binder.to_vec(), now);
+        true
+    }
+}
+
 /// Something which never produces tickets.
 pub struct NeverProducesTickets {}
 
@@ -105,23 +164,55 @@ impl server::ResolvesServerCert for FailResolveChain {
     }
 }
 
+/// Something which never resolves a PSK.
+pub struct FailResolvePsk {}
+
+impl server::ResolvesPsk for FailResolvePsk {
+    fn resolve(&self, _identity: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Something which resolves PSKs from a fixed set, held in memory.
+pub struct ServerPskStore {
+    by_identity: collections::HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl ServerPskStore {
+    /// Create a new and empty (ie, knows no keys) store.
+    pub fn new() -> ServerPskStore {
+        ServerPskStore { by_identity: collections::HashMap::new() }
+    }
+
+    /// Add the key `secret`, known by `identity`.  This replaces
+    /// any existing key with the same identity.
+    pub fn add(&mut self, identity: &[u8], secret: &[u8]) {
+        self.by_identity.insert(identity.to_vec(), secret.to_vec());
+    }
+}
+
+impl server::ResolvesPsk for ServerPskStore {
+    fn resolve(&self, identity: &[u8]) -> Option<Vec<u8>> {
+        self.by_identity.get(identity).cloned()
+    }
+}
+
 /// Something which always resolves to the same cert chain.
 pub struct AlwaysResolvesChain(sign::CertifiedKey);
 
 impl AlwaysResolvesChain {
-    pub fn new_rsa(chain: Vec<key::Certificate>,
-                   priv_key: &key::PrivateKey) -> AlwaysResolvesChain {
-        let key = sign::RSASigningKey::new(priv_key)
-            .expect("Invalid RSA private key");
-        let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
-        AlwaysResolvesChain(sign::CertifiedKey::new(chain, key))
-    }
-
-    pub fn new_rsa_with_extras(chain: Vec<key::Certificate>,
-                               priv_key: &key::PrivateKey,
-                               ocsp: Vec<u8>,
-                               scts: Vec<u8>) -> AlwaysResolvesChain {
-        let mut r = AlwaysResolvesChain::new_rsa(chain, priv_key);
+    pub fn new(chain: Vec<key::Certificate>,
+               priv_key: &key::PrivateKey) -> AlwaysResolvesChain {
+        let key = sign::any_supported_type(priv_key)
+            .expect("Invalid private key");
+        AlwaysResolvesChain(sign::CertifiedKey::new(chain, Arc::new(key)))
+    }
+
+    pub fn new_with_extras(chain: Vec<key::Certificate>,
+                           priv_key: &key::PrivateKey,
+                           ocsp: Vec<u8>,
+                           scts: Vec<u8>) -> AlwaysResolvesChain {
+        let mut r = AlwaysResolvesChain::new(chain, priv_key);
         if !ocsp.is_empty() {
             r.0.ocsp = Some(ocsp);
         }
@@ -187,6 +278,7 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSNI {
 mod test {
     use super::*;
     use StoresServerSessions;
+    use ResolvesPsk;
 
     #[test]
     fn test_noserversessionstorage_yields_no_sessid() {
@@ -239,6 +331,18 @@ mod test {
         assert_eq!(c.get(&[0x01]), Some(vec![0x04]));
     }
 
+    #[test]
+    fn test_serverpskstore_resolves_by_identity() {
+        let mut c = ServerPskStore::new();
+        c.add(b"one", b"secret1");
+        c.add(b"two", b"secret2");
+        c.add(b"one", b"secret3");
+        assert_eq!(c.resolve(b"one"), Some(b"secret3".to_vec()));
+        assert_eq!(c.resolve(b"two"), Some(b"secret2".to_vec()));
+        assert_eq!(c.resolve(b"three"), None);
+        assert_eq!(FailResolvePsk {}.resolve(b"one"), None);
+    }
+
     #[test]
     fn test_serversessionmemorycache_drops_to_maintain_size_invariant() {
         let c = ServerSessionMemoryCache::new(4);
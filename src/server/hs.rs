@@ -8,27 +8,39 @@ use msgs::handshake::{HandshakePayload, SupportedSignatureSchemes};
 use msgs::handshake::{HandshakeMessagePayload, ServerHelloPayload, Random};
 use msgs::handshake::{ClientHelloPayload, ServerExtension, SessionID};
 use msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList};
-use msgs::handshake::{NamedGroups, SupportedGroups, ClientExtension};
-use msgs::handshake::{ECPointFormatList, SupportedPointFormats};
+use msgs::handshake::ClientExtension;
 use msgs::handshake::{ServerECDHParams, DigitallySignedStruct};
 use msgs::handshake::{ServerKeyExchangePayload, ECDHEServerKeyExchange};
+use msgs::handshake::{DHEServerKeyExchange, ServerDHParams, KeyExchangeAlgorithm};
 use msgs::handshake::{CertificateRequestPayload, NewSessionTicketPayload};
 use msgs::handshake::{CertificateRequestPayloadTLS13, NewSessionTicketPayloadTLS13};
+use msgs::handshake::NewSessionTicketExtension;
 use msgs::handshake::{HelloRetryRequest, HelloRetryExtension, KeyShareEntry};
 use msgs::handshake::{CertificatePayloadTLS13, CertificateEntry};
 use msgs::handshake::{CertificateStatus, CertificateExtension};
 use msgs::handshake::{CertReqExtension, SupportedMandatedSignatureSchemes};
+#[cfg(feature = "ech")]
+use msgs::handshake::EncryptedClientHello;
 use msgs::ccs::ChangeCipherSpecPayload;
 use msgs::codec::Codec;
 use msgs::persist;
-use session::SessionSecrets;
+use msgs::fragmenter;
+use compress;
+use ech;
+use ech::ServerECHState;
+use session::{SessionSecrets, Protocol};
 use cipher;
-use server::ServerSessionImpl;
+use server::{ServerSessionImpl, EarlyDataState};
 use key_schedule::{KeySchedule, SecretKind};
+use hash_hs::HandshakeHash;
 use suites;
+use ffdhe::FfdheGroup;
 use verify;
 use util;
+use vecbuf::ChunkVecBuffer;
 use rand;
+use crypto::SecureRandom;
+use ticketer;
 use sign;
 use error::TLSError;
 use handshake::{check_handshake_message, check_message};
@@ -37,9 +49,14 @@ use webpki;
 use server::common::{HandshakeDetails, ServerKXDetails, ClientCertDetails};
 
 use ring::constant_time;
+use std::cmp;
 
 const TLS13_DRAFT: u16 = 0x7f17;
 
+/// How far apart our and the client's idea of a ticket's age may be
+/// before we refuse early data sent with it.
+pub const MAX_EARLY_DATA_TICKET_AGE_SKEW_MS: u64 = 10_000;
+
 macro_rules! extract_handshake(
   ( $m:expr, $t:path ) => (
     match $m.payload {
@@ -59,6 +76,12 @@ type NextStateOrError = Result<NextState, TLSError>;
 pub trait State {
     fn check_message(&self, m: &Message) -> CheckResult;
     fn handle(self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError;
+
+    /// Ask the client for a certificate after the handshake.  Only
+    /// possible in TLS1.3 traffic states.
+    fn request_client_cert(&mut self, _sess: &mut ServerSessionImpl) -> Result<(), TLSError> {
+        Err(TLSError::General("cannot request client certificate now".to_string()))
+    }
 }
 
 fn incompatible(sess: &mut ServerSessionImpl, why: &str) -> TLSError {
@@ -76,6 +99,25 @@ fn decode_error(sess: &mut ServerSessionImpl, why: &str) -> TLSError {
     TLSError::PeerMisbehavedError(why.to_string())
 }
 
+fn send_cert_error_alert(sess: &mut ServerSessionImpl, err: TLSError) -> TLSError {
+    match err {
+        TLSError::CertRevoked => {
+            sess.common.send_fatal_alert(AlertDescription::CertificateRevoked);
+        }
+        TLSError::InvalidSPIFFEID(_) => {
+            sess.common.send_fatal_alert(AlertDescription::BadCertificate);
+        }
+        TLSError::SPIFFEIDNotAllowed(_) => {
+            sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+        }
+        _ => {
+            sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
+        }
+    };
+
+    err
+}
+
 fn can_resume(sess: &ServerSessionImpl,
               handshake: &HandshakeDetails,
               resumedata: &Option<persist::ServerSessionValue>) -> bool {
@@ -86,12 +128,17 @@ fn can_resume(sess: &ServerSessionImpl,
     // the request to resume the session if the server_name extension contains
     // a different name. Instead, it proceeds with a full handshake to
     // establish a new session."
+    //
+    // Likewise, the client's SPIFFE ID (or lack of one) must still be
+    // acceptable to the verifier, which may have changed since the
+    // session began.
 
     if let Some(ref resume) = *resumedata {
         resume.cipher_suite == sess.common.get_suite_assert().suite &&
             (resume.extended_ms == handshake.using_ems ||
              (resume.extended_ms && !handshake.using_ems)) &&
-            same_dns_name_or_both_none(resume.sni.as_ref(), sess.sni.as_ref())
+            same_dns_name_or_both_none(resume.sni.as_ref(), sess.sni.as_ref()) &&
+            sess.config.verifier.allows_resumed_spiffe_id(resume.client_spiffe_id.as_ref())
     } else {
         false
     }
@@ -124,22 +171,50 @@ fn check_aligned_handshake(sess: &mut ServerSessionImpl) -> Result<(), TLSError>
     }
 }
 
+fn make_certificate_req_tls13(sess: &ServerSessionImpl, context: Vec<u8>) -> Message {
+    let mut cr = CertificateRequestPayloadTLS13 {
+        context: PayloadU8::new(context),
+        extensions: Vec::new(),
+    };
+
+    let schemes = sess.config.provider.signature_verifier().supported_schemes();
+    cr.extensions.push(CertReqExtension::SignatureAlgorithms(schemes));
+
+    let names = sess.config.verifier.client_auth_root_subjects();
+    if !names.is_empty() {
+        cr.extensions.push(CertReqExtension::AuthorityNames(names));
+    }
+
+    Message {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_3,
+        payload: MessagePayload::Handshake(HandshakeMessagePayload {
+            typ: HandshakeType::CertificateRequest,
+            payload: HandshakePayload::CertificateRequestTLS13(cr),
+        }),
+    }
+}
+
 pub struct ExpectClientHello {
     handshake: HandshakeDetails,
     done_retry: bool,
     send_cert_status: bool,
     send_sct: bool,
     send_ticket: bool,
+    accept_early_data: bool,
+    ech: ServerECHState,
 }
 
 impl ExpectClientHello {
-    pub fn new(perhaps_client_auth: bool) -> ExpectClientHello {
+    pub fn new(perhaps_client_auth: bool, rng: &SecureRandom) -> ExpectClientHello {
         let mut ret = ExpectClientHello {
-            handshake: HandshakeDetails::new(),
+            handshake: HandshakeDetails::new(rng),
             done_retry: false,
             send_cert_status: false,
             send_sct: false,
             send_ticket: false,
+            accept_early_data: false,
+            ech: ServerECHState::NotOffered,
         };
 
         if perhaps_client_auth {
@@ -164,6 +239,8 @@ impl ExpectClientHello {
             send_cert_status: self.send_cert_status,
             send_sct: self.send_sct,
             send_ticket: self.send_ticket,
+            accept_early_data: false,
+            ech: self.ech,
         })
     }
 
@@ -181,6 +258,14 @@ impl ExpectClientHello {
         })
     }
 
+    fn into_expect_tls13_early_data(self, max_early_data_size: u32) -> NextState {
+        Box::new(ExpectTLS13EarlyData {
+            handshake: self.handshake,
+            send_ticket: self.send_ticket,
+            left: max_early_data_size as usize,
+        })
+    }
+
     fn into_expect_tls12_certificate(self, kx: suites::KeyExchange) -> NextState {
         Box::new(ExpectTLS12Certificate {
             handshake: self.handshake,
@@ -206,22 +291,9 @@ impl ExpectClientHello {
                           -> Result<Vec<ServerExtension>, TLSError> {
         let mut ret = Vec::new();
 
-        // ALPN
-        let our_protocols = &sess.config.alpn_protocols;
-        let maybe_their_protocols = hello.get_alpn_extension();
-        if let Some(their_protocols) = maybe_their_protocols {
-            let their_proto_strings = their_protocols.to_strings();
-
-            if their_proto_strings.contains(&"".to_string()) {
-                return Err(TLSError::PeerMisbehavedError("client offered empty ALPN protocol"
-                    .to_string()));
-            }
-
-            sess.alpn_protocol = util::first_in_both(our_protocols, &their_proto_strings);
-            if let Some(ref selected_protocol) = sess.alpn_protocol {
-                debug!("Chosen ALPN protocol {:?}", selected_protocol);
-                ret.push(ServerExtension::make_alpn(selected_protocol.clone()));
-            }
+        // ALPN: chosen earlier by `choose_alpn_protocol`.
+        if let Some(ref selected_protocol) = sess.alpn_protocol {
+            ret.push(ServerExtension::make_alpn(selected_protocol.clone()));
         }
 
         // SNI
@@ -286,13 +358,170 @@ impl ExpectClientHello {
 
         }
 
+        if sess.common.is_tls13() && self.accept_early_data {
+            ret.push(ServerExtension::EarlyData);
+        }
+
+        if sess.common.is_tls13() && sess.common.protocol == Protocol::Quic {
+            let params = Payload::new(sess.common.quic.params.clone());
+            ret.push(ServerExtension::TransportParameters(params));
+        }
+
+        // If we couldn't decrypt the client's ECH offer, tell it
+        // what it should have used instead.
+        #[cfg(feature = "ech")]
+        {
+            if sess.common.is_tls13() {
+                if let ServerECHState::Rejected = self.ech {
+                    ret.push(ServerExtension::EncryptedClientHello(
+                        ech::get_config_list(&sess.config.ech_keys)));
+                }
+            }
+        }
+
+        self.process_fragment_limits(sess, hello, &mut ret)?;
         Ok(ret)
     }
 
+    /// Agree record size limits in each direction, preferring
+    /// `record_size_limit` (RFC8449) to `max_fragment_length` (RFC6066)
+    /// if the client offers both.
+    fn process_fragment_limits(&mut self,
+                               sess: &mut ServerSessionImpl,
+                               hello: &ClientHelloPayload,
+                               exts: &mut Vec<ServerExtension>) -> Result<(), TLSError> {
+        let tls13 = sess.common.is_tls13();
+
+        let our_limit = if let Some(limit) = hello.get_record_size_limit() {
+            if limit < fragmenter::MIN_RECORD_SIZE_LIMIT {
+                return Err(illegal_param(sess, "client sent record_size_limit below minimum"));
+            }
+
+            sess.common.set_peer_fragment_limit(
+                fragmenter::record_size_limit_to_fragment_len(limit, tls13));
+
+            let protocol_max = if tls13 {
+                fragmenter::MAX_RECORD_SIZE_LIMIT
+            } else {
+                fragmenter::MAX_FRAGMENT_LEN as u16
+            };
+            let ours = sess.config.record_size_limit
+                .map(fragmenter::clamp_record_size_limit)
+                .map(|limit| cmp::min(limit, protocol_max));
+
+            exts.push(ServerExtension::RecordSizeLimit(ours.unwrap_or(protocol_max)));
+            ours.map(|limit| fragmenter::record_size_limit_to_fragment_len(limit, tls13))
+        } else if let Some(mfl) = hello.get_max_fragment_length() {
+            let len = fragmenter::max_fragment_length_to_len(mfl)
+                .ok_or_else(|| illegal_param(sess, "client sent invalid max_fragment_length"))?;
+
+            sess.common.set_peer_fragment_limit(len);
+            exts.push(ServerExtension::MaxFragmentLength(mfl));
+            Some(len)
+        } else {
+            None
+        };
+
+        // Early data was sent before the client knew our limit, so
+        // only enforce it once that is over.
+        match our_limit {
+            Some(len) if tls13 && self.accept_early_data => {
+                self.handshake.deferred_fragment_limit = Some(len);
+            }
+            Some(len) => sess.common.set_our_fragment_limit(len),
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn choose_alpn_protocol(&mut self,
+                            sess: &mut ServerSessionImpl,
+                            sni: Option<&webpki::DNSName>,
+                            hello: &ClientHelloPayload) -> Result<(), TLSError> {
+        let maybe_their_protocols = hello.get_alpn_extension();
+        if let Some(their_protocols) = maybe_their_protocols {
+            let their_proto_strings = their_protocols.to_strings();
+
+            if their_proto_strings.contains(&"".to_string()) {
+                return Err(TLSError::PeerMisbehavedError("client offered empty ALPN protocol"
+                    .to_string()));
+            }
+
+            sess.alpn_protocol = match sess.config.alpn_resolver {
+                Some(ref resolver) => {
+                    let chosen = resolver.resolve(sni.map(|s| s.as_ref()), &their_proto_strings);
+                    match chosen {
+                        Ok(Some(ref protocol)) if !their_proto_strings.contains(protocol) => {
+                            return Err(TLSError::General("ALPN resolver chose unoffered protocol"
+                                .to_string()));
+                        }
+                        Ok(chosen) => chosen,
+                        Err(()) => {
+                            sess.common.send_fatal_alert(AlertDescription::NoApplicationProtocol);
+                            return Err(TLSError::PeerIncompatibleError("no application protocol"
+                                .to_string()));
+                        }
+                    }
+                }
+                None => util::first_in_both(&sess.config.alpn_protocols, &their_proto_strings),
+            };
+            if let Some(ref selected_protocol) = sess.alpn_protocol {
+                debug!("Chosen ALPN protocol {:?}", selected_protocol);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decide whether to accept early data offered alongside the
+    /// PSK `resume`, which must be the first PSK the client offered.
+    fn can_accept_early_data(&self,
+                             sess: &ServerSessionImpl,
+                             resume: &persist::ServerSessionValue,
+                             obfuscated_ticket_age: u32,
+                             binder: &[u8]) -> bool {
+        if self.done_retry || sess.config.max_early_data_size == 0 ||
+           sess.common.protocol == Protocol::Quic {
+            return false;
+        }
+
+        // The selected ALPN protocol must match the original session.
+        let resumed_alpn = resume.alpn.as_ref().map(|p| &p.0[..]);
+        let chosen_alpn = sess.alpn_protocol.as_ref().map(|p| p.as_bytes());
+        if resumed_alpn != chosen_alpn {
+            debug!("Rejecting early data: ALPN protocol differs");
+            return false;
+        }
+
+        // The ticket must be fresh: the client's idea of its age must
+        // agree with ours, within some allowance for clock skew and RTT.
+        let client_age = obfuscated_ticket_age.wrapping_sub(resume.age_obfuscation_offset);
+        let server_age = ticketer::timebase()
+            .saturating_sub(resume.creation_time_sec)
+            .saturating_mul(1000);
+        let skew = (client_age as u64).max(server_age) - (client_age as u64).min(server_age);
+        if skew > MAX_EARLY_DATA_TICKET_AGE_SKEW_MS {
+            debug!("Rejecting early data: ticket age skew {}ms", skew);
+            return false;
+        }
+
+        // And we must not have seen this ClientHello before.  If it
+        // can't be recorded we couldn't detect a replay, so that also
+        // refuses early data.
+        if !sess.config.early_data_replay.record(binder) {
+            warn!("Rejecting early data: ClientHello replayed or not recorded");
+            return false;
+        }
+
+        true
+    }
+
     fn check_binder(&self,
                     sess: &mut ServerSessionImpl,
                     client_hello: &Message,
                     psk: &[u8],
+                    kind: SecretKind,
                     binder: &[u8])
                     -> bool {
         let binder_plaintext = match client_hello.payload {
@@ -305,8 +534,7 @@ impl ExpectClientHello {
 
         let mut key_schedule = KeySchedule::new(suite_hash);
         key_schedule.input_secret(psk);
-        let base_key = key_schedule.derive(SecretKind::ResumptionPSKBinderKey,
-                                           key_schedule.get_hash_of_empty_message());
+        let base_key = key_schedule.derive(kind, key_schedule.get_hash_of_empty_message());
         let real_binder = key_schedule.sign_verify_data(&base_key, &handshake_hash);
 
         constant_time::verify_slices_are_equal(&real_binder, binder).is_ok()
@@ -315,26 +543,34 @@ impl ExpectClientHello {
     fn emit_server_hello_tls13(&mut self,
                                sess: &mut ServerSessionImpl,
                                session_id: &SessionID,
-                               share: &KeyShareEntry,
+                               share: Option<&KeyShareEntry>,
                                chosen_psk_idx: Option<usize>,
-                               resuming_psk: Option<Vec<u8>>)
+                               chosen_psk: Option<Vec<u8>>)
                                -> Result<(), TLSError> {
         let mut extensions = Vec::new();
+        let client_hello_hash = self.handshake.transcript.get_current_hash();
+
+        // Do key exchange, unless we're doing PSK_KE.
+        let kxr = match share {
+            Some(share) => {
+                let kxr = suites::KeyExchange::start(sess.config.provider.as_ref(), share.group)
+                    .and_then(|kx| kx.complete(&share.payload.0))
+                    .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
+
+                let kse = KeyShareEntry::new(share.group, &kxr.pubkey);
+                extensions.push(ServerExtension::KeyShare(kse));
+                Some(kxr)
+            }
+            None => None,
+        };
 
-        // Do key exchange
-        let kxr = suites::KeyExchange::start_ecdhe(share.group)
-            .and_then(|kx| kx.complete(&share.payload.0))
-            .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
-
-        let kse = KeyShareEntry::new(share.group, &kxr.pubkey);
-        extensions.push(ServerExtension::KeyShare(kse));
         extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::Unknown(TLS13_DRAFT)));
 
         if let Some(psk_idx) = chosen_psk_idx {
             extensions.push(ServerExtension::PresharedKey(psk_idx as u16));
         }
 
-        let sh = Message {
+        let mut sh = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
@@ -350,6 +586,22 @@ impl ExpectClientHello {
             }),
         };
 
+        // Signal ECH acceptance in the last 8 bytes of our random.
+        if self.ech.is_accepted() {
+            let confirmation = ech::compute_confirmation(sess.common.get_suite_assert().get_hash(),
+                                                         &self.handshake.transcript,
+                                                         &self.handshake.randoms.client,
+                                                         ech::ACCEPT_CONFIRMATION_LABEL,
+                                                         &sh);
+            self.handshake.randoms.server[24..].copy_from_slice(&confirmation);
+
+            if let MessagePayload::Handshake(ref mut hmp) = sh.payload {
+                if let HandshakePayload::ServerHello(ref mut shp) = hmp.payload {
+                    shp.random = Random::from_slice(&self.handshake.randoms.server);
+                }
+            }
+        }
+
         check_aligned_handshake(sess)?;
 
         trace!("sending server hello {:?}", sh);
@@ -359,18 +611,44 @@ impl ExpectClientHello {
         // Start key schedule
         let suite = sess.common.get_suite_assert();
         let mut key_schedule = KeySchedule::new(suite.get_hash());
-        if let Some(psk) = resuming_psk {
+        if let Some(psk) = chosen_psk {
             key_schedule.input_secret(&psk);
         } else {
             key_schedule.input_empty();
         }
-        key_schedule.input_secret(&kxr.premaster_secret);
+
+        // If accepting early data, the client's next records are protected
+        // with the early traffic key rather than the handshake key.
+        let early_key = if self.accept_early_data {
+            Some(key_schedule.derive_logged_secret(SecretKind::ClientEarlyTrafficSecret,
+                                                   &client_hello_hash,
+                                                   &*sess.config.key_log,
+                                                   &self.handshake.randoms.client))
+        } else {
+            None
+        };
+
+        match kxr {
+            Some(kxr) => key_schedule.input_secret(&kxr.premaster_secret),
+            None => key_schedule.input_empty(),
+        }
 
         let handshake_hash = self.handshake.transcript.get_current_hash();
-        let write_key = key_schedule.derive(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
-        let read_key = key_schedule.derive(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+        let write_key = key_schedule.derive_logged_secret(SecretKind::ServerHandshakeTrafficSecret,
+                                                          &handshake_hash,
+                                                          &*sess.config.key_log,
+                                                          &self.handshake.randoms.client);
+        let read_key = key_schedule.derive_logged_secret(SecretKind::ClientHandshakeTrafficSecret,
+                                                         &handshake_hash,
+                                                         &*sess.config.key_log,
+                                                         &self.handshake.randoms.client);
         sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
-        sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+        sess.common.set_message_decrypter(cipher::new_tls13_read(suite,
+                                                                 early_key.as_ref()
+                                                                     .unwrap_or(&read_key)));
+        if sess.common.protocol == Protocol::Quic {
+            sess.common.quic.set_secrets(read_key.clone(), write_key.clone());
+        }
         key_schedule.current_client_traffic_secret = read_key;
         key_schedule.current_server_traffic_secret = write_key;
         sess.common.set_key_schedule(key_schedule);
@@ -380,6 +658,11 @@ impl ExpectClientHello {
 
     fn emit_fake_ccs(&mut self,
                      sess: &mut ServerSessionImpl) {
+        // There are no middleboxes to fool in QUIC.
+        if sess.common.protocol == Protocol::Quic {
+            return;
+        }
+
         let m = Message {
             typ: ContentType::ChangeCipherSpec,
             version: ProtocolVersion::TLSv1_2,
@@ -401,7 +684,12 @@ impl ExpectClientHello {
         req.extensions.push(HelloRetryExtension::KeyShare(group));
         req.extensions.push(HelloRetryExtension::SupportedVersions(ProtocolVersion::Unknown(TLS13_DRAFT)));
 
-        let m = Message {
+        // The ECH confirmation is filled in below, once we know the transcript.
+        if self.ech.is_accepted() {
+            req.extensions.push(HelloRetryExtension::EncryptedClientHello(Payload::new(vec![0u8; 8])));
+        }
+
+        let mut m = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
@@ -410,19 +698,35 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("Requesting retry {:?}", m);
         self.handshake.transcript.rollup_for_hrr();
+
+        if self.ech.is_accepted() {
+            let confirmation = ech::compute_confirmation(sess.common.get_suite_assert().get_hash(),
+                                                         &self.handshake.transcript,
+                                                         &self.handshake.randoms.client,
+                                                         ech::HRR_ACCEPT_CONFIRMATION_LABEL,
+                                                         &m);
+
+            if let MessagePayload::Handshake(ref mut hmp) = m.payload {
+                if let HandshakePayload::HelloRetryRequest(ref mut hrr) = hmp.payload {
+                    hrr.extensions.pop();
+                    hrr.extensions.push(HelloRetryExtension::EncryptedClientHello(Payload::new(confirmation)));
+                }
+            }
+        }
+
+        trace!("Requesting retry {:?}", m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, false);
     }
 
     fn emit_encrypted_extensions(&mut self,
                                  sess: &mut ServerSessionImpl,
-                                 server_key: &mut sign::CertifiedKey,
+                                 server_key: Option<&mut sign::CertifiedKey>,
                                  hello: &ClientHelloPayload,
                                  for_resume: bool)
                                  -> Result<(), TLSError> {
-        let encrypted_exts = self.process_extensions(sess, Some(server_key), hello, for_resume)?;
+        let encrypted_exts = self.process_extensions(sess, server_key, hello, for_resume)?;
         let ee = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_3,
@@ -443,28 +747,7 @@ impl ExpectClientHello {
             return false;
         }
 
-        let mut cr = CertificateRequestPayloadTLS13 {
-            context: PayloadU8::empty(),
-            extensions: Vec::new(),
-        };
-
-        let schemes = SupportedSignatureSchemes::supported_verify();
-        cr.extensions.push(CertReqExtension::SignatureAlgorithms(schemes));
-
-        let names = sess.config.verifier.client_auth_root_subjects();
-        if !names.is_empty() {
-            cr.extensions.push(CertReqExtension::AuthorityNames(names));
-        }
-
-        let m = Message {
-            typ: ContentType::Handshake,
-            version: ProtocolVersion::TLSv1_3,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::CertificateRequest,
-                payload: HandshakePayload::CertificateRequestTLS13(cr),
-            }),
-        };
-
+        let m = make_certificate_req_tls13(sess, Vec::new());
         trace!("Sending CertificateRequest {:?}", m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, true);
@@ -473,7 +756,8 @@ impl ExpectClientHello {
 
     fn emit_certificate_tls13(&mut self,
                               sess: &mut ServerSessionImpl,
-                              server_key: &mut sign::CertifiedKey) {
+                              server_key: &mut sign::CertifiedKey,
+                              hello: &ClientHelloPayload) {
         let mut cert_body = CertificatePayloadTLS13::new();
 
         let (certs, ocsp, sct_list) = {
@@ -508,13 +792,30 @@ impl ExpectClientHello {
             first_entry.exts.push(CertificateExtension::make_sct(sct_list.unwrap()));
         }
 
+        // Compress it, if the client supports one of our algorithms.
+        let compressed = hello.get_certificate_compression_extension()
+            .and_then(|offered| {
+                sess.config.certificate_compressors
+                    .iter()
+                    .find(|c| offered.contains(&c.algorithm()))
+            })
+            .and_then(|compressor| compress::compress_certificate(compressor.as_ref(), &cert_body));
+
+        let payload = match compressed {
+            Some(compressed) => HandshakeMessagePayload {
+                typ: HandshakeType::CompressedCertificate,
+                payload: HandshakePayload::CompressedCertificate(compressed),
+            },
+            None => HandshakeMessagePayload {
+                typ: HandshakeType::Certificate,
+                payload: HandshakePayload::CertificateTLS13(cert_body),
+            },
+        };
+
         let c = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_3,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::Certificate,
-                payload: HandshakePayload::CertificateTLS13(cert_body),
-            }),
+            payload: MessagePayload::Handshake(payload),
         };
 
         trace!("sending certificate {:?}", c);
@@ -581,18 +882,33 @@ impl ExpectClientHello {
         sess.common.get_mut_key_schedule().input_empty();
         let write_key = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ServerApplicationTrafficSecret,
-                    &self.handshake.hash_at_server_fin);
+            .derive_logged_secret(SecretKind::ServerApplicationTrafficSecret,
+                                  &self.handshake.hash_at_server_fin,
+                                  &*sess.config.key_log,
+                                  &self.handshake.randoms.client);
         let suite = sess.common.get_suite_assert();
         sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+
+        // QUIC needs both 1-RTT secrets now.  We log the client's when
+        // we start using it.
+        if sess.common.protocol == Protocol::Quic {
+            let read_key = sess.common
+                .get_key_schedule()
+                .derive(SecretKind::ClientApplicationTrafficSecret,
+                        &self.handshake.hash_at_server_fin);
+            sess.common.quic.set_secrets(read_key, write_key.clone());
+        }
+
         sess.common
             .get_mut_key_schedule()
             .current_server_traffic_secret = write_key;
 
         let exporter_secret = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ExporterMasterSecret,
-                    &self.handshake.hash_at_server_fin);
+            .derive_logged_secret(SecretKind::ExporterMasterSecret,
+                                  &self.handshake.hash_at_server_fin,
+                                  &*sess.config.key_log,
+                                  &self.handshake.randoms.client);
         sess.common
             .get_mut_key_schedule()
             .current_exporter_secret = exporter_secret;
@@ -672,29 +988,46 @@ impl ExpectClientHello {
 
     fn emit_server_kx(&mut self,
                       sess: &mut ServerSessionImpl,
-                      sigscheme: SignatureScheme,
+                      sigschemes: &[SignatureScheme],
                       group: &NamedGroup,
                       server_certkey: &mut sign::CertifiedKey)
                       -> Result<suites::KeyExchange, TLSError> {
         let kx = sess.common.get_suite_assert()
-            .start_server_kx(*group)
+            .start_server_kx(sess.config.provider.as_ref(), *group)
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
+        let dh_params = FfdheGroup::from_named_group(*group)
+            .map(|ffdhe| ServerDHParams::new(ffdhe.get_prime(), ffdhe.get_generator(), &kx.pubkey));
         let secdh = ServerECDHParams::new(group, &kx.pubkey);
 
         let mut msg = Vec::new();
         msg.extend(&self.handshake.randoms.client);
         msg.extend(&self.handshake.randoms.server);
-        secdh.encode(&mut msg);
+        match dh_params {
+            Some(ref dh_params) => dh_params.encode(&mut msg),
+            None => secdh.encode(&mut msg),
+        }
 
         let signing_key = &server_certkey.key;
-        let sig = signing_key.choose_scheme(&[sigscheme])
-            .ok_or_else(|| TLSError::General("incompatible signing key".to_string()))
-            .and_then(|signer| signer.sign(&msg))?;
-
-        let skx = ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange {
-            params: secdh,
-            dss: DigitallySignedStruct::new(sigscheme, sig),
-        });
+        let signer = signing_key.choose_scheme(sigschemes)
+            .ok_or_else(|| TLSError::General("incompatible signing key".to_string()))?;
+        let sigscheme = signer.get_scheme();
+        let sig = signer.sign(&msg)?;
+
+        let dss = DigitallySignedStruct::new(sigscheme, sig);
+        let skx = match dh_params {
+            Some(dh_params) => {
+                ServerKeyExchangePayload::DHE(DHEServerKeyExchange {
+                    params: dh_params,
+                    dss: dss,
+                })
+            }
+            None => {
+                ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange {
+                    params: secdh,
+                    dss: dss,
+                })
+            }
+        };
 
         let m = Message {
             typ: ContentType::Handshake,
@@ -722,7 +1055,7 @@ impl ExpectClientHello {
         let cr = CertificateRequestPayload {
             certtypes: vec![ ClientCertificateType::RSASign,
                          ClientCertificateType::ECDSASign ],
-            sigschemes: SupportedSignatureSchemes::supported_verify(),
+            sigschemes: sess.config.provider.signature_verifier().supported_schemes(),
             canames: names,
         };
 
@@ -775,9 +1108,11 @@ impl ExpectClientHello {
         sess.common.start_encryption_tls12(
             SessionSecrets::new_resume(&self.handshake.randoms,
                                        hashalg,
-                                       &resumedata.master_secret.0)
+                                       &resumedata.master_secret.0,
+                                       &*sess.config.key_log)
         );
         sess.client_cert_chain = resumedata.client_cert_chain;
+        sess.client_spiffe_id = resumedata.client_spiffe_id;
 
         if self.send_ticket {
             emit_ticket(&mut self.handshake, sess);
@@ -793,7 +1128,7 @@ impl ExpectClientHello {
     fn handle_client_hello_tls13(mut self,
                                  sess: &mut ServerSessionImpl,
                                  sni: Option<webpki::DNSName>,
-                                 mut server_key: sign::CertifiedKey,
+                                 mut server_key: Option<sign::CertifiedKey>,
                                  chm: &Message)
                                  -> NextStateOrError {
         let client_hello = extract_handshake!(chm, HandshakePayload::ClientHello).unwrap();
@@ -802,9 +1137,27 @@ impl ExpectClientHello {
             return Err(illegal_param(sess, "client offered wrong compressions"));
         }
 
+        if sess.common.protocol == Protocol::Quic {
+            // QUIC has no middlebox compatibility mode.
+            if !client_hello.session_id.is_empty() {
+                return Err(illegal_param(sess, "client sent session id in quic"));
+            }
+
+            match client_hello.get_quic_params_extension() {
+                Some(params) => sess.common.quic.peer_params = Some(params.to_vec()),
+                None => {
+                    sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+                    let msg = "client did not send quic transport parameters".to_string();
+                    return Err(TLSError::PeerMisbehavedError(msg));
+                }
+            }
+        }
+
         let groups_ext = client_hello.get_namedgroups_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe groups"))?;
 
+        self.handshake.post_handshake_auth_offered = client_hello.post_handshake_auth_offered();
+
         let mut sigschemes_ext = client_hello.get_sigalgs_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe sigschemes"))?
             .clone();
@@ -823,11 +1176,12 @@ impl ExpectClientHello {
             .map(|share| share.group)
             .collect();
 
-        let chosen_group = util::first_in_both(&NamedGroups::supported(), &share_groups);
+        let kx_groups = sess.get_kx_groups();
+        let chosen_group = util::first_in_both(&kx_groups, &share_groups);
         if chosen_group.is_none() {
             // We don't have a suitable key share.  Choose a suitable group and
             // send a HelloRetryRequest.
-            let retry_group_maybe = util::first_in_both(&NamedGroups::supported(), groups_ext);
+            let retry_group_maybe = util::first_in_both(&kx_groups, groups_ext);
             self.handshake.transcript.add_message(chm);
 
             if let Some(group) = retry_group_maybe {
@@ -835,6 +1189,11 @@ impl ExpectClientHello {
                     return Err(illegal_param(sess, "did not follow retry request"));
                 }
 
+                // Any early data the client sent is now dead.
+                if client_hello.early_data_extension_offered() {
+                    sess.early_data = EarlyDataState::Skipping(sess.config.max_early_data_size as usize);
+                }
+
                 self.emit_hello_retry_request(sess, group);
                 self.emit_fake_ccs(sess);
                 return Ok(self.into_expect_retried_client_hello());
@@ -851,7 +1210,9 @@ impl ExpectClientHello {
             .unwrap();
 
         let mut chosen_psk_index = None;
-        let mut resuming_psk = None;
+        let mut chosen_psk = None;
+        let mut external_psk_identity = None;
+        let mut resumed_client_cert = None;
         if let Some(psk_offer) = client_hello.get_psk() {
             if !client_hello.check_psk_ext_is_last() {
                 return Err(illegal_param(sess, "psk extension in wrong position"));
@@ -872,43 +1233,114 @@ impl ExpectClientHello {
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain));
 
                 if !can_resume(sess, &self.handshake, &maybe_resume) {
-                    continue;
+                    // Perhaps it's an external PSK instead?
+                    if !sess.common.get_suite_assert().usable_for_external_psk() {
+                        continue;
+                    }
+
+                    let external_psk = match sess.config.psk_resolver.resolve(&psk_id.identity.0) {
+                        Some(psk) => psk,
+                        None => continue,
+                    };
+
+                    if !self.check_binder(sess, chm, &external_psk,
+                                          SecretKind::ExternalPSKBinderKey,
+                                          &psk_offer.binders[i].0) {
+                        sess.common.send_fatal_alert(AlertDescription::DecryptError);
+                        return Err(TLSError::PeerMisbehavedError("client sent wrong binder".to_string()));
+                    }
+
+                    chosen_psk_index = Some(i);
+                    chosen_psk = Some(external_psk);
+                    external_psk_identity = Some(psk_id.identity.0.clone());
+                    break;
                 }
 
                 let resume = maybe_resume.unwrap();
 
-                if !self.check_binder(sess, chm, &resume.master_secret.0, &psk_offer.binders[i].0) {
+                if !self.check_binder(sess, chm, &resume.master_secret.0,
+                                      SecretKind::ResumptionPSKBinderKey,
+                                      &psk_offer.binders[i].0) {
                     sess.common.send_fatal_alert(AlertDescription::DecryptError);
                     return Err(TLSError::PeerMisbehavedError("client sent wrong binder".to_string()));
                 }
 
+                if i == 0 && client_hello.early_data_extension_offered() {
+                    self.accept_early_data = self.can_accept_early_data(sess,
+                                                                        &resume,
+                                                                        psk_id.obfuscated_ticket_age,
+                                                                        &psk_offer.binders[i].0);
+                }
+
                 chosen_psk_index = Some(i);
-                resuming_psk = Some(resume.master_secret.0);
+                resumed_client_cert = Some((resume.client_cert_chain, resume.client_spiffe_id));
+                chosen_psk = Some(resume.master_secret.0);
                 break;
             }
         }
 
-        if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
-            warn!("Resumption ignored, DHE_KE not offered");
+        let psk_mode = client_hello.get_psk_modes()
+            .and_then(|modes| util::first_in_both(&sess.config.psk_modes, modes));
+
+        if psk_mode.is_none() {
+            if chosen_psk.is_some() {
+                warn!("PSK ignored, no acceptable PSK mode offered");
+            }
             self.send_ticket = false;
+            self.accept_early_data = false;
             chosen_psk_index = None;
-            resuming_psk = None;
+            chosen_psk = None;
+            external_psk_identity = None;
+            resumed_client_cert = None;
         } else {
             self.send_ticket = true;
         }
 
-        let full_handshake = resuming_psk.is_none();
+        let full_handshake = chosen_psk.is_none();
+        if full_handshake && server_key.is_none() {
+            sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+            return Err(TLSError::General("no server certificate chain resolved".to_string()));
+        }
+
+        // PSK_KE means we don't do a key exchange, and ignore the
+        // client's key shares.
+        let share = if !full_handshake && psk_mode == Some(PSKKeyExchangeMode::PSK_KE) {
+            debug!("Using PSK without key exchange");
+            None
+        } else {
+            Some(chosen_share)
+        };
+
+        if let Some(identity) = external_psk_identity {
+            debug!("Client authenticated by external PSK");
+            sess.psk_identity = Some(identity);
+        }
+
+        if let Some((chain, spiffe_id)) = resumed_client_cert {
+            sess.client_cert_chain = chain;
+            sess.client_spiffe_id = spiffe_id;
+        }
+
+        if self.accept_early_data {
+            debug!("Accepting early data");
+            sess.early_data = EarlyDataState::Accepted(ChunkVecBuffer::new());
+        } else if client_hello.early_data_extension_offered() {
+            debug!("Rejecting early data");
+            sess.early_data = EarlyDataState::Skipping(sess.config.max_early_data_size as usize);
+        }
+
         self.handshake.transcript.add_message(chm);
         self.emit_server_hello_tls13(sess, &client_hello.session_id,
-                                     chosen_share, chosen_psk_index, resuming_psk)?;
+                                     share, chosen_psk_index, chosen_psk)?;
         if !self.done_retry {
             self.emit_fake_ccs(sess);
         }
-        self.emit_encrypted_extensions(sess, &mut server_key, client_hello, !full_handshake)?;
+        self.emit_encrypted_extensions(sess, server_key.as_mut(), client_hello, !full_handshake)?;
 
         let doing_client_auth = if full_handshake {
+            let mut server_key = server_key.unwrap();
             let client_auth = self.emit_certificate_req_tls13(sess);
-            self.emit_certificate_tls13(sess, &mut server_key);
+            self.emit_certificate_tls13(sess, &mut server_key, client_hello);
             self.emit_certificate_verify_tls13(sess, &mut server_key, &sigschemes_ext)?;
             client_auth
         } else {
@@ -920,6 +1352,9 @@ impl ExpectClientHello {
 
         if doing_client_auth {
             Ok(self.into_expect_tls13_certificate())
+        } else if self.accept_early_data {
+            let max_early_data_size = sess.config.max_early_data_size;
+            Ok(self.into_expect_tls13_early_data(max_early_data_size))
         } else {
             Ok(self.into_expect_tls13_finished())
         }
@@ -933,6 +1368,84 @@ impl ExpectClientHello {
             sess.set_sni(sni);
         }
     }
+
+    /// If `m` offers Encrypted Client Hello and we can decrypt it,
+    /// return the inner ClientHello to carry on with instead.
+    #[cfg(feature = "ech")]
+    fn process_ech(&mut self,
+                   sess: &mut ServerSessionImpl,
+                   m: Message) -> Result<Message, TLSError> {
+        if sess.config.ech_keys.is_empty() ||
+           !sess.config.versions.contains(&ProtocolVersion::TLSv1_3) {
+            return Ok(m);
+        }
+
+        let inner = {
+            let outer = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
+            let offer = match outer.get_ech_extension() {
+                Some(&EncryptedClientHello::Outer(ref offer)) => offer,
+                Some(&EncryptedClientHello::Inner) => {
+                    return Err(illegal_param(sess, "client sent inner ECH extension in outer hello"));
+                }
+                None if self.ech.is_accepted() => {
+                    return Err(illegal_param(sess, "client did not offer ECH after retry"));
+                }
+                None => return Ok(m),
+            };
+
+            let encoded = match self.ech {
+                ServerECHState::Accepted(ref mut context) => {
+                    ech::open_retried_inner_hello(context, outer, offer)
+                        .ok_or_else(|| {
+                            sess.common.send_fatal_alert(AlertDescription::DecryptError);
+                            TLSError::DecryptError
+                        })?
+                }
+                ServerECHState::Rejected => return Ok(m),
+                ServerECHState::NotOffered => {
+                    match ech::open_inner_hello(&sess.config.ech_keys, outer, offer) {
+                        Some((context, encoded)) => {
+                            self.ech = ServerECHState::Accepted(context);
+                            encoded
+                        }
+                        None => {
+                            debug!("Rejecting ECH offer");
+                            self.ech = ServerECHState::Rejected;
+                            return Ok(m);
+                        }
+                    }
+                }
+            };
+
+            ech::decode_inner_hello(&encoded, outer)
+                .ok_or_else(|| illegal_param(sess, "client sent invalid inner hello"))?
+        };
+
+        let offers_tls13 = inner.get_versions_extension()
+            .map_or(false, |versions| versions.contains(&ProtocolVersion::Unknown(TLS13_DRAFT)));
+        if !offers_tls13 {
+            return Err(illegal_param(sess, "client inner hello did not offer TLS1.3"));
+        }
+
+        debug!("Accepted ECH offer");
+        Ok(Message {
+            typ: m.typ,
+            version: m.version,
+            payload: MessagePayload::Handshake(HandshakeMessagePayload {
+                typ: HandshakeType::ClientHello,
+                payload: HandshakePayload::ClientHello(inner),
+            }),
+        })
+    }
+
+    /// Without the `ech` feature we have no keys, so always
+    /// carry on with the outer ClientHello.
+    #[cfg(not(feature = "ech"))]
+    fn process_ech(&mut self,
+                   _sess: &mut ServerSessionImpl,
+                   m: Message) -> Result<Message, TLSError> {
+        Ok(m)
+    }
 }
 
 impl State for ExpectClientHello {
@@ -941,6 +1454,7 @@ impl State for ExpectClientHello {
     }
 
     fn handle(mut self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError {
+        let m = self.process_ech(sess, m)?;
         let client_hello = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
         let tls13_enabled = sess.config.versions.contains(&ProtocolVersion::TLSv1_3);
         let tls12_enabled = sess.config.versions.contains(&ProtocolVersion::TLSv1_2);
@@ -1001,27 +1515,36 @@ impl State for ExpectClientHello {
         let sigschemes_ext = client_hello.get_sigalgs_extension()
           .unwrap_or(&default_sigschemes_ext);
 
-        // Choose a certificate.
-        let mut certkey = {
+        // Choose a certificate.  TLS1.3 handshakes might instead be
+        // authenticated by an external PSK, so we can't insist on one yet.
+        let maybe_certkey = {
             let sni_ref = sni.as_ref().map(|dns_name| dns_name.as_ref());
             trace!("sni {:?}", sni_ref);
             trace!("sig schemes {:?}", sigschemes_ext);
-            let certkey = sess.config.cert_resolver.resolve(sni_ref, sigschemes_ext);
-            certkey.ok_or_else(|| {
-                sess.common.send_fatal_alert(AlertDescription::AccessDenied);
-                TLSError::General("no server certificate chain resolved".to_string())
-            })?
+            sess.config.cert_resolver.resolve(sni_ref, sigschemes_ext)
         };
 
         // Reduce our supported ciphersuites by the certificate.
         // (no-op for TLS1.3)
-        let suitable_suites = suites::reduce_given_sigalg(&sess.config.ciphersuites,
-                                                          &certkey.key.algorithm());
+        let suitable_suites = match maybe_certkey {
+            Some(ref certkey) => suites::reduce_given_sigalg(&sess.config.ciphersuites,
+                                                             &certkey.key.algorithm()),
+            None => sess.config.ciphersuites.clone(),
+        };
 
         // And version
         let protocol_version = sess.common.negotiated_version.unwrap();
         let suitable_suites = suites::reduce_given_version(&suitable_suites, protocol_version);
 
+        // And, for TLS1.2, whether we have a group in common for
+        // the suite's key exchange.
+        let suitable_suites = match client_hello.get_namedgroups_extension() {
+            Some(groups) if !sess.common.is_tls13() => {
+                suites::reduce_given_groups(&suitable_suites, &sess.get_kx_groups(), groups)
+            }
+            _ => suitable_suites,
+        };
+
         let maybe_ciphersuite = if sess.config.ignore_client_order {
             suites::choose_ciphersuite_preferring_server(&client_hello.cipher_suites, &suitable_suites)
         } else {
@@ -1042,33 +1565,50 @@ impl State for ExpectClientHello {
                 .to_string()));
         }
 
+        self.choose_alpn_protocol(sess, sni.as_ref(), client_hello)?;
+
+        // Save their Random.
+        client_hello.random.write_slice(&mut self.handshake.randoms.client);
+
         if sess.common.is_tls13() {
-            return self.handle_client_hello_tls13(sess, sni, certkey, &m);
+            return self.handle_client_hello_tls13(sess, sni, maybe_certkey, &m);
         }
 
         // -- TLS1.2 only from hereon in --
+        let mut certkey = maybe_certkey.ok_or_else(|| {
+            sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+            TLSError::General("no server certificate chain resolved".to_string())
+        })?;
+
         self.save_sni(sess, sni.clone());
         self.handshake.transcript.add_message(&m);
 
-        // Save their Random.
-        client_hello.random.write_slice(&mut self.handshake.randoms.client);
-
         if client_hello.ems_support_offered() {
             self.handshake.using_ems = true;
         }
 
         let groups_ext = client_hello.get_namedgroups_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe groups"))?;
-        let ecpoints_ext = client_hello.get_ecpoints_extension()
-            .ok_or_else(|| incompatible(sess, "client didn't describe ec points"))?;
+        let ecpoints_ext = client_hello.get_ecpoints_extension();
 
         trace!("namedgroups {:?}", groups_ext);
         trace!("ecpoints {:?}", ecpoints_ext);
 
-        if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
-            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
-            return Err(TLSError::PeerIncompatibleError("client didn't support uncompressed ec points"
-                .to_string()));
+        // Point formats only matter for ECDHE.
+        let using_ecdhe = match sess.common.get_suite_assert().kx {
+            KeyExchangeAlgorithm::ECDHE => true,
+            _ => false,
+        };
+
+        if using_ecdhe {
+            let ecpoints_ext = ecpoints_ext
+                .ok_or_else(|| incompatible(sess, "client didn't describe ec points"))?;
+
+            if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
+                sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+                return Err(TLSError::PeerIncompatibleError("client didn't support uncompressed ec points"
+                    .to_string()));
+            }
         }
 
         // -- Check for resumption --
@@ -1131,24 +1671,22 @@ impl State for ExpectClientHello {
         }
 
         // Now we have chosen a ciphersuite, we can make kx decisions.
-        let sigscheme = sess.common.get_suite_assert()
-            .resolve_sig_scheme(sigschemes_ext)
-            .ok_or_else(|| incompatible(sess, "no supported sig scheme"))?;
-
-        let group = util::first_in_both(NamedGroups::supported().as_slice(),
-                                        groups_ext.as_slice())
-            .ok_or_else(|| incompatible(sess, "no supported group"))?;
+        let sigschemes = sess.common.get_suite_assert()
+            .resolve_sig_schemes(sigschemes_ext);
 
-        let ecpoint = util::first_in_both(ECPointFormatList::supported().as_slice(),
-                                          ecpoints_ext.as_slice())
-            .ok_or_else(|| incompatible(sess, "no supported point format"))?;
+        if sigschemes.is_empty() {
+            return Err(incompatible(sess, "no supported sig scheme"));
+        }
 
-        debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
+        let suite = sess.common.get_suite_assert();
+        let group = sess.get_kx_groups().into_iter()
+            .find(|group| groups_ext.contains(group) && suite.usable_for_group(*group));
+        let group = group.ok_or_else(|| incompatible(sess, "no supported group"))?;
 
         self.emit_server_hello(sess, Some(&mut certkey), client_hello, false)?;
         self.emit_certificate(sess, &mut certkey);
         self.emit_cert_status(sess, &mut certkey);
-        let kx = self.emit_server_kx(sess, sigscheme, &group, &mut certkey)?;
+        let kx = self.emit_server_kx(sess, &sigschemes, &group, &mut certkey)?;
         let doing_client_auth = self.emit_certificate_req(sess);
         self.emit_server_hello_done(sess);
 
@@ -1196,13 +1734,10 @@ impl State for ExpectTLS12Certificate {
 
         trace!("certs {:?}", cert_chain);
 
-        sess.config.verifier.verify_client_cert(cert_chain)
-            .or_else(|err| {
-                     incompatible(sess, "certificate invalid");
-                     Err(err)
-                     })?;
+        let verified = sess.config.verifier.verify_client_cert(cert_chain)
+            .map_err(|err| send_cert_error_alert(sess, err))?;
 
-        let cert = ClientCertDetails::new(cert_chain.clone());
+        let cert = ClientCertDetails::new(cert_chain.clone(), &verified);
         Ok(self.into_expect_tls12_client_kx(Some(cert)))
     }
 }
@@ -1259,13 +1794,10 @@ impl State for ExpectTLS13Certificate {
             return Err(TLSError::NoCertificatesPresented);
         }
 
-        sess.config.get_verifier().verify_client_cert(&cert_chain)
-            .or_else(|err| {
-                     incompatible(sess, "certificate invalid");
-                     Err(err)
-                     })?;
+        let verified = sess.config.get_verifier().verify_client_cert(&cert_chain)
+            .map_err(|err| send_cert_error_alert(sess, err))?;
 
-        let cert = ClientCertDetails::new(cert_chain);
+        let cert = ClientCertDetails::new(cert_chain, &verified);
         Ok(self.into_expect_tls13_certificate_verify(cert))
     }
 }
@@ -1323,11 +1855,13 @@ impl State for ExpectTLS12ClientKX {
             SessionSecrets::new_ems(&self.handshake.randoms,
                                     &handshake_hash,
                                     hashalg,
-                                    &kxd.premaster_secret)
+                                    &kxd.premaster_secret,
+                                    &*sess.config.key_log)
         } else {
             SessionSecrets::new(&self.handshake.randoms,
                                 hashalg,
-                                &kxd.premaster_secret)
+                                &kxd.premaster_secret,
+                                &*sess.config.key_log)
         };
         sess.common.start_encryption_tls12(secrets);
 
@@ -1367,7 +1901,8 @@ impl State for ExpectTLS12CertificateVerify {
             let handshake_msgs = self.handshake.transcript.take_handshake_buf();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_signed_struct(&handshake_msgs, &certs[0], sig)
+            verify::verify_signed_struct(sess.config.provider.signature_verifier(),
+                                         &handshake_msgs, &certs[0], sig)
         };
 
         if let Err(e) = rc {
@@ -1377,6 +1912,7 @@ impl State for ExpectTLS12CertificateVerify {
 
         trace!("client CertificateVerify OK");
         sess.client_cert_chain = Some(self.client_cert.take_chain());
+        sess.client_spiffe_id = self.client_cert.take_spiffe_id();
 
         self.handshake.transcript.add_message(&m);
         Ok(self.into_expect_tls12_ccs())
@@ -1410,7 +1946,8 @@ impl State for ExpectTLS13CertificateVerify {
             self.handshake.transcript.abandon_client_auth();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_tls13(&certs[0],
+            verify::verify_tls13(sess.config.provider.signature_verifier(),
+                                 &certs[0],
                                  sig,
                                  &handshake_hash,
                                  b"TLS 1.3, client CertificateVerify\x00")
@@ -1423,6 +1960,7 @@ impl State for ExpectTLS13CertificateVerify {
 
         trace!("client CertificateVerify OK");
         sess.client_cert_chain = Some(self.client_cert.take_chain());
+        sess.client_spiffe_id = self.client_cert.take_spiffe_id();
 
         self.handshake.transcript.add_message(&m);
         Ok(self.into_expect_tls13_finished())
@@ -1467,6 +2005,71 @@ impl State for ExpectTLS12CCS {
     }
 }
 
+// --- Process client's early data ---
+pub struct ExpectTLS13EarlyData {
+    handshake: HandshakeDetails,
+    send_ticket: bool,
+    left: usize,
+}
+
+impl ExpectTLS13EarlyData {
+    fn into_expect_tls13_finished(self) -> NextState {
+        Box::new(ExpectTLS13Finished {
+            handshake: self.handshake,
+            send_ticket: self.send_ticket,
+        })
+    }
+
+    fn handle_early_data(&mut self, sess: &mut ServerSessionImpl, mut m: Message) -> Result<(), TLSError> {
+        let payload = m.take_opaque_payload().unwrap();
+
+        if payload.0.len() > self.left {
+            sess.common.send_fatal_alert(AlertDescription::UnexpectedMessage);
+            return Err(TLSError::PeerMisbehavedError("too much early data".to_string()));
+        }
+        self.left -= payload.0.len();
+
+        if let EarlyDataState::Accepted(ref mut received) = sess.early_data {
+            received.append(payload.0);
+        }
+        Ok(())
+    }
+
+    fn handle_end_of_early_data(&mut self, sess: &mut ServerSessionImpl, m: &Message) -> Result<(), TLSError> {
+        self.handshake.transcript.add_message(m);
+
+        // Move to the client handshake traffic key for the rest of its
+        // flight.
+        check_aligned_handshake(sess)?;
+        let suite = sess.common.get_suite_assert();
+        let read_key = sess.common.get_key_schedule().current_client_traffic_secret.clone();
+        sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+
+        if let Some(len) = self.handshake.deferred_fragment_limit.take() {
+            sess.common.set_our_fragment_limit(len);
+        }
+        Ok(())
+    }
+}
+
+impl State for ExpectTLS13EarlyData {
+    fn check_message(&self, m: &Message) -> CheckResult {
+        check_message(m,
+                      &[ContentType::ApplicationData, ContentType::Handshake],
+                      &[HandshakeType::EndOfEarlyData])
+    }
+
+    fn handle(mut self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError {
+        if m.is_content_type(ContentType::ApplicationData) {
+            self.handle_early_data(sess, m)?;
+            Ok(self)
+        } else {
+            self.handle_end_of_early_data(sess, &m)?;
+            Ok(self.into_expect_tls13_finished())
+        }
+    }
+}
+
 // --- Process client's Finished ---
 fn get_server_session_value_tls12(handshake: &HandshakeDetails,
                                   sess: &ServerSessionImpl) -> persist::ServerSessionValue {
@@ -1479,7 +2082,9 @@ fn get_server_session_value_tls12(handshake: &HandshakeDetails,
 
     let mut v = persist::ServerSessionValue::new(sess.get_sni(), version,
                                                  scs.suite, secret,
-                                                 &sess.client_cert_chain);
+                                                 &sess.client_cert_chain,
+                                                 &sess.client_spiffe_id,
+                                                 sess.alpn_protocol.clone().map(String::into_bytes));
 
     if handshake.using_ems {
         v.set_extended_ms_used();
@@ -1506,7 +2111,9 @@ fn get_server_session_value_tls13(handshake: &HandshakeDetails,
 
     persist::ServerSessionValue::new(sess.get_sni(), version,
                                      scs.suite, secret,
-                                     &sess.client_cert_chain)
+                                     &sess.client_cert_chain,
+                                     &sess.client_spiffe_id,
+                                     sess.alpn_protocol.clone().map(String::into_bytes))
 }
 
 fn emit_ticket(handshake: &mut HandshakeDetails,
@@ -1642,6 +2249,8 @@ pub struct ExpectTLS13Finished {
 impl ExpectTLS13Finished {
     fn into_expect_tls13_traffic(self, fin: verify::FinishedMessageVerified) -> NextState {
         Box::new(ExpectTLS13Traffic {
+            handshake: self.handshake,
+            cert_request: None,
             _fin_verified: fin,
         })
     }
@@ -1651,9 +2260,12 @@ impl ExpectTLS13Finished {
             return;
         }
 
-        let nonce = rand::random_vec(32);
-        let plain = get_server_session_value_tls13(&self.handshake, sess, &nonce)
-            .get_encoding();
+        let rng = sess.config.provider.secure_random();
+        let nonce = rand::random_vec(rng, 32);
+        let age_add = rand::random_u32(rng);
+        let mut value = get_server_session_value_tls13(&self.handshake, sess, &nonce);
+        value.set_freshness_params(ticketer::timebase(), age_add);
+        let plain = value.get_encoding();
         let maybe_ticket = sess.config
             .ticketer
             .encrypt(&plain);
@@ -1664,8 +2276,10 @@ impl ExpectTLS13Finished {
         }
 
         let ticket = maybe_ticket.unwrap();
-        let age_add = rand::random_u32(); // nb, we don't do 0-RTT data, so whatever
-        let payload = NewSessionTicketPayloadTLS13::new(ticket_lifetime, age_add, nonce, ticket);
+        let mut payload = NewSessionTicketPayloadTLS13::new(ticket_lifetime, age_add, nonce, ticket);
+        if sess.config.max_early_data_size > 0 && sess.common.protocol != Protocol::Quic {
+            payload.exts.push(NewSessionTicketExtension::EarlyData(sess.config.max_early_data_size));
+        }
         let m = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_3,
@@ -1676,7 +2290,6 @@ impl ExpectTLS13Finished {
         };
 
         trace!("sending new ticket {:?}", m);
-        self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, true);
     }
 }
@@ -1710,8 +2323,10 @@ impl State for ExpectTLS13Finished {
         // Server traffic is already done.
         let read_key = sess.common
             .get_key_schedule()
-            .derive(SecretKind::ClientApplicationTrafficSecret,
-                    &self.handshake.hash_at_server_fin);
+            .derive_logged_secret(SecretKind::ClientApplicationTrafficSecret,
+                                  &self.handshake.hash_at_server_fin,
+                                  &*sess.config.key_log,
+                                  &self.handshake.randoms.client);
 
         let suite = sess.common.get_suite_assert();
         check_aligned_handshake(sess)?;
@@ -1749,7 +2364,17 @@ impl State for ExpectTLS12Traffic {
     }
 }
 
+/// An outstanding post-handshake request for a client certificate.
+struct PostHandshakeCertRequest {
+    context: Vec<u8>,
+    transcript: HandshakeHash,
+    expect: HandshakeType,
+    client_cert: Option<ClientCertDetails>,
+}
+
 pub struct ExpectTLS13Traffic {
+    handshake: HandshakeDetails,
+    cert_request: Option<PostHandshakeCertRequest>,
     _fin_verified: verify::FinishedMessageVerified,
 }
 
@@ -1763,22 +2388,147 @@ impl ExpectTLS13Traffic {
         let kur = extract_handshake!(m, HandshakePayload::KeyUpdate).unwrap();
         sess.common.process_key_update(kur, SecretKind::ClientApplicationTrafficSecret)
     }
+
+    fn handle_certificate(&mut self, sess: &mut ServerSessionImpl, m: Message) -> Result<(), TLSError> {
+        let certp = extract_handshake!(m, HandshakePayload::CertificateTLS13).unwrap();
+        let req = self.cert_request.as_mut().unwrap();
+
+        if certp.context.0 != req.context {
+            return Err(illegal_param(sess, "client cert context mismatch"));
+        }
+
+        if certp.any_entry_has_extension() {
+            return Err(TLSError::PeerMisbehavedError("client sent unsolicited cert extension"
+                                                     .to_string()));
+        }
+
+        req.transcript.add_message(&m);
+        let cert_chain = certp.convert();
+
+        if cert_chain.is_empty() {
+            if !sess.config.verifier.client_auth_mandatory() {
+                debug!("post-handshake client auth requested but no certificate supplied");
+                req.expect = HandshakeType::Finished;
+                return Ok(());
+            }
+
+            sess.common.send_fatal_alert(AlertDescription::CertificateRequired);
+            return Err(TLSError::NoCertificatesPresented);
+        }
+
+        let verified = sess.config.get_verifier().verify_client_cert(&cert_chain)
+            .map_err(|err| send_cert_error_alert(sess, err))?;
+
+        req.client_cert = Some(ClientCertDetails::new(cert_chain, &verified));
+        req.expect = HandshakeType::CertificateVerify;
+        Ok(())
+    }
+
+    fn handle_certificate_verify(&mut self, sess: &mut ServerSessionImpl, m: Message) -> Result<(), TLSError> {
+        let req = self.cert_request.as_mut().unwrap();
+        let rc = {
+            let sig = extract_handshake!(m, HandshakePayload::CertificateVerify).unwrap();
+            let handshake_hash = req.transcript.get_current_hash();
+            let certs = &req.client_cert.as_ref().unwrap().cert_chain;
+
+            verify::verify_tls13(sess.config.provider.signature_verifier(),
+                                 &certs[0],
+                                 sig,
+                                 &handshake_hash,
+                                 b"TLS 1.3, client CertificateVerify\x00")
+        };
+
+        if let Err(e) = rc {
+            sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+            return Err(e);
+        }
+
+        trace!("post-handshake client CertificateVerify OK");
+        req.transcript.add_message(&m);
+        req.expect = HandshakeType::Finished;
+        Ok(())
+    }
+
+    fn handle_finished(&mut self, sess: &mut ServerSessionImpl, m: Message) -> Result<(), TLSError> {
+        let mut req = self.cert_request.take().unwrap();
+        let finished = extract_handshake!(m, HandshakePayload::Finished).unwrap();
+
+        let handshake_hash = req.transcript.get_current_hash();
+        let expect_verify_data = sess.common
+            .get_key_schedule()
+            .sign_finish(SecretKind::ClientApplicationTrafficSecret, &handshake_hash);
+
+        constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
+            .map_err(|_| {
+                     sess.common.send_fatal_alert(AlertDescription::DecryptError);
+                     warn!("post-handshake Finished wrong");
+                     TLSError::DecryptError
+                     })?;
+
+        if let Some(mut cert) = req.client_cert.take() {
+            debug!("post-handshake client auth complete");
+            sess.client_cert_chain = Some(cert.take_chain());
+            sess.client_spiffe_id = cert.take_spiffe_id();
+        }
+
+        Ok(())
+    }
 }
 
 impl State for ExpectTLS13Traffic {
     fn check_message(&self, m: &Message) -> CheckResult {
-        check_message(m,
-                      &[ContentType::ApplicationData, ContentType::Handshake],
-                      &[HandshakeType::KeyUpdate])
+        if let Some(ref req) = self.cert_request {
+            check_message(m,
+                          &[ContentType::ApplicationData, ContentType::Handshake],
+                          &[HandshakeType::KeyUpdate, req.expect])
+        } else {
+            check_message(m,
+                          &[ContentType::ApplicationData, ContentType::Handshake],
+                          &[HandshakeType::KeyUpdate])
+        }
     }
 
-    fn handle(self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError {
+    fn handle(mut self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError {
         if m.is_content_type(ContentType::ApplicationData) {
             self.handle_traffic(sess, m)?;
         } else if m.is_handshake_type(HandshakeType::KeyUpdate) {
             self.handle_key_update(sess, m)?;
+        } else if m.is_handshake_type(HandshakeType::Certificate) {
+            self.handle_certificate(sess, m)?;
+        } else if m.is_handshake_type(HandshakeType::CertificateVerify) {
+            self.handle_certificate_verify(sess, m)?;
+        } else if m.is_handshake_type(HandshakeType::Finished) {
+            self.handle_finished(sess, m)?;
         }
 
         Ok(self)
     }
+
+    fn request_client_cert(&mut self, sess: &mut ServerSessionImpl) -> Result<(), TLSError> {
+        if !self.handshake.post_handshake_auth_offered {
+            return Err(TLSError::PeerIncompatibleError("client does not support post-handshake auth"
+                                                       .to_string()));
+        }
+
+        if self.cert_request.is_some() {
+            return Err(TLSError::General("client certificate request already outstanding"
+                                         .to_string()));
+        }
+
+        let context = rand::random_vec(sess.config.provider.secure_random(), 32);
+        let m = make_certificate_req_tls13(sess, context.clone());
+        trace!("Sending post-handshake CertificateRequest {:?}", m);
+
+        let mut transcript = self.handshake.transcript.clone();
+        transcript.add_message(&m);
+        sess.common.send_msg(m, true);
+
+        self.cert_request = Some(PostHandshakeCertRequest {
+            context: context,
+            transcript: transcript,
+            expect: HandshakeType::Certificate,
+            client_cert: None,
+        });
+        Ok(())
+    }
 }
@@ -1,13 +1,31 @@
-use session::{Session, SessionCommon};
-use suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
+use session::{Session, SessionCommon, Protocol};
+use suites::SupportedCipherSuite;
+use crypto::CryptoProvider;
+use crypto::ring::RingProvider;
+use msgs::enums::NamedGroup;
+use msgs::handshake::{NamedGroups, SupportedGroups};
 use msgs::enums::{ContentType, SignatureScheme};
 use msgs::enums::{AlertDescription, HandshakeType, ProtocolVersion};
+use msgs::enums::PSKKeyExchangeMode;
 use msgs::handshake::SessionID;
-use msgs::message::Message;
+use msgs::message::{Message, MessagePayload};
+use msgs::deframer::MessageDeframer;
+use msgs::hsjoiner::HandshakeJoiner;
+#[cfg(feature = "ech")]
+use msgs::codec::Codec;
 use error::TLSError;
+use vecbuf::ChunkVecBuffer;
+use keylog::{KeyLog, NoKeyLog};
+use padding::{RecordPadding, NoPadding};
+use compress;
+use compress::CertificateCompressor;
+#[cfg(feature = "ech")]
+use ech::{self, ECHKey};
+use quic::{QuicExt, QuicSecrets};
 use sign;
 use verify;
 use key;
+use spiffe::SPIFFEID;
 use webpki;
 
 use std::sync::Arc;
@@ -16,8 +34,11 @@ use std::fmt;
 
 mod hs;
 mod common;
+mod acceptor;
 pub mod handy;
 
+pub use self::acceptor::{Acceptor, Accepted};
+
 /// A trait for the ability to generate Session IDs, and store
 /// server session data. The keys and values are opaque.
 ///
@@ -43,6 +64,22 @@ pub trait StoresServerSessions : Send + Sync {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
 }
 
+/// A trait for detecting replays of TLS1.3 early data.  Each
+/// ClientHello we accept early data from is recorded, identified
+/// by its PSK binder, and early data is refused from any ClientHello
+/// seen before.
+///
+/// Implementations must not forget a ClientHello while a replay of
+/// it could still pass the ticket freshness check: when they are
+/// full they should refuse to record more, rather than evicting
+/// entries.
+pub trait DetectsEarlyDataReplay : Send + Sync {
+    /// Record the ClientHello with PSK binder `binder`.  Returns `true`
+    /// if it had not been seen before and was recorded: only then
+    /// is its early data accepted.
+    fn record(&self, binder: &[u8]) -> bool;
+}
+
 /// A trait for the ability to encrypt and decrypt tickets.
 pub trait ProducesTickets : Send + Sync {
     /// Returns true if this implementation will encrypt/decrypt
@@ -90,12 +127,51 @@ pub trait ResolvesServerCert : Send + Sync {
                -> Option<sign::CertifiedKey>;
 }
 
+/// How to choose an ALPN protocol, given everything the client
+/// offered.  Use this in place of `ServerConfig::alpn_protocols`
+/// when the choice depends on more than a fixed preference list.
+pub trait ResolvesServerAlpn : Send + Sync {
+    /// Choose a protocol from `offered`, the client's ALPN protocol
+    /// list in its order of preference.  `server_name` is the DNS name
+    /// the client gave via SNI, if any.
+    ///
+    /// Return `Ok(Some(protocol))` to use `protocol`, which must be one
+    /// of `offered`.  Return `Ok(None)` to continue without ALPN, or
+    /// `Err(())` to abort the handshake with a `no_application_protocol`
+    /// alert.
+    ///
+    /// This is only called if the client sent the ALPN extension.
+    fn resolve(&self,
+               server_name: Option<webpki::DNSNameRef>,
+               offered: &[String])
+               -> Result<Option<String>, ()>;
+}
+
+/// How to find a TLS1.3 external pre-shared key, given the
+/// identity a client offered it under.
+///
+/// An external PSK is agreed with the client out-of-band, and
+/// authenticates both sides in place of certificates.  External
+/// PSKs are always used with the SHA256 hash, so only with
+/// ciphersuites using SHA256.
+pub trait ResolvesPsk : Send + Sync {
+    /// Return the key known by `identity`, or None if there
+    /// is no such key.  `identity` is fully attacker controlled.
+    fn resolve(&self, identity: &[u8]) -> Option<Vec<u8>>;
+}
+
 /// Common configuration for a set of server sessions.
 ///
 /// Making one of these can be expensive, and should be
 /// once per process rather than once per connection.
 #[derive(Clone)]
 pub struct ServerConfig {
+    /// Where we get our cryptography.  `ciphersuites` should
+    /// contain this provider's suites.
+    ///
+    /// The default is `RingProvider`.
+    pub provider: Arc<CryptoProvider>,
+
     /// List of ciphersuites, in preference order.
     pub ciphersuites: Vec<&'static SupportedCipherSuite>,
 
@@ -107,6 +183,17 @@ pub struct ServerConfig {
     /// Our MTU.  If None, we don't limit TLS message sizes.
     pub mtu: Option<usize>,
 
+    /// The largest record we want to receive, sent as an RFC8449
+    /// `record_size_limit` to clients which offer that extension.
+    /// Values outside the range 64 to 16385 are clamped to it.
+    ///
+    /// Record size limits asked for by clients, with either
+    /// `record_size_limit` or RFC6066 `max_fragment_length`, are
+    /// obeyed whatever this is.
+    ///
+    /// The default is None: we accept records of any legal size.
+    pub record_size_limit: Option<u16>,
+
     /// How to store client sessions.
     pub session_storage: Arc<StoresServerSessions + Send + Sync>,
 
@@ -120,10 +207,90 @@ pub struct ServerConfig {
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<String>,
 
+    /// How to choose an ALPN protocol at runtime.  If set, this
+    /// decides instead of `alpn_protocols`.
+    ///
+    /// The default is None.
+    pub alpn_resolver: Option<Arc<ResolvesServerAlpn>>,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub versions: Vec<ProtocolVersion>,
 
+    /// The key exchange groups we support, most preferred first.
+    /// We choose the first of these the client supports.  In TLS1.3
+    /// we prefer groups the client sent a key share for, and only
+    /// send a HelloRetryRequest if there are none.  In TLS1.2, this
+    /// also decides which suites are usable: ECDHE suites need an
+    /// elliptic curve in common, and DHE suites a finite field group.
+    /// Groups we don't implement are ignored.
+    ///
+    /// The default is X25519, P-384 and P-256.  Add the RFC7919
+    /// `FFDHE` groups to use finite field Diffie-Hellman, in TLS1.3
+    /// or with the TLS1.2 `DHE_RSA` suites.
+    pub kx_groups: Vec<NamedGroup>,
+
+    /// The maximum amount of TLS1.3 early data ("0-RTT data") we
+    /// will accept from a client resuming a session, in bytes.
+    /// If zero (the default) early data is not offered in our
+    /// tickets and never accepted.
+    ///
+    /// Early data is replayable by an attacker.  We accept it only
+    /// with the first PSK offered, for a fresh ticket, and only once
+    /// per ClientHello as recorded by `early_data_replay`.  That only
+    /// covers this `ServerConfig`: servers sharing ticket keys don't
+    /// see each other's records unless they share an implementation
+    /// of `DetectsEarlyDataReplay`.  Even so, only accept early data
+    /// for requests which are safe to replay.
+    pub max_early_data_size: u32,
+
+    /// How to detect replayed early data.
+    ///
+    /// The default is an `EarlyDataReplayCache` holding 1024
+    /// ClientHellos.
+    pub early_data_replay: Arc<DetectsEarlyDataReplay>,
+
+    /// How to output key material for debugging.  The default
+    /// does nothing.
+    pub key_log: Arc<KeyLog>,
+
+    /// How much padding to add to the TLS1.3 records we send, to
+    /// hide the length of their contents from eavesdroppers.
+    ///
+    /// The default adds no padding.
+    pub record_padding: Arc<RecordPadding>,
+
+    /// How to find TLS1.3 external pre-shared keys offered by
+    /// clients.  A handshake authenticated by one of these doesn't
+    /// need a certificate.
+    ///
+    /// The default knows no keys.
+    pub psk_resolver: Arc<ResolvesPsk>,
+
+    /// The TLS1.3 PSK key exchange modes we accept, for both resumption
+    /// and external PSKs, most preferred first.  `PSK_KE` connections
+    /// don't have forward secrecy.
+    ///
+    /// The default is just `PSK_DHE_KE`.
+    pub psk_modes: Vec<PSKKeyExchangeMode>,
+
+    /// How to compress our certificate chain for TLS1.3 clients
+    /// supporting RFC8879 certificate compression.  We use the first
+    /// of these that the client offered.
+    ///
+    /// The default is `ZlibCertificateCompression` if the `zlib`
+    /// feature is enabled, and empty otherwise.
+    pub certificate_compressors: Vec<Arc<CertificateCompressor>>,
+
+    /// Keys for decrypting Encrypted Client Hello offers from TLS1.3
+    /// clients.  Publish their configurations, from
+    /// `get_ech_config_list`, for clients to use.
+    ///
+    /// The default is empty: we don't do ECH.  This needs the
+    /// `ech` feature.
+    #[cfg(feature = "ech")]
+    pub ech_keys: Vec<ECHKey>,
+
     /// How to verify client certificates.
     verifier: Arc<verify::ClientCertVerifier>,
 }
@@ -143,15 +310,38 @@ impl ServerConfig {
     /// default, requiring client authentication, requires additional
     /// configuration that we cannot provide reasonable defaults for.
     pub fn new(client_cert_verifier: Arc<verify::ClientCertVerifier>) -> ServerConfig {
+        ServerConfig::with_provider(client_cert_verifier, Arc::new(RingProvider))
+    }
+
+    /// Make a `ServerConfig` like `new`, but getting all its
+    /// cryptography from `provider`.  Its ciphersuites are the
+    /// provider's, and its session storage generates session IDs
+    /// with the provider.  To enable tickets, set `ticketer` to
+    /// `Ticketer::with_provider(provider)`.
+    pub fn with_provider(client_cert_verifier: Arc<verify::ClientCertVerifier>,
+                         provider: Arc<CryptoProvider>) -> ServerConfig {
         ServerConfig {
-            ciphersuites: ALL_CIPHERSUITES.to_vec(),
+            ciphersuites: provider.cipher_suites(),
+            provider: provider.clone(),
             ignore_client_order: false,
             mtu: None,
-            session_storage: handy::ServerSessionMemoryCache::new(256),
+            record_size_limit: None,
+            session_storage: handy::ServerSessionMemoryCache::with_provider(256, provider),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
             alpn_protocols: Vec::new(),
+            alpn_resolver: None,
             cert_resolver: Arc::new(handy::FailResolveChain {}),
             versions: vec![ ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2 ],
+            kx_groups: NamedGroups::supported(),
+            max_early_data_size: 0,
+            early_data_replay: handy::EarlyDataReplayCache::new(1024),
+            key_log: Arc::new(NoKeyLog {}),
+            record_padding: Arc::new(NoPadding),
+            psk_resolver: Arc::new(handy::FailResolvePsk {}),
+            psk_modes: vec![ PSKKeyExchangeMode::PSK_DHE_KE ],
+            certificate_compressors: compress::default_compressors(),
+            #[cfg(feature = "ech")]
+            ech_keys: Vec::new(),
             verifier: client_cert_verifier,
         }
     }
@@ -176,11 +366,11 @@ impl ServerConfig {
     /// disregarded.
     ///
     /// `cert_chain` is a vector of DER-encoded certificates.
-    /// `key_der` is a DER-encoded RSA private key.
+    /// `key_der` is a DER-encoded RSA or ECDSA private key.
     pub fn set_single_cert(&mut self,
                            cert_chain: Vec<key::Certificate>,
                            key_der: key::PrivateKey) {
-        self.cert_resolver = Arc::new(handy::AlwaysResolvesChain::new_rsa(cert_chain, &key_der));
+        self.cert_resolver = Arc::new(handy::AlwaysResolvesChain::new(cert_chain, &key_der));
     }
 
     /// Sets a single certificate chain, matching private key and OCSP
@@ -188,7 +378,7 @@ impl ServerConfig {
     /// connections, irrespective of things like SNI hostname.
     ///
     /// `cert_chain` is a vector of DER-encoded certificates.
-    /// `key_der` is a DER-encoded RSA private key.
+    /// `key_der` is a DER-encoded RSA or ECDSA private key.
     /// `ocsp` is a DER-encoded OCSP response.  Ignored if zero length.
     /// `scts` is an `SignedCertificateTimestampList` encoding (see RFC6962)
     /// and is ignored if empty.
@@ -197,10 +387,10 @@ impl ServerConfig {
                                              key_der: key::PrivateKey,
                                              ocsp: Vec<u8>,
                                              scts: Vec<u8>) {
-        let resolver = handy::AlwaysResolvesChain::new_rsa_with_extras(cert_chain,
-                                                                       &key_der,
-                                                                       ocsp,
-                                                                       scts);
+        let resolver = handy::AlwaysResolvesChain::new_with_extras(cert_chain,
+                                                                   &key_der,
+                                                                   ocsp,
+                                                                   scts);
         self.cert_resolver = Arc::new(resolver);
     }
 
@@ -213,6 +403,32 @@ impl ServerConfig {
         self.alpn_protocols.clear();
         self.alpn_protocols.extend_from_slice(protocols);
     }
+
+    /// The configurations of `ech_keys`, in the `ECHConfigList`
+    /// encoding clients want.  We also send this to clients whose
+    /// offers we couldn't decrypt.
+    #[cfg(feature = "ech")]
+    pub fn get_ech_config_list(&self) -> Vec<u8> {
+        ech::get_config_list(&self.ech_keys).get_encoding()
+    }
+}
+
+/// What happened to early data the client may have sent.
+pub enum EarlyDataState {
+    /// The client didn't offer early data, or we haven't decided yet.
+    New,
+
+    /// We accepted early data; it is buffered here until the caller
+    /// reads it.
+    Accepted(ChunkVecBuffer),
+
+    /// We rejected early data, and will skip up to this many more bytes
+    /// of records we can't decrypt (or plaintext records, after
+    /// HelloRetryRequest).
+    Skipping(usize),
+
+    /// We rejected early data, and the client has moved on.
+    Rejected,
 }
 
 pub struct ServerSessionImpl {
@@ -223,6 +439,9 @@ pub struct ServerSessionImpl {
     pub error: Option<TLSError>,
     pub state: Option<Box<hs::State + Send + Sync>>,
     pub client_cert_chain: Option<Vec<key::Certificate>>,
+    pub client_spiffe_id: Option<SPIFFEID>,
+    pub early_data: EarlyDataState,
+    pub psk_identity: Option<Vec<u8>>,
 }
 
 impl fmt::Debug for ServerSessionImpl {
@@ -234,18 +453,32 @@ impl fmt::Debug for ServerSessionImpl {
 impl ServerSessionImpl {
     pub fn new(server_config: &Arc<ServerConfig>) -> ServerSessionImpl {
         let perhaps_client_auth = server_config.verifier.offer_client_auth();
+        let mut common = SessionCommon::new(server_config.mtu, false);
+        common.record_padding = server_config.record_padding.clone();
 
         ServerSessionImpl {
             config: server_config.clone(),
-            common: SessionCommon::new(server_config.mtu, false),
+            common: common,
             sni: None,
             alpn_protocol: None,
             error: None,
-            state: Some(Box::new(hs::ExpectClientHello::new(perhaps_client_auth))),
+            state: Some(Box::new(hs::ExpectClientHello::new(perhaps_client_auth,
+                                                                server_config.provider.secure_random()))),
             client_cert_chain: None,
+            client_spiffe_id: None,
+            early_data: EarlyDataState::New,
+            psk_identity: None,
         }
     }
 
+    /// Our `kx_groups`, without any we can't do key exchange in.
+    pub fn get_kx_groups(&self) -> Vec<NamedGroup> {
+        self.config.kx_groups.iter()
+            .filter(|group| self.config.provider.find_kx_group(**group).is_some())
+            .cloned()
+            .collect()
+    }
+
     pub fn wants_read(&self) -> bool {
         // We want to read more data all the time, except when we
         // have unprocessed plaintext.  This provides back-pressure
@@ -279,8 +512,24 @@ impl ServerSessionImpl {
 
         // Decrypt if demanded by current state.
         if self.common.peer_encrypting {
-            let dm = self.common.decrypt_incoming(msg)?;
-            msg = dm;
+            let len = msg.payload.length();
+            match self.common.decrypt_incoming(msg) {
+                Ok(dm) => {
+                    if let EarlyDataState::Skipping(_) = self.early_data {
+                        self.early_data = EarlyDataState::Rejected;
+                    }
+                    msg = dm;
+                }
+                Err(TLSError::DecryptError) if self.skip_early_data(len) => {
+                    trace!("Dropping undecryptable early data");
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            }
+        } else if msg.is_content_type(ContentType::ApplicationData) &&
+                  self.skip_early_data(msg.payload.length()) {
+            trace!("Dropping early data after HelloRetryRequest");
+            return Ok(());
         }
 
         // For handshake messages, we need to join them before parsing
@@ -304,6 +553,31 @@ impl ServerSessionImpl {
         self.process_main_protocol(msg)
     }
 
+    /// Returns true if we're skipping rejected early data, and `len`
+    /// more bytes of it fits within our limit.
+    fn skip_early_data(&mut self, len: usize) -> bool {
+        match self.early_data {
+            EarlyDataState::Skipping(left) if len <= left => {
+                self.early_data = EarlyDataState::Skipping(left - len);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Continue from data read by an `Acceptor`, which has already
+    /// joined the ClientHello.  Errors are kept for `process_new_packets`.
+    fn process_accepted(&mut self,
+                        deframer: MessageDeframer,
+                        joiner: HandshakeJoiner) {
+        self.common.message_deframer = deframer;
+        self.common.handshake_joiner = joiner;
+
+        if let Err(err) = self.process_new_handshake_messages() {
+            self.error = Some(err);
+        }
+    }
+
     fn process_new_handshake_messages(&mut self) -> Result<(), TLSError> {
         while let Some(msg) = self.common.handshake_joiner.frames.pop_front() {
             self.process_main_protocol(msg)?;
@@ -332,6 +606,32 @@ impl ServerSessionImpl {
         Ok(())
     }
 
+    /// Process handshake data `plaintext`, given to us by QUIC.
+    pub fn read_quic_hs(&mut self, plaintext: &[u8]) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        let msg = Message {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::new_opaque(plaintext.to_vec()),
+        };
+
+        let result = match self.common.handshake_joiner.take_message(msg) {
+            Some(_) => self.process_new_handshake_messages(),
+            None => {
+                self.common.send_fatal_alert(AlertDescription::DecodeError);
+                Err(TLSError::CorruptMessagePayload(ContentType::Handshake))
+            }
+        };
+
+        if let Err(ref err) = result {
+            self.error = Some(err.clone());
+        }
+        result
+    }
+
     pub fn process_new_packets(&mut self) -> Result<(), TLSError> {
         if let Some(ref err) = self.error {
             return Err(err.clone());
@@ -381,6 +681,25 @@ impl ServerSessionImpl {
         self.common.get_suite()
     }
 
+    pub fn get_psk_identity(&self) -> Option<&[u8]> {
+        self.psk_identity.as_ref().map(|id| &id[..])
+    }
+
+    pub fn request_client_certificate(&mut self) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.is_handshaking() {
+            return Err(TLSError::HandshakeNotComplete);
+        }
+
+        let mut st = self.state.take().unwrap();
+        let rc = st.request_client_cert(self);
+        self.state = Some(st);
+        rc
+    }
+
     pub fn get_sni(&self)-> Option<&webpki::DNSName> {
         self.sni.as_ref()
     }
@@ -390,6 +709,31 @@ impl ServerSessionImpl {
         assert!(self.sni.is_none());
         self.sni = Some(value)
     }
+
+    pub fn is_early_data_accepted(&self) -> bool {
+        match self.early_data {
+            EarlyDataState::Accepted(_) => true,
+            _ => false,
+        }
+    }
+
+    fn read_early_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.early_data {
+            EarlyDataState::Accepted(ref mut received) => received.read(buf),
+            _ => Ok(0),
+        }
+    }
+}
+
+/// Stub that implements io::Read and dispatches to `read_early_data`.
+pub struct ReadEarlyData<'a> {
+    sess: &'a mut ServerSessionImpl,
+}
+
+impl<'a> io::Read for ReadEarlyData<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.sess.read_early_data(buf)
+    }
 }
 
 /// This represents a single TLS server session.
@@ -409,6 +753,25 @@ impl ServerSession {
         ServerSession { imp: ServerSessionImpl::new(config) }
     }
 
+    /// Make a new ServerSession for QUIC, answering with our
+    /// transport parameters `params`.  Rather than reading and
+    /// writing TLS records, use the `QuicExt` methods to exchange
+    /// handshake messages.
+    ///
+    /// # Panics
+    ///
+    /// QUIC requires TLS1.3: this panics if `config` allows any
+    /// other version.
+    pub fn new_quic(config: &Arc<ServerConfig>, params: Vec<u8>) -> ServerSession {
+        assert!(config.versions == [ProtocolVersion::TLSv1_3],
+                "QUIC requires TLS1.3 only");
+
+        let mut imp = ServerSessionImpl::new(config);
+        imp.common.protocol = Protocol::Quic;
+        imp.common.quic.params = params;
+        ServerSession { imp: imp }
+    }
+
     /// Retrieves the SNI hostname, if any, used to select the certificate and
     /// private key.
     ///
@@ -428,6 +791,60 @@ impl ServerSession {
     pub fn get_sni_hostname(&self)-> Option<&str> {
         self.imp.get_sni().map(|s| s.as_ref().into())
     }
+
+    /// Retrieves the client's SPIFFE ID, as established by the
+    /// `ClientCertVerifier` (for example, `AllowSPIFFEClient`).
+    ///
+    /// This returns `None` until the client has proved possession of
+    /// its certificate's key, and always if the verifier doesn't
+    /// identify clients this way.  It is kept when a session is
+    /// resumed, and replaced by post-handshake authentication.
+    pub fn get_peer_spiffe_id(&self) -> Option<&SPIFFEID> {
+        self.imp.client_spiffe_id.as_ref()
+    }
+
+    /// Returns an `io::Read` implementer you can read TLS1.3 early
+    /// data (a.k.a. "0-RTT data") from, if we accepted any.
+    ///
+    /// This returns None unless `ServerConfig::max_early_data_size`
+    /// is non-zero and the client's early data was accepted.  Early
+    /// data becomes readable during the handshake, before the client
+    /// is authenticated by its Finished message, and may have been
+    /// replayed by an attacker.  It is kept separate from data read
+    /// through `io::Read` on this object for that reason.
+    pub fn early_data(&mut self) -> Option<ReadEarlyData> {
+        if self.imp.is_early_data_accepted() {
+            Some(ReadEarlyData { sess: &mut self.imp })
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the client sent early data and we accepted it.
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.imp.is_early_data_accepted()
+    }
+
+    /// Returns the identity of the external PSK that authenticated
+    /// the client, if any.
+    ///
+    /// This returns None before the handshake gets that far, and
+    /// for handshakes authenticated by certificate or resumption.
+    pub fn get_psk_identity(&self) -> Option<&[u8]> {
+        self.imp.get_psk_identity()
+    }
+
+    /// Asks the client for a certificate, using TLS1.3 post-handshake
+    /// authentication.  The client must have offered this, and the
+    /// handshake must be complete.
+    ///
+    /// The chain is checked with the configured `ClientCertVerifier`.
+    /// Once the client has answered, `get_peer_certificates` returns
+    /// the new chain.  If the client declines and the verifier doesn't
+    /// make client auth mandatory, nothing changes.
+    pub fn request_client_certificate(&mut self) -> Result<(), TLSError> {
+        self.imp.request_client_certificate()
+    }
 }
 
 impl Session for ServerSession {
@@ -464,6 +881,10 @@ impl Session for ServerSession {
         self.imp.common.send_close_notify()
     }
 
+    fn refresh_traffic_keys(&mut self, request_peer: bool) -> Result<(), TLSError> {
+        self.imp.common.refresh_traffic_keys(request_peer)
+    }
+
     fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
         self.imp.get_peer_certificates()
     }
@@ -488,6 +909,24 @@ impl Session for ServerSession {
     }
 }
 
+impl QuicExt for ServerSession {
+    fn get_quic_transport_parameters(&self) -> Option<&[u8]> {
+        self.imp.common.quic.peer_params.as_ref().map(|params| &params[..])
+    }
+
+    fn read_hs(&mut self, plaintext: &[u8]) -> Result<(), TLSError> {
+        self.imp.read_quic_hs(plaintext)
+    }
+
+    fn write_hs(&mut self, buf: &mut Vec<u8>) -> Option<QuicSecrets> {
+        self.imp.common.quic.write_hs(buf)
+    }
+
+    fn get_alert(&self) -> Option<AlertDescription> {
+        self.imp.common.quic.alert
+    }
+}
+
 impl io::Read for ServerSession {
     /// Obtain plaintext data received from the peer over
     /// this TLS connection.
@@ -496,6 +935,18 @@ impl io::Read for ServerSession {
     }
 }
 
+impl io::BufRead for ServerSession {
+    /// Obtain a chunk of plaintext data received from the peer
+    /// over this TLS connection, without copying it.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.imp.common.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.imp.common.consume(amt)
+    }
+}
+
 impl io::Write for ServerSession {
     /// Send the plaintext `buf` to the peer, encrypting
     /// and authenticating it.  Once this function succeeds
@@ -1,4 +1,3 @@
-use ring;
 use std::io::{Read, Write};
 use msgs::message::{BorrowMessage, Message, MessagePayload};
 use msgs::deframer::MessageDeframer;
@@ -10,15 +9,20 @@ use msgs::enums::{ContentType, ProtocolVersion, AlertDescription, AlertLevel};
 use msgs::enums::KeyUpdateRequest;
 use error::TLSError;
 use suites::SupportedCipherSuite;
+use crypto::{Hash, SecureRandom};
 use cipher::{MessageDecrypter, MessageEncrypter, self};
 use vecbuf::ChunkVecBuffer;
 use key;
 use key_schedule::{SecretKind, KeySchedule};
+use keylog::KeyLog;
+use padding::{RecordPadding, NoPadding};
+use quic::Quic;
 use prf;
 use rand;
 
 use std::io;
 use std::collections::VecDeque;
+use std::sync::Arc;
 
 /// Generalises `ClientSession` and `ServerSession`
 pub trait Session: Read + Write + Send + Sync {
@@ -85,6 +89,21 @@ pub trait Session: Read + Write + Send + Sync {
     /// connection is being closed.
     fn send_close_notify(&mut self);
 
+    /// Queues a TLS1.3 KeyUpdate message, and moves to new keys for
+    /// everything we send after it.  If `request_peer` is true, the
+    /// peer is asked to move to new keys too.
+    ///
+    /// We do this automatically before a key has been used too much,
+    /// so this is only needed if you want to update keys more often.
+    /// It fails if the handshake isn't complete, or TLS1.3 wasn't
+    /// negotiated.
+    ///
+    /// TLS1.2 has no key updates.  Instead, once we've sent 2^24
+    /// records under an AES-GCM key, we send a close_notify alert
+    /// and writes fail with `io::ErrorKind::ConnectionAborted`.
+    /// Limiting what the peer sends is up to the peer.
+    fn refresh_traffic_keys(&mut self, request_peer: bool) -> Result<(), TLSError>;
+
     /// Retrieves the certificate chain used by the peer to authenticate.
     ///
     /// For clients, this is the certificate chain of the server.
@@ -206,25 +225,25 @@ pub struct SessionRandoms {
 }
 
 impl SessionRandoms {
-    pub fn for_server() -> SessionRandoms {
+    pub fn for_server(rng: &SecureRandom) -> SessionRandoms {
         let mut ret = SessionRandoms {
             we_are_client: false,
             client: [0u8; 32],
             server: [0u8; 32],
         };
 
-        rand::fill_random(&mut ret.server);
+        rand::fill_random(rng, &mut ret.server);
         ret
     }
 
-    pub fn for_client() -> SessionRandoms {
+    pub fn for_client(rng: &SecureRandom) -> SessionRandoms {
         let mut ret = SessionRandoms {
             we_are_client: true,
             client: [0u8; 32],
             server: [0u8; 32],
         };
 
-        rand::fill_random(&mut ret.client);
+        rand::fill_random(rng, &mut ret.client);
         ret
     }
 }
@@ -238,14 +257,15 @@ fn join_randoms(first: &[u8], second: &[u8]) -> [u8; 64] {
 
 pub struct SessionSecrets {
     pub randoms: SessionRandoms,
-    hash: &'static ring::digest::Algorithm,
+    hash: &'static Hash,
     master_secret: [u8; 48],
 }
 
 impl SessionSecrets {
     pub fn new(randoms: &SessionRandoms,
-               hashalg: &'static ring::digest::Algorithm,
-               pms: &[u8])
+               hashalg: &'static Hash,
+               pms: &[u8],
+               key_log: &KeyLog)
                -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
@@ -259,13 +279,15 @@ impl SessionSecrets {
                  pms,
                  b"master secret",
                  &randoms);
+        ret.log(key_log);
         ret
     }
 
     pub fn new_ems(randoms: &SessionRandoms,
                    hs_hash: &[u8],
-                   hashalg: &'static ring::digest::Algorithm,
-                   pms: &[u8]) -> SessionSecrets {
+                   hashalg: &'static Hash,
+                   pms: &[u8],
+                   key_log: &KeyLog) -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
             hash: hashalg,
@@ -277,12 +299,14 @@ impl SessionSecrets {
                  pms,
                  b"extended master secret",
                  hs_hash);
+        ret.log(key_log);
         ret
     }
 
     pub fn new_resume(randoms: &SessionRandoms,
-                      hashalg: &'static ring::digest::Algorithm,
-                      master_secret: &[u8])
+                      hashalg: &'static Hash,
+                      master_secret: &[u8],
+                      key_log: &KeyLog)
                       -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
@@ -290,9 +314,15 @@ impl SessionSecrets {
             master_secret: [0u8; 48],
         };
         ret.master_secret.as_mut().write_all(master_secret).unwrap();
+        ret.log(key_log);
         ret
     }
 
+    /// Tell `key_log` about our master secret.
+    fn log(&self, key_log: &KeyLog) {
+        key_log.log("CLIENT_RANDOM", &self.randoms.client, &self.master_secret);
+    }
+
     pub fn make_key_block(&self, len: usize) -> Vec<u8> {
         let mut out = Vec::new();
         out.resize(len, 0u8);
@@ -365,6 +395,14 @@ enum Limit {
     No
 }
 
+/// Whether a session carries TLS records, or hands bare handshake
+/// messages to QUIC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Tls,
+    Quic,
+}
+
 pub struct SessionCommon {
     pub negotiated_version: Option<ProtocolVersion>,
     pub is_client: bool,
@@ -380,12 +418,17 @@ pub struct SessionCommon {
     pub we_encrypting: bool,
     pub traffic: bool,
     pub want_write_key_update: bool,
+    want_peer_key_update: bool,
+    pub record_padding: Arc<RecordPadding>,
     pub message_deframer: MessageDeframer,
     pub handshake_joiner: HandshakeJoiner,
     pub message_fragmenter: MessageFragmenter,
+    max_incoming_fragment: Option<usize>,
     received_plaintext: ChunkVecBuffer,
     sendable_plaintext: ChunkVecBuffer,
     pub sendable_tls: ChunkVecBuffer,
+    pub protocol: Protocol,
+    pub quic: Quic,
 }
 
 impl SessionCommon {
@@ -405,12 +448,17 @@ impl SessionCommon {
             we_encrypting: false,
             traffic: false,
             want_write_key_update: false,
+            want_peer_key_update: false,
+            record_padding: Arc::new(NoPadding),
             message_deframer: MessageDeframer::new(),
             handshake_joiner: HandshakeJoiner::new(),
             message_fragmenter: MessageFragmenter::new(mtu.unwrap_or(MAX_FRAGMENT_LEN)),
+            max_incoming_fragment: None,
             received_plaintext: ChunkVecBuffer::new(),
             sendable_plaintext: ChunkVecBuffer::new(),
             sendable_tls: ChunkVecBuffer::new(),
+            protocol: Protocol::Tls,
+            quic: Quic::new(),
         }
     }
 
@@ -466,7 +514,22 @@ impl SessionCommon {
                                  cipher: Box<MessageDecrypter>) {
         self.message_decrypter = cipher;
         self.read_seq = 0;
-        self.peer_encrypting = true;
+        self.peer_now_encrypting();
+    }
+
+    /// The peer told us the largest record plaintext it will accept.
+    pub fn set_peer_fragment_limit(&mut self, max_fragment_len: usize) {
+        self.message_fragmenter.limit_fragment_len(max_fragment_len);
+    }
+
+    /// The peer agreed not to send us protected records with more
+    /// than `max_fragment_len` bytes of plaintext.
+    pub fn set_our_fragment_limit(&mut self, max_fragment_len: usize) {
+        self.max_incoming_fragment = Some(max_fragment_len);
+
+        if self.peer_encrypting {
+            self.message_deframer.limit_fragment_len(max_fragment_len);
+        }
     }
 
     pub fn has_readable_plaintext(&self) -> bool {
@@ -481,7 +544,10 @@ impl SessionCommon {
     pub fn encrypt_outgoing(&mut self, plain: BorrowMessage) -> Message {
         let seq = self.write_seq;
         self.write_seq += 1;
-        self.message_encrypter.encrypt(plain, seq).unwrap()
+        let max_len = self.message_fragmenter.get_max_fragment_len();
+        self.message_encrypter
+            .encrypt_padded(plain, seq, self.record_padding.as_ref(), max_len)
+            .unwrap()
     }
 
     pub fn decrypt_incoming(&mut self, encr: Message) -> Result<Message, TLSError> {
@@ -493,11 +559,42 @@ impl SessionCommon {
             self.send_close_notify();
         }
 
+        // A peer which keeps using its key well past the point where
+        // it should have moved to a new one (TLS1.3) or closed the
+        // connection (TLS1.2) gets no more records read.
+        if self.read_key_exhausted() {
+            warn!("Peer read key exhausted, sending fatal alert");
+            self.send_fatal_alert(AlertDescription::UnexpectedMessage);
+            return Err(TLSError::PeerMisbehavedError("peer exceeded key usage limit".to_string()));
+        }
+
+        // Only advance the sequence number for records we could
+        // decrypt: a server may skip undecryptable early data.
         let seq = self.read_seq;
-        self.read_seq += 1;
         let ret = self.message_decrypter.decrypt(encr, seq);
-        if let Err(TLSError::PeerSentOversizedRecord) = ret {
-            self.send_fatal_alert(AlertDescription::RecordOverflow);
+        match ret {
+            Ok(ref msg) => {
+                self.read_seq += 1;
+
+                // Ask the peer to move to a new key before it uses
+                // this one too much.
+                if self.read_seq == self.message_decrypter.confidentiality_limit() &&
+                   self.is_tls13() && self.traffic {
+                    self.want_peer_key_update = true;
+                    self.do_write_key_update();
+                }
+
+                if self.max_incoming_fragment
+                    .map(|max| msg.payload.length() > max)
+                    .unwrap_or(false) {
+                    self.send_fatal_alert(AlertDescription::RecordOverflow);
+                    return Err(TLSError::PeerSentOversizedRecord);
+                }
+            }
+            Err(TLSError::PeerSentOversizedRecord) => {
+                self.send_fatal_alert(AlertDescription::RecordOverflow);
+            }
+            Err(_) => {}
         }
 
         ret
@@ -546,8 +643,21 @@ impl SessionCommon {
             SecretKind::ServerApplicationTrafficSecret
         };
 
+        let m = if self.want_peer_key_update {
+            Message::build_key_update_request()
+        } else {
+            Message::build_key_update_notify()
+        };
         self.want_write_key_update = false;
-        self.send_msg_encrypt(Message::build_key_update_notify());
+        self.want_peer_key_update = false;
+
+        // This goes under the old key, so mustn't come back here.
+        let mut plain_messages = VecDeque::new();
+        self.message_fragmenter.fragment(m, &mut plain_messages);
+        for m in plain_messages {
+            let em = self.encrypt_outgoing(m.to_borrowed());
+            self.queue_tls_message(em);
+        }
 
         let write_key = self.get_key_schedule().derive_next(kind);
         let scs = self.get_suite_assert();
@@ -563,10 +673,6 @@ impl SessionCommon {
     /// Fragment `m`, encrypt the fragments, and then queue
     /// the encrypted fragments for sending.
     pub fn send_msg_encrypt(&mut self, m: Message) {
-        if self.want_write_key_update {
-            self.do_write_key_update();
-        }
-
         let mut plain_messages = VecDeque::new();
         self.message_fragmenter.fragment(m, &mut plain_messages);
 
@@ -579,10 +685,6 @@ impl SessionCommon {
     fn send_appdata_encrypt(&mut self,
                             payload: &[u8],
                             limit: Limit) -> usize {
-        if self.want_write_key_update {
-            self.do_write_key_update();
-        }
-
         // Here, the limit on sendable_tls applies to encrypted data,
         // but we're respecting it for plaintext data -- so we'll
         // be out by whatever the cipher+record overhead is.  That's a
@@ -598,14 +700,27 @@ impl SessionCommon {
                                                 &payload[..len],
                                                 &mut plain_messages);
 
+        let mut sent = 0;
         for m in plain_messages {
+            if self.write_key_exhausted() {
+                break;
+            }
+            sent += m.payload.len();
             self.send_single_fragment(m);
         }
 
-        len
+        sent
     }
 
     fn send_single_fragment(&mut self, m: BorrowMessage) {
+        // Move to a new key if the peer asked us to, or before we
+        // use this one too much.
+        if self.want_write_key_update ||
+           (self.write_seq >= self.message_encrypter.confidentiality_limit() &&
+            self.is_tls13() && self.traffic) {
+            self.do_write_key_update();
+        }
+
         // Close connection once we start to run out of
         // sequence space.
         if self.write_seq == SEQ_SOFT_LIMIT {
@@ -614,12 +729,42 @@ impl SessionCommon {
 
         // Refuse to wrap counter at all costs.  This
         // is basically untestable unfortunately.
-        if self.write_seq >= SEQ_HARD_LIMIT {
+        if self.write_seq >= SEQ_HARD_LIMIT || self.write_key_exhausted() {
             return;
         }
 
         let em = self.encrypt_outgoing(m);
         self.queue_tls_message(em);
+
+        // TLS1.2 can't move to a new key, so once this one has been
+        // used as much as it safely can be, the connection is over.
+        // The close_notify goes out directly: send_close_notify would
+        // come back here and find nothing more may be sent.
+        if self.write_key_exhausted() {
+            warn!("Write key exhausted, sending close_notify");
+            let alert = Message::build_alert(AlertLevel::Warning, AlertDescription::CloseNotify);
+            let mut plain_messages = VecDeque::new();
+            self.message_fragmenter.fragment(alert, &mut plain_messages);
+            for m in plain_messages {
+                let em = self.encrypt_outgoing(m.to_borrowed());
+                self.queue_tls_message(em);
+            }
+        }
+    }
+
+    /// Have we sent as many records as our TLS1.2 write key allows?
+    /// TLS1.3 keys are updated before this happens.
+    fn write_key_exhausted(&self) -> bool {
+        !self.is_tls13() &&
+            self.write_seq >= self.message_encrypter.confidentiality_limit()
+    }
+
+    /// Has the peer sent more records under its current key than we
+    /// will read?  It may go an eighth over the confidentiality limit,
+    /// so that its KeyUpdate or close_notify has time to arrive.
+    fn read_key_exhausted(&self) -> bool {
+        let limit = self.message_decrypter.confidentiality_limit();
+        self.read_seq >= limit.saturating_add(limit / 8)
     }
 
     /// Are we done? ie, have we processed all received messages,
@@ -649,6 +794,18 @@ impl SessionCommon {
         self.send_plain(data, Limit::Yes)
     }
 
+    /// Encrypt and send TLS1.3 early data.  The caller has checked
+    /// the amount against the server's limit.
+    pub fn send_early_plaintext(&mut self, data: &[u8]) -> io::Result<usize> {
+        debug_assert!(self.we_encrypting);
+
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(self.send_appdata_encrypt(data, Limit::Yes))
+    }
+
 
     fn send_plain(&mut self, data: &[u8], limit: Limit) -> io::Result<usize> {
         if !self.traffic {
@@ -663,6 +820,11 @@ impl SessionCommon {
 
         debug_assert!(self.we_encrypting);
 
+        if self.write_key_exhausted() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                      "TLS1.2 write key used up; connection closed"));
+        }
+
         if data.is_empty() {
             // Don't send empty fragments.
             return Ok(0);
@@ -696,8 +858,15 @@ impl SessionCommon {
     }
 
     /// Send a raw TLS message, fragmenting it if needed.
+    ///
+    /// For QUIC, we queue handshake messages for the caller instead:
+    /// it does its own packet protection.
     pub fn send_msg(&mut self, m: Message, must_encrypt: bool) {
-        if !must_encrypt {
+        if self.protocol == Protocol::Quic {
+            if let MessagePayload::Handshake(ref hmp) = m.payload {
+                self.quic.send_hs(hmp.get_encoding());
+            }
+        } else if !must_encrypt {
             let mut to_send = VecDeque::new();
             self.message_fragmenter.fragment(m, &mut to_send);
             for mm in to_send {
@@ -723,6 +892,19 @@ impl SessionCommon {
         Ok(len)
     }
 
+    pub fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.received_plaintext.is_empty() && self.connection_at_eof() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                      "CloseNotify alert received"));
+        }
+
+        Ok(self.received_plaintext.chunk())
+    }
+
+    pub fn consume(&mut self, used: usize) {
+        self.received_plaintext.consume(used);
+    }
+
     pub fn start_encryption_tls12(&mut self, secrets: SessionSecrets) {
         let (dec, enc) = cipher::new_tls12(self.get_suite_assert(), &secrets);
         self.message_encrypter = enc;
@@ -732,6 +914,10 @@ impl SessionCommon {
 
     pub fn peer_now_encrypting(&mut self) {
         self.peer_encrypting = true;
+
+        if let Some(max_fragment_len) = self.max_incoming_fragment {
+            self.message_deframer.limit_fragment_len(max_fragment_len);
+        }
     }
 
     pub fn we_now_encrypting(&mut self) {
@@ -739,6 +925,11 @@ impl SessionCommon {
     }
 
     pub fn send_warning_alert(&mut self, desc: AlertDescription) {
+        // QUIC has its own ways of closing connections.
+        if self.protocol == Protocol::Quic {
+            return;
+        }
+
         warn!("Sending warning alert {:?}", desc);
         let m = Message::build_alert(AlertLevel::Warning, desc);
         let enc = self.we_encrypting;
@@ -746,6 +937,12 @@ impl SessionCommon {
     }
 
     pub fn send_fatal_alert(&mut self, desc: AlertDescription) {
+        if self.protocol == Protocol::Quic {
+            warn!("QUIC handshake failed with alert {:?}", desc);
+            self.quic.alert = Some(desc);
+            return;
+        }
+
         warn!("Sending fatal alert {:?}", desc);
         let m = Message::build_alert(AlertLevel::Fatal, desc);
         let enc = self.we_encrypting;
@@ -756,10 +953,34 @@ impl SessionCommon {
         self.send_warning_alert(AlertDescription::CloseNotify)
     }
 
+    /// Send a KeyUpdate, and move to a new write key.  If
+    /// `request_peer` is true, ask the peer to do the same.
+    pub fn refresh_traffic_keys(&mut self, request_peer: bool) -> Result<(), TLSError> {
+        if !self.traffic {
+            return Err(TLSError::HandshakeNotComplete);
+        }
+
+        if !self.is_tls13() || self.protocol == Protocol::Quic {
+            return Err(TLSError::General("key updates need TLS1.3 records".to_string()));
+        }
+
+        self.want_peer_key_update = request_peer;
+        self.do_write_key_update();
+        Ok(())
+    }
+
     pub fn process_key_update(&mut self,
                               kur: &KeyUpdateRequest,
                               read_kind: SecretKind)
                               -> Result<(), TLSError> {
+        // QUIC has its own key update mechanism.
+        if self.protocol == Protocol::Quic {
+            self.send_fatal_alert(AlertDescription::UnexpectedMessage);
+            let msg = "KeyUpdate received in QUIC connection".to_string();
+            warn!("{}", msg);
+            return Err(TLSError::PeerMisbehavedError(msg));
+        }
+
         // Mustn't be interleaved with other handshake messages.
         if !self.handshake_joiner.is_empty() {
             let msg = "KeyUpdate received at wrong time".to_string();
@@ -814,3 +1035,164 @@ impl SessionCommon {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::SessionCommon;
+    use cipher::{self, MessageEncrypter, MessageDecrypter};
+    use error::TLSError;
+    use key_schedule::KeySchedule;
+    use msgs::codec::{Codec, Reader};
+    use msgs::enums::ProtocolVersion;
+    use msgs::message::{BorrowMessage, Message};
+    use suites::TLS13_AES_128_GCM_SHA256;
+
+    struct Limited<T>(T, u64);
+
+    impl MessageEncrypter for Limited<Box<MessageEncrypter>> {
+        fn encrypt(&self, m: BorrowMessage, seq: u64) -> Result<Message, TLSError> {
+            self.0.encrypt(m, seq)
+        }
+
+        fn confidentiality_limit(&self) -> u64 {
+            self.1
+        }
+    }
+
+    impl MessageDecrypter for Limited<Box<MessageDecrypter>> {
+        fn decrypt(&self, m: Message, seq: u64) -> Result<Message, TLSError> {
+            self.0.decrypt(m, seq)
+        }
+
+        fn confidentiality_limit(&self) -> u64 {
+            self.1
+        }
+    }
+
+    fn session_in_traffic(client: bool) -> SessionCommon {
+        let suite = &TLS13_AES_128_GCM_SHA256;
+        let mut sess = SessionCommon::new(None, client);
+        sess.negotiated_version = Some(ProtocolVersion::TLSv1_3);
+        sess.set_suite(suite);
+
+        let mut key_schedule = KeySchedule::new(suite.get_hash());
+        key_schedule.input_empty();
+        key_schedule.current_client_traffic_secret = vec![ 1u8; 32 ];
+        key_schedule.current_server_traffic_secret = vec![ 2u8; 32 ];
+        sess.set_key_schedule(key_schedule);
+        sess.traffic = true;
+        sess
+    }
+
+    fn take_records(sess: &mut SessionCommon) -> Vec<Message> {
+        let mut buf = Vec::new();
+        while !sess.sendable_tls.is_empty() {
+            sess.write_tls(&mut buf).unwrap();
+        }
+
+        let mut rd = Reader::init(&buf);
+        let mut records = Vec::new();
+        while let Some(m) = Message::read(&mut rd) {
+            records.push(m);
+        }
+        records
+    }
+
+    #[test]
+    fn new_write_key_before_confidentiality_limit() {
+        let mut sess = session_in_traffic(true);
+        let secret = sess.get_key_schedule().current_client_traffic_secret.clone();
+        let enc = cipher::new_tls13_write(sess.get_suite_assert(), &secret);
+        sess.set_message_encrypter(Box::new(Limited(enc, 2)));
+
+        sess.send_some_plaintext(b"one").unwrap();
+        sess.send_some_plaintext(b"two").unwrap();
+        assert_eq!(sess.get_key_schedule().current_client_traffic_secret, secret);
+
+        // The third record needs a new key: a KeyUpdate goes first.
+        sess.send_some_plaintext(b"three").unwrap();
+        assert_ne!(sess.get_key_schedule().current_client_traffic_secret, secret);
+        assert_eq!(sess.write_seq, 1);
+        assert_eq!(take_records(&mut sess).len(), 4);
+    }
+
+    #[test]
+    fn peer_asked_for_new_key_at_confidentiality_limit() {
+        let mut client = session_in_traffic(true);
+        let mut server = session_in_traffic(false);
+        let secret = client.get_key_schedule().current_client_traffic_secret.clone();
+        let enc = cipher::new_tls13_write(client.get_suite_assert(), &secret);
+        let dec = cipher::new_tls13_read(server.get_suite_assert(), &secret);
+        client.set_message_encrypter(enc);
+        server.set_message_decrypter(Box::new(Limited(dec, 2)));
+        let server_secret = server.get_key_schedule().current_server_traffic_secret.clone();
+        let server_enc = cipher::new_tls13_write(server.get_suite_assert(), &server_secret);
+        server.set_message_encrypter(server_enc);
+
+        client.send_some_plaintext(b"one").unwrap();
+        client.send_some_plaintext(b"two").unwrap();
+        let mut records = take_records(&mut client);
+
+        server.decrypt_incoming(records.remove(0)).unwrap();
+        assert!(server.sendable_tls.is_empty());
+
+        // Having read as much as we want under this key, we request
+        // an update, which updates our key too.
+        server.decrypt_incoming(records.remove(0)).unwrap();
+        assert_eq!(take_records(&mut server).len(), 1);
+        assert_ne!(server.get_key_schedule().current_server_traffic_secret, server_secret);
+    }
+
+    #[test]
+    fn peer_ignoring_key_update_request_is_cut_off() {
+        let mut client = session_in_traffic(true);
+        let mut server = session_in_traffic(false);
+        let secret = client.get_key_schedule().current_client_traffic_secret.clone();
+        let enc = cipher::new_tls13_write(client.get_suite_assert(), &secret);
+        let dec = cipher::new_tls13_read(server.get_suite_assert(), &secret);
+        client.set_message_encrypter(enc);
+        server.set_message_decrypter(Box::new(Limited(dec, 16)));
+        let server_secret = server.get_key_schedule().current_server_traffic_secret.clone();
+        let server_enc = cipher::new_tls13_write(server.get_suite_assert(), &server_secret);
+        server.set_message_encrypter(server_enc);
+
+        for _ in 0..19 {
+            client.send_some_plaintext(b"data").unwrap();
+        }
+        let mut records = take_records(&mut client);
+
+        // The server asks for a new key after 16 records, and then
+        // reads 2 more while the client's KeyUpdate could be in flight.
+        for _ in 0..18 {
+            server.decrypt_incoming(records.remove(0)).unwrap();
+        }
+        take_records(&mut server);
+
+        match server.decrypt_incoming(records.remove(0)) {
+            Err(TLSError::PeerMisbehavedError(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(take_records(&mut server).len(), 1);
+    }
+
+    #[test]
+    fn tls12_closes_at_confidentiality_limit() {
+        let mut sess = session_in_traffic(true);
+        sess.negotiated_version = Some(ProtocolVersion::TLSv1_2);
+        let secret = sess.get_key_schedule().current_client_traffic_secret.clone();
+        let enc = cipher::new_tls13_write(sess.get_suite_assert(), &secret);
+        sess.set_message_encrypter(Box::new(Limited(enc, 2)));
+
+        assert_eq!(sess.send_some_plaintext(b"one").unwrap(), 3);
+        assert_eq!(sess.send_some_plaintext(b"two").unwrap(), 3);
+
+        // No key update is possible: the second record is followed
+        // by a close_notify, and nothing more may be written.
+        assert_eq!(sess.get_key_schedule().current_client_traffic_secret, secret);
+        assert_eq!(sess.send_some_plaintext(b"three").unwrap_err().kind(),
+                   io::ErrorKind::ConnectionAborted);
+        sess.send_close_notify();
+        assert_eq!(take_records(&mut sess).len(), 3);
+    }
+}
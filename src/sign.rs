@@ -7,7 +7,7 @@ use untrusted;
 
 use ring;
 use ring::signature;
-use ring::signature::RSAKeyPair;
+use ring::signature::{RSAKeyPair, ECDSAKeyPair, Ed25519KeyPair};
 use webpki;
 
 use std::sync::Arc;
@@ -135,6 +135,42 @@ impl CertifiedKey {
     }
 }
 
+/// Parse `der` as any supported key encoding/type, returning
+/// the first which works.
+pub fn any_supported_type(der: &key::PrivateKey) -> Result<Box<SigningKey>, ()> {
+    if let Ok(rsa) = RSASigningKey::new(der) {
+        return Ok(Box::new(rsa));
+    }
+
+    if let Ok(ecdsa) = any_ecdsa_type(der) {
+        return Ok(ecdsa);
+    }
+
+    any_eddsa_type(der)
+}
+
+/// Parse `der` as any ECDSA key type, returning the first which works.
+pub fn any_ecdsa_type(der: &key::PrivateKey) -> Result<Box<SigningKey>, ()> {
+    if let Ok(ecdsa_p256) = ECDSASigningKey::new(der, SignatureScheme::ECDSA_NISTP256_SHA256) {
+        return Ok(Box::new(ecdsa_p256));
+    }
+
+    if let Ok(ecdsa_p384) = ECDSASigningKey::new(der, SignatureScheme::ECDSA_NISTP384_SHA384) {
+        return Ok(Box::new(ecdsa_p384));
+    }
+
+    Err(())
+}
+
+/// Parse `der` as any EdDSA key type, returning the first which works.
+pub fn any_eddsa_type(der: &key::PrivateKey) -> Result<Box<SigningKey>, ()> {
+    if let Ok(ed25519) = Ed25519SigningKey::new(der) {
+        return Ok(Box::new(ed25519));
+    }
+
+    Err(())
+}
+
 /// A `SigningKey` for RSA-PKCS1 or RSA-PSS
 pub struct RSASigningKey {
     key: Arc<RSAKeyPair>,
@@ -214,3 +250,119 @@ impl Signer for RSASigner {
         self.scheme
     }
 }
+
+/// A `SigningKey` for ECDSA, over the NIST P-256 or P-384 curves.
+///
+/// Each key supports exactly one `SignatureScheme`, determined
+/// by its curve.
+pub struct ECDSASigningKey {
+    key: Arc<ECDSAKeyPair>,
+    scheme: SignatureScheme,
+}
+
+impl ECDSASigningKey {
+    /// Make a new `ECDSASigningKey` from a PKCS#8 DER encoding,
+    /// for use with `scheme`.  `scheme` must be `ECDSA_NISTP256_SHA256`
+    /// or `ECDSA_NISTP384_SHA384`, and the key must be on the
+    /// matching curve.
+    pub fn new(der: &key::PrivateKey, scheme: SignatureScheme) -> Result<ECDSASigningKey, ()> {
+        let alg = match scheme {
+            SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+            _ => return Err(()),
+        };
+
+        ECDSAKeyPair::from_pkcs8(alg, untrusted::Input::from(&der.0))
+            .map(|kp| {
+                ECDSASigningKey {
+                    key: Arc::new(kp),
+                    scheme: scheme,
+                }
+            })
+            .map_err(|_| ())
+    }
+}
+
+impl SigningKey for ECDSASigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
+        if offered.contains(&self.scheme) {
+            Some(Box::new(ECDSASigner {
+                key: self.key.clone(),
+                scheme: self.scheme,
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+struct ECDSASigner {
+    key: Arc<ECDSAKeyPair>,
+    scheme: SignatureScheme,
+}
+
+impl Signer for ECDSASigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        let rng = ring::rand::SystemRandom::new();
+        self.key.sign(untrusted::Input::from(message), &rng)
+            .map(|sig| sig.as_ref().to_vec())
+            .map_err(|_| TLSError::General("signing failed".to_string()))
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// A `SigningKey` for Ed25519.
+pub struct Ed25519SigningKey {
+    key: Arc<Ed25519KeyPair>,
+}
+
+impl Ed25519SigningKey {
+    /// Make a new `Ed25519SigningKey` from a PKCS#8 DER encoding.
+    /// Both v1 and v2 PKCS#8 encodings are accepted.
+    pub fn new(der: &key::PrivateKey) -> Result<Ed25519SigningKey, ()> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(untrusted::Input::from(&der.0))
+            .map(|kp| {
+                Ed25519SigningKey {
+                    key: Arc::new(kp),
+                }
+            })
+            .map_err(|_| ())
+    }
+}
+
+impl SigningKey for Ed25519SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
+        if offered.contains(&SignatureScheme::ED25519) {
+            Some(Box::new(Ed25519Signer {
+                key: self.key.clone(),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ED25519
+    }
+}
+
+struct Ed25519Signer {
+    key: Arc<Ed25519KeyPair>,
+}
+
+impl Signer for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        Ok(self.key.sign(message).as_ref().to_vec())
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        SignatureScheme::ED25519
+    }
+}
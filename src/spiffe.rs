@@ -0,0 +1,189 @@
+// SPIFFE IDs: workload identities of the form
+// spiffe://trust-domain/path, carried as the single URI subject
+// alternative name of an X509-SVID certificate.
+//
+// Parsing follows the SPIFFE ID specification strictly: IDs are
+// compared byte-for-byte, so we don't accept anything which has
+// more than one spelling.
+
+use untrusted;
+
+use error::TLSError;
+use key::Certificate;
+use x509;
+
+use std::fmt;
+use std::str;
+
+const SCHEME: &'static str = "spiffe://";
+
+/// The longest SPIFFE ID, in bytes, that we accept.
+const MAX_ID_LEN: usize = 2048;
+
+/// The longest trust domain name, in bytes, that we accept.
+const MAX_TRUST_DOMAIN_LEN: usize = 255;
+
+/// A SPIFFE ID, like `spiffe://example.org/ns/prod/sa/web`.
+///
+/// This is made of a trust domain (`example.org`) and a path
+/// (`/ns/prod/sa/web`) naming a workload within it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SPIFFEID {
+    trust_domain: String,
+    path: String,
+}
+
+impl SPIFFEID {
+    /// Parse `uri` as a SPIFFE ID.
+    ///
+    /// The trust domain may only have lowercase letters, digits,
+    /// dots, dashes and underscores.  The path is either empty, or
+    /// slash-separated segments of letters, digits, dots, dashes
+    /// and underscores; segments can't be empty, `.` or `..`.  Ports,
+    /// user information, queries and fragments aren't allowed.
+    pub fn from_uri(uri: &str) -> Result<SPIFFEID, TLSError> {
+        if uri.len() > MAX_ID_LEN {
+            return Err(invalid("too long"));
+        }
+
+        if !uri.starts_with(SCHEME) {
+            return Err(invalid("scheme is not spiffe"));
+        }
+
+        let rest = &uri[SCHEME.len()..];
+        let (trust_domain, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+
+        if trust_domain.is_empty() {
+            return Err(invalid("trust domain is empty"));
+        }
+
+        if trust_domain.len() > MAX_TRUST_DOMAIN_LEN {
+            return Err(invalid("trust domain is too long"));
+        }
+
+        if !trust_domain.bytes().all(is_trust_domain_char) {
+            return Err(invalid("trust domain has invalid characters"));
+        }
+
+        if !path.is_empty() {
+            for segment in path[1..].split('/') {
+                if segment.is_empty() {
+                    return Err(invalid("path has an empty segment"));
+                }
+
+                if segment == "." || segment == ".." {
+                    return Err(invalid("path has a relative segment"));
+                }
+
+                if !segment.bytes().all(is_path_char) {
+                    return Err(invalid("path has invalid characters"));
+                }
+            }
+        }
+
+        Ok(SPIFFEID {
+            trust_domain: trust_domain.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Find the SPIFFE ID of the X509-SVID `cert`, which must
+    /// have exactly one URI subject alternative name.
+    ///
+    /// This does not validate `cert`: use webpki for that.
+    pub fn from_cert(cert: &Certificate) -> Result<SPIFFEID, TLSError> {
+        let fields = x509::cert_fields(untrusted::Input::from(&cert.0))
+            .map_err(|_| TLSError::General("cannot parse certificate".to_string()))?;
+
+        let uris = fields.subject_alt_uris();
+        match uris.len() {
+            0 => return Err(invalid("certificate has no URI name")),
+            1 => {}
+            _ => return Err(invalid("certificate has more than one URI name")),
+        }
+
+        let uri = str::from_utf8(uris[0].as_slice_less_safe())
+            .map_err(|_| invalid("URI name is not ASCII"))?;
+        SPIFFEID::from_uri(uri)
+    }
+
+    /// The trust domain, like `example.org`.
+    pub fn trust_domain(&self) -> &str {
+        &self.trust_domain
+    }
+
+    /// The path, like `/ns/prod/sa/web`.  This is empty
+    /// for the ID of a trust domain itself.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for SPIFFEID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", SCHEME, self.trust_domain, self.path)
+    }
+}
+
+fn invalid(why: &str) -> TLSError {
+    TLSError::InvalidSPIFFEID(why.to_string())
+}
+
+fn is_trust_domain_char(c: u8) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || b".-_".contains(&c)
+}
+
+fn is_path_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b".-_".contains(&c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_valid_ids() {
+        let id = SPIFFEID::from_uri("spiffe://example.org/ns/prod/sa/web").unwrap();
+        assert_eq!(id.trust_domain(), "example.org");
+        assert_eq!(id.path(), "/ns/prod/sa/web");
+        assert_eq!(id.to_string(), "spiffe://example.org/ns/prod/sa/web");
+
+        let id = SPIFFEID::from_uri("spiffe://my_domain-1.test").unwrap();
+        assert_eq!(id.trust_domain(), "my_domain-1.test");
+        assert_eq!(id.path(), "");
+
+        assert!(SPIFFEID::from_uri("spiffe://example.org/Web.1_a-b").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        for uri in &["",
+                     "spiffe://",
+                     "spiffe:///path",
+                     "SPIFFE://example.org/x",
+                     "https://example.org/x",
+                     "spiffe:example.org/x",
+                     "spiffe://Example.org/x",
+                     "spiffe://example.org:8080/x",
+                     "spiffe://user@example.org/x",
+                     "spiffe://example.org/",
+                     "spiffe://example.org//x",
+                     "spiffe://example.org/x/",
+                     "spiffe://example.org/./x",
+                     "spiffe://example.org/x/..",
+                     "spiffe://example.org/x?y=z",
+                     "spiffe://example.org/x#y",
+                     "spiffe://example.org/x%20y"] {
+            assert!(SPIFFEID::from_uri(uri).is_err(), "accepted {:?}", uri);
+        }
+
+        let long_domain = format!("spiffe://{}/x", "a".repeat(256));
+        assert!(SPIFFEID::from_uri(&long_domain).is_err());
+
+        let long_path = format!("spiffe://example.org/{}", "a".repeat(2048));
+        assert!(SPIFFEID::from_uri(&long_path).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{Read, Write, Result};
+use std::io::{Read, Write, BufRead, Result};
 use session::Session;
 
 /// This type implements `io::Read` and `io::Write`, encapsulating
@@ -34,10 +34,10 @@ impl<'a, S, T> Stream<'a, S, T> where S: 'a + Session, T: 'a + Read + Write {
 
         Ok(())
     }
-}
 
-impl<'a, S, T> Read for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read + Write {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    /// Do whatever IO is needed to have some plaintext to read,
+    /// unless the transport has reached EOF.
+    fn prepare_read(&mut self) -> Result<()> {
         self.complete_prior_io()?;
 
         // We call complete_io() in a loop since a single call may read only
@@ -51,10 +51,28 @@ impl<'a, S, T> Read for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read + W
             self.sess.complete_io(self.sock)?.0 != 0
         { }
 
+        Ok(())
+    }
+}
+
+impl<'a, S, T> Read for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read + Write {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.prepare_read()?;
         self.sess.read(buf)
     }
 }
 
+impl<'a, S, T> BufRead for Stream<'a, S, T> where S: 'a + Session + BufRead, T: 'a + Read + Write {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.prepare_read()?;
+        self.sess.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.sess.consume(amt)
+    }
+}
+
 impl<'a, S, T> Write for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read + Write {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         self.complete_prior_io()?;
@@ -75,9 +93,81 @@ impl<'a, S, T> Write for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read +
     }
 }
 
+/// This type implements `io::Read`, `io::Write` and `io::BufRead`,
+/// encapsulating and owning a Session `S` and an underlying blocking
+/// transport `T`, such as a socket.
+///
+/// This is like `Stream`, but can be stored in structs, returned
+/// from functions and sent to other threads, because it doesn't
+/// borrow anything.  Any handshake IO happens as needed when it is
+/// first read or written.
+pub struct StreamOwned<S: Session + Sized, T: Read + Write + Sized> {
+    /// Our session
+    pub sess: S,
+
+    /// The underlying transport, like a socket
+    pub sock: T,
+}
+
+impl<S, T> StreamOwned<S, T> where S: Session, T: Read + Write {
+    /// Make a new StreamOwned taking the Session `sess` and socket-like
+    /// object `sock`.  This does not fail and does no IO.
+    pub fn new(sess: S, sock: T) -> StreamOwned<S, T> {
+        StreamOwned { sess, sock }
+    }
+
+    /// Get a reference to the underlying session.
+    pub fn get_session(&self) -> &S {
+        &self.sess
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_sock(&self) -> &T {
+        &self.sock
+    }
+
+    /// Take back the session and transport.
+    pub fn into_parts(self) -> (S, T) {
+        (self.sess, self.sock)
+    }
+
+    fn as_stream(&mut self) -> Stream<S, T> {
+        Stream::new(&mut self.sess, &mut self.sock)
+    }
+}
+
+impl<S, T> Read for StreamOwned<S, T> where S: Session, T: Read + Write {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.as_stream().read(buf)
+    }
+}
+
+impl<S, T> BufRead for StreamOwned<S, T> where S: Session + BufRead, T: Read + Write {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.as_stream().prepare_read()?;
+        self.sess.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.sess.consume(amt)
+    }
+}
+
+impl<S, T> Write for StreamOwned<S, T> where S: Session, T: Read + Write {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.as_stream().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.as_stream().flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Stream;
+    use super::{Stream, StreamOwned};
+    use client::ClientSession;
+    use server::ServerSession;
     use session::Session;
     use std::net::TcpStream;
 
@@ -90,4 +180,15 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn stream_owned_can_be_instantiated_with() {
+        fn _client(sess: ClientSession, sock: TcpStream) -> StreamOwned<ClientSession, TcpStream> {
+            StreamOwned::new(sess, sock)
+        }
+
+        fn _server(sess: ServerSession, sock: TcpStream) -> StreamOwned<ServerSession, TcpStream> {
+            StreamOwned::new(sess, sock)
+        }
+    }
 }
@@ -3,17 +3,26 @@ use msgs::enums::{NamedGroup, ProtocolVersion};
 use msgs::handshake::KeyExchangeAlgorithm;
 use msgs::handshake::DecomposedSignatureScheme;
 use msgs::handshake::{ClientECDHParams, ServerECDHParams};
+use msgs::handshake::{ClientDHParams, ServerDHParams};
 use msgs::codec::{Reader, Codec};
-use util;
 
-use ring;
-use untrusted;
+use ffdhe::{self, FfdheGroup};
+use crypto::{CryptoProvider, ActiveKeyExchange, Hash, Aead};
+use crypto::ring::{SHA256, SHA384, AES_128_GCM, AES_256_GCM, CHACHA20_POLY1305};
 
+use std::fmt;
+
+/// The bulk encryption algorithm of a cipher suite.
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BulkAlgorithm {
+    /// AES with 128-bit keys in Galois counter mode.
     AES_128_GCM,
+
+    /// AES with 256-bit keys in Galois counter mode.
     AES_256_GCM,
+
+    /// ChaCha20 for confidentiality with poly1305 for integrity.
     CHACHA20_POLY1305,
 }
 
@@ -24,48 +33,52 @@ pub struct KeyExchangeResult {
     pub premaster_secret: Vec<u8>,
 }
 
-/// An in-progress key exchange.  This has the algorithm,
+/// An in-progress key exchange.  This has the group,
 /// our private key, and our public key.
 pub struct KeyExchange {
     pub group: NamedGroup,
-    alg: &'static ring::agreement::Algorithm,
-    privkey: ring::agreement::EphemeralPrivateKey,
+    privkey: Box<ActiveKeyExchange>,
     pub pubkey: Vec<u8>,
 }
 
 impl KeyExchange {
-    pub fn named_group_to_ecdh_alg(group: NamedGroup)
-                                   -> Option<&'static ring::agreement::Algorithm> {
-        match group {
-            NamedGroup::X25519 => Some(&ring::agreement::X25519),
-            NamedGroup::secp256r1 => Some(&ring::agreement::ECDH_P256),
-            NamedGroup::secp384r1 => Some(&ring::agreement::ECDH_P384),
-            _ => None,
-        }
+    /// Is `group` one of the RFC7919 finite field groups?
+    fn is_ffdhe(group: NamedGroup) -> bool {
+        FfdheGroup::from_named_group(group).is_some()
     }
 
-    pub fn client_ecdhe(kx_params: &[u8]) -> Option<KeyExchangeResult> {
+    pub fn client_ecdhe(provider: &CryptoProvider, kx_params: &[u8]) -> Option<KeyExchangeResult> {
         let mut rd = Reader::init(kx_params);
         let ecdh_params = try_ret!(ServerECDHParams::read(&mut rd));
+        let group = ecdh_params.curve_params.named_group;
 
-        try_ret!(KeyExchange::start_ecdhe(ecdh_params.curve_params.named_group))
+        if KeyExchange::is_ffdhe(group) {
+            return None;
+        }
+
+        try_ret!(KeyExchange::start(provider, group))
             .complete(&ecdh_params.public.0)
     }
 
-    pub fn start_ecdhe(named_group: NamedGroup) -> Option<KeyExchange> {
-        let alg = try_ret!(KeyExchange::named_group_to_ecdh_alg(named_group));
-        let rng = ring::rand::SystemRandom::new();
-        let ours = ring::agreement::EphemeralPrivateKey::generate(alg, &rng).unwrap();
+    pub fn client_dhe(provider: &CryptoProvider, kx_params: &[u8]) -> Option<KeyExchangeResult> {
+        let mut rd = Reader::init(kx_params);
+        let dh_params = try_ret!(ServerDHParams::read(&mut rd));
+        let group = try_ret!(FfdheGroup::from_params(&dh_params.dh_p.0, &dh_params.dh_g.0));
+
+        try_ret!(KeyExchange::start(provider, group.named_group))
+            .complete_tls12_dhe(&dh_params.dh_ys.0)
+    }
 
-        let mut pubkey = Vec::new();
-        pubkey.resize(ours.public_key_len(), 0u8);
-        ours.compute_public_key(pubkey.as_mut_slice()).unwrap();
+    /// Start a key exchange in `named_group`, which may be an
+    /// elliptic curve or finite field group, using `provider`.
+    pub fn start(provider: &CryptoProvider, named_group: NamedGroup) -> Option<KeyExchange> {
+        let group = try_ret!(provider.find_kx_group(named_group));
+        let ours = try_ret!(group.start(provider.secure_random()).ok());
 
         Some(KeyExchange {
             group: named_group,
-            alg: alg,
+            pubkey: ours.pub_key().to_vec(),
             privkey: ours,
-            pubkey: pubkey,
         })
     }
 
@@ -73,48 +86,63 @@ impl KeyExchange {
         self.decode_client_params(kx_params).is_some()
     }
 
-    fn decode_client_params(&self, kx_params: &[u8]) -> Option<ClientECDHParams> {
+    fn decode_client_params(&self, kx_params: &[u8]) -> Option<Vec<u8>> {
         let mut rd = Reader::init(kx_params);
-        let ecdh_params = ClientECDHParams::read(&mut rd).unwrap();
+        let public = if KeyExchange::is_ffdhe(self.group) {
+            try_ret!(ClientDHParams::read(&mut rd)).public.0
+        } else {
+            try_ret!(ClientECDHParams::read(&mut rd)).public.0
+        };
+
         if rd.any_left() {
             None
         } else {
-            Some(ecdh_params)
+            Some(public)
         }
     }
 
     pub fn server_complete(self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
-        self.decode_client_params(kx_params)
-            .and_then(|ecdh| self.complete(&ecdh.public.0))
+        let public = try_ret!(self.decode_client_params(kx_params));
+
+        if KeyExchange::is_ffdhe(self.group) {
+            self.complete_tls12_dhe(&public)
+        } else {
+            self.complete(&public)
+        }
     }
 
-    pub fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
-        let secret = ring::agreement::agree_ephemeral(self.privkey,
-                                                      self.alg,
-                                                      untrusted::Input::from(peer),
-                                                      (),
-                                                      |v| {
-                                                          let mut r = Vec::new();
-                                                          r.extend_from_slice(v);
-                                                          Ok(r)
-                                                      });
-
-        if secret.is_err() {
+    /// Complete a TLS1.2 DHE key exchange.  Here public values
+    /// needn't be padded, and the premaster secret is not.
+    fn complete_tls12_dhe(self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let len = try_ret!(FfdheGroup::from_named_group(self.group)).len();
+
+        let peer = ffdhe::strip_leading_zeroes(peer);
+        if peer.len() > len {
             return None;
         }
 
+        let mut padded_peer = vec![0u8; len - peer.len()];
+        padded_peer.extend_from_slice(peer);
+
+        let mut kxr = try_ret!(self.complete(&padded_peer));
+        kxr.premaster_secret = ffdhe::strip_leading_zeroes(&kxr.premaster_secret).to_vec();
+        Some(kxr)
+    }
+
+    pub fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let secret = try_ret!(self.privkey.complete(peer));
+
         Some(KeyExchangeResult {
             pubkey: self.pubkey,
-            premaster_secret: secret.unwrap(),
+            premaster_secret: secret,
         })
     }
 }
 
 /// A cipher suite supported by rustls.
 ///
-/// All possible instances of this class are provided by the library in
-/// the `ALL_CIPHERSUITES` array.
-#[derive(Debug)]
+/// The instances using *ring* are provided by the library in the
+/// `ALL_CIPHERSUITES` array.  Other `CryptoProvider`s make their own.
 pub struct SupportedCipherSuite {
     /// The TLS enumeration naming this cipher suite.
     pub suite: CipherSuite,
@@ -145,6 +173,24 @@ pub struct SupportedCipherSuite {
     /// in a deterministic and safe way.  GCM needs this,
     /// chacha20poly1305 works this way by design.
     pub explicit_nonce_len: usize,
+
+    /// The implementation of `hash`.
+    pub hash_alg: &'static Hash,
+
+    /// The implementation of `bulk`.
+    pub aead_alg: &'static Aead,
+}
+
+impl fmt::Debug for SupportedCipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SupportedCipherSuite")
+            .field("suite", &self.suite)
+            .field("kx", &self.kx)
+            .field("bulk", &self.bulk)
+            .field("hash", &self.hash)
+            .field("sign", &self.sign)
+            .finish()
+    }
 }
 
 impl PartialEq for SupportedCipherSuite {
@@ -155,41 +201,53 @@ impl PartialEq for SupportedCipherSuite {
 
 impl SupportedCipherSuite {
     /// Which hash function to use with this suite.
-    pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
-        match self.hash {
-            HashAlgorithm::SHA1 => &ring::digest::SHA1,
-            HashAlgorithm::SHA256 => &ring::digest::SHA256,
-            HashAlgorithm::SHA384 => &ring::digest::SHA384,
-            HashAlgorithm::SHA512 => &ring::digest::SHA512,
-            _ => unreachable!(),
-        }
+    pub fn get_hash(&self) -> &'static Hash {
+        self.hash_alg
     }
 
     /// We have parameters and a verified public key in `kx_params`.
-    /// Generate an ephemeral key, generate the shared secret, and
-    /// return it and the public half in a `KeyExchangeResult`.
-    pub fn do_client_kx(&self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
+    /// Generate an ephemeral key using `provider`, generate the
+    /// shared secret, and return it and the public half in a
+    /// `KeyExchangeResult`.
+    pub fn do_client_kx(&self,
+                        provider: &CryptoProvider,
+                        kx_params: &[u8]) -> Option<KeyExchangeResult> {
         match self.kx {
-            KeyExchangeAlgorithm::ECDHE => KeyExchange::client_ecdhe(kx_params),
+            KeyExchangeAlgorithm::ECDHE => KeyExchange::client_ecdhe(provider, kx_params),
+            KeyExchangeAlgorithm::DHE => KeyExchange::client_dhe(provider, kx_params),
             _ => None,
         }
     }
 
     /// Start the KX process with the given group.  This generates
     /// the server's share, but we don't yet have the client's share.
-    pub fn start_server_kx(&self, named_group: NamedGroup) -> Option<KeyExchange> {
+    pub fn start_server_kx(&self,
+                           provider: &CryptoProvider,
+                           named_group: NamedGroup) -> Option<KeyExchange> {
+        if self.usable_for_group(named_group) {
+            KeyExchange::start(provider, named_group)
+        } else {
+            None
+        }
+    }
+
+    /// Return true if this suite's key exchange can use `group`.
+    /// TLS1.3 suites can use any group.
+    pub fn usable_for_group(&self, group: NamedGroup) -> bool {
         match self.kx {
-            KeyExchangeAlgorithm::ECDHE => KeyExchange::start_ecdhe(named_group),
-            _ => None,
+            KeyExchangeAlgorithm::ECDHE => !KeyExchange::is_ffdhe(group),
+            KeyExchangeAlgorithm::DHE => KeyExchange::is_ffdhe(group),
+            _ => true,
         }
     }
 
-    /// Resolve a single supported `SignatureScheme` from the
-    /// offered `SupportedSignatureSchemes`.  If we return None,
-    /// the handshake terminates.
-    pub fn resolve_sig_scheme(&self,
-                              offered: &[SignatureScheme])
-                              -> Option<SignatureScheme> {
+    /// Resolve the supported `SignatureScheme`s from the
+    /// offered `SupportedSignatureSchemes`, in our order of
+    /// preference.  If we return an empty list, the handshake
+    /// terminates.
+    pub fn resolve_sig_schemes(&self,
+                               offered: &[SignatureScheme])
+                               -> Vec<SignatureScheme> {
         let mut our_preference = vec![
             // Prefer the designated hash algorithm of this suite, for
             // security level consistency.
@@ -209,16 +267,19 @@ impl SupportedCipherSuite {
             our_preference.push(SignatureScheme::RSA_PSS_SHA256);
         }
 
-        util::first_in_both(our_preference.as_slice(), offered)
+        // ECDSA suites also cover EdDSA keys (RFC8422).
+        if self.sign == SignatureAlgorithm::ECDSA {
+            our_preference.push(SignatureScheme::ED25519);
+        }
+
+        our_preference.retain(|scheme| offered.contains(scheme));
+        our_preference.dedup();
+        our_preference
     }
 
     /// Which AEAD algorithm to use for this suite.
-    pub fn get_aead_alg(&self) -> &'static ring::aead::Algorithm {
-        match self.bulk {
-            BulkAlgorithm::AES_128_GCM => &ring::aead::AES_128_GCM,
-            BulkAlgorithm::AES_256_GCM => &ring::aead::AES_256_GCM,
-            BulkAlgorithm::CHACHA20_POLY1305 => &ring::aead::CHACHA20_POLY1305,
-        }
+    pub fn get_aead_alg(&self) -> &'static Aead {
+        self.aead_alg
     }
 
     /// Length of key block that needs to be output by the key
@@ -236,6 +297,18 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Return true if this suite can authenticate the key exchange
+    /// using a key of type `sigalg`.  In TLS1.2, the ECDSA suites
+    /// are also used with EdDSA keys (RFC8422).
+    pub fn usable_for_sigalg(&self, sigalg: SignatureAlgorithm) -> bool {
+        match self.sign {
+            SignatureAlgorithm::Anonymous => true,
+            SignatureAlgorithm::ECDSA => sigalg == SignatureAlgorithm::ECDSA ||
+                sigalg == SignatureAlgorithm::ED25519,
+            _ => self.sign == sigalg,
+        }
+    }
+
     /// Can a session using suite self resume using suite new_suite?
     pub fn can_resume_to(&self, new_suite: &SupportedCipherSuite) -> bool {
         if self.usable_for_version(ProtocolVersion::TLSv1_3) &&
@@ -253,8 +326,19 @@ impl SupportedCipherSuite {
             false
         }
     }
+
+    /// Can an external PSK be used with this suite?
+    pub fn usable_for_external_psk(&self) -> bool {
+        self.usable_for_version(ProtocolVersion::TLSv1_3) &&
+            self.hash == EXTERNAL_PSK_HASH
+    }
 }
 
+/// The hash function used with external PSKs.  RFC8446 says this
+/// is SHA256 unless agreed otherwise, and we have no way of agreeing
+/// otherwise.
+pub const EXTERNAL_PSK_HASH: HashAlgorithm = HashAlgorithm::SHA256;
+
 pub static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
     SupportedCipherSuite {
         suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
@@ -265,6 +349,8 @@ pub static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
         enc_key_len: 32,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
+        hash_alg: &SHA256,
+        aead_alg: &CHACHA20_POLY1305,
     };
 
 pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
@@ -277,6 +363,8 @@ pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
         enc_key_len: 32,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
+        hash_alg: &SHA256,
+        aead_alg: &CHACHA20_POLY1305,
     };
 
 pub static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -288,6 +376,8 @@ pub static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = Support
     enc_key_len: 16,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
+    hash_alg: &SHA256,
+    aead_alg: &AES_128_GCM,
 };
 
 pub static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -299,6 +389,8 @@ pub static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = Support
     enc_key_len: 32,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
+    hash_alg: &SHA384,
+    aead_alg: &AES_256_GCM,
 };
 
 pub static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -310,6 +402,8 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = Suppo
     enc_key_len: 16,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
+    hash_alg: &SHA256,
+    aead_alg: &AES_128_GCM,
 };
 
 pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -321,6 +415,48 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = Suppo
     enc_key_len: 32,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
+    hash_alg: &SHA384,
+    aead_alg: &AES_256_GCM,
+};
+
+pub static TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite {
+        suite: CipherSuite::TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        kx: KeyExchangeAlgorithm::DHE,
+        sign: SignatureAlgorithm::RSA,
+        bulk: BulkAlgorithm::CHACHA20_POLY1305,
+        hash: HashAlgorithm::SHA256,
+        enc_key_len: 32,
+        fixed_iv_len: 12,
+        explicit_nonce_len: 0,
+        hash_alg: &SHA256,
+        aead_alg: &CHACHA20_POLY1305,
+    };
+
+pub static TLS_DHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+    kx: KeyExchangeAlgorithm::DHE,
+    sign: SignatureAlgorithm::RSA,
+    bulk: BulkAlgorithm::AES_128_GCM,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 4,
+    explicit_nonce_len: 8,
+    hash_alg: &SHA256,
+    aead_alg: &AES_128_GCM,
+};
+
+pub static TLS_DHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+    kx: KeyExchangeAlgorithm::DHE,
+    sign: SignatureAlgorithm::RSA,
+    bulk: BulkAlgorithm::AES_256_GCM,
+    hash: HashAlgorithm::SHA384,
+    enc_key_len: 32,
+    fixed_iv_len: 4,
+    explicit_nonce_len: 8,
+    hash_alg: &SHA384,
+    aead_alg: &AES_256_GCM,
 };
 
 pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -332,6 +468,8 @@ pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCiphe
     enc_key_len: 32,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
+    hash_alg: &SHA256,
+    aead_alg: &CHACHA20_POLY1305,
 };
 
 pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -343,6 +481,8 @@ pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite
     enc_key_len: 32,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
+    hash_alg: &SHA384,
+    aead_alg: &AES_256_GCM,
 };
 
 pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -354,10 +494,15 @@ pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite
     enc_key_len: 16,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
+    hash_alg: &SHA256,
+    aead_alg: &AES_128_GCM,
 };
 
 /// A list of all the cipher suites supported by rustls.
-pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 9] =
+///
+/// The DHE suites are only used if finite field groups are
+/// enabled in the config's `kx_groups`.
+pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 12] =
     [// TLS1.3 suites
      &TLS13_CHACHA20_POLY1305_SHA256,
      &TLS13_AES_256_GCM_SHA384,
@@ -369,7 +514,10 @@ pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 9] =
      &TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
      &TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
      &TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-     &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256];
+     &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+     &TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+     &TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+     &TLS_DHE_RSA_WITH_AES_128_GCM_SHA256];
 
 // These both O(N^2)!
 pub fn choose_ciphersuite_preferring_client(client_suites: &[CipherSuite],
@@ -400,7 +548,21 @@ pub fn reduce_given_sigalg(all: &[&'static SupportedCipherSuite],
                            sigalg: &SignatureAlgorithm)
                            -> Vec<&'static SupportedCipherSuite> {
     all.iter()
-        .filter(|&&suite| suite.sign == SignatureAlgorithm::Anonymous || &suite.sign == sigalg)
+        .filter(|&&suite| suite.usable_for_sigalg(*sigalg))
+        .cloned()
+        .collect()
+}
+
+/// Return a list of the ciphersuites in `all` with the suites
+/// which can't use any group in both `ours` and `theirs` removed.
+pub fn reduce_given_groups(all: &[&'static SupportedCipherSuite],
+                           ours: &[NamedGroup],
+                           theirs: &[NamedGroup])
+                           -> Vec<&'static SupportedCipherSuite> {
+    all.iter()
+        .filter(|&&suite| {
+            ours.iter().any(|group| theirs.contains(group) && suite.usable_for_group(*group))
+        })
         .cloned()
         .collect()
 }
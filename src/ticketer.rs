@@ -1,11 +1,12 @@
 
 use server::ProducesTickets;
 use rand;
+use crypto::{Aead, AeadKey, CryptoProvider};
+use crypto::ring::RingProvider;
 
 use std::mem;
 use std::sync::{Mutex, Arc};
 use std::time;
-use ring::aead;
 
 /// The timebase for expiring and rolling tickets and ticketing
 /// keys.  This is UNIX wall time in seconds.
@@ -16,39 +17,45 @@ pub fn timebase() -> u64 {
         .as_secs()
 }
 
-/// This is a `ProducesTickets` implementation which uses
-/// any *ring* `aead::Algorithm` to encrypt and authentication
-/// the ticket payload.  It does not enforce any lifetime
-/// constraint.
+/// `Aead` nonces are always 96 bits.
+const NONCE_LEN: usize = 12;
+
+/// This is a `ProducesTickets` implementation which uses any
+/// `Aead` to encrypt and authentication the ticket payload.  It
+/// does not enforce any lifetime constraint.
 pub struct AEADTicketer {
-    alg: &'static aead::Algorithm,
-    enc: aead::SealingKey,
-    dec: aead::OpeningKey,
+    key: Box<AeadKey>,
+    tag_len: usize,
+    provider: Arc<CryptoProvider>,
     lifetime: u32,
 }
 
 impl AEADTicketer {
     /// Make a new `AEADTicketer` using the given `alg`, `key` material
-    /// and advertised `lifetime_seconds`.  Note that `lifetime_seconds`
-    /// does not affect the lifetime of the key.  `key` must be the
-    /// right length for `alg` or this will panic.
-    pub fn new_custom(alg: &'static aead::Algorithm,
+    /// and advertised `lifetime_seconds`.  Nonces come from `provider`'s
+    /// `secure_random`.  Note that `lifetime_seconds` does not affect
+    /// the lifetime of the key.  `key` must be the right length for
+    /// `alg` or this will panic.
+    pub fn new_custom(provider: Arc<CryptoProvider>,
+                      alg: &'static Aead,
                       key: &[u8],
                       lifetime_seconds: u32)
                       -> AEADTicketer {
         AEADTicketer {
-            alg: alg,
-            enc: aead::SealingKey::new(alg, key).unwrap(),
-            dec: aead::OpeningKey::new(alg, key).unwrap(),
+            key: alg.new_key(key),
+            tag_len: alg.tag_len(),
+            provider: provider,
             lifetime: lifetime_seconds,
         }
     }
 
-    /// Make a ticketer with recommended configuration and a random key.
-    pub fn new() -> AEADTicketer {
-        let mut key = [0u8; 32];
-        rand::fill_random(&mut key);
-        AEADTicketer::new_custom(&aead::CHACHA20_POLY1305, &key, 60 * 60 * 12)
+    /// Make a ticketer with recommended configuration and a random key,
+    /// getting all its cryptography from `provider`.  This encrypts with the AEAD of `provider`'s most preferred
+    /// cipher suite, so panics if `provider` has none.
+    pub fn with_provider(provider: Arc<CryptoProvider>) -> AEADTicketer {
+        let suite = provider.cipher_suites()[0];
+        let key = rand::random_vec(provider.secure_random(), suite.enc_key_len);
+        AEADTicketer::new_custom(provider, suite.aead_alg, &key, 60 * 60 * 12)
     }
 }
 
@@ -63,37 +70,30 @@ impl ProducesTickets for AEADTicketer {
     /// Encrypt `message` and return the ciphertext.
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Random nonce, because a counter is a privacy leak.
-        let mut nonce = [0u8; 12];
-        rand::fill_random(&mut nonce);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::fill_random(self.provider.secure_random(), &mut nonce);
 
         let mut out = Vec::new();
         out.extend_from_slice(&nonce);
         out.extend_from_slice(message);
-        out.resize(nonce.len() + message.len() + self.alg.tag_len(), 0u8);
+        out.resize(nonce.len() + message.len() + self.tag_len, 0u8);
 
-        let rc = aead::seal_in_place(&self.enc,
-                                     &nonce,
-                                     &[],
-                                     &mut out[nonce.len()..],
-                                     self.alg.tag_len());
+        let rc = self.key.seal_in_place(&nonce, &[], &mut out[nonce.len()..]);
         if rc.is_err() { None } else { Some(out) }
     }
 
     /// Decrypt `ciphertext` and recover the original message.
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        let nonce_len = self.alg.nonce_len();
-        let tag_len = self.alg.tag_len();
-
-        if ciphertext.len() < nonce_len + tag_len {
+        if ciphertext.len() < NONCE_LEN + self.tag_len {
             return None;
         }
 
-        let nonce = &ciphertext[0..nonce_len];
+        let nonce = &ciphertext[0..NONCE_LEN];
         let mut out = Vec::new();
-        out.extend_from_slice(&ciphertext[nonce_len..]);
+        out.extend_from_slice(&ciphertext[NONCE_LEN..]);
 
-        let plain_len = match aead::open_in_place(&self.dec, nonce, &[], 0, &mut out) {
-            Ok(plaintext) => plaintext.len(),
+        let plain_len = match self.key.open_in_place(nonce, &[], &mut out) {
+            Ok(len) => len,
             Err(..) => { return None; }
         };
 
@@ -112,7 +112,7 @@ struct TicketSwitcherState {
 /// 'previous' ticketer.  It creates a new ticketer every so
 /// often, demoting the current ticketer.
 pub struct TicketSwitcher {
-    generator: fn() -> Box<ProducesTickets>,
+    generator: Box<Fn() -> Box<ProducesTickets> + Send + Sync>,
     lifetime: u32,
     state: Mutex<TicketSwitcherState>,
 }
@@ -122,17 +122,17 @@ impl TicketSwitcher {
     /// is used to generate new tickets.  Tickets are accepted for no
     /// longer than twice this duration.  `generator` produces a new
     /// `ProducesTickets` implementation.
-    pub fn new(lifetime: u32,
-               generator: fn() -> Box<ProducesTickets>)
-               -> TicketSwitcher {
+    pub fn new<F>(lifetime: u32, generator: F) -> TicketSwitcher
+        where F: Fn() -> Box<ProducesTickets> + Send + Sync + 'static
+    {
         TicketSwitcher {
-            generator: generator,
             lifetime: lifetime,
             state: Mutex::new(TicketSwitcherState {
                 current: generator(),
                 previous: None,
                 next_switch_time: timebase() + lifetime as u64,
             }),
+            generator: Box::new(generator),
         }
     }
 
@@ -188,16 +188,20 @@ impl ProducesTickets for TicketSwitcher {
 /// A concrete, safe ticket creation mechanism.
 pub struct Ticketer {}
 
-fn generate_inner() -> Box<ProducesTickets> {
-    Box::new(AEADTicketer::new())
-}
-
 impl Ticketer {
     /// Make the recommended Ticketer.  This produces tickets
     /// with a 12 hour life and randomly generated keys.
     ///
     /// The encryption mechanism used in Chacha20Poly1305.
     pub fn new() -> Arc<ProducesTickets> {
-        Arc::new(TicketSwitcher::new(6 * 60 * 60, generate_inner))
+        Ticketer::with_provider(Arc::new(RingProvider))
+    }
+
+    /// Like `new`, but getting keys, nonces and encryption from
+    /// `provider`, as `AEADTicketer::with_provider` does.
+    pub fn with_provider(provider: Arc<CryptoProvider>) -> Arc<ProducesTickets> {
+        Arc::new(TicketSwitcher::new(6 * 60 * 60, move || -> Box<ProducesTickets> {
+            Box::new(AEADTicketer::with_provider(provider.clone()))
+        }))
     }
 }
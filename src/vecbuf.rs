@@ -79,6 +79,26 @@ impl ChunkVecBuffer {
         self.chunks.pop_front().unwrap()
     }
 
+    /// The first chunk of data in this object, or an empty
+    /// slice if there isn't any.
+    pub fn chunk(&self) -> &[u8] {
+        self.chunks.front().map_or(&[], |ch| ch.as_slice())
+    }
+
+    /// Discard `used` bytes from the front of the first chunk,
+    /// as returned by `chunk`.
+    pub fn consume(&mut self, used: usize) {
+        if used == 0 || self.is_empty() {
+            return;
+        }
+
+        if used >= self.chunks[0].len() {
+            self.take_one();
+        } else {
+            self.chunks[0] = self.chunks[0].split_off(used);
+        }
+    }
+
     /// Read data out of this object, writing it into `buf`
     /// and returning how many bytes were written there.
     pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -86,13 +106,7 @@ impl ChunkVecBuffer {
 
         while offs < buf.len() && !self.is_empty() {
             let used = self.chunks[0].as_slice().read(&mut buf[offs..])?;
-
-            if used == self.chunks[0].len() {
-                self.take_one();
-            } else {
-                self.chunks[0] = self.chunks[0].split_off(used);
-            }
-
+            self.consume(used);
             offs += used;
         }
 
@@ -137,4 +151,23 @@ mod test {
         assert_eq!(buf.to_vec(),
                    b"helloworldhe".to_vec());
     }
+
+    #[test]
+    fn chunk_and_consume()
+    {
+        let mut cvb = ChunkVecBuffer::new();
+        assert_eq!(cvb.chunk(), b"");
+        cvb.append(b"hello".to_vec());
+        cvb.append(b"world".to_vec());
+
+        assert_eq!(cvb.chunk(), b"hello");
+        cvb.consume(2);
+        assert_eq!(cvb.chunk(), b"llo");
+        cvb.consume(3);
+        assert_eq!(cvb.chunk(), b"world");
+        cvb.consume(5);
+        assert!(cvb.is_empty());
+        cvb.consume(1);
+        assert_eq!(cvb.chunk(), b"");
+    }
 }
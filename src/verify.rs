@@ -3,19 +3,27 @@ use untrusted;
 use sct;
 use std;
 use std::sync::Arc;
+use std::net::IpAddr;
 
 use key::Certificate;
 use msgs::handshake::DigitallySignedStruct;
 use msgs::handshake::SCTList;
-use msgs::enums::SignatureScheme;
 use error::TLSError;
 use anchors::{DistinguishedNames, RootCertStore};
+use chain::Usage;
+use crl::{CRLChecker, CRLStore, CRLPolicy};
+use ocsp;
+use x509;
+use client::ServerName;
+use pinning::SPKIPinSet;
+use spiffe::SPIFFEID;
+use crypto::SignatureVerifier;
 
 type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 
 /// Which signature verification mechanisms we support.  No particular
 /// order.
-static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
+pub static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
                                                    &webpki::ECDSA_P256_SHA384,
                                                    &webpki::ECDSA_P384_SHA256,
                                                    &webpki::ECDSA_P384_SHA384,
@@ -50,22 +58,30 @@ impl ServerCertVerified {
     pub fn assertion() -> Self { Self { 0: () } }
 }
 
-/// Zero-sized marker type representing verification of a client cert chain.
-pub struct ClientCertVerified(());
+/// Marker type representing verification of a client cert chain,
+/// and the client's SPIFFE ID if the verifier established one.
+pub struct ClientCertVerified(Option<SPIFFEID>);
 impl ClientCertVerified {
     /// Make a `ClientCertVerified`
-    pub fn assertion() -> Self { Self { 0: () } }
+    pub fn assertion() -> Self { Self { 0: None } }
+
+    /// Make a `ClientCertVerified` for a client identified by `id`.
+    /// This is then available from `ServerSession::get_peer_spiffe_id`.
+    pub fn with_spiffe_id(id: SPIFFEID) -> Self { Self { 0: Some(id) } }
+
+    /// The client's SPIFFE ID, if any.
+    pub fn spiffe_id(&self) -> Option<&SPIFFEID> { self.0.as_ref() }
 }
 
 /// Something that can verify a server certificate chain
 pub trait ServerCertVerifier : Send + Sync {
     /// Verify a the certificate chain `presented_certs` against the roots
-    /// configured in `roots`.  Make sure that `dns_name` is quoted by
+    /// configured in `roots`.  Make sure that `server_name` is quoted by
     /// the top certificate in the chain.
     fn verify_server_cert(&self,
                           roots: &RootCertStore,
                           presented_certs: &[Certificate],
-                          dns_name: webpki::DNSNameRef,
+                          server_name: &ServerName,
                           ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError>;
 }
 
@@ -87,17 +103,24 @@ pub trait ClientCertVerifier : Send + Sync {
     /// Does no further checking of the certificate.
     fn verify_client_cert(&self,
                           presented_certs: &[Certificate]) -> Result<ClientCertVerified, TLSError>;
+
+    /// Returns `true` if a session whose client was verified with SPIFFE ID
+    /// `id`, or without one if `id` is `None`, may be resumed.  Returning
+    /// `false` makes the server do a full handshake instead.  Defaults to
+    /// `true`.
+    fn allows_resumed_spiffe_id(&self, _id: Option<&SPIFFEID>) -> bool { true }
 }
 
 pub struct WebPKIVerifier {
     pub time: fn() -> Result<webpki::Time, TLSError>,
+    pub crl_checker: Option<CRLChecker>,
 }
 
 impl ServerCertVerifier for WebPKIVerifier {
     fn verify_server_cert(&self,
                           roots: &RootCertStore,
                           presented_certs: &[Certificate],
-                          dns_name: webpki::DNSNameRef,
+                          server_name: &ServerName,
                           ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
         let (cert, chain, trustroots) = prepare(roots, presented_certs)?;
         let now = (self.time)()?;
@@ -107,12 +130,16 @@ impl ServerCertVerifier for WebPKIVerifier {
             .map(|_| cert)?;
 
         if !ocsp_response.is_empty() {
-            debug!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+            ocsp::check_stapled(ocsp_response, roots, presented_certs, now)?;
         }
 
-        cert.verify_is_valid_for_dns_name(dns_name)
-            .map_err(TLSError::WebPKIError)
-            .map(|_| ServerCertVerified::assertion())
+        verify_server_name(&cert, &presented_certs[0], server_name)?;
+
+        if let Some(ref checker) = self.crl_checker {
+            checker.check(roots, presented_certs, Usage::TLSServer)?;
+        }
+
+        Ok(ServerCertVerified::assertion())
     }
 }
 
@@ -120,10 +147,74 @@ impl WebPKIVerifier {
     pub fn new() -> WebPKIVerifier {
         WebPKIVerifier {
             time: try_now,
+            crl_checker: None,
+        }
+    }
+
+    pub fn with_crls(crls: Arc<CRLStore>, policy: CRLPolicy) -> WebPKIVerifier {
+        WebPKIVerifier {
+            time: try_now,
+            crl_checker: Some(CRLChecker::new(crls, policy)),
         }
     }
 }
 
+/// A `ServerCertVerifier` which does everything `WebPKIVerifier`
+/// does, and then requires some certificate in the validated chain
+/// to have a key in `pins`.
+pub struct SPKIPinningVerifier {
+    inner: WebPKIVerifier,
+    pins: SPKIPinSet,
+}
+
+impl SPKIPinningVerifier {
+    pub fn new(inner: WebPKIVerifier, pins: SPKIPinSet) -> SPKIPinningVerifier {
+        SPKIPinningVerifier {
+            inner: inner,
+            pins: pins,
+        }
+    }
+}
+
+impl ServerCertVerifier for SPKIPinningVerifier {
+    fn verify_server_cert(&self,
+                          roots: &RootCertStore,
+                          presented_certs: &[Certificate],
+                          server_name: &ServerName,
+                          ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots,
+                                                     presented_certs,
+                                                     server_name,
+                                                     ocsp_response)?;
+        self.pins.check(roots, presented_certs, (self.inner.time)()?)?;
+        Ok(verified)
+    }
+}
+
+/// Check the end-entity certificate `cert`, whose DER encoding is
+/// `cert_der`, is valid for `server_name`.  webpki only knows about
+/// DNS names, so we check IP addresses ourselves.
+fn verify_server_name(cert: &webpki::EndEntityCert,
+                      cert_der: &Certificate,
+                      server_name: &ServerName) -> Result<(), TLSError> {
+    let ip = match *server_name {
+        ServerName::DNSName(ref dns_name) => {
+            return cert.verify_is_valid_for_dns_name(dns_name.as_ref())
+                .map_err(TLSError::WebPKIError);
+        }
+        ServerName::IPAddress(IpAddr::V4(ref ip)) => ip.octets().to_vec(),
+        ServerName::IPAddress(IpAddr::V6(ref ip)) => ip.octets().to_vec(),
+    };
+
+    let fields = x509::cert_fields(untrusted::Input::from(&cert_der.0))
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?;
+    if fields.has_subject_alt_ip_address(&ip) {
+        Ok(())
+    } else {
+        Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName))
+    }
+}
+
 fn prepare<'a, 'b>(roots: &'b RootCertStore, presented_certs: &'a [Certificate])
                    -> Result<(webpki::EndEntityCert<'a>,
                               Vec<untrusted::Input<'a>>,
@@ -159,6 +250,7 @@ fn try_now() -> Result<webpki::Time, TLSError> {
 /// certificate, without any name checking.
 pub struct AllowAnyAuthenticatedClient {
     roots: RootCertStore,
+    crl_checker: Option<CRLChecker>,
 }
 
 impl AllowAnyAuthenticatedClient {
@@ -166,7 +258,23 @@ impl AllowAnyAuthenticatedClient {
     ///
     /// `roots` is the list of trust anchors to use for certificate validation.
     pub fn new(roots: RootCertStore) -> Arc< ClientCertVerifier > {
-        Arc::new(AllowAnyAuthenticatedClient { roots })
+        Arc::new(AllowAnyAuthenticatedClient { roots, crl_checker: None })
+    }
+
+    /// Construct a new `AllowAnyAuthenticatedClient` which also rejects
+    /// revoked certificates.
+    ///
+    /// `roots` is the list of trust anchors to use for certificate validation.
+    /// Every certificate in the client's chain is checked against the CRL
+    /// from its issuer in `crls`, with `policy` saying what to do if there
+    /// isn't a current one.
+    pub fn with_crls(roots: RootCertStore,
+                     crls: Arc<CRLStore>,
+                     policy: CRLPolicy) -> Arc<ClientCertVerifier> {
+        Arc::new(AllowAnyAuthenticatedClient {
+            roots,
+            crl_checker: Some(CRLChecker::new(crls, policy)),
+        })
     }
 }
 
@@ -186,8 +294,13 @@ impl ClientCertVerifier for AllowAnyAuthenticatedClient {
         cert.verify_is_valid_tls_client_cert(
                 SUPPORTED_SIG_ALGS, &webpki::TLSClientTrustAnchors(&trustroots),
                 &chain, now)
-            .map_err(TLSError::WebPKIError)
-            .map(|_| ClientCertVerified::assertion())
+            .map_err(TLSError::WebPKIError)?;
+
+        if let Some(ref checker) = self.crl_checker {
+            checker.check(&self.roots, presented_certs, Usage::TLSClient)?;
+        }
+
+        Ok(ClientCertVerified::assertion())
     }
 }
 
@@ -207,7 +320,21 @@ impl AllowAnyAnonymousOrAuthenticatedClient {
     /// `roots` is the list of trust anchors to use for certificate validation.
     pub fn new(roots: RootCertStore) -> Arc<ClientCertVerifier> {
         Arc::new(AllowAnyAnonymousOrAuthenticatedClient {
-            inner: AllowAnyAuthenticatedClient { roots }
+            inner: AllowAnyAuthenticatedClient { roots, crl_checker: None }
+        })
+    }
+
+    /// Construct a new `AllowAnyAnonymousOrAuthenticatedClient` which
+    /// rejects revoked certificates, like
+    /// `AllowAnyAuthenticatedClient::with_crls`.
+    pub fn with_crls(roots: RootCertStore,
+                     crls: Arc<CRLStore>,
+                     policy: CRLPolicy) -> Arc<ClientCertVerifier> {
+        Arc::new(AllowAnyAnonymousOrAuthenticatedClient {
+            inner: AllowAnyAuthenticatedClient {
+                roots,
+                crl_checker: Some(CRLChecker::new(crls, policy)),
+            }
         })
     }
 }
@@ -227,6 +354,103 @@ impl ClientCertVerifier for AllowAnyAnonymousOrAuthenticatedClient {
     }
 }
 
+/// A `ClientCertVerifier` for X509-SVIDs: it requires a trusted client
+/// certificate, like `AllowAnyAuthenticatedClient`, which also has a
+/// SPIFFE ID as its only URI subject alternative name.
+///
+/// The SPIFFE ID must be in the configured trust domain and, unless
+/// the list of allowed paths is empty, have exactly one of those
+/// paths.  A verified ID is available from
+/// `ServerSession::get_peer_spiffe_id`.  Sessions are only resumed
+/// if their client had an ID, and it is still allowed.
+pub struct AllowSPIFFEClient {
+    inner: AllowAnyAuthenticatedClient,
+    trust_domain: String,
+    allowed_paths: Vec<String>,
+}
+
+impl AllowSPIFFEClient {
+    /// Construct a new `AllowSPIFFEClient`.
+    ///
+    /// `roots` is the list of trust anchors to use for certificate
+    /// validation.  `trust_domain` is like `example.org`, and
+    /// `allowed_paths` like `["/ns/prod/sa/web"]`; if that's empty,
+    /// any workload in the trust domain is allowed.
+    pub fn new(roots: RootCertStore,
+               trust_domain: &str,
+               allowed_paths: &[&str]) -> Arc<ClientCertVerifier> {
+        Arc::new(AllowSPIFFEClient::with_inner(
+            AllowAnyAuthenticatedClient { roots, crl_checker: None },
+            trust_domain,
+            allowed_paths))
+    }
+
+    /// Construct a new `AllowSPIFFEClient` which also rejects
+    /// revoked certificates, like `AllowAnyAuthenticatedClient::with_crls`.
+    pub fn with_crls(roots: RootCertStore,
+                     trust_domain: &str,
+                     allowed_paths: &[&str],
+                     crls: Arc<CRLStore>,
+                     policy: CRLPolicy) -> Arc<ClientCertVerifier> {
+        Arc::new(AllowSPIFFEClient::with_inner(
+            AllowAnyAuthenticatedClient {
+                roots,
+                crl_checker: Some(CRLChecker::new(crls, policy)),
+            },
+            trust_domain,
+            allowed_paths))
+    }
+
+    fn with_inner(inner: AllowAnyAuthenticatedClient,
+                  trust_domain: &str,
+                  allowed_paths: &[&str]) -> AllowSPIFFEClient {
+        AllowSPIFFEClient {
+            inner,
+            trust_domain: trust_domain.to_string(),
+            allowed_paths: allowed_paths.iter()
+                .map(|path| path.to_string())
+                .collect(),
+        }
+    }
+
+    fn is_allowed(&self, id: &SPIFFEID) -> bool {
+        // The ID of the trust domain itself doesn't name a workload.
+        if id.trust_domain() != self.trust_domain || id.path().is_empty() {
+            return false;
+        }
+
+        self.allowed_paths.is_empty() ||
+            self.allowed_paths.iter().any(|path| path == id.path())
+    }
+}
+
+impl ClientCertVerifier for AllowSPIFFEClient {
+    fn offer_client_auth(&self) -> bool { true }
+
+    fn client_auth_mandatory(&self) -> bool { true }
+
+    fn client_auth_root_subjects<'a>(&'a self) -> DistinguishedNames {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(&self, presented_certs: &[Certificate])
+                          -> Result<ClientCertVerified, TLSError> {
+        self.inner.verify_client_cert(presented_certs)?;
+
+        let id = SPIFFEID::from_cert(&presented_certs[0])?;
+        if !self.is_allowed(&id) {
+            return Err(TLSError::SPIFFEIDNotAllowed(id.to_string()));
+        }
+
+        debug!("client has SPIFFE ID {}", id);
+        Ok(ClientCertVerified::with_spiffe_id(id))
+    }
+
+    fn allows_resumed_spiffe_id(&self, id: Option<&SPIFFEID>) -> bool {
+        id.map_or(false, |id| self.is_allowed(id))
+    }
+}
+
 /// Turns off client authentication.
 pub struct NoClientAuth;
 
@@ -248,117 +472,32 @@ impl ClientCertVerifier for NoClientAuth {
     }
 }
 
-static ECDSA_SHA256: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
-                                             &webpki::ECDSA_P384_SHA256];
-static ECDSA_SHA384: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA384,
-                                             &webpki::ECDSA_P384_SHA384];
-
-static RSA_SHA1: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA1];
-static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
-static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
-static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
-static RSA_PSS_SHA256: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY];
-static RSA_PSS_SHA384: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY];
-static RSA_PSS_SHA512: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY];
-
-fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, TLSError> {
-    match scheme {
-        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
-        SignatureScheme::ECDSA_NISTP256_SHA256 => Ok(ECDSA_SHA256),
-        SignatureScheme::ECDSA_NISTP384_SHA384 => Ok(ECDSA_SHA384),
-
-        SignatureScheme::RSA_PKCS1_SHA1 => Ok(RSA_SHA1),
-        SignatureScheme::RSA_PKCS1_SHA256 => Ok(RSA_SHA256),
-        SignatureScheme::RSA_PKCS1_SHA384 => Ok(RSA_SHA384),
-        SignatureScheme::RSA_PKCS1_SHA512 => Ok(RSA_SHA512),
-
-        SignatureScheme::RSA_PSS_SHA256 => Ok(RSA_PSS_SHA256),
-        SignatureScheme::RSA_PSS_SHA384 => Ok(RSA_PSS_SHA384),
-        SignatureScheme::RSA_PSS_SHA512 => Ok(RSA_PSS_SHA512),
-
-        _ => {
-            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
-            Err(TLSError::PeerMisbehavedError(error_msg))
-        }
-    }
-}
-
-fn verify_sig_using_any_alg(cert: &webpki::EndEntityCert,
-                            algs: SignatureAlgorithms,
-                            message: &[u8],
-                            sig: &[u8])
-                            -> Result<(), webpki::Error> {
-    // TLS doesn't itself give us enough info to map to a single webpki::SignatureAlgorithm.
-    // Therefore, convert_algs maps to several and we try them all.
-    for alg in algs {
-        match cert.verify_signature(alg,
-                                    untrusted::Input::from(message),
-                                    untrusted::Input::from(sig)) {
-            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
-            res => return res,
-        }
-    }
-
-    Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
-}
-
 /// Verify the signed `message` using the public key quoted in
 /// `cert` and algorithm and signature in `dss`.
 ///
 /// `cert` MUST have been authenticated before using this function,
 /// typically using `verify_cert`.
-pub fn verify_signed_struct(message: &[u8],
+pub fn verify_signed_struct(verifier: &SignatureVerifier,
+                            message: &[u8],
                             cert: &Certificate,
                             dss: &DigitallySignedStruct)
                             -> Result<HandshakeSignatureValid, TLSError> {
-
-    let possible_algs = convert_scheme(dss.scheme)?;
-    let cert_in = untrusted::Input::from(&cert.0);
-    let cert = webpki::EndEntityCert::from(cert_in)
-        .map_err(TLSError::WebPKIError)?;
-
-    verify_sig_using_any_alg(&cert, possible_algs, message, &dss.sig.0)
-        .map_err(TLSError::WebPKIError)
+    verifier.verify_tls12_signature(message, cert, dss.scheme, &dss.sig.0)
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
-fn convert_alg_tls13(scheme: SignatureScheme)
-                     -> Result<&'static webpki::SignatureAlgorithm, TLSError> {
-    use msgs::enums::SignatureScheme::*;
-
-    match scheme {
-        ECDSA_NISTP256_SHA256 => Ok(&webpki::ECDSA_P256_SHA256),
-        ECDSA_NISTP384_SHA384 => Ok(&webpki::ECDSA_P384_SHA384),
-        RSA_PSS_SHA256 => Ok(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
-        RSA_PSS_SHA384 => Ok(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
-        RSA_PSS_SHA512 => Ok(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
-        _ => {
-            let error_msg = format!("received unsupported sig scheme {:?}", scheme);
-            Err(TLSError::PeerMisbehavedError(error_msg))
-        }
-    }
-}
-
-pub fn verify_tls13(cert: &Certificate,
+pub fn verify_tls13(verifier: &SignatureVerifier,
+                    cert: &Certificate,
                     dss: &DigitallySignedStruct,
                     handshake_hash: &[u8],
                     context_string_with_0: &[u8])
                     -> Result<HandshakeSignatureValid, TLSError> {
-    let alg = convert_alg_tls13(dss.scheme)?;
-
     let mut msg = Vec::new();
     msg.resize(64, 0x20u8);
     msg.extend_from_slice(context_string_with_0);
     msg.extend_from_slice(handshake_hash);
 
-    let cert_in = untrusted::Input::from(&cert.0);
-    let cert = webpki::EndEntityCert::from(cert_in)
-        .map_err(TLSError::WebPKIError)?;
-
-    cert.verify_signature(alg,
-                          untrusted::Input::from(&msg),
-                          untrusted::Input::from(&dss.sig.0))
-        .map_err(TLSError::WebPKIError)
+    verifier.verify_tls13_signature(&msg, cert, dss.scheme, &dss.sig.0)
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
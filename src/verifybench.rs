@@ -43,6 +43,7 @@ fn fixed_time() -> Result<webpki::Time, TLSError> {
 
 static V: &'static verify::WebPKIVerifier = &verify::WebPKIVerifier {
     time: fixed_time,
+    crl_checker: None,
 };
 
 #[test]
@@ -57,7 +58,7 @@ fn test_reddit_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("reddit.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -73,7 +74,7 @@ fn test_github_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("github.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -90,7 +91,7 @@ fn test_arstechnica_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("arstechnica.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -107,7 +108,7 @@ fn test_servo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("servo.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -123,7 +124,7 @@ fn test_twitter_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("twitter.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap(); });
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap(); });
 }
 
 #[test]
@@ -138,7 +139,7 @@ fn test_wikipedia_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("wikipedia.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -155,7 +156,7 @@ fn test_google_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.google.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -172,7 +173,7 @@ fn test_hn_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("news.ycombinator.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -188,7 +189,7 @@ fn test_stackoverflow_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("stackoverflow.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -204,7 +205,7 @@ fn test_duckduckgo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("duckduckgo.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -221,7 +222,7 @@ fn test_rustlang_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.rust-lang.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -238,7 +239,7 @@ fn test_wapo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.washingtonpost.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[]).unwrap();
     });
 }
 
@@ -1,6 +1,11 @@
 // Additional x509/asn1 functions to those provided in webpki/ring.
 
-use ring::der;
+use ring::{der, signature};
+use ring::error::Unspecified;
+use untrusted;
+
+/// id-Ed25519, from RFC8410.
+static ED25519_OID: &'static [u8] = &[0x2b, 0x65, 0x70];
 
 fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     let len = bytes.len();
@@ -23,6 +28,447 @@ pub fn wrap_in_sequence(bytes: &mut Vec<u8>) {
     bytes.insert(0, der::Tag::Sequence as u8);
 }
 
+/// Find the subject public key in the DER-encoded certificate `cert`,
+/// requiring that it is an Ed25519 key.
+///
+/// This is needed because webpki doesn't support Ed25519 keys.
+/// `cert` should already have been parsed and validated by webpki,
+/// so this doesn't do more checking than is needed to find the key.
+pub fn ed25519_public_key(cert: untrusted::Input) -> Result<untrusted::Input, ()> {
+    cert.read_all(Unspecified, |cert| {
+        der::nested(cert, der::Tag::Sequence, Unspecified, |cert| {
+            let tbs = der::expect_tag_and_get_value(cert, der::Tag::Sequence)?;
+            cert.skip_to_end();
+            tbs.read_all(Unspecified, read_tbs_for_ed25519_key)
+        })
+    }).map_err(|_| ())
+}
+
+fn read_tbs_for_ed25519_key<'a>(tbs: &mut untrusted::Reader<'a>)
+                                -> Result<untrusted::Input<'a>, Unspecified> {
+    // version is optional, and explicitly tagged [0]
+    if tbs.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+        der::read_tag_and_get_value(tbs)?;
+    }
+
+    der::expect_tag_and_get_value(tbs, der::Tag::Integer)?; // serialNumber
+    der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?; // signature
+    der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?; // issuer
+    der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?; // validity
+    der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?; // subject
+
+    let key = der::nested(tbs, der::Tag::Sequence, Unspecified, |spki| {
+        let alg = der::expect_tag_and_get_value(spki, der::Tag::Sequence)?;
+        let oid = alg.read_all(Unspecified, |alg| {
+            der::expect_tag_and_get_value(alg, der::Tag::OID)
+        })?;
+
+        if oid.as_slice_less_safe() != ED25519_OID {
+            return Err(Unspecified);
+        }
+
+        der::bit_string_with_no_unused_bits(spki)
+    })?;
+
+    // ignore the remainder: unique ids and extensions
+    tbs.skip_to_end();
+    Ok(key)
+}
+
+/// Read a sequence of extensions, for a caller which acts on none
+/// of them.  That means we must refuse any that are critical.
+pub fn check_extensions(exts: &mut untrusted::Reader) -> Result<(), Unspecified> {
+    while !exts.at_end() {
+        der::nested(exts, der::Tag::Sequence, Unspecified, |ext| {
+            der::expect_tag_and_get_value(ext, der::Tag::OID)?;
+            let critical = if ext.peek(der::Tag::Boolean as u8) {
+                der::expect_tag_and_get_value(ext, der::Tag::Boolean)?
+                    .as_slice_less_safe() == [0xff]
+            } else {
+                false
+            };
+            der::expect_tag_and_get_value(ext, der::Tag::OctetString)?;
+
+            if critical {
+                Err(Unspecified)
+            } else {
+                Ok(())
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Read a UTCTime or GeneralizedTime, returning seconds since
+/// the Unix epoch.
+pub fn read_time(rd: &mut untrusted::Reader) -> Result<u64, Unspecified> {
+    let is_utc_time = rd.peek(der::Tag::UTCTime as u8);
+    let expect_tag = if is_utc_time { der::Tag::UTCTime } else { der::Tag::GeneralizedTime };
+
+    der::nested(rd, expect_tag, Unspecified, |value| {
+        let year = if is_utc_time {
+            let yy = read_digits(value, 2)?;
+            if yy >= 50 { 1900 + yy } else { 2000 + yy }
+        } else {
+            read_digits(value, 4)?
+        };
+        let month = read_digits(value, 2)?;
+        let day = read_digits(value, 2)?;
+        let hours = read_digits(value, 2)?;
+        let minutes = read_digits(value, 2)?;
+        let seconds = read_digits(value, 2)?;
+
+        if value.read_byte()? != b'Z' {
+            return Err(Unspecified);
+        }
+
+        seconds_since_epoch(year, month, day, hours, minutes, seconds)
+    })
+}
+
+fn read_digits(rd: &mut untrusted::Reader, count: usize) -> Result<u64, Unspecified> {
+    let mut value = 0;
+
+    for _ in 0..count {
+        let digit = rd.read_byte()?;
+        if !digit.is_ascii_digit() {
+            return Err(Unspecified);
+        }
+        value = value * 10 + u64::from(digit - b'0');
+    }
+
+    Ok(value)
+}
+
+fn seconds_since_epoch(year: u64, month: u64, day: u64,
+                       hours: u64, minutes: u64, seconds: u64) -> Result<u64, Unspecified> {
+    fn is_leap_year(year: u64) -> bool {
+        match (year % 4, year % 100, year % 400) {
+            (_, _, 0) => true,
+            (_, 0, _) => false,
+            (0, _, _) => true,
+            _ => false,
+        }
+    }
+
+    fn days_in_month(year: u64, month: u64) -> u64 {
+        match month {
+            2 if is_leap_year(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    if year < 1970 || month == 0 || month > 12 ||
+        day == 0 || day > days_in_month(year, month) ||
+        hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(Unspecified);
+    }
+
+    let mut days = day - 1;
+    for y in 1970..year {
+        days += if is_leap_year(y) { 366 } else { 365 };
+    }
+    for m in 1..month {
+        days += days_in_month(year, m);
+    }
+
+    Ok(((days * 24 + hours) * 60 + minutes) * 60 + seconds)
+}
+
+static RSA_ENCRYPTION: &'static [u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+static SHA256_WITH_RSA: &'static [u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+static SHA384_WITH_RSA: &'static [u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+static SHA512_WITH_RSA: &'static [u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+static EC_PUBLIC_KEY: &'static [u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+static SECP256R1: &'static [u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+static SECP384R1: &'static [u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+static ECDSA_WITH_SHA256: &'static [u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+static ECDSA_WITH_SHA384: &'static [u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// Read an AlgorithmIdentifier, returning its OID.  Parameters
+/// are ignored: we only use algorithms where they are fixed.
+pub fn read_algorithm_oid<'a>(rd: &mut untrusted::Reader<'a>)
+                              -> Result<untrusted::Input<'a>, Unspecified> {
+    der::nested(rd, der::Tag::Sequence, Unspecified, |alg| {
+        let oid = der::expect_tag_and_get_value(alg, der::Tag::OID)?;
+        alg.skip_to_end();
+        Ok(oid)
+    })
+}
+
+/// Verify `signature`, made with the algorithm with OID `signature_alg`
+/// over `data`, using the key in `spki` (the contents of a
+/// SubjectPublicKeyInfo).
+pub fn verify_signed_data(spki: untrusted::Input,
+                          signature_alg: &[u8],
+                          data: untrusted::Input,
+                          signature: untrusted::Input) -> Result<(), Unspecified> {
+    let (key_alg, key) = spki.read_all(Unspecified, |spki| {
+        let alg = der::expect_tag_and_get_value(spki, der::Tag::Sequence)?;
+        let key = der::bit_string_with_no_unused_bits(spki)?;
+        Ok((alg, key))
+    })?;
+
+    let alg = choose_verify_alg(signature_alg, key_alg.as_slice_less_safe())?;
+    signature::verify(alg, key, data, signature)
+}
+
+/// Choose how to verify a signature made with algorithm `sig_alg`
+/// by the key with algorithm identifier `key_alg`.
+fn choose_verify_alg(sig_alg: &[u8], key_alg: &[u8])
+                     -> Result<&'static signature::VerificationAlgorithm, Unspecified> {
+    let (key_oid, key_params) = untrusted::Input::from(key_alg)
+        .read_all(Unspecified, |alg| {
+            let oid = der::expect_tag_and_get_value(alg, der::Tag::OID)?;
+            let params = alg.skip_to_end();
+            Ok((oid.as_slice_less_safe(), params.as_slice_less_safe()))
+        })?;
+
+    let curve = if key_oid == EC_PUBLIC_KEY {
+        untrusted::Input::from(key_params)
+            .read_all(Unspecified, |params| der::expect_tag_and_get_value(params, der::Tag::OID))?
+            .as_slice_less_safe()
+    } else {
+        &[]
+    };
+
+    if key_oid == RSA_ENCRYPTION {
+        if sig_alg == SHA256_WITH_RSA {
+            return Ok(&signature::RSA_PKCS1_2048_8192_SHA256);
+        } else if sig_alg == SHA384_WITH_RSA {
+            return Ok(&signature::RSA_PKCS1_2048_8192_SHA384);
+        } else if sig_alg == SHA512_WITH_RSA {
+            return Ok(&signature::RSA_PKCS1_2048_8192_SHA512);
+        }
+    } else if curve == SECP256R1 {
+        if sig_alg == ECDSA_WITH_SHA256 {
+            return Ok(&signature::ECDSA_P256_SHA256_ASN1);
+        } else if sig_alg == ECDSA_WITH_SHA384 {
+            return Ok(&signature::ECDSA_P256_SHA384_ASN1);
+        }
+    } else if curve == SECP384R1 {
+        if sig_alg == ECDSA_WITH_SHA256 {
+            return Ok(&signature::ECDSA_P384_SHA256_ASN1);
+        } else if sig_alg == ECDSA_WITH_SHA384 {
+            return Ok(&signature::ECDSA_P384_SHA384_ASN1);
+        }
+    } else if key_oid == ED25519_OID && sig_alg == ED25519_OID {
+        return Ok(&signature::ED25519);
+    }
+
+    Err(Unspecified)
+}
+
+/// The parts of a certificate needed to check its revocation
+/// status.  Names and the key are the contents of their DER
+/// SEQUENCEs, like in a `webpki::TrustAnchor`.
+pub struct CertFields<'a> {
+    pub serial: untrusted::Input<'a>,
+    pub issuer: untrusted::Input<'a>,
+    pub subject: untrusted::Input<'a>,
+    pub spki: untrusted::Input<'a>,
+    pub not_before: u64,
+    pub not_after: u64,
+    pub extensions: Option<untrusted::Input<'a>>,
+
+    /// The signed TBSCertificate, and the issuer's signature over it.
+    pub signed_data: untrusted::Input<'a>,
+    pub signature_alg: untrusted::Input<'a>,
+    pub signature: untrusted::Input<'a>,
+}
+
+impl<'a> CertFields<'a> {
+    /// Is this certificate valid for `purpose`, according to
+    /// its extended key usage extension?  If it has none, it is
+    /// valid for no particular purpose.
+    pub fn has_extended_key_usage(&self, purpose: &[u8]) -> bool {
+        self.find_extension(EXTENDED_KEY_USAGE)
+            .and_then(|eku| {
+                eku.read_all(Unspecified, |eku| {
+                    der::nested(eku, der::Tag::Sequence, Unspecified, |purposes| {
+                        let mut found = false;
+                        while !purposes.at_end() {
+                            let oid = der::expect_tag_and_get_value(purposes, der::Tag::OID)?;
+                            found |= oid.as_slice_less_safe() == purpose;
+                        }
+                        Ok(found)
+                    })
+                }).ok()
+            })
+            .unwrap_or(false)
+    }
+
+    /// Does this certificate have the TLS feature extension (RFC7633),
+    /// requiring a stapled OCSP response?
+    pub fn requires_ocsp_staple(&self) -> bool {
+        self.find_extension(TLS_FEATURE)
+            .and_then(|features| {
+                features.read_all(Unspecified, |features| {
+                    der::nested(features, der::Tag::Sequence, Unspecified, |features| {
+                        let mut found = false;
+                        while !features.at_end() {
+                            let feature = der::expect_tag_and_get_value(features,
+                                                                        der::Tag::Integer)?;
+                            found |= feature.as_slice_less_safe() == [STATUS_REQUEST];
+                        }
+                        Ok(found)
+                    })
+                }).ok()
+            })
+            .unwrap_or(false)
+    }
+
+    /// Does this certificate's subject alternative name extension
+    /// include the iPAddress `ip`: four octets for IPv4, or sixteen
+    /// for IPv6?
+    pub fn has_subject_alt_ip_address(&self, ip: &[u8]) -> bool {
+        self.subject_alt_names(IP_ADDRESS_TAG)
+            .iter()
+            .any(|name| name.as_slice_less_safe() == ip)
+    }
+
+    /// Return the uniformResourceIdentifier names in this certificate's
+    /// subject alternative name extension, in order.
+    pub fn subject_alt_uris(&self) -> Vec<untrusted::Input<'a>> {
+        self.subject_alt_names(URI_TAG)
+    }
+
+    /// Return the values of the GeneralNames with the implicit tag
+    /// `tag` in the subject alternative name extension.  A malformed
+    /// extension has no names.
+    fn subject_alt_names(&self, tag: u8) -> Vec<untrusted::Input<'a>> {
+        self.find_extension(SUBJECT_ALT_NAME)
+            .and_then(|names| {
+                names.read_all(Unspecified, |names| {
+                    der::nested(names, der::Tag::Sequence, Unspecified, |names| {
+                        let mut found = Vec::new();
+                        while !names.at_end() {
+                            let (name_tag, name) = der::read_tag_and_get_value(names)?;
+                            if name_tag == tag {
+                                found.push(name);
+                            }
+                        }
+                        Ok(found)
+                    })
+                }).ok()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Return the value of the extension with OID `id`, if present.
+    fn find_extension(&self, id: &[u8]) -> Option<untrusted::Input<'a>> {
+        let extensions = match self.extensions {
+            Some(extensions) => extensions,
+            None => return None,
+        };
+
+        extensions.read_all(Unspecified, |exts| {
+            let mut found = None;
+            while !exts.at_end() {
+                der::nested(exts, der::Tag::Sequence, Unspecified, |ext| {
+                    let oid = der::expect_tag_and_get_value(ext, der::Tag::OID)?;
+                    if ext.peek(der::Tag::Boolean as u8) {
+                        der::expect_tag_and_get_value(ext, der::Tag::Boolean)?;
+                    }
+                    let value = der::expect_tag_and_get_value(ext, der::Tag::OctetString)?;
+                    if oid.as_slice_less_safe() == id {
+                        found = Some(value);
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(found)
+        }).unwrap_or(None)
+    }
+}
+
+/// id-ce-extKeyUsage, from RFC5280.
+static EXTENDED_KEY_USAGE: &'static [u8] = &[0x55, 0x1d, 0x25];
+
+/// id-ce-subjectAltName, from RFC5280.
+static SUBJECT_ALT_NAME: &'static [u8] = &[0x55, 0x1d, 0x11];
+
+/// The implicit [7] tag of an iPAddress GeneralName.
+const IP_ADDRESS_TAG: u8 = 0x87;
+
+/// The implicit [6] tag of a uniformResourceIdentifier GeneralName.
+const URI_TAG: u8 = 0x86;
+
+/// id-pe-tlsfeature, from RFC7633.
+static TLS_FEATURE: &'static [u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+
+/// The status_request TLS extension number, as a TLS feature.
+const STATUS_REQUEST: u8 = 5;
+
+/// Find the serial number, names, key and other details of the
+/// DER-encoded certificate `cert`.  Like `ed25519_public_key`, this
+/// doesn't check more than it needs to: use webpki for that.
+pub fn cert_fields(cert: untrusted::Input) -> Result<CertFields, ()> {
+    cert.read_all(Unspecified, |cert| {
+        der::nested(cert, der::Tag::Sequence, Unspecified, |cert| {
+            let start = cert.mark();
+            let tbs = der::expect_tag_and_get_value(cert, der::Tag::Sequence)?;
+            let signed_data = cert.get_input_between_marks(start, cert.mark())?;
+            let signature_alg = read_algorithm_oid(cert)?;
+            let signature = der::bit_string_with_no_unused_bits(cert)?;
+
+            let mut fields = tbs.read_all(Unspecified, read_tbs_fields)?;
+            fields.signed_data = signed_data;
+            fields.signature = signature;
+
+            // The signature algorithm must match the one in the signed data.
+            if fields.signature_alg.as_slice_less_safe() != signature_alg.as_slice_less_safe() {
+                return Err(Unspecified);
+            }
+
+            Ok(fields)
+        })
+    }).map_err(|_| ())
+}
+
+fn read_tbs_fields<'a>(tbs: &mut untrusted::Reader<'a>)
+                       -> Result<CertFields<'a>, Unspecified> {
+    if tbs.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+        der::read_tag_and_get_value(tbs)?;
+    }
+
+    let serial = der::expect_tag_and_get_value(tbs, der::Tag::Integer)?;
+    let signature_alg = read_algorithm_oid(tbs)?;
+    let issuer = der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?;
+    let (not_before, not_after) = der::nested(tbs, der::Tag::Sequence, Unspecified, |validity| {
+        Ok((read_time(validity)?, read_time(validity)?))
+    })?;
+    let subject = der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?;
+    let spki = der::expect_tag_and_get_value(tbs, der::Tag::Sequence)?;
+
+    // skip unique ids; extensions are explicitly tagged [3]
+    let mut extensions = None;
+    while !tbs.at_end() {
+        let (tag, value) = der::read_tag_and_get_value(tbs)?;
+        if tag == der::Tag::ContextSpecificConstructed3 as u8 {
+            extensions = Some(value.read_all(Unspecified, |exts| {
+                der::expect_tag_and_get_value(exts, der::Tag::Sequence)
+            })?);
+        }
+    }
+
+    Ok(CertFields {
+        serial: serial,
+        issuer: issuer,
+        subject: subject,
+        spki: spki,
+        not_before: not_before,
+        not_after: not_after,
+        extensions: extensions,
+        signed_data: untrusted::Input::from(&[]),
+        signature_alg: signature_alg,
+        signature: untrusted::Input::from(&[]),
+    })
+}
+
 #[test]
 fn test_empty() {
     let mut val = Vec::new();
@@ -60,3 +506,48 @@ fn test_large() {
     assert_eq!(vec![0x30, 0x82, 0x12, 0x34, 0x12, 0x12],
                val[..6].to_vec());
 }
+
+#[test]
+fn test_ed25519_public_key() {
+    let cert = include_bytes!("testdata/cert-ed25519.0.der");
+    let key = ed25519_public_key(untrusted::Input::from(cert)).unwrap();
+    assert_eq!(vec![0xe6, 0x25, 0xee, 0x94, 0x75, 0x81, 0x54, 0xb7,
+                    0x3c, 0x33, 0x9f, 0x4f, 0xf7, 0xc5, 0x25, 0x1f,
+                    0x0a, 0xbc, 0x37, 0xeb, 0xd3, 0x37, 0x26, 0xb5,
+                    0xcd, 0xa4, 0x04, 0x82, 0x72, 0x5e, 0x2a, 0x73],
+               key.as_slice_less_safe().to_vec());
+}
+
+#[test]
+fn test_ed25519_public_key_rejects_other_keys() {
+    let cert = include_bytes!("testdata/cert-reddit.0.der");
+    assert!(ed25519_public_key(untrusted::Input::from(cert)).is_err());
+    assert!(ed25519_public_key(untrusted::Input::from(&[0x30, 0x00])).is_err());
+}
+
+#[test]
+fn test_cert_fields() {
+    let cert = include_bytes!("testdata/cert-ed25519.0.der");
+    let fields = cert_fields(untrusted::Input::from(cert)).unwrap();
+    assert!(fields.serial.len() > 0);
+    assert!(fields.issuer.as_slice_less_safe() != fields.subject.as_slice_less_safe());
+
+    let spki = fields.spki.as_slice_less_safe();
+    assert_eq!(&spki[spki.len() - 32..],
+               &ed25519_public_key(untrusted::Input::from(cert)).unwrap()
+                   .as_slice_less_safe()[..]);
+}
+
+#[test]
+fn test_read_time() {
+    fn time(bytes: &[u8]) -> Result<u64, Unspecified> {
+        untrusted::Input::from(bytes).read_all(Unspecified, read_time)
+    }
+
+    assert_eq!(time(b"\x17\x0d700101000000Z"), Ok(0));
+    assert_eq!(time(b"\x17\x0d491231235959Z"), Ok(2524607999));
+    assert_eq!(time(b"\x18\x0f20000229120000Z"), Ok(951825600));
+    assert!(time(b"\x18\x0f19000229120000Z").is_err());
+    assert!(time(b"\x17\x0d700101000000+").is_err());
+    assert!(time(b"\x17\x0b7001010000Z").is_err());
+}
@@ -2,8 +2,8 @@
 
 set -xe
 
-rm -rf rsa/ ecdsa/
-mkdir -p rsa/ ecdsa/
+rm -rf rsa/ ecdsa/ eddsa/
+mkdir -p rsa/ ecdsa/ eddsa/
 
 openssl req -nodes \
           -x509 \
@@ -88,7 +88,42 @@ openssl req -nodes \
           -days 2000 \
           -subj "/CN=ponytown client"
 
-for kt in rsa ecdsa ; do
+# eddsa
+# webpki cannot verify Ed25519 signatures on certificates, so only the
+# leaf certificates use Ed25519 keys.  The issuers are ECDSA.
+openssl req -nodes \
+          -x509 \
+          -newkey ec:ecdsa/nistp384.pem \
+          -keyout eddsa/ca.key \
+          -out eddsa/ca.cert \
+          -sha256 \
+          -batch \
+          -days 3650 \
+          -subj "/CN=ponytown EdDSA CA"
+
+openssl req -nodes \
+          -newkey ec:ecdsa/nistp256.pem \
+          -keyout eddsa/inter.key \
+          -out eddsa/inter.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown EdDSA level 2 intermediate"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/end.key \
+          -out eddsa/end.req \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/client.key \
+          -out eddsa/client.req \
+          -batch \
+          -subj "/CN=ponytown client"
+
+for kt in rsa ecdsa eddsa ; do
   openssl x509 -req \
             -in $kt/inter.req \
             -out $kt/inter.cert \
@@ -127,3 +162,220 @@ for kt in rsa ecdsa ; do
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
 done
+
+# crls, made with `openssl ca`: each one lists the revoked serials
+# given after its issuer.  These are dated from 2019 so they are valid
+# for as long as the certificates are; the expired one ran out in 2019.
+crl() {
+  issuer=$1
+  out=$2
+  nextupdate=$3
+  shift 3
+
+  : > crl-index.txt
+  echo 01 > crl-number.txt
+  for serial in "$@" ; do
+    printf 'R\t491231235959Z\t190101000000Z\t%s\tunknown\t/CN=revoked\n' $serial >> crl-index.txt
+  done
+
+  openssl ca -gencrl \
+            -config openssl.cnf \
+            -keyfile rsa/$issuer.key \
+            -cert rsa/$issuer.cert \
+            -crl_lastupdate 20190101000000Z \
+            -crl_nextupdate $nextupdate \
+            -out rsa/$out
+
+  rm -f crl-index.txt* crl-number.txt*
+}
+
+crl ca ca.crl 20490101000000Z
+crl ca ca.revoked.crl 20490101000000Z 7B
+crl inter inter.crl 20490101000000Z
+crl inter inter.revoked.crl 20490101000000Z 01C8 0315
+crl inter inter.expired.crl 20190601000000Z
+
+# ocsp responses, and a certificate with the TLS feature extension
+# requiring one (aka. must-staple).  openssl dates these from the
+# current time, so we use libfaketime to date them from 2019 too.
+at2019="faketime 2019-01-01"
+
+openssl req -new \
+          -key rsa/end.key \
+          -out rsa/mustaple.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=testserver.com"
+
+$at2019 openssl x509 -req \
+          -in rsa/mustaple.req \
+          -out rsa/mustaple.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 3650 \
+          -set_serial 1011 \
+          -extensions v3_mustaple -extfile openssl.cnf
+
+cat rsa/mustaple.cert rsa/inter.cert rsa/ca.cert > rsa/mustaple.fullchain
+
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout rsa/ocsp.key \
+          -out rsa/ocsp.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown RSA OCSP responder"
+
+$at2019 openssl x509 -req \
+          -in rsa/ocsp.req \
+          -out rsa/ocsp.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 3650 \
+          -set_serial 1213 \
+          -extensions v3_ocsp -extfile openssl.cnf
+
+$at2019 openssl x509 -req \
+          -in rsa/ocsp.req \
+          -out rsa/ocsp-noeku.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 3650 \
+          -set_serial 1415 \
+          -extensions v3_ocsp_noeku -extfile openssl.cnf
+
+# ocsp_response ISSUER SIGNER KEY OUT CERT STATUS DAYS [openssl ocsp args...]
+# makes a response about CERT (issued by ISSUER) which is good or
+# revoked, valid for DAYS days (or with no nextUpdate if DAYS is
+# 'none'), and signed by SIGNER.
+ocsp_response() {
+  issuer=$1
+  signer=$2
+  key=$3
+  out=$4
+  cert=$5
+  status=$6
+  days=$7
+  shift 7
+
+  validity=""
+  if [ $days != none ] ; then
+    validity="-ndays $days"
+  fi
+
+  serial=$(openssl x509 -in rsa/$cert.cert -noout -serial | cut -d= -f2)
+  if [ $status = revoked ] ; then
+    printf 'R\t491231235959Z\t190101000000Z\t%s\tunknown\t/CN=revoked\n' $serial > ocsp-index.txt
+  else
+    printf 'V\t491231235959Z\t\t%s\tunknown\t/CN=good\n' $serial > ocsp-index.txt
+  fi
+
+  openssl ocsp \
+            -issuer rsa/$issuer.cert \
+            -cert rsa/$cert.cert \
+            -no_nonce \
+            -reqout ocsp-req.der
+
+  $at2019 openssl ocsp \
+            -index ocsp-index.txt \
+            -CA rsa/$issuer.cert \
+            -rsigner rsa/$signer.cert \
+            -rkey rsa/$key.key \
+            -reqin ocsp-req.der \
+            $validity \
+            -respout rsa/$out \
+            "$@"
+
+  rm -f ocsp-index.txt* ocsp-req.der
+}
+
+ocsp_response inter inter inter end.ocsp end good 10950 -resp_no_certs
+ocsp_response inter inter inter end.revoked.ocsp end revoked 10950 -resp_no_certs
+ocsp_response inter inter inter end.expired.ocsp end good 30 -resp_no_certs
+ocsp_response inter ocsp ocsp end.delegated.ocsp end good 10950 -resp_key_id
+ocsp_response inter ocsp-noeku ocsp end.noeku.ocsp end good 10950
+ocsp_response inter inter inter mustaple.ocsp mustaple good 10950
+ocsp_response inter inter inter end.no-next-update.ocsp end good none -resp_no_certs
+
+# a decoy with the intermediate's name but its own key, which a server
+# might send alongside the real chain, and a good response about
+# end.cert signed by it.  OCSP issuers must be on a chain webpki accepts.
+$at2019 openssl req -nodes \
+          -x509 \
+          -days 3650 \
+          -newkey rsa:2048 \
+          -keyout rsa/inter.decoy.key \
+          -out rsa/inter.decoy.cert \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown RSA level 2 intermediate"
+
+ocsp_response inter.decoy inter.decoy inter.decoy end.decoy.ocsp end good 10950 -resp_no_certs
+
+# a certificate for IP addresses, as well as a DNS name
+$at2019 openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end.ip.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 3650 \
+          -set_serial 1617 \
+          -extensions v3_end_ip -extfile openssl.cnf
+
+cat rsa/end.ip.cert rsa/inter.cert rsa/ca.cert > rsa/end.ip.fullchain
+
+# client certificates with SPIFFE IDs (X509-SVIDs)
+serial=1618
+for svid in web other-domain two-uris ; do
+  $at2019 openssl x509 -req \
+            -in rsa/client.req \
+            -out rsa/spiffe.$svid.cert \
+            -CA rsa/inter.cert \
+            -CAkey rsa/inter.key \
+            -sha256 \
+            -days 3650 \
+            -set_serial $serial \
+            -extensions v3_spiffe_$(echo $svid | tr - _) -extfile openssl.cnf
+
+  cat rsa/spiffe.$svid.cert rsa/inter.cert rsa/ca.cert > rsa/spiffe.$svid.fullchain
+  serial=$((serial + 1))
+done
+
+# the RSA intermediate, cross-signed by the ECDSA CA but long expired,
+# for checking pins are only matched on chains webpki accepts.
+faketime 2010-01-01 openssl x509 -req \
+          -in rsa/inter.req \
+          -out rsa/inter.expired-cross.cert \
+          -CA ecdsa/ca.cert \
+          -CAkey ecdsa/ca.key \
+          -sha256 \
+          -days 30 \
+          -set_serial 1621 \
+          -extensions v3_inter -extfile openssl.cnf
+
+# a copy of the RSA intermediate, with its name and key but a serial
+# number no CRL lists, issued by a decoy with the CA's name.  CRLs
+# must only be checked on chains webpki accepts.
+$at2019 openssl req -nodes \
+          -x509 \
+          -days 3650 \
+          -newkey rsa:2048 \
+          -keyout rsa/ca.decoy.key \
+          -out rsa/ca.decoy.cert \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown RSA CA"
+
+$at2019 openssl x509 -req \
+          -in rsa/inter.req \
+          -out rsa/inter.copy.cert \
+          -CA rsa/ca.decoy.cert \
+          -CAkey rsa/ca.decoy.key \
+          -sha256 \
+          -days 3650 \
+          -set_serial 1622 \
+          -extensions v3_inter -extfile openssl.cnf
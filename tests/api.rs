@@ -1,43 +1,49 @@
 // Assorted public API tests.
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::atomic;
 use std::fs;
-use std::io::{self, Write, Read};
+use std::time::UNIX_EPOCH;
+use std::io::{self, Write, Read, BufRead};
 
 extern crate rustls;
 
 use rustls::{ClientConfig, ClientSession, ResolvesClientCert};
-use rustls::{ServerConfig, ServerSession, ResolvesServerCert};
+use rustls::{ServerConfig, ServerSession, ResolvesServerCert, ResolvesServerAlpn};
+use rustls::Acceptor;
 use rustls::Session;
-use rustls::Stream;
-use rustls::{ProtocolVersion, SignatureScheme, CipherSuite};
+use rustls::{Stream, StreamOwned};
+use rustls::{ProtocolVersion, SignatureScheme, CipherSuite, NamedGroup};
 use rustls::TLSError;
 use rustls::sign;
 use rustls::{ALL_CIPHERSUITES, SupportedCipherSuite};
 use rustls::{Certificate, PrivateKey};
 use rustls::internal::pemfile;
 use rustls::{RootCertStore, NoClientAuth, AllowAnyAuthenticatedClient};
+use rustls::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::{Ticketer, ServerSessionMemoryCache};
+use rustls::{ProducesTickets, StoresServerSessions};
+use rustls::EarlyDataReplayCache;
+use rustls::KeyLog;
+use rustls::{ClientPskStore, ServerPskStore, ExternalPsk, PSKKeyExchangeMode};
+use rustls::{MaxFragmentLength, CertificateCompressionAlgorithm};
+use rustls::{CertificateCompressor, CertificateDecompressor};
+#[cfg(feature = "ech")]
+use rustls::ECHKey;
+use rustls::{QuicExt, QuicSecrets, AlertDescription};
+use rustls::{PadToBlockSize, PadToFullRecord, ContentType};
+use rustls::crypto::{self, CryptoProvider, SupportedKxGroup, SignatureVerifier, SecureRandom};
+use rustls::crypto::ring::RingProvider;
+use rustls::{CertRevocationList, CRLStore, CRLPolicy};
+use rustls::SPKIPinSet;
+use rustls::ServerName;
+use rustls::{AllowSPIFFEClient, SPIFFEID};
 
 extern crate webpki;
 
-fn transfer(left: &mut Session, right: &mut Session) {
-    let mut buf = [0u8; 262144];
-
-    while left.wants_write() {
-        let sz = left.write_tls(&mut buf.as_mut()).unwrap();
-        if sz == 0 {
-            return;
-        }
-
-        let mut offs = 0;
-        loop {
-            offs += right.read_tls(&mut buf[offs..sz].as_ref()).unwrap();
-            if sz == offs {
-                break;
-            }
-        }
-    }
-}
+#[allow(dead_code)]
+mod common;
+use common::{transfer, do_handshake_until_error, check_both_versions, TLSErrorFromPeer};
 
 fn get_chain() -> Vec<Certificate> {
     pemfile::certs(&mut io::BufReader::new(fs::File::open("test-ca/rsa/end.fullchain").unwrap()))
@@ -51,6 +57,19 @@ fn get_key() -> PrivateKey {
         .clone()
 }
 
+fn get_chain_for(keytype: &str) -> Vec<Certificate> {
+    let path = format!("test-ca/{}/end.fullchain", keytype);
+    pemfile::certs(&mut io::BufReader::new(fs::File::open(path).unwrap()))
+        .unwrap()
+}
+
+fn get_pkcs8_key_for(keytype: &str) -> PrivateKey {
+    let path = format!("test-ca/{}/end.key", keytype);
+    pemfile::pkcs8_private_keys(&mut io::BufReader::new(fs::File::open(path).unwrap()))
+            .unwrap()[0]
+        .clone()
+}
+
 fn make_server_config() -> ServerConfig {
     let mut cfg = ServerConfig::new(NoClientAuth::new());
     cfg.set_single_cert(get_chain(), get_key());
@@ -58,6 +77,13 @@ fn make_server_config() -> ServerConfig {
     cfg
 }
 
+fn make_server_config_for(keytype: &str) -> ServerConfig {
+    let mut cfg = ServerConfig::new(NoClientAuth::new());
+    cfg.set_single_cert(get_chain_for(keytype), get_pkcs8_key_for(keytype));
+
+    cfg
+}
+
 fn make_server_config_with_mandatory_client_auth() -> ServerConfig {
     let roots = get_chain();
     let mut client_auth_roots = RootCertStore::empty();
@@ -80,6 +106,15 @@ fn make_client_config() -> ClientConfig {
     cfg
 }
 
+fn make_client_config_for(keytype: &str) -> ClientConfig {
+    let mut cfg = ClientConfig::new();
+    let path = format!("test-ca/{}/ca.cert", keytype);
+    let mut rootbuf = io::BufReader::new(fs::File::open(path).unwrap());
+    cfg.root_store.add_pem_file(&mut rootbuf).unwrap();
+
+    cfg
+}
+
 fn do_handshake(client: &mut ClientSession, server: &mut ServerSession) {
     while server.is_handshaking() || client.is_handshaking() {
         transfer(client, server);
@@ -89,24 +124,6 @@ fn do_handshake(client: &mut ClientSession, server: &mut ServerSession) {
     }
 }
 
-#[derive(PartialEq, Debug)]
-enum TLSErrorFromPeer { Client(TLSError), Server(TLSError) }
-
-fn do_handshake_until_error(client: &mut ClientSession,
-                            server: &mut ServerSession)
-                            -> Result<(), TLSErrorFromPeer> {
-    while server.is_handshaking() || client.is_handshaking() {
-        transfer(client, server);
-        server.process_new_packets()
-            .map_err(|err| TLSErrorFromPeer::Server(err))?;
-        transfer(server, client);
-        client.process_new_packets()
-            .map_err(|err| TLSErrorFromPeer::Client(err))?;
-    }
-
-    Ok(())
-}
-
 fn dns_name(name: &'static str) -> webpki::DNSNameRef {
     webpki::DNSNameRef::try_from_ascii_str(name).unwrap()
 }
@@ -153,6 +170,65 @@ fn alpn() {
     alpn_test(vec!["PROTO".to_string()], vec!["proto".to_string()], None);
 }
 
+/// Never picks "h2" for localhost, otherwise takes the client's
+/// last choice.  Refuses clients offering "refuse-me".
+struct TenantAlpnResolver;
+
+impl ResolvesServerAlpn for TenantAlpnResolver {
+    fn resolve(&self,
+               server_name: Option<webpki::DNSNameRef>,
+               offered: &[String])
+               -> Result<Option<String>, ()> {
+        if offered.contains(&"refuse-me".to_string()) {
+            return Err(());
+        }
+
+        let name: Option<&str> = server_name.map(|n| n.into());
+        Ok(offered.iter()
+           .rev()
+           .find(|p| !(name == Some("localhost") && *p == "h2"))
+           .cloned())
+    }
+}
+
+fn alpn_resolver_test(client_protos: Vec<String>,
+                      agreed: Option<&str>) -> Result<(), TLSErrorFromPeer> {
+    let mut client_config = make_client_config();
+    let mut server_config = make_server_config();
+
+    client_config.alpn_protocols = client_protos;
+    server_config.alpn_protocols = vec!["server-proto".to_string()];
+    server_config.alpn_resolver = Some(Arc::new(TenantAlpnResolver));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    do_handshake_until_error(&mut client, &mut server)?;
+    assert_eq!(client.get_alpn_protocol(), agreed);
+    assert_eq!(server.get_alpn_protocol(), agreed);
+    Ok(())
+}
+
+#[test]
+fn alpn_resolver() {
+    // not called without client support
+    alpn_resolver_test(vec![], None).unwrap();
+
+    // chooses instead of alpn_protocols
+    alpn_resolver_test(vec!["server-proto".to_string(), "http/1.1".to_string()],
+                       Some("http/1.1")).unwrap();
+
+    // can use SNI
+    alpn_resolver_test(vec!["http/1.1".to_string(), "h2".to_string()],
+                       Some("http/1.1")).unwrap();
+    alpn_resolver_test(vec!["h2".to_string()], None).unwrap();
+
+    // can abort
+    assert_eq!(alpn_resolver_test(vec!["refuse-me".to_string()], None),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no application protocol".to_string()))));
+}
+
 fn version_test(client_versions: Vec<ProtocolVersion>,
                 server_versions: Vec<ProtocolVersion>,
                 result: Option<ProtocolVersion>) {
@@ -310,6 +386,86 @@ fn server_can_get_client_cert() {
     assert_eq!(certs, Some(get_chain()));
 }
 
+fn server_can_get_client_cert_for(keytype: &str) {
+    let mut client_auth_roots = RootCertStore::empty();
+    for root in get_chain_for(keytype) {
+        client_auth_roots.add(&root).unwrap();
+    }
+    let mut server_config = ServerConfig::new(AllowAnyAuthenticatedClient::new(client_auth_roots));
+    server_config.set_single_cert(get_chain(), get_key());
+    let server_config = Arc::new(server_config);
+
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.set_single_client_cert(get_chain_for(keytype), get_pkcs8_key_for(keytype));
+        client_config.versions = vec![*version];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+
+        do_handshake(&mut client, &mut server);
+
+        let certs = server.get_peer_certificates();
+        assert_eq!(certs, Some(get_chain_for(keytype)));
+    }
+}
+
+#[test]
+fn server_can_get_ecdsa_client_cert() {
+    server_can_get_client_cert_for("ecdsa");
+}
+
+#[test]
+fn server_can_get_eddsa_client_cert() {
+    server_can_get_client_cert_for("eddsa");
+}
+
+fn client_can_get_server_cert_for(keytype: &str) {
+    let server_config = Arc::new(make_server_config_for(keytype));
+
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config_for(keytype);
+        client_config.versions = vec![*version];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("testserver.com"));
+        let mut server = ServerSession::new(&server_config);
+
+        do_handshake(&mut client, &mut server);
+
+        // TLS1.2 clients don't retain the server's certificates.
+        if *version == ProtocolVersion::TLSv1_3 {
+            let certs = client.get_peer_certificates();
+            assert_eq!(certs, Some(get_chain_for(keytype)));
+        }
+    }
+}
+
+#[test]
+fn client_can_get_ecdsa_server_cert() {
+    client_can_get_server_cert_for("ecdsa");
+}
+
+#[test]
+fn client_can_get_eddsa_server_cert() {
+    client_can_get_server_cert_for("eddsa");
+}
+
+#[test]
+fn any_supported_type_detects_key_type() {
+    let rsa = sign::any_supported_type(&get_key()).unwrap();
+    assert_eq!(rsa.algorithm(), rustls::internal::msgs::enums::SignatureAlgorithm::RSA);
+
+    let ecdsa = sign::any_supported_type(&get_pkcs8_key_for("ecdsa")).unwrap();
+    assert_eq!(ecdsa.algorithm(), rustls::internal::msgs::enums::SignatureAlgorithm::ECDSA);
+    assert!(ecdsa.choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256]).is_none());
+
+    let eddsa = sign::any_supported_type(&get_pkcs8_key_for("eddsa")).unwrap();
+    assert_eq!(eddsa.algorithm(), rustls::internal::msgs::enums::SignatureAlgorithm::ED25519);
+    assert!(eddsa.choose_scheme(&[SignatureScheme::ED25519]).is_some());
+
+    assert!(sign::any_supported_type(&PrivateKey(vec![0u8; 32])).is_err());
+}
+
 fn check_read_and_close(reader: &mut io::Read, expect: &[u8]) {
     let mut buf = Vec::new();
     buf.resize(expect.len(), 0u8);
@@ -842,6 +998,76 @@ fn server_stream_read() {
     }
 }
 
+#[test]
+fn client_stream_owned_write() {
+    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    {
+        let pipe = OtherSession::new(&mut server);
+        let mut stream = StreamOwned::new(client, pipe);
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+        assert!(!stream.get_session().is_handshaking());
+    }
+    check_read(&mut server, b"hello");
+}
+
+#[test]
+fn client_stream_owned_read() {
+    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    server.write(b"world").unwrap();
+
+    {
+        let pipe = OtherSession::new(&mut server);
+        let mut stream = StreamOwned::new(client, pipe);
+        check_read(&mut stream, b"world");
+    }
+}
+
+#[test]
+fn server_stream_owned_buf_read() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let server = ServerSession::new(&Arc::new(make_server_config()));
+
+    client.write(b"hello\nworld\n").unwrap();
+
+    let (server, _) = {
+        let pipe = OtherSession::new(&mut client);
+        let mut stream = StreamOwned::new(server, pipe);
+
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+        assert!(stream.get_sock().reads > 0);
+        stream.into_parts()
+    };
+    assert!(!server.is_handshaking());
+
+    let mut stream = StreamOwned::new(server, io::Cursor::new(Vec::new()));
+    assert_eq!(stream.fill_buf().unwrap(), b"world\n");
+    stream.consume(6);
+    assert!(stream.sess.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn server_stream_buf_read() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    client.write(b"hello world").unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let mut stream = Stream::new(&mut server, &mut pipe);
+        let mut words = Vec::new();
+        stream.read_until(b' ', &mut words).unwrap();
+        assert_eq!(words, b"hello ".to_vec());
+    }
+    check_read(&mut server, b"world");
+}
+
 #[test]
 fn server_config_is_clone() {
     make_server_config().clone();
@@ -1055,7 +1281,7 @@ fn find_suite(suite: CipherSuite) -> &'static SupportedCipherSuite {
     panic!("find_suite given unsuppported suite");
 }
 
-static TEST_CIPHERSUITES: [(ProtocolVersion, CipherSuite); 9] = [
+static TEST_CIPHERSUITES: [(ProtocolVersion, CipherSuite); 12] = [
     (ProtocolVersion::TLSv1_3, CipherSuite::TLS13_CHACHA20_POLY1305_SHA256),
     (ProtocolVersion::TLSv1_3, CipherSuite::TLS13_AES_256_GCM_SHA384),
     (ProtocolVersion::TLSv1_3, CipherSuite::TLS13_AES_128_GCM_SHA256),
@@ -1064,9 +1290,21 @@ static TEST_CIPHERSUITES: [(ProtocolVersion, CipherSuite); 9] = [
     (ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384),
     (ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256),
     (ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384),
-    (ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256)
+    (ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256),
+    (ProtocolVersion::TLSv1_2, CipherSuite::TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256),
+    (ProtocolVersion::TLSv1_2, CipherSuite::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384),
+    (ProtocolVersion::TLSv1_2, CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256)
 ];
 
+fn enable_ffdhe_for(scs: &SupportedCipherSuite,
+                    client_config: &mut ClientConfig,
+                    server_config: &mut ServerConfig) {
+    if format!("{:?}", scs.kx) == "DHE" {
+        client_config.kx_groups.push(NamedGroup::FFDHE2048);
+        server_config.kx_groups.push(NamedGroup::FFDHE2048);
+    }
+}
+
 #[test]
 fn negotiated_ciphersuite_default() {
     do_suite_test(make_client_config(),
@@ -1085,17 +1323,24 @@ fn negotiated_ciphersuite_client() {
     for item in TEST_CIPHERSUITES.iter() {
         let (version, suite) = *item;
         let scs = find_suite(suite);
-        let mut client_config = make_client_config();
+        let is_ecdsa = format!("{:?}", scs.sign) == "ECDSA";
+        let mut client_config = if is_ecdsa {
+            make_client_config_for("ecdsa")
+        } else {
+            make_client_config()
+        };
         client_config.ciphersuites = vec![scs];
         client_config.versions = vec![version];
 
-        if format!("{:?}", scs.sign) == "ECDSA" {
-            println!("cannot test ECDSA");
-            continue;
-        }
+        let mut server_config = if is_ecdsa {
+            make_server_config_for("ecdsa")
+        } else {
+            make_server_config()
+        };
+        enable_ffdhe_for(scs, &mut client_config, &mut server_config);
 
         do_suite_test(client_config,
-                      make_server_config(),
+                      server_config,
                       scs,
                       version);
     }
@@ -1106,18 +1351,2364 @@ fn negotiated_ciphersuite_server() {
     for item in TEST_CIPHERSUITES.iter() {
         let (version, suite) = *item;
         let scs = find_suite(suite);
-        let mut server_config = make_server_config();
+        let is_ecdsa = format!("{:?}", scs.sign) == "ECDSA";
+        let mut server_config = if is_ecdsa {
+            make_server_config_for("ecdsa")
+        } else {
+            make_server_config()
+        };
         server_config.ciphersuites = vec![scs];
         server_config.versions = vec![version];
 
-        if format!("{:?}", scs.sign) == "ECDSA" {
-            println!("cannot test ECDSA");
-            continue;
-        }
+        let mut client_config = if is_ecdsa {
+            make_client_config_for("ecdsa")
+        } else {
+            make_client_config()
+        };
+        enable_ffdhe_for(scs, &mut client_config, &mut server_config);
 
-        do_suite_test(make_client_config(),
+        do_suite_test(client_config,
                       server_config,
                       scs,
                       version);
     }
 }
+
+fn make_early_data_configs(max_early_data_size: u32) -> (Arc<ClientConfig>, Arc<ServerConfig>) {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.enable_early_data = true;
+
+    let mut server_config = make_server_config();
+    server_config.ticketer = Ticketer::new();
+    server_config.max_early_data_size = max_early_data_size;
+
+    (Arc::new(client_config), Arc::new(server_config))
+}
+
+fn get_ticket_for_early_data(client_config: &Arc<ClientConfig>,
+                             server_config: &Arc<ServerConfig>) {
+    let mut client = ClientSession::new(client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(server_config);
+    assert!(client.early_data().is_none());
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+}
+
+#[test]
+fn early_data_is_accepted() {
+    let (client_config, server_config) = make_early_data_configs(1234);
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    assert_eq!(client.early_data().unwrap().bytes_left(), 1234);
+    assert_eq!(client.early_data().unwrap().write(b"hello").unwrap(), 5);
+    assert_eq!(client.early_data().unwrap().bytes_left(), 1229);
+
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert!(client.is_early_data_accepted());
+    assert!(server.is_early_data_accepted());
+    check_read(&mut server.early_data().unwrap(), b"hello");
+
+    // Normal data flows as usual afterwards.
+    client.write(b"world").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"world");
+}
+
+#[test]
+fn tls13_tickets_are_used_once_each() {
+    let (client_config, server_config) = make_early_data_configs(1234);
+
+    // Two full handshakes in parallel leave us two tickets.
+    let mut first = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut second = ClientSession::new(&client_config, dns_name("localhost"));
+    assert!(first.early_data().is_none());
+    assert!(second.early_data().is_none());
+
+    for client in &mut [&mut first, &mut second] {
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(client, &mut server);
+        transfer(&mut server, *client);
+        client.process_new_packets().unwrap();
+    }
+
+    // Each of two parallel connections gets its own; a third has none left.
+    let mut resumed = vec![ClientSession::new(&client_config, dns_name("localhost")),
+                           ClientSession::new(&client_config, dns_name("localhost"))];
+    let mut third = ClientSession::new(&client_config, dns_name("localhost"));
+    assert!(third.early_data().is_none());
+
+    for client in &mut resumed {
+        assert!(client.early_data().is_some());
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(client, &mut server);
+        assert!(server.is_early_data_accepted());
+    }
+}
+
+#[test]
+fn early_data_is_limited() {
+    let (client_config, server_config) = make_early_data_configs(4);
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    assert_eq!(client.early_data().unwrap().write(b"hello").unwrap(), 4);
+    assert_eq!(client.early_data().unwrap().bytes_left(), 0);
+
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    check_read(&mut server.early_data().unwrap(), b"hell");
+}
+
+#[test]
+fn early_data_not_offered_without_server_support() {
+    let (client_config, server_config) = make_early_data_configs(0);
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    assert!(client.early_data().is_none());
+}
+
+#[test]
+fn early_data_replay_is_rejected() {
+    let (client_config, server_config) = make_early_data_configs(1234);
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    client.early_data().unwrap().write(b"hello").unwrap();
+
+    let mut first_flight = Vec::new();
+    while client.wants_write() {
+        client.write_tls(&mut first_flight).unwrap();
+    }
+
+    // The first server to see this flight accepts the early data.
+    let mut server = ServerSession::new(&server_config);
+    server.read_tls(&mut first_flight.as_slice()).unwrap();
+    server.process_new_packets().unwrap();
+    assert!(server.is_early_data_accepted());
+
+    // A replay is rejected, but the handshake can still complete.
+    let mut server = ServerSession::new(&server_config);
+    server.read_tls(&mut first_flight.as_slice()).unwrap();
+    server.process_new_packets().unwrap();
+    assert!(!server.is_early_data_accepted());
+    assert!(server.early_data().is_none());
+
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_early_data_accepted());
+    assert!(client.early_data().is_none());
+
+    client.write(b"world").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"world");
+}
+
+#[test]
+fn early_data_refused_when_replay_cache_full() {
+    let (client_config, _) = make_early_data_configs(1234);
+    let mut server_config = make_server_config();
+    server_config.ticketer = Ticketer::new();
+    server_config.max_early_data_size = 1234;
+    server_config.early_data_replay = EarlyDataReplayCache::new(1);
+    let server_config = Arc::new(server_config);
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    // The first ClientHello with early data fills the cache.
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    client.early_data().unwrap().write(b"hello").unwrap();
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server.is_early_data_accepted());
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    // Another can't be recorded, so its early data is refused
+    // rather than forgetting the first.
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    client.early_data().unwrap().write(b"hello").unwrap();
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!server.is_early_data_accepted());
+    assert!(!client.is_early_data_accepted());
+}
+
+#[derive(Debug, PartialEq)]
+struct KeyLogItem {
+    label: String,
+    client_random: Vec<u8>,
+    secret: Vec<u8>,
+}
+
+struct KeyLogToVec {
+    items: Mutex<Vec<KeyLogItem>>,
+}
+
+impl KeyLogToVec {
+    fn new() -> Self {
+        KeyLogToVec {
+            items: Mutex::new(vec![]),
+        }
+    }
+
+    fn take(&self) -> Vec<KeyLogItem> {
+        use std::mem;
+        let mut items = self.items.lock().unwrap();
+        mem::replace(&mut *items, vec![])
+    }
+}
+
+impl KeyLog for KeyLogToVec {
+    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
+        let value = KeyLogItem {
+            label: label.into(),
+            client_random: client.into(),
+            secret: secret.into(),
+        };
+
+        self.items.lock().unwrap().push(value);
+    }
+}
+
+#[test]
+fn key_log_for_tls12() {
+    let client_key_log = Arc::new(KeyLogToVec::new());
+    let server_key_log = Arc::new(KeyLogToVec::new());
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config();
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+    assert_eq!(client_full_log, server_full_log);
+    assert_eq!(1, client_full_log.len());
+    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+    assert_eq!(48, client_full_log[0].secret.len());
+
+    // resumed
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+    assert_eq!(client_resume_log, server_resume_log);
+    assert_eq!(1, client_resume_log.len());
+    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
+    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+    assert_ne!(client_full_log[0].client_random, client_resume_log[0].client_random);
+}
+
+#[test]
+fn key_log_for_tls13() {
+    let client_key_log = Arc::new(KeyLogToVec::new());
+    let server_key_log = Arc::new(KeyLogToVec::new());
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config();
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut client_log = client_key_log.take();
+    let mut server_log = server_key_log.take();
+
+    // Each side derives the secrets in a different order.
+    client_log.sort_by(|a, b| a.label.cmp(&b.label));
+    server_log.sort_by(|a, b| a.label.cmp(&b.label));
+    assert_eq!(client_log, server_log);
+
+    let labels = client_log.iter()
+        .map(|item| item.label.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(vec!["CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                    "CLIENT_TRAFFIC_SECRET_0",
+                    "EXPORTER_SECRET",
+                    "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+                    "SERVER_TRAFFIC_SECRET_0"],
+               labels);
+
+    for item in &client_log {
+        assert_eq!(client_log[0].client_random, item.client_random);
+        assert_eq!(32, item.secret.len());
+    }
+}
+
+fn make_psk_configs(client_secret: &[u8], server_secret: &[u8]) -> (ClientConfig, ServerConfig) {
+    let mut client_psks = ClientPskStore::new();
+    client_psks.add(dns_name("localhost"), ExternalPsk::new(b"gateway-1", client_secret));
+    let mut client_config = ClientConfig::new();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.psk_store = Arc::new(client_psks);
+
+    let mut server_psks = ServerPskStore::new();
+    server_psks.add(b"gateway-1", server_secret);
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.psk_resolver = Arc::new(server_psks);
+
+    (client_config, server_config)
+}
+
+fn do_external_psk_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(Some(&b"gateway-1"[..]), client.get_psk_identity());
+    assert_eq!(Some(&b"gateway-1"[..]), server.get_psk_identity());
+    assert!(client.get_peer_certificates().is_none());
+    assert!(server.get_peer_certificates().is_none());
+
+    server.write(b"from-server!").unwrap();
+    client.write(b"from-client!").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut client, b"from-server!");
+    check_read(&mut server, b"from-client!");
+}
+
+#[test]
+fn external_psk_with_dhe() {
+    let (client_config, server_config) = make_psk_configs(b"secret", b"secret");
+    do_external_psk_test(client_config, server_config);
+}
+
+#[test]
+fn external_psk_without_dhe() {
+    let (mut client_config, mut server_config) = make_psk_configs(b"secret", b"secret");
+    client_config.psk_modes = vec![PSKKeyExchangeMode::PSK_KE];
+    server_config.psk_modes = vec![PSKKeyExchangeMode::PSK_KE];
+    do_external_psk_test(client_config, server_config);
+}
+
+#[test]
+fn external_psk_server_chooses_mode() {
+    let (mut client_config, mut server_config) = make_psk_configs(b"secret", b"secret");
+    client_config.psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE, PSKKeyExchangeMode::PSK_KE];
+    server_config.psk_modes = vec![PSKKeyExchangeMode::PSK_KE, PSKKeyExchangeMode::PSK_DHE_KE];
+    do_external_psk_test(client_config, server_config);
+}
+
+#[test]
+fn external_psk_with_wrong_secret_fails() {
+    let (client_config, server_config) = make_psk_configs(b"secret", b"other secret");
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerMisbehavedError("client sent wrong binder".to_string()))));
+}
+
+#[test]
+fn external_psk_needs_certificate_without_common_mode() {
+    let (mut client_config, server_config) = make_psk_configs(b"secret", b"secret");
+    client_config.psk_modes = vec![PSKKeyExchangeMode::PSK_KE];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::General("no server certificate chain resolved".to_string()))));
+}
+
+#[test]
+fn unknown_external_psk_falls_back_to_certificate() {
+    let mut client_psks = ClientPskStore::new();
+    client_psks.add(dns_name("localhost"), ExternalPsk::new(b"gateway-2", b"secret"));
+    let mut client_config = make_client_config();
+    client_config.psk_store = Arc::new(client_psks);
+
+    let (_, psk_server_config) = make_psk_configs(b"secret", b"secret");
+    let mut server_config = make_server_config();
+    server_config.psk_resolver = psk_server_config.psk_resolver;
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(Some(ProtocolVersion::TLSv1_3), client.get_protocol_version());
+    assert_eq!(None, client.get_psk_identity());
+    assert_eq!(None, server.get_psk_identity());
+    assert!(client.get_peer_certificates().is_some());
+}
+
+#[test]
+fn external_psk_not_used_with_sha384_suite() {
+    let (mut client_config, mut server_config) = make_psk_configs(b"secret", b"secret");
+    client_config.ciphersuites = vec![find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384)];
+    let mut rootbuf = io::BufReader::new(fs::File::open("test-ca/rsa/ca.cert").unwrap());
+    client_config.root_store.add_pem_file(&mut rootbuf).unwrap();
+    server_config.set_single_cert(get_chain(), get_key());
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(None, client.get_psk_identity());
+    assert_eq!(None, server.get_psk_identity());
+    assert!(client.get_peer_certificates().is_some());
+}
+
+/// Answers certificate requests only once `enabled` is set.
+struct ClientCertLater {
+    enabled: atomic::AtomicBool,
+}
+
+impl ClientCertLater {
+    fn new() -> ClientCertLater {
+        ClientCertLater { enabled: atomic::AtomicBool::new(false) }
+    }
+
+    fn enable(&self) {
+        self.enabled.store(true, atomic::Ordering::SeqCst);
+    }
+}
+
+impl ResolvesClientCert for ClientCertLater {
+    fn resolve(&self,
+               _acceptable_issuers: &[&[u8]],
+               _sigschemes: &[SignatureScheme])
+        -> Option<sign::CertifiedKey> {
+        if !self.enabled.load(atomic::Ordering::SeqCst) {
+            return None;
+        }
+
+        let key = sign::any_supported_type(&get_key()).unwrap();
+        Some(sign::CertifiedKey::new(get_chain(), Arc::new(key)))
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn make_server_config_with_optional_client_auth() -> ServerConfig {
+    let mut client_auth_roots = RootCertStore::empty();
+    for root in get_chain() {
+        client_auth_roots.add(&root).unwrap();
+    }
+
+    let mut cfg = ServerConfig::new(AllowAnyAnonymousOrAuthenticatedClient::new(client_auth_roots));
+    cfg.set_single_cert(get_chain(), get_key());
+    cfg
+}
+
+fn do_post_handshake_auth(client: &mut ClientSession, server: &mut ServerSession) {
+    server.request_client_certificate().unwrap();
+    transfer(server, client);
+    client.process_new_packets().unwrap();
+    transfer(client, server);
+    server.process_new_packets().unwrap();
+}
+
+#[test]
+fn server_can_request_client_cert_after_handshake() {
+    let resolver = Arc::new(ClientCertLater::new());
+    let mut client_config = make_client_config();
+    client_config.enable_post_handshake_auth = true;
+    client_config.client_auth_cert_resolver = resolver.clone();
+    let server_config = make_server_config_with_optional_client_auth();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(None, server.get_peer_certificates());
+
+    resolver.enable();
+    do_post_handshake_auth(&mut client, &mut server);
+    assert_eq!(Some(get_chain()), server.get_peer_certificates());
+
+    // Traffic still flows both ways afterwards.
+    client.write(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"hello");
+
+    server.write(b"world").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"world");
+}
+
+#[test]
+fn server_can_request_client_cert_repeatedly() {
+    let mut client_config = make_client_config();
+    client_config.enable_post_handshake_auth = true;
+    client_config.set_single_client_cert(get_chain(), get_key());
+    let server_config = make_server_config_with_optional_client_auth();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some(get_chain()), server.get_peer_certificates());
+
+    do_post_handshake_auth(&mut client, &mut server);
+    do_post_handshake_auth(&mut client, &mut server);
+    assert_eq!(Some(get_chain()), server.get_peer_certificates());
+}
+
+#[test]
+fn client_may_decline_post_handshake_auth() {
+    let mut client_config = make_client_config();
+    client_config.enable_post_handshake_auth = true;
+    let server_config = make_server_config_with_optional_client_auth();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    do_post_handshake_auth(&mut client, &mut server);
+    assert_eq!(None, server.get_peer_certificates());
+}
+
+#[test]
+fn post_handshake_auth_needs_client_support() {
+    let client_config = make_client_config();
+    let server_config = make_server_config_with_optional_client_auth();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.request_client_certificate(),
+               Err(TLSError::PeerIncompatibleError("client does not support post-handshake auth"
+                                                   .to_string())));
+}
+
+#[test]
+fn post_handshake_auth_needs_complete_tls13_handshake() {
+    let mut client_config = make_client_config();
+    client_config.enable_post_handshake_auth = true;
+    let server_config = Arc::new(make_server_config_with_optional_client_auth());
+
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server.request_client_certificate(),
+               Err(TLSError::HandshakeNotComplete));
+
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    do_handshake(&mut client, &mut server);
+    assert!(server.request_client_certificate().is_err());
+}
+
+/// Feed `client`'s first flight into a new `Acceptor`, `max` bytes
+/// at a time.
+fn accept_client_hello(client: &mut ClientSession, max: usize) -> rustls::Accepted {
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::new();
+    for chunk in buf.chunks(max) {
+        assert!(acceptor.accept().unwrap().is_none());
+        acceptor.read_tls(&mut &chunk[..]).unwrap();
+    }
+
+    acceptor.accept().unwrap().unwrap()
+}
+
+#[test]
+fn acceptor_exposes_client_hello() {
+    let mut client_config = make_client_config();
+    client_config.alpn_protocols = vec!["h2".to_string(), "http/1.1".to_string()];
+    client_config.ciphersuites = vec![find_suite(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256),
+                                      find_suite(CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256)];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let accepted = accept_client_hello(&mut client, 7);
+    assert_eq!(accepted.get_sni_hostname(), Some("localhost"));
+    assert_eq!(accepted.get_alpn_protocols(),
+               vec!["h2".to_string(), "http/1.1".to_string()]);
+    assert!(accepted.get_cipher_suites()
+            .starts_with(&[CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+                           CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256]));
+    assert!(accepted.get_sigschemes().contains(&SignatureScheme::RSA_PSS_SHA256));
+
+    let mut server = accepted.into_session(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some(ProtocolVersion::TLSv1_3), server.get_protocol_version());
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
+}
+
+#[test]
+fn acceptor_config_chosen_per_connection() {
+    let tls12_only = {
+        let mut cfg = make_server_config();
+        cfg.versions = vec![ProtocolVersion::TLSv1_2];
+        Arc::new(cfg)
+    };
+    let any_version = Arc::new(make_server_config());
+
+    for &(protos, version) in &[(&["legacy"][..], ProtocolVersion::TLSv1_2),
+                                (&["modern"][..], ProtocolVersion::TLSv1_3)] {
+        let mut client_config = make_client_config();
+        client_config.alpn_protocols = protos.iter().map(|p| p.to_string()).collect();
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+        let accepted = accept_client_hello(&mut client, 1000);
+        let config = if accepted.get_alpn_protocols().contains(&"legacy".to_string()) {
+            &tls12_only
+        } else {
+            &any_version
+        };
+
+        let mut server = accepted.into_session(config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some(version), server.get_protocol_version());
+    }
+}
+
+#[test]
+fn acceptor_session_reports_handshake_errors() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let mut server_config = make_server_config();
+    server_config.versions = vec![ProtocolVersion::TLSv1_2];
+
+    let accepted = accept_client_hello(&mut client, 1000);
+    let mut server = accepted.into_session(&Arc::new(server_config));
+    assert!(server.process_new_packets().is_err());
+    assert!(server.wants_write());
+}
+
+#[test]
+fn acceptor_rejects_non_handshake_data() {
+    let mut acceptor = Acceptor::new();
+    acceptor.read_tls(&mut &b"\x17\x03\x03\x00\x01\x00"[..]).unwrap();
+    assert_eq!(acceptor.accept().err(),
+               Some(TLSError::InappropriateMessage {
+                   expect_types: vec![rustls::internal::msgs::enums::ContentType::Handshake],
+                   got_type: rustls::internal::msgs::enums::ContentType::ApplicationData,
+               }));
+}
+
+fn record_payload_lengths(sess: &mut Session) -> Vec<usize> {
+    let mut buf = Vec::new();
+    while sess.wants_write() {
+        sess.write_tls(&mut buf).unwrap();
+    }
+
+    let mut lengths = Vec::new();
+    let mut offs = 0;
+    while offs < buf.len() {
+        let len = ((buf[offs + 3] as usize) << 8) | buf[offs + 4] as usize;
+        lengths.push(len);
+        offs += 5 + len;
+    }
+    lengths
+}
+
+fn check_record_lengths(sess: &mut Session, data: &[u8], max_fragment: usize) {
+    sess.write(data).unwrap();
+
+    // Allow for the AEAD tag, explicit nonce or TLS1.3 inner type.
+    let lengths = record_payload_lengths(sess);
+    assert!(lengths.len() > data.len() / max_fragment);
+    assert!(lengths.iter().all(|len| *len <= max_fragment + 32));
+}
+
+#[test]
+fn client_record_size_limit_shrinks_server_records() {
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        client_config.record_size_limit = Some(256);
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+
+        check_record_lengths(&mut server, &[0u8; 2000], 256);
+        check_record_lengths(&mut client, &[0u8; 2000], 16384);
+    }
+}
+
+#[test]
+fn server_record_size_limit_shrinks_client_records() {
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        client_config.record_size_limit = Some(16385);
+
+        let mut server_config = make_server_config();
+        server_config.record_size_limit = Some(512);
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+
+        client.write(&[1u8; 3000]).unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server, &[1u8; 3000]);
+
+        client.write(&[1u8; 3000]).unwrap();
+        let lengths = record_payload_lengths(&mut client);
+        assert!(lengths.iter().all(|len| *len <= 512 + 32));
+    }
+}
+
+#[test]
+fn max_fragment_length_limits_both_directions() {
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        client_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+
+        check_record_lengths(&mut server, &[0u8; 2000], 512);
+        check_record_lengths(&mut client, &[0u8; 2000], 512);
+    }
+}
+
+#[test]
+fn record_size_limit_preferred_over_max_fragment_length() {
+    let mut client_config = make_client_config();
+    client_config.record_size_limit = Some(1024);
+    client_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    check_record_lengths(&mut server, &[0u8; 3000], 1023);
+    check_record_lengths(&mut client, &[0u8; 3000], 16384);
+}
+
+#[test]
+fn invalid_max_fragment_length_is_refused() {
+    let mut client_config = make_client_config();
+    client_config.max_fragment_length = Some(MaxFragmentLength::Unknown(9));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err,
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerMisbehavedError("client sent invalid max_fragment_length"
+                                                 .to_string()))));
+}
+
+#[test]
+fn server_record_size_limit_waits_for_end_of_early_data() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.enable_early_data = true;
+    client_config.record_size_limit = Some(16385);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config();
+    server_config.ticketer = Ticketer::new();
+    server_config.max_early_data_size = 4096;
+    server_config.record_size_limit = Some(256);
+    let server_config = Arc::new(server_config);
+
+    get_ticket_for_early_data(&client_config, &server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    assert_eq!(client.early_data().unwrap().write(&[2u8; 1000]).unwrap(), 1000);
+
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server.is_early_data_accepted());
+    check_read(&mut server.early_data().unwrap(), &[2u8; 1000]);
+
+    client.write(&[3u8; 1000]).unwrap();
+    let lengths = record_payload_lengths(&mut client);
+    assert!(lengths.iter().all(|len| *len <= 256 + 32));
+}
+
+struct CountingCompression {
+    alg: CertificateCompressionAlgorithm,
+    compressed: atomic::AtomicUsize,
+    decompressed: atomic::AtomicUsize,
+    fail_decompress: bool,
+}
+
+impl CountingCompression {
+    fn new(fail_decompress: bool) -> Arc<CountingCompression> {
+        Arc::new(CountingCompression {
+            alg: CertificateCompressionAlgorithm::Unknown(0xfe00),
+            compressed: atomic::AtomicUsize::new(0),
+            decompressed: atomic::AtomicUsize::new(0),
+            fail_decompress: fail_decompress,
+        })
+    }
+}
+
+// "Compresses" by reversing the input.
+impl CertificateCompressor for CountingCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        self.alg
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, ()> {
+        self.compressed.fetch_add(1, atomic::Ordering::SeqCst);
+        Ok(input.iter().rev().cloned().collect())
+    }
+}
+
+impl CertificateDecompressor for CountingCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        self.alg
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), ()> {
+        self.decompressed.fetch_add(1, atomic::Ordering::SeqCst);
+        if self.fail_decompress || input.len() != output.len() {
+            return Err(());
+        }
+
+        for (out, byte) in output.iter_mut().zip(input.iter().rev()) {
+            *out = *byte;
+        }
+        Ok(())
+    }
+}
+
+fn make_compression_configs(version: ProtocolVersion,
+                            compression: &Arc<CountingCompression>)
+                            -> (ClientConfig, ServerConfig) {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![version];
+    client_config.certificate_decompressors = vec![compression.clone()];
+
+    let mut server_config = make_server_config();
+    server_config.certificate_compressors = vec![compression.clone()];
+
+    (client_config, server_config)
+}
+
+#[test]
+fn certificate_compression_with_custom_algorithm() {
+    let compression = CountingCompression::new(false);
+    let (client_config, server_config) = make_compression_configs(ProtocolVersion::TLSv1_3,
+                                                                  &compression);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(compression.compressed.load(atomic::Ordering::SeqCst), 1);
+    assert_eq!(compression.decompressed.load(atomic::Ordering::SeqCst), 1);
+    assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+}
+
+#[test]
+fn certificate_compression_needs_client_support() {
+    let compression = CountingCompression::new(false);
+    let (mut client_config, server_config) = make_compression_configs(ProtocolVersion::TLSv1_3,
+                                                                      &compression);
+    client_config.certificate_decompressors = Vec::new();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(compression.compressed.load(atomic::Ordering::SeqCst), 0);
+}
+
+#[test]
+fn certificate_compression_not_used_for_tls12() {
+    let compression = CountingCompression::new(false);
+    let (client_config, server_config) = make_compression_configs(ProtocolVersion::TLSv1_2,
+                                                                  &compression);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(compression.compressed.load(atomic::Ordering::SeqCst), 0);
+    assert_eq!(compression.decompressed.load(atomic::Ordering::SeqCst), 0);
+}
+
+#[test]
+fn certificate_decompression_failure_is_fatal() {
+    let compression = CountingCompression::new(true);
+    let (client_config, server_config) = make_compression_configs(ProtocolVersion::TLSv1_3,
+                                                                  &compression);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err,
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::PeerMisbehavedError("server sent bad compressed certificate"
+                                                 .to_string()))));
+}
+
+#[cfg(feature = "zlib")]
+#[test]
+fn certificate_compression_with_zlib() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.certificate_decompressors = vec![Arc::new(rustls::ZlibCertificateCompression)];
+
+    let mut server_config = make_server_config();
+    server_config.certificate_compressors = vec![Arc::new(rustls::ZlibCertificateCompression)];
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+}
+
+#[cfg(feature = "ech")]
+fn make_ech_key(config_id: u8) -> ECHKey {
+    ECHKey::generate(config_id, dns_name("testserver.com"), RingProvider.secure_random())
+}
+
+#[cfg(feature = "ech")]
+fn make_ech_configs(server_key: ECHKey, client_key: ECHKey) -> (ClientConfig, ServerConfig) {
+    let mut server_config = make_server_config();
+    server_config.ech_keys = vec![server_key];
+
+    let mut offered_config = make_server_config();
+    offered_config.ech_keys = vec![client_key];
+
+    let mut client_config = make_client_config();
+    client_config.set_ech_config_list(&offered_config.get_ech_config_list())
+        .unwrap();
+
+    (client_config, server_config)
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_accepted() {
+    let key = make_ech_key(1);
+    let (client_config, server_config) = make_ech_configs(key.clone(), key);
+
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.get_sni_hostname(), Some("second.testserver.com"));
+    assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+    server.write_all(b"hello").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"hello");
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_key_survives_reload() {
+    let key = make_ech_key(7);
+    let reloaded = ECHKey::new(7, dns_name("testserver.com"), &key.get_private_key())
+        .unwrap();
+    let (client_config, server_config) = make_ech_configs(reloaded, key);
+
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.get_sni_hostname(), Some("second.testserver.com"));
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_rejected_gives_retry_configs() {
+    let (mut client_config, server_config) =
+        make_ech_configs(make_ech_key(1), make_ech_key(1));
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientSession::new(&Arc::new(client_config.clone()),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    let retry_configs = server_config.get_ech_config_list();
+    assert_eq!(err,
+               Err(TLSErrorFromPeer::Client(TLSError::ECHRejected(Some(retry_configs.clone())))));
+    assert_eq!(server.get_sni_hostname(), Some("testserver.com"));
+
+    client_config.set_ech_config_list(&retry_configs)
+        .unwrap();
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.get_sni_hostname(), Some("second.testserver.com"));
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_needs_tls13() {
+    let key = make_ech_key(1);
+    let (client_config, mut server_config) = make_ech_configs(key.clone(), key);
+    server_config.versions = vec![ProtocolVersion::TLSv1_2];
+
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err, Err(TLSErrorFromPeer::Client(TLSError::ECHRejected(None))));
+}
+
+#[test]
+fn unusable_ech_config_list_is_refused() {
+    let mut client_config = make_client_config();
+    assert!(client_config.set_ech_config_list(&[]).is_err());
+    assert!(client_config.set_ech_config_list(&[0x00, 0x04, 0xfe, 0x0a, 0x00, 0x00]).is_err());
+}
+
+fn make_quic_configs() -> (Arc<ClientConfig>, Arc<ServerConfig>) {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    let mut server_config = make_server_config();
+    server_config.versions = vec![ProtocolVersion::TLSv1_3];
+    (Arc::new(client_config), Arc::new(server_config))
+}
+
+/// Give `right` any handshake data `left` has for it, returning the
+/// secrets `left` says to use next.
+fn quic_step(left: &mut QuicExt, right: &mut QuicExt) -> Result<Option<QuicSecrets>, TLSError> {
+    let mut buf = Vec::new();
+    let secrets = left.write_hs(&mut buf);
+    if !buf.is_empty() {
+        right.read_hs(&buf)?;
+    }
+    Ok(secrets)
+}
+
+#[test]
+fn quic_handshake() {
+    let (client_config, server_config) = make_quic_configs();
+    let mut client = ClientSession::new_quic(&client_config, dns_name("localhost"),
+                                             b"client params".to_vec());
+    let mut server = ServerSession::new_quic(&server_config, b"server params".to_vec());
+
+    // ClientHello
+    assert!(quic_step(&mut client, &mut server).unwrap().is_none());
+    // ServerHello
+    let server_hs = quic_step(&mut server, &mut client).unwrap().unwrap();
+    let client_hs = quic_step(&mut client, &mut server).unwrap().unwrap();
+    assert_eq!(client_hs.client, server_hs.client);
+    assert_eq!(client_hs.server, server_hs.server);
+    // EncryptedExtensions through server Finished
+    let server_1rtt = quic_step(&mut server, &mut client).unwrap().unwrap();
+    // Client Finished
+    let client_1rtt = quic_step(&mut client, &mut server).unwrap().unwrap();
+    assert_eq!(client_1rtt.client, server_1rtt.client);
+    assert_eq!(client_1rtt.server, server_1rtt.server);
+    assert_ne!(client_1rtt.client, client_hs.client);
+    assert_ne!(client_1rtt.server, client_hs.server);
+    // Any tickets go at 1-RTT
+    assert!(quic_step(&mut server, &mut client).unwrap().is_none());
+
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert_eq!(client.get_quic_transport_parameters(), Some(&b"server params"[..]));
+    assert_eq!(server.get_quic_transport_parameters(), Some(&b"client params"[..]));
+
+    // Nothing went via TLS records, including middlebox-compat CCS.
+    assert!(!client.wants_write());
+    assert!(!server.wants_write());
+}
+
+#[test]
+fn quic_server_requires_transport_parameters() {
+    let (client_config, server_config) = make_quic_configs();
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new_quic(&server_config, b"server params".to_vec());
+
+    // Take the ClientHello out of its TLS record.
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let err = server.read_hs(&buf[5..]);
+    let msg = "client did not send quic transport parameters".to_string();
+    assert_eq!(err, Err(TLSError::PeerMisbehavedError(msg)));
+    assert_eq!(server.get_alert(), Some(AlertDescription::MissingExtension));
+    assert!(!server.wants_write());
+}
+
+#[test]
+fn quic_server_refuses_middlebox_compatibility_mode() {
+    use rustls::internal::msgs::codec::Codec;
+    use rustls::internal::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+    use rustls::internal::msgs::handshake::SessionID;
+
+    let (client_config, server_config) = make_quic_configs();
+    let mut client = ClientSession::new_quic(&client_config, dns_name("localhost"),
+                                             b"client params".to_vec());
+    let mut server = ServerSession::new_quic(&server_config, b"server params".to_vec());
+
+    let mut buf = Vec::new();
+    client.write_hs(&mut buf);
+    let mut hello = HandshakeMessagePayload::read_bytes(&buf).unwrap();
+    if let HandshakePayload::ClientHello(ref mut ch) = hello.payload {
+        ch.session_id = SessionID::new(&[0x5a; 32]);
+    }
+
+    let err = server.read_hs(&hello.get_encoding());
+    assert_eq!(err, Err(TLSError::PeerMisbehavedError("client sent session id in quic"
+                                                      .to_string())));
+    assert_eq!(server.get_alert(), Some(AlertDescription::IllegalParameter));
+}
+
+#[test]
+#[should_panic]
+fn quic_needs_tls13_only() {
+    let client_config = Arc::new(make_client_config());
+    ClientSession::new_quic(&client_config, dns_name("localhost"), Vec::new());
+}
+
+fn exchange_data(client: &mut ClientSession, server: &mut ServerSession) {
+    client.write(b"hello").unwrap();
+    transfer(client, server);
+    server.process_new_packets().unwrap();
+    check_read(server, b"hello");
+
+    server.write(b"world").unwrap();
+    transfer(server, client);
+    client.process_new_packets().unwrap();
+    check_read(client, b"world");
+}
+
+#[test]
+fn refresh_traffic_keys_needs_complete_handshake() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    assert_eq!(client.refresh_traffic_keys(false), Err(TLSError::HandshakeNotComplete));
+    assert_eq!(server.refresh_traffic_keys(true), Err(TLSError::HandshakeNotComplete));
+}
+
+#[test]
+fn refresh_traffic_keys_needs_tls13() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    assert!(client.refresh_traffic_keys(false).is_err());
+    assert!(server.refresh_traffic_keys(false).is_err());
+    exchange_data(&mut client, &mut server);
+}
+
+#[test]
+fn client_can_refresh_traffic_keys() {
+    for request_peer in &[false, true] {
+        let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+
+        client.refresh_traffic_keys(*request_peer).unwrap();
+        assert!(client.wants_write());
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        // Any requested update goes out with the peer's next write.
+        assert!(!server.wants_write());
+
+        exchange_data(&mut client, &mut server);
+        exchange_data(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn server_can_refresh_traffic_keys() {
+    for request_peer in &[false, true] {
+        let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+
+        server.refresh_traffic_keys(*request_peer).unwrap();
+        assert!(server.wants_write());
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        // Any requested update goes out with the peer's next write.
+        assert!(!client.wants_write());
+
+        exchange_data(&mut client, &mut server);
+        exchange_data(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn refresh_traffic_keys_repeatedly() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    for _ in 0..5 {
+        client.refresh_traffic_keys(true).unwrap();
+        server.refresh_traffic_keys(false).unwrap();
+        exchange_data(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn tls13_records_padded_to_block_size() {
+    let mut client_config = make_client_config();
+    client_config.record_padding = Arc::new(PadToBlockSize(256));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    // Content and padding, then the content type and tag.
+    client.write(&[1u8; 10]).unwrap();
+    client.write(&[1u8; 300]).unwrap();
+    assert_eq!(record_payload_lengths(&mut client), vec![256 + 17, 512 + 17]);
+
+    server.write(&[1u8; 10]).unwrap();
+    assert_eq!(record_payload_lengths(&mut server), vec![10 + 17]);
+}
+
+#[test]
+fn padded_records_are_readable() {
+    let mut client_config = make_client_config();
+    client_config.record_padding = Arc::new(PadToBlockSize(1000));
+    let mut server_config = make_server_config();
+    server_config.record_padding = Arc::new(PadToFullRecord);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    client.write(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"hello");
+
+    server.write(b"world").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"world");
+}
+
+#[test]
+fn full_record_padding_respects_record_size_limit() {
+    let mut client_config = make_client_config();
+    client_config.record_size_limit = Some(256);
+    let mut server_config = make_server_config();
+    server_config.record_padding = Arc::new(PadToFullRecord);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    // The limit covers the content, padding and content type.
+    server.write(&[1u8; 300]).unwrap();
+    assert_eq!(record_payload_lengths(&mut server), vec![256 + 16, 256 + 16]);
+}
+
+#[test]
+fn record_padding_by_closure() {
+    let mut client_config = make_client_config();
+    client_config.record_padding = Arc::new(|typ, len| {
+        if typ == ContentType::ApplicationData { 100 - len } else { 0 }
+    });
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    client.write(&[1u8; 40]).unwrap();
+    assert_eq!(record_payload_lengths(&mut client), vec![100 + 17]);
+
+    client.send_close_notify();
+    assert_eq!(record_payload_lengths(&mut client), vec![2 + 17]);
+}
+
+#[test]
+fn tls12_records_not_padded() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    client_config.record_padding = Arc::new(PadToFullRecord);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    client.write(b"hello").unwrap();
+    let lengths = record_payload_lengths(&mut client);
+    assert_eq!(lengths.len(), 1);
+    assert!(lengths[0] < 100);
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+}
+
+#[test]
+fn tls13_ffdhe_key_exchange() {
+    let mut client_config = make_client_config();
+    client_config.kx_groups.push(NamedGroup::FFDHE2048);
+    let mut server_config = make_server_config();
+    server_config.kx_groups = vec![NamedGroup::FFDHE2048];
+
+    // The server asks for an FFDHE key share with a HelloRetryRequest.
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+    client.write(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"hello");
+}
+
+#[test]
+fn ffdhe_needs_enabling_on_client() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    client_config.ciphersuites = vec![find_suite(CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256)];
+    let mut server_config = make_server_config();
+    server_config.kx_groups.push(NamedGroup::FFDHE2048);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no ciphersuites in common".to_string()))));
+}
+
+#[test]
+fn dhe_not_chosen_without_common_ffdhe_group() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    client_config.kx_groups.push(NamedGroup::FFDHE3072);
+    client_config.ciphersuites = vec![
+        find_suite(CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256),
+        find_suite(CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256),
+    ];
+
+    for server_groups in &[vec![], vec![NamedGroup::FFDHE2048]] {
+        let mut server_config = make_server_config();
+        server_config.kx_groups.extend_from_slice(server_groups);
+
+        let mut client = ClientSession::new(&Arc::new(client_config.clone()), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite,
+                   CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256);
+    }
+}
+
+#[test]
+fn tls12_dhe_with_larger_group() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    client_config.kx_groups = vec![NamedGroup::FFDHE3072];
+    let mut server_config = make_server_config();
+    server_config.kx_groups = vec![NamedGroup::FFDHE2048, NamedGroup::FFDHE3072];
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite,
+               CipherSuite::TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256);
+
+    server.write(b"world").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"world");
+}
+
+fn offered_groups(accepted: &rustls::Accepted) -> (Vec<NamedGroup>, Vec<NamedGroup>) {
+    let hello = accepted.client_hello();
+    let groups = hello.get_namedgroups_extension().unwrap().clone();
+    let shares = hello.get_keyshare_extension().unwrap()
+        .iter()
+        .map(|share| share.group)
+        .collect();
+    (groups, shares)
+}
+
+#[test]
+fn client_kx_groups_drive_client_hello() {
+    let mut client_config = make_client_config();
+    client_config.kx_groups = vec![NamedGroup::secp384r1, NamedGroup::secp256r1];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let accepted = accept_client_hello(&mut client, 16384);
+    assert_eq!(offered_groups(&accepted),
+               (vec![NamedGroup::secp384r1, NamedGroup::secp256r1],
+                vec![NamedGroup::secp384r1]));
+
+    let mut server = accepted.into_session(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn client_ignores_unsupported_kx_groups() {
+    let mut client_config = make_client_config();
+    client_config.kx_groups = vec![NamedGroup::secp521r1, NamedGroup::secp256r1];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let accepted = accept_client_hello(&mut client, 16384);
+    assert_eq!(offered_groups(&accepted),
+               (vec![NamedGroup::secp256r1], vec![NamedGroup::secp256r1]));
+}
+
+#[test]
+fn client_key_share_follows_kx_hint_only_if_still_supported() {
+    let mut client_config = make_client_config();
+    let mut server_config = make_server_config();
+    server_config.kx_groups = vec![NamedGroup::secp256r1];
+    let server_config = Arc::new(server_config);
+
+    // The first handshake needs a retry, and leaves a hint.
+    let client_config = Arc::new(client_config.clone());
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let accepted = accept_client_hello(&mut client, 16384);
+    assert_eq!(offered_groups(&accepted).1, vec![NamedGroup::secp256r1]);
+
+    // Without P-256, the hint is ignored.
+    let mut restricted_config = (*client_config).clone();
+    restricted_config.kx_groups = vec![NamedGroup::X25519, NamedGroup::secp384r1];
+    let mut client = ClientSession::new(&Arc::new(restricted_config), dns_name("localhost"));
+    let accepted = accept_client_hello(&mut client, 16384);
+    assert_eq!(offered_groups(&accepted).1, vec![NamedGroup::X25519]);
+}
+
+#[test]
+fn server_kx_groups_choose_group() {
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        client_config.kx_groups = vec![NamedGroup::X25519, NamedGroup::secp384r1];
+        let mut server_config = make_server_config();
+        server_config.kx_groups = vec![NamedGroup::secp256r1, NamedGroup::secp384r1];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_protocol_version(), Some(*version));
+    }
+}
+
+#[test]
+fn no_kx_groups_in_common() {
+    let mut client_config = make_client_config();
+    client_config.kx_groups = vec![NamedGroup::secp384r1];
+    let mut server_config = make_server_config();
+    server_config.kx_groups = vec![NamedGroup::secp256r1];
+
+    let mut client = ClientSession::new(&Arc::new(client_config.clone()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config.clone()));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no kx group overlap with client".to_string()))));
+
+    // In TLS1.2 none of the ECDHE suites are usable.
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no ciphersuites in common".to_string()))));
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_accepted_after_retry() {
+    let key = make_ech_key(1);
+    let (client_config, mut server_config) = make_ech_configs(key.clone(), key);
+    server_config.kx_groups = vec![NamedGroup::secp384r1];
+
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.get_sni_hostname(), Some("second.testserver.com"));
+    assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+    server.write_all(b"hello").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"hello");
+}
+
+struct CountingProvider {
+    inner: RingProvider,
+    suites: Vec<&'static SupportedCipherSuite>,
+    randoms: atomic::AtomicUsize,
+    verifies: atomic::AtomicUsize,
+}
+
+impl CountingProvider {
+    fn new(suites: Vec<&'static SupportedCipherSuite>) -> CountingProvider {
+        CountingProvider {
+            inner: RingProvider,
+            suites: suites,
+            randoms: atomic::AtomicUsize::new(0),
+            verifies: atomic::AtomicUsize::new(0),
+        }
+    }
+
+    fn counts(&self) -> (usize, usize) {
+        (self.randoms.load(atomic::Ordering::SeqCst),
+         self.verifies.load(atomic::Ordering::SeqCst))
+    }
+}
+
+impl CryptoProvider for CountingProvider {
+    fn cipher_suites(&self) -> Vec<&'static SupportedCipherSuite> {
+        self.suites.clone()
+    }
+
+    fn kx_groups(&self) -> Vec<&'static SupportedKxGroup> {
+        vec![&crypto::ring::X25519]
+    }
+
+    fn signature_verifier(&self) -> &SignatureVerifier {
+        self
+    }
+
+    fn secure_random(&self) -> &SecureRandom {
+        self
+    }
+}
+
+impl SignatureVerifier for CountingProvider {
+    fn supported_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.signature_verifier().supported_schemes()
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError> {
+        self.verifies.fetch_add(1, atomic::Ordering::SeqCst);
+        self.inner.signature_verifier()
+            .verify_tls12_signature(message, cert, scheme, signature)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              signature: &[u8]) -> Result<(), TLSError> {
+        self.verifies.fetch_add(1, atomic::Ordering::SeqCst);
+        self.inner.signature_verifier()
+            .verify_tls13_signature(message, cert, scheme, signature)
+    }
+}
+
+impl SecureRandom for CountingProvider {
+    fn fill(&self, bytes: &mut [u8]) -> Result<(), TLSError> {
+        self.randoms.fetch_add(1, atomic::Ordering::SeqCst);
+        self.inner.secure_random().fill(bytes)
+    }
+}
+
+fn make_provider_configs(client_provider: &Arc<CountingProvider>,
+                         server_provider: &Arc<CountingProvider>)
+                         -> (ClientConfig, ServerConfig) {
+    let mut client_config = ClientConfig::with_provider(client_provider.clone());
+    let mut rootbuf = io::BufReader::new(fs::File::open("test-ca/rsa/ca.cert").unwrap());
+    client_config.root_store.add_pem_file(&mut rootbuf).unwrap();
+
+    let mut server_config = ServerConfig::with_provider(NoClientAuth::new(),
+                                                       server_provider.clone());
+    server_config.set_single_cert(get_chain(), get_key());
+    (client_config, server_config)
+}
+
+#[test]
+fn default_provider_is_ring() {
+    let client_config = ClientConfig::new();
+    assert_eq!(client_config.ciphersuites, RingProvider.cipher_suites());
+    let server_config = ServerConfig::new(NoClientAuth::new());
+    assert_eq!(server_config.ciphersuites, RingProvider.cipher_suites());
+}
+
+#[test]
+fn handshake_uses_config_provider() {
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let suites = vec![find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
+                          find_suite(CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256)];
+        let client_provider = Arc::new(CountingProvider::new(suites.clone()));
+        let server_provider = Arc::new(CountingProvider::new(suites));
+        let (mut client_config, server_config) =
+            make_provider_configs(&client_provider, &server_provider);
+        client_config.versions = vec![*version];
+        client_config.kx_groups = vec![NamedGroup::X25519, NamedGroup::secp256r1];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+
+        let expect_suite = match *version {
+            ProtocolVersion::TLSv1_3 => CipherSuite::TLS13_AES_256_GCM_SHA384,
+            _ => CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        };
+        assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite, expect_suite);
+
+        // The client verified the server's signature, and both
+        // sides got their randoms from their own provider.
+        let (client_randoms, client_verifies) = client_provider.counts();
+        let (server_randoms, server_verifies) = server_provider.counts();
+        assert!(client_randoms > 0);
+        assert_eq!(client_verifies, 1);
+        assert!(server_randoms > 0);
+        assert_eq!(server_verifies, 0);
+    }
+}
+
+#[cfg(feature = "ech")]
+#[test]
+fn encrypted_client_hello_uses_config_provider() {
+    let count_client_randoms = |with_ech: bool| {
+        let suites = vec![find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384)];
+        let client_provider = Arc::new(CountingProvider::new(suites.clone()));
+        let server_provider = Arc::new(CountingProvider::new(suites));
+        let (mut client_config, mut server_config) =
+            make_provider_configs(&client_provider, &server_provider);
+
+        let key = ECHKey::generate(1, dns_name("testserver.com"),
+                                   server_provider.secure_random());
+        assert_eq!(server_provider.counts().0, 1);
+        server_config.ech_keys = vec![key];
+        if with_ech {
+            client_config.set_ech_config_list(&server_config.get_ech_config_list())
+                .unwrap();
+        }
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+        client_provider.counts().0
+    };
+
+    // The outer ClientHello's random comes from the provider too.
+    assert!(count_client_randoms(true) > count_client_randoms(false));
+}
+
+#[test]
+fn session_ids_and_tickets_use_provider() {
+    let suites = vec![find_suite(CipherSuite::TLS13_AES_128_GCM_SHA256)];
+    let provider = Arc::new(CountingProvider::new(suites));
+
+    let cache = ServerSessionMemoryCache::with_provider(4, provider.clone());
+    assert_eq!(cache.generate().len(), 32);
+    assert_eq!(provider.counts().0, 1);
+
+    // One random for the key, then one for each ticket's nonce.
+    let ticketer = Ticketer::with_provider(provider.clone());
+    assert_eq!(provider.counts().0, 2);
+    let ticket = ticketer.encrypt(b"hello").unwrap();
+    assert_eq!(provider.counts().0, 3);
+
+    // Tickets are sealed with the provider's AES-128-GCM: the nonce
+    // and tag add 28 bytes.
+    assert_eq!(ticket.len(), 5 + 12 + 16);
+    assert_eq!(ticketer.decrypt(&ticket), Some(b"hello".to_vec()));
+    assert_eq!(ticketer.decrypt(&ticket[1..]), None);
+}
+
+#[test]
+fn provider_kx_groups_limit_config_kx_groups() {
+    let suites = vec![find_suite(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256)];
+    let client_provider = Arc::new(CountingProvider::new(suites.clone()));
+    let server_provider = Arc::new(CountingProvider::new(suites));
+    let (mut client_config, server_config) =
+        make_provider_configs(&client_provider, &server_provider);
+
+    // secp384r1 isn't implemented by this provider, so isn't offered.
+    client_config.kx_groups = vec![NamedGroup::secp384r1, NamedGroup::X25519];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let accepted = accept_client_hello(&mut client, 16384);
+    assert_eq!(offered_groups(&accepted),
+               (vec![NamedGroup::X25519], vec![NamedGroup::X25519]));
+
+    let mut server = accepted.into_session(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+}
+
+fn load_crls(names: &[&str]) -> Arc<CRLStore> {
+    let crls = CRLStore::empty();
+    for name in names {
+        let path = format!("test-ca/rsa/{}", name);
+        let mut rd = io::BufReader::new(fs::File::open(path).unwrap());
+        assert_eq!(crls.add_pem_file(&mut rd), Ok((1, 0)));
+    }
+    Arc::new(crls)
+}
+
+fn crl_policy(allow_missing: bool, allow_expired: bool) -> CRLPolicy {
+    CRLPolicy {
+        allow_missing: allow_missing,
+        allow_expired: allow_expired,
+    }
+}
+
+fn server_check_with_crls(crls: &Arc<CRLStore>, policy: CRLPolicy)
+                          -> Result<(), TLSErrorFromPeer> {
+    let mut client_config = make_client_config();
+    client_config.set_crls(crls.clone(), policy);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake_until_error(&mut client, &mut server)
+}
+
+#[test]
+fn client_accepts_server_with_current_crls() {
+    let crls = load_crls(&["ca.crl", "inter.crl"]);
+    assert_eq!(crls.len(), 2);
+    assert_eq!(server_check_with_crls(&crls, CRLPolicy::default()), Ok(()));
+}
+
+#[test]
+fn client_rejects_revoked_server_cert() {
+    let crls = load_crls(&["ca.crl", "inter.revoked.crl"]);
+
+    let mut client_config = make_client_config();
+    client_config.set_crls(crls, CRLPolicy::default());
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::AlertReceived(AlertDescription::CertificateRevoked)));
+}
+
+#[test]
+fn client_rejects_revoked_intermediate() {
+    let crls = load_crls(&["ca.revoked.crl", "inter.crl"]);
+    assert_eq!(server_check_with_crls(&crls, CRLPolicy::default()),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+}
+
+#[test]
+fn client_rejects_revoked_intermediate_sent_with_copy() {
+    // The server sends, ahead of its revoked intermediate, a copy with
+    // the same name and key but a serial number no CRL lists.  The copy
+    // was issued by a decoy CA, so isn't on any chain webpki accepts.
+    let crls = load_crls(&["ca.revoked.crl", "inter.crl"]);
+    let chain = get_chain();
+    let presented = vec![chain[0].clone(),
+                         load_chain("inter.copy.cert")[0].clone(),
+                         chain[1].clone(),
+                         chain[2].clone()];
+
+    assert_eq!(check_both_versions(|| {
+                                       let mut cfg = make_client_config();
+                                       cfg.set_crls(crls.clone(), CRLPolicy::default());
+                                       cfg
+                                   },
+                                   || {
+                                       let mut cfg = ServerConfig::new(NoClientAuth::new());
+                                       cfg.set_single_cert(presented.clone(), get_key());
+                                       cfg
+                                   },
+                                   dns_name("localhost").into(),
+                                   |_| ()),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+}
+
+#[test]
+fn missing_crl_policy() {
+    let crls = load_crls(&["inter.crl"]);
+    assert_eq!(server_check_with_crls(&crls, CRLPolicy::default()),
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::UnknownRevocationStatus("no CRL for issuer".to_string()))));
+    assert_eq!(server_check_with_crls(&crls, crl_policy(true, false)), Ok(()));
+
+    // Allowing missing CRLs doesn't allow revoked certificates.
+    let crls = load_crls(&["inter.revoked.crl"]);
+    assert_eq!(server_check_with_crls(&crls, crl_policy(true, false)),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+}
+
+#[test]
+fn expired_crl_policy() {
+    let crls = load_crls(&["ca.crl", "inter.expired.crl"]);
+    assert_eq!(server_check_with_crls(&crls, CRLPolicy::default()),
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::UnknownRevocationStatus("CRL has expired".to_string()))));
+    assert_eq!(server_check_with_crls(&crls, crl_policy(false, true)), Ok(()));
+}
+
+#[test]
+fn crl_with_bad_signature_is_rejected() {
+    let mut der = pemfile::crls(&mut io::BufReader::new(
+        fs::File::open("test-ca/rsa/inter.crl").unwrap())).unwrap().pop().unwrap();
+    *der.last_mut().unwrap() ^= 0x01;
+
+    let crls = Arc::new(CRLStore::empty());
+    crls.add(CertRevocationList::from_der(&der).unwrap());
+    crls.add_der(&load_pem_der("ca.crl")).unwrap();
+    assert_eq!(server_check_with_crls(&crls, crl_policy(true, true)),
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::UnknownRevocationStatus("CRL has invalid signature".to_string()))));
+
+    assert_eq!(CertRevocationList::from_der(&der[1..]).err(),
+               Some(TLSError::InvalidCertRevocationList));
+}
+
+fn load_pem_der(name: &str) -> Vec<u8> {
+    let path = format!("test-ca/rsa/{}", name);
+    pemfile::crls(&mut io::BufReader::new(fs::File::open(path).unwrap()))
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+#[test]
+fn server_rejects_revoked_client_cert() {
+    let mut client_auth_roots = RootCertStore::empty();
+    for root in get_chain() {
+        client_auth_roots.add(&root).unwrap();
+    }
+
+    let crls = load_crls(&["ca.crl", "inter.revoked.crl"]);
+    let client_auth = AllowAnyAuthenticatedClient::with_crls(client_auth_roots,
+                                                             crls.clone(),
+                                                             CRLPolicy::default());
+    let mut server_config = ServerConfig::new(client_auth);
+    server_config.set_single_cert(get_chain(), get_key());
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key());
+    let client_config = Arc::new(client_config);
+
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let mut client_config = (*client_config).clone();
+        client_config.versions = vec![*version];
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Server(TLSError::CertRevoked)));
+
+        transfer(&mut server, &mut client);
+        let err = client.process_new_packets().unwrap_err();
+        assert_eq!(err, TLSError::AlertReceived(AlertDescription::CertificateRevoked));
+    }
+
+    // The CRLs can be changed without making a new config.
+    crls.add_der(&load_pem_der("inter.crl")).unwrap();
+    assert_eq!(crls.len(), 2);
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+
+    crls.replace_all(vec![]);
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::UnknownRevocationStatus("no CRL for issuer".to_string()))));
+}
+
+fn load_ocsp(name: &str) -> Vec<u8> {
+    let mut der = Vec::new();
+    fs::File::open(format!("test-ca/rsa/{}", name))
+        .unwrap()
+        .read_to_end(&mut der)
+        .unwrap();
+    der
+}
+
+fn load_chain(name: &str) -> Vec<Certificate> {
+    let path = format!("test-ca/rsa/{}", name);
+    pemfile::certs(&mut io::BufReader::new(fs::File::open(path).unwrap()))
+        .unwrap()
+}
+
+fn server_check_with_ocsp(chain: Vec<Certificate>, ocsp: Vec<u8>,
+                          enforce_must_staple: bool) -> Result<(), TLSErrorFromPeer> {
+    check_both_versions(|| {
+                            let mut cfg = make_client_config();
+                            cfg.enforce_ocsp_must_staple = enforce_must_staple;
+                            cfg
+                        },
+                        || {
+                            let mut cfg = ServerConfig::new(NoClientAuth::new());
+                            cfg.set_single_cert_with_ocsp_and_sct(chain.clone(), get_key(),
+                                                                  ocsp.clone(), vec![]);
+                            cfg
+                        },
+                        dns_name("localhost").into(),
+                        |_| ())
+}
+
+#[test]
+fn client_accepts_good_ocsp_response() {
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.ocsp"), false), Ok(()));
+}
+
+#[test]
+fn client_accepts_delegated_ocsp_response() {
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.delegated.ocsp"), false),
+               Ok(()));
+}
+
+#[test]
+fn client_rejects_revoked_ocsp_response() {
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.revoked.ocsp"), false),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+}
+
+#[test]
+fn client_rejects_bad_ocsp_responses() {
+    fn invalid(why: &str) -> Result<(), TLSErrorFromPeer> {
+        Err(TLSErrorFromPeer::Client(TLSError::InvalidOCSPResponse(why.to_string())))
+    }
+
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.expired.ocsp"), false),
+               invalid("response has expired"));
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.no-next-update.ocsp"), false),
+               invalid("response has expired"));
+    assert_eq!(server_check_with_ocsp(get_chain(), load_ocsp("end.noeku.ocsp"), false),
+               invalid("responder certificate not valid for OCSP signing"));
+    assert_eq!(server_check_with_ocsp(get_chain(), b"garbage".to_vec(), false),
+               invalid("cannot parse response"));
+    assert_eq!(server_check_with_ocsp(load_chain("mustaple.fullchain"),
+                                      load_ocsp("end.ocsp"),
+                                      false),
+               invalid("no response for certificate"));
+
+    let mut forged = load_ocsp("end.ocsp");
+    *forged.last_mut().unwrap() ^= 0x01;
+    assert_eq!(server_check_with_ocsp(get_chain(), forged, false),
+               invalid("bad signature"));
+}
+
+#[test]
+fn client_rejects_ocsp_response_from_decoy_issuer() {
+    // A certificate with the intermediate's name but another key
+    // signed this good response.  It is sent ahead of the real
+    // intermediate, but isn't on any chain webpki accepts.
+    let chain = get_chain();
+    let presented = vec![chain[0].clone(),
+                         load_chain("inter.decoy.cert")[0].clone(),
+                         chain[1].clone(),
+                         chain[2].clone()];
+    assert_eq!(server_check_with_ocsp(presented, load_ocsp("end.decoy.ocsp"), false),
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::InvalidOCSPResponse("bad signature".to_string()))));
+}
+
+#[test]
+fn client_sends_alert_for_bad_ocsp_response() {
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.set_single_cert_with_ocsp_and_sct(get_chain(),
+                                                    get_key(),
+                                                    load_ocsp("end.expired.ocsp"),
+                                                    vec![]);
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::AlertReceived(AlertDescription::BadCertificateStatusResponse)));
+}
+
+#[test]
+fn client_enforces_ocsp_must_staple() {
+    let chain = load_chain("mustaple.fullchain");
+
+    assert_eq!(server_check_with_ocsp(chain.clone(), vec![], false), Ok(()));
+    assert_eq!(server_check_with_ocsp(chain.clone(), vec![], true),
+               Err(TLSErrorFromPeer::Client(TLSError::OCSPStapleRequired)));
+    assert_eq!(server_check_with_ocsp(chain, load_ocsp("mustaple.ocsp"), true), Ok(()));
+
+    // Certificates without the TLS feature extension needn't have a staple.
+    assert_eq!(server_check_with_ocsp(get_chain(), vec![], true), Ok(()));
+}
+
+fn server_check_with_pins(pins: SPKIPinSet, server_chain: Vec<Certificate>)
+                          -> Result<(), TLSErrorFromPeer> {
+    check_both_versions(|| {
+                            let mut cfg = make_client_config();
+                            cfg.set_spki_pins(pins.clone());
+                            cfg
+                        },
+                        || {
+                            let mut cfg = ServerConfig::new(NoClientAuth::new());
+                            cfg.set_single_cert(server_chain.clone(), get_key());
+                            cfg
+                        },
+                        dns_name("localhost").into(),
+                        |_| ())
+}
+
+fn pin_set(pins: &[&Certificate], backup_pins: &[&Certificate]) -> SPKIPinSet {
+    let mut set = SPKIPinSet::new();
+    for cert in pins {
+        set.add_pin(SPKIPinSet::pin_for(cert).unwrap());
+    }
+    for cert in backup_pins {
+        set.add_backup_pin(SPKIPinSet::pin_for(cert).unwrap());
+    }
+    set
+}
+
+#[test]
+fn client_accepts_pinned_key_anywhere_in_chain() {
+    let chain = get_chain();
+    for cert in &chain {
+        assert_eq!(server_check_with_pins(pin_set(&[cert], &[]), get_chain()), Ok(()));
+        assert_eq!(server_check_with_pins(pin_set(&[], &[cert]), get_chain()), Ok(()));
+    }
+
+    // The trust anchor needn't be sent by the server.
+    let root = chain[2].clone();
+    assert_eq!(server_check_with_pins(pin_set(&[&root], &[]), chain[..2].to_vec()), Ok(()));
+}
+
+#[test]
+fn client_rejects_server_without_pinned_key() {
+    let other = get_chain_for("ecdsa");
+    let pins = pin_set(&[&other[0]], &[&other[1]]);
+    assert_eq!(pins.len(), 2);
+    assert_eq!(server_check_with_pins(pins.clone(), get_chain()),
+               Err(TLSErrorFromPeer::Client(TLSError::NoMatchingSPKIPin)));
+
+    // Sending a pinned certificate which isn't part of the chain
+    // doesn't help.
+    let mut chain = get_chain();
+    chain.push(other[0].clone());
+    assert_eq!(server_check_with_pins(pins.clone(), chain),
+               Err(TLSErrorFromPeer::Client(TLSError::NoMatchingSPKIPin)));
+
+    assert_eq!(server_check_with_pins(SPKIPinSet::new(), get_chain()),
+               Err(TLSErrorFromPeer::Client(TLSError::NoMatchingSPKIPin)));
+}
+
+#[test]
+fn client_ignores_expired_pins() {
+    let other = get_chain_for("ecdsa");
+    let mut pins = pin_set(&[&other[0]], &[]);
+    pins.set_expiry(UNIX_EPOCH);
+    assert_eq!(server_check_with_pins(pins, get_chain()), Ok(()));
+}
+
+#[test]
+fn pins_still_need_valid_chain() {
+    let chain = get_chain();
+    let mut client_config = make_client_config();
+    client_config.set_crls(load_crls(&["ca.crl", "inter.revoked.crl"]), CRLPolicy::default());
+    client_config.set_spki_pins(pin_set(&[&chain[0]], &[]));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::CertRevoked)));
+
+    let mut client_config = make_client_config();
+    client_config.set_spki_pins(pin_set(&[&chain[0]], &[]));
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("example.com"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+fn ip_address(addr: &str) -> ServerName {
+    ServerName::IPAddress(addr.parse().unwrap())
+}
+
+fn make_server_config_for_ip_addresses() -> ServerConfig {
+    let chain = pemfile::certs(&mut io::BufReader::new(
+        fs::File::open("test-ca/rsa/end.ip.fullchain").unwrap())).unwrap();
+    let mut cfg = ServerConfig::new(NoClientAuth::new());
+    cfg.set_single_cert(chain, get_key());
+    cfg
+}
+
+fn server_check_with_name(make_server_config: fn() -> ServerConfig,
+                          server_name: ServerName) -> Result<(), TLSErrorFromPeer> {
+    let expect_sni = server_name.as_dns_name().map(|n| n.into());
+    check_both_versions(make_client_config,
+                        make_server_config,
+                        server_name.clone(),
+                        |server| assert_eq!(server.get_sni_hostname(), expect_sni))
+}
+
+#[test]
+fn client_verifies_ip_address_server_names() {
+    for addr in &["127.0.0.1", "198.51.100.1", "::1", "2001:db8::1"] {
+        assert_eq!(server_check_with_name(make_server_config_for_ip_addresses,
+                                          ip_address(addr)),
+                   Ok(()));
+    }
+
+    // DNS names in the same certificate still work.
+    assert_eq!(server_check_with_name(make_server_config_for_ip_addresses,
+                                      dns_name("testserver.com").into()),
+               Ok(()));
+}
+
+#[test]
+fn client_rejects_ip_address_not_in_certificate() {
+    let not_valid = Err(TLSErrorFromPeer::Client(
+        TLSError::WebPKIError(webpki::Error::CertNotValidForName)));
+
+    for addr in &["192.0.2.1", "2001:db8::2", "::ffff:127.0.0.1"] {
+        assert_eq!(server_check_with_name(make_server_config_for_ip_addresses,
+                                          ip_address(addr)),
+                   not_valid);
+    }
+
+    // A dNSName spelling an IP address doesn't count.
+    assert_eq!(server_check_with_name(make_server_config, ip_address("127.0.0.1")),
+               not_valid);
+}
+
+#[test]
+fn server_name_parses_ip_addresses_and_dns_names() {
+    assert_eq!(ServerName::try_from_ascii_str("198.51.100.1"),
+               Ok(ip_address("198.51.100.1")));
+    assert_eq!(ServerName::try_from_ascii_str("2001:db8::1"),
+               Ok(ip_address("2001:db8::1")));
+    assert_eq!(ServerName::try_from_ascii_str("testserver.com"),
+               Ok(dns_name("testserver.com").into()));
+    assert_eq!(ServerName::try_from_ascii_str("not a name"),
+               Err(TLSError::InvalidDNSName("not a name".to_string())));
+
+    assert!(ip_address("::1").as_dns_name().is_none());
+}
+
+#[test]
+fn sessions_are_kept_per_ip_address() {
+    let (client_config, _) = make_early_data_configs(1234);
+    let mut server_config = make_server_config_for_ip_addresses();
+    server_config.ticketer = Ticketer::new();
+    server_config.max_early_data_size = 1234;
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientSession::new(&client_config, ip_address("127.0.0.1"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    let mut other = ClientSession::new(&client_config, ip_address("198.51.100.1"));
+    assert!(other.early_data().is_none());
+    let mut other = ClientSession::new(&client_config, dns_name("localhost"));
+    assert!(other.early_data().is_none());
+
+    let mut resumed = ClientSession::new(&client_config, ip_address("127.0.0.1"));
+    assert!(resumed.early_data().is_some());
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut resumed, &mut server);
+    assert!(server.is_early_data_accepted());
+}
+
+fn get_client_key() -> PrivateKey {
+    pemfile::rsa_private_keys(&mut io::BufReader::new(fs::File::open("test-ca/rsa/client.rsa")
+                .unwrap()))
+            .unwrap()[0]
+        .clone()
+}
+
+fn make_server_config_for_spiffe(allowed_paths: &[&str]) -> ServerConfig {
+    let mut client_auth_roots = RootCertStore::empty();
+    client_auth_roots.add(&load_chain("ca.cert")[0]).unwrap();
+
+    let client_auth = AllowSPIFFEClient::new(client_auth_roots, "example.org", allowed_paths);
+    let mut cfg = ServerConfig::new(client_auth);
+    cfg.set_single_cert(get_chain(), get_key());
+    cfg.ticketer = Ticketer::new();
+    cfg
+}
+
+fn make_client_config_with_svid(svid: &str) -> ClientConfig {
+    let mut cfg = make_client_config();
+    cfg.set_single_client_cert(load_chain(svid), get_client_key());
+    cfg
+}
+
+fn spiffe_check(svid: &str, allowed_paths: &[&str])
+                -> Result<Option<SPIFFEID>, TLSErrorFromPeer> {
+    check_both_versions(|| make_client_config_with_svid(svid),
+                        || make_server_config_for_spiffe(allowed_paths),
+                        dns_name("localhost").into(),
+                        |server| server.get_peer_spiffe_id().cloned())
+}
+
+#[test]
+fn server_gets_client_spiffe_id() {
+    let expect = SPIFFEID::from_uri("spiffe://example.org/ns/prod/sa/web").unwrap();
+    assert_eq!(expect.trust_domain(), "example.org");
+    assert_eq!(expect.path(), "/ns/prod/sa/web");
+
+    assert_eq!(spiffe_check("spiffe.web.fullchain", &[]), Ok(Some(expect.clone())));
+    assert_eq!(spiffe_check("spiffe.web.fullchain", &["/ns/prod/sa/db", "/ns/prod/sa/web"]),
+               Ok(Some(expect)));
+}
+
+#[test]
+fn server_rejects_client_spiffe_id_not_allowed() {
+    assert_eq!(spiffe_check("spiffe.other-domain.fullchain", &[]),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::SPIFFEIDNotAllowed("spiffe://other.org/ns/prod/sa/web".to_string()))));
+    assert_eq!(spiffe_check("spiffe.web.fullchain", &["/ns/prod/sa/db"]),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::SPIFFEIDNotAllowed("spiffe://example.org/ns/prod/sa/web".to_string()))));
+}
+
+#[test]
+fn client_is_told_spiffe_id_not_allowed() {
+    let server_config = make_server_config_for_spiffe(&["/ns/prod/sa/db"]);
+    let mut client_config = make_client_config_with_svid("spiffe.web.fullchain");
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+    transfer(&mut server, &mut client);
+    assert_eq!(client.process_new_packets(),
+               Err(TLSError::AlertReceived(AlertDescription::AccessDenied)));
+}
+
+#[test]
+fn server_requires_exactly_one_client_spiffe_id() {
+    assert_eq!(spiffe_check("client.fullchain", &[]),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::InvalidSPIFFEID("certificate has no URI name".to_string()))));
+    assert_eq!(spiffe_check("spiffe.two-uris.fullchain", &[]),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::InvalidSPIFFEID("certificate has more than one URI name"
+                                             .to_string()))));
+}
+
+#[test]
+fn client_spiffe_id_survives_resumption() {
+    let server_config = Arc::new(make_server_config_for_spiffe(&[]));
+    let expect = SPIFFEID::from_uri("spiffe://example.org/ns/prod/sa/web").ok();
+
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let mut client_config = make_client_config_with_svid("spiffe.web.fullchain");
+        client_config.versions = vec![*version];
+        let mut client = ClientSession::new(&Arc::new(client_config.clone()),
+                                            dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        assert_eq!(server.get_peer_spiffe_id().cloned(), expect);
+
+        // Without a certificate, this only succeeds by resuming.
+        let mut resuming_config = client_config;
+        resuming_config.client_auth_cert_resolver = ClientConfig::new().client_auth_cert_resolver;
+        let mut client = ClientSession::new(&Arc::new(resuming_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.get_peer_spiffe_id().cloned(), expect);
+        assert_eq!(server.get_peer_certificates(), Some(load_chain("spiffe.web.fullchain")));
+    }
+}
+
+#[test]
+fn client_spiffe_id_no_longer_allowed_is_not_resumed() {
+    let server_config = make_server_config_for_spiffe(&[]);
+    let mut narrowed_config = make_server_config_for_spiffe(&["/ns/prod/sa/db"]);
+    narrowed_config.ticketer = server_config.ticketer.clone();
+    narrowed_config.session_storage = server_config.session_storage.clone();
+    let server_config = Arc::new(server_config);
+    let narrowed_config = Arc::new(narrowed_config);
+
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let mut client_config = make_client_config_with_svid("spiffe.web.fullchain");
+        client_config.versions = vec![*version];
+        let client_config = Arc::new(client_config);
+
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        // The allowlist no longer admits this client, so neither may its session.
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&narrowed_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Server(
+                       TLSError::SPIFFEIDNotAllowed(
+                           "spiffe://example.org/ns/prod/sa/web".to_string()))));
+    }
+}
+
+#[test]
+fn session_without_client_spiffe_id_is_not_resumed() {
+    let server_config = make_server_config();
+    let mut spiffe_config = make_server_config_for_spiffe(&[]);
+    spiffe_config.ticketer = server_config.ticketer.clone();
+    spiffe_config.session_storage = server_config.session_storage.clone();
+    let server_config = Arc::new(server_config);
+    let spiffe_config = Arc::new(spiffe_config);
+
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        let client_config = Arc::new(client_config);
+
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        // The session had no client authentication, so a server which
+        // requires a SPIFFE ID must not resume it.
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&spiffe_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Server(TLSError::NoCertificatesPresented)));
+    }
+}
+
+#[test]
+fn other_verifiers_give_no_spiffe_id() {
+    let server_config = make_server_config_with_mandatory_client_auth();
+    let client_config = make_client_config_with_svid("spiffe.web.fullchain");
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert!(server.get_peer_certificates().is_some());
+    assert_eq!(server.get_peer_spiffe_id(), None);
+}
@@ -0,0 +1,355 @@
+// Tests for the async adapter, over an in-memory duplex transport.
+#![cfg(feature = "async")]
+
+use std::collections::VecDeque;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+extern crate rustls;
+extern crate futures_io;
+extern crate webpki;
+
+use futures_io::{AsyncRead, AsyncWrite};
+use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession};
+use rustls::{AsyncStream, Session, NoClientAuth, ProtocolVersion};
+use rustls::internal::pemfile;
+
+fn make_server_config() -> ServerConfig {
+    let chain = pemfile::certs(&mut io::BufReader::new(fs::File::open("test-ca/rsa/end.fullchain")
+                                                           .unwrap()))
+        .unwrap();
+    let key = pemfile::rsa_private_keys(&mut io::BufReader::new(fs::File::open("test-ca/rsa/end.rsa")
+                                                                   .unwrap()))
+        .unwrap()[0]
+        .clone();
+
+    let mut cfg = ServerConfig::new(NoClientAuth::new());
+    cfg.set_single_cert(chain, key);
+    cfg
+}
+
+fn make_client_config() -> ClientConfig {
+    let mut cfg = ClientConfig::new();
+    let mut rootbuf = io::BufReader::new(fs::File::open("test-ca/rsa/ca.cert").unwrap());
+    cfg.root_store.add_pem_file(&mut rootbuf).unwrap();
+    cfg
+}
+
+fn dns_name(name: &'static str) -> webpki::DNSNameRef {
+    webpki::DNSNameRef::try_from_ascii_str(name).unwrap()
+}
+
+/// One direction of a duplex: a bounded byte queue.
+struct Pipe {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+/// One end of an in-memory duplex transport.
+struct DuplexEnd {
+    rx: Arc<Mutex<Pipe>>,
+    tx: Arc<Mutex<Pipe>>,
+}
+
+fn duplex(capacity: usize) -> (DuplexEnd, DuplexEnd) {
+    let pipe = || Arc::new(Mutex::new(Pipe {
+        buf: VecDeque::new(),
+        capacity: capacity,
+        closed: false,
+        reader: None,
+        writer: None,
+    }));
+    let left = pipe();
+    let right = pipe();
+
+    (DuplexEnd { rx: left.clone(), tx: right.clone() },
+     DuplexEnd { rx: right, tx: left })
+}
+
+impl DuplexEnd {
+    fn is_write_closed(&self) -> bool {
+        self.tx.lock().unwrap().closed
+    }
+}
+
+impl AsyncRead for DuplexEnd {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = self.rx.lock().unwrap();
+
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = buf.len().min(pipe.buf.len());
+        for (i, byte) in pipe.buf.drain(..len).enumerate() {
+            buf[i] = byte;
+        }
+
+        if let Some(waker) = pipe.writer.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for DuplexEnd {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = self.tx.lock().unwrap();
+
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let len = buf.len().min(pipe.capacity - pipe.buf.len());
+        if len == 0 && !buf.is_empty() {
+            pipe.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        pipe.buf.extend(&buf[..len]);
+        if let Some(waker) = pipe.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        let mut pipe = self.tx.lock().unwrap();
+        pipe.closed = true;
+        if let Some(waker) = pipe.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Run `a` and `b` to completion together, on this thread.  Panics
+/// if neither can make progress.
+fn run_both<A, B>(a: A, b: B) -> (A::Output, B::Output)
+    where A: Future, B: Future
+{
+    let mut a = Box::pin(a);
+    let mut b = Box::pin(b);
+    let mut a_out = None;
+    let mut b_out = None;
+
+    let flag = Arc::new(Flag(AtomicBool::new(true)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    while a_out.is_none() || b_out.is_none() {
+        assert!(flag.0.swap(false, Ordering::SeqCst), "deadlock");
+
+        if a_out.is_none() {
+            if let Poll::Ready(out) = a.as_mut().poll(&mut cx) {
+                a_out = Some(out);
+                flag.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        if b_out.is_none() {
+            if let Poll::Ready(out) = b.as_mut().poll(&mut cx) {
+                b_out = Some(out);
+                flag.0.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    (a_out.unwrap(), b_out.unwrap())
+}
+
+struct PollFn<F>(F);
+
+impl<F, T> Future for PollFn<F> where F: FnMut(&mut Context) -> Poll<T> + Unpin {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        (self.0)(cx)
+    }
+}
+
+fn write_all<'a, W>(wr: &'a mut W, data: &'a [u8]) -> impl Future<Output = io::Result<()>> + 'a
+    where W: AsyncWrite + Unpin
+{
+    let mut offs = 0;
+    PollFn(move |cx: &mut Context| {
+        while offs < data.len() {
+            match Pin::new(&mut *wr).poll_write(cx, &data[offs..]) {
+                Poll::Ready(Ok(len)) => offs += len,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Pin::new(&mut *wr).poll_flush(cx)
+    })
+}
+
+fn close<'a, W>(wr: &'a mut W) -> impl Future<Output = io::Result<()>> + 'a
+    where W: AsyncWrite + Unpin
+{
+    PollFn(move |cx: &mut Context| Pin::new(&mut *wr).poll_close(cx))
+}
+
+fn read_exact<'a, R>(rd: &'a mut R, len: usize) -> impl Future<Output = io::Result<Vec<u8>>> + 'a
+    where R: AsyncRead + Unpin
+{
+    let mut out = Vec::new();
+    PollFn(move |cx: &mut Context| {
+        let mut buf = [0u8; 1024];
+        while out.len() < len {
+            let want = buf.len().min(len - out.len());
+            match Pin::new(&mut *rd).poll_read(cx, &mut buf[..want]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Poll::Ready(Ok(got)) => out.extend_from_slice(&buf[..got]),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(out.split_off(0)))
+    })
+}
+
+fn read_to_end<'a, R>(rd: &'a mut R) -> impl Future<Output = io::Result<Vec<u8>>> + 'a
+    where R: AsyncRead + Unpin
+{
+    let mut out = Vec::new();
+    PollFn(move |cx: &mut Context| {
+        let mut buf = [0u8; 1024];
+        loop {
+            match Pin::new(&mut *rd).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(out.split_off(0))),
+                Poll::Ready(Ok(got)) => out.extend_from_slice(&buf[..got]),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    })
+}
+
+fn connected_pair(capacity: usize)
+                  -> (AsyncStream<ClientSession, DuplexEnd>, AsyncStream<ServerSession, DuplexEnd>) {
+    let (client_io, server_io) = duplex(capacity);
+    let (client, server) = run_both(
+        rustls::connect(&Arc::new(make_client_config()), dns_name("localhost"), client_io),
+        rustls::accept(&Arc::new(make_server_config()), server_io));
+    (client.unwrap(), server.unwrap())
+}
+
+#[test]
+fn connect_and_accept() {
+    let (mut client, mut server) = connected_pair(4096);
+    assert!(!client.get_session().is_handshaking());
+    assert!(!server.get_session().is_handshaking());
+    assert_eq!(client.get_session().get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+    let (wrote, read) = run_both(write_all(&mut client, b"hello"),
+                                 read_exact(&mut server, 5));
+    wrote.unwrap();
+    assert_eq!(read.unwrap(), b"hello".to_vec());
+
+    let (wrote, read) = run_both(write_all(&mut server, b"world"),
+                                 read_exact(&mut client, 5));
+    wrote.unwrap();
+    assert_eq!(read.unwrap(), b"world".to_vec());
+}
+
+#[test]
+fn handshake_happens_lazily() {
+    let (client_io, server_io) = duplex(4096);
+    let client_sess = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let server_sess = ServerSession::new(&Arc::new(make_server_config()));
+    let mut client = AsyncStream::new(client_sess, client_io);
+    let mut server = AsyncStream::new(server_sess, server_io);
+
+    let (wrote, read) = run_both(write_all(&mut client, b"hello"),
+                                 read_exact(&mut server, 5));
+    wrote.unwrap();
+    assert_eq!(read.unwrap(), b"hello".to_vec());
+    assert_eq!(server.get_session().get_sni_hostname(), Some("localhost"));
+}
+
+#[test]
+fn large_transfer_over_small_transport() {
+    let (mut client, mut server) = connected_pair(100);
+    let data = (0..200000).map(|i| i as u8).collect::<Vec<u8>>();
+
+    let (wrote, read) = run_both(write_all(&mut client, &data),
+                                 read_exact(&mut server, data.len()));
+    wrote.unwrap();
+    assert_eq!(read.unwrap(), data);
+}
+
+fn write_then_close<'a>(stream: &'a mut AsyncStream<ClientSession, DuplexEnd>,
+                        data: &'a [u8]) -> impl Future<Output = io::Result<()>> + 'a {
+    let mut wrote = false;
+    PollFn(move |cx: &mut Context| {
+        if !wrote {
+            match Pin::new(&mut *stream).poll_write(cx, data) {
+                Poll::Ready(Ok(len)) => assert_eq!(len, data.len()),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+            wrote = true;
+        }
+        Pin::new(&mut *stream).poll_close(cx)
+    })
+}
+
+#[test]
+fn close_sends_close_notify() {
+    let (mut client, mut server) = connected_pair(4096);
+
+    let (closed, read) = run_both(write_then_close(&mut client, b"goodbye"),
+                                  read_to_end(&mut server));
+    closed.unwrap();
+    assert_eq!(read.unwrap(), b"goodbye".to_vec());
+
+    let (_, client_io) = client.into_parts();
+    assert!(client_io.is_write_closed());
+}
+
+#[test]
+fn handshake_failure_is_reported() {
+    let (client_io, server_io) = duplex(4096);
+    let (client, server) = run_both(
+        rustls::connect(&Arc::new(make_client_config()), dns_name("not-the-server.com"), client_io),
+        rustls::accept(&Arc::new(make_server_config()), server_io));
+
+    assert_eq!(client.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(server.err().unwrap().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn eof_during_handshake_is_an_error() {
+    let (client_io, mut server_io) = duplex(4096);
+    let (client, closed) = run_both(
+        rustls::connect(&Arc::new(make_client_config()), dns_name("localhost"), client_io),
+        close(&mut server_io));
+
+    closed.unwrap();
+    assert_eq!(client.err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+}
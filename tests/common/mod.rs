@@ -3,10 +3,16 @@ use std::process;
 use std::thread;
 use std::time;
 use std::net;
+use std::fmt;
+use std::sync::Arc;
 
 extern crate regex;
 use self::regex::Regex;
 
+extern crate rustls;
+use self::rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession};
+use self::rustls::{ProtocolVersion, ServerName, Session, TLSError};
+
 // For tests which connect to internet servers, don't go crazy.
 pub fn polite() {
     thread::sleep(time::Duration::from_secs(1));
@@ -440,12 +446,19 @@ pub struct TlsServer {
 
 impl TlsServer {
     pub fn new(port: u16) -> Self {
-        let keytype = "rsa";
+        TlsServer::new_keytype("rsa", "end.rsa", port)
+    }
+
+    pub fn new_ecdsa(port: u16) -> Self {
+        TlsServer::new_keytype("ecdsa", "end.key", port)
+    }
+
+    fn new_keytype(keytype: &str, keyfile: &str, port: u16) -> Self {
         TlsServer {
             port: unused_port(port),
             http: false,
             echo: false,
-            key: format!("test-ca/{}/end.rsa", keytype),
+            key: format!("test-ca/{}/{}", keytype, keyfile),
             certs: format!("test-ca/{}/end.fullchain", keytype),
             cafile: format!("test-ca/{}/ca.cert", keytype),
             verbose: false,
@@ -696,3 +709,70 @@ impl OpenSSLClient {
         Some(())
     }
 }
+
+pub fn transfer(left: &mut Session, right: &mut Session) {
+    let mut buf = [0u8; 262144];
+
+    while left.wants_write() {
+        let sz = left.write_tls(&mut buf.as_mut()).unwrap();
+        if sz == 0 {
+            return;
+        }
+
+        let mut offs = 0;
+        loop {
+            offs += right.read_tls(&mut buf[offs..sz].as_ref()).unwrap();
+            if sz == offs {
+                break;
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum TLSErrorFromPeer { Client(TLSError), Server(TLSError) }
+
+pub fn do_handshake_until_error(client: &mut ClientSession,
+                                server: &mut ServerSession)
+                                -> Result<(), TLSErrorFromPeer> {
+    while server.is_handshaking() || client.is_handshaking() {
+        transfer(client, server);
+        server.process_new_packets()
+            .map_err(|err| TLSErrorFromPeer::Server(err))?;
+        transfer(server, client);
+        client.process_new_packets()
+            .map_err(|err| TLSErrorFromPeer::Client(err))?;
+    }
+
+    Ok(())
+}
+
+// Handshake once with TLS1.3 and once with TLS1.2, using fresh configs
+// from `make_client_config` and `make_server_config` each time.  A
+// successful handshake is mapped through `check`.  Both versions must
+// give the same result, which is returned.
+pub fn check_both_versions<T, C, S, F>(make_client_config: C,
+                                       make_server_config: S,
+                                       server_name: ServerName,
+                                       check: F) -> Result<T, TLSErrorFromPeer>
+    where T: PartialEq + fmt::Debug,
+          C: Fn() -> ClientConfig,
+          S: Fn() -> ServerConfig,
+          F: Fn(&ServerSession) -> T
+{
+    let mut result = None;
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name.clone());
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        let this_result = do_handshake_until_error(&mut client, &mut server)
+            .map(|_| check(&server));
+        if let Some(ref result) = result {
+            assert_eq!(&this_result, result);
+        }
+        result = Some(this_result);
+    }
+    result.unwrap()
+}